@@ -0,0 +1,236 @@
+// Group scanned tracks into albums. Multi-disc sets are one album, even
+// when each disc is in its own CD1/, CD2/... directory or has the disc in
+// the album tag.
+use crate::layout::album_dir;
+use crate::{dates, file_ext, TrackInfo};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const ART_EXTS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif"];
+
+// What an album adds up to, counted as the scan goes so it's there even
+// when the tracks aren't kept. Albums are by album artist here, so a
+// compilation is one album however many artists are on it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AlbumInfo {
+    // The album artist, or the artist of the first track without one
+    pub artist: String,
+    pub title: String,
+    // The earliest year of any track
+    pub year: Option<u32>,
+    pub tracks: u32,
+    pub duration: Duration,
+    // Extensions of the tracks
+    pub formats: BTreeSet<String>,
+    // The cover image in the directory, if there is one
+    pub art: Option<String>,
+    // Of the first track, disc directories taken as part of the album's
+    pub dir: String,
+}
+
+#[derive(Default)]
+pub struct Tally(BTreeMap<(String, String), AlbumInfo>);
+
+impl Tally {
+    pub fn add(&mut self, t: &TrackInfo) {
+        if t.album.is_empty() {
+            return;
+        }
+        let artist = t.album_artist.as_deref().unwrap_or(&t.artist);
+        let title = split_disc(&t.album).0;
+        let a = self
+            .0
+            .entry((artist.to_string(), title.to_string()))
+            .or_insert_with(|| AlbumInfo {
+                artist: artist.to_string(),
+                title: title.to_string(),
+                year: None,
+                tracks: 0,
+                duration: Duration::ZERO,
+                formats: BTreeSet::new(),
+                art: None,
+                dir: Path::new(&t.path)
+                    .parent()
+                    .map(|d| album_dir(d).to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            });
+        if let Some(Ok(d)) = t.date.as_deref().map(dates::parse) {
+            a.year = Some(a.year.map_or(d.year, |y| y.min(d.year)));
+        }
+        a.tracks += 1;
+        a.duration += t.duration;
+        a.formats.insert(file_ext(&t.path));
+    }
+
+    // The albums in artist and title order, with their art looked for
+    pub fn finish(self) -> Vec<AlbumInfo> {
+        self.0
+            .into_values()
+            .map(|mut a| {
+                a.art = art(Path::new(&a.dir));
+                a
+            })
+            .collect()
+    }
+}
+
+pub fn info(tracks: &[TrackInfo]) -> Vec<AlbumInfo> {
+    let mut tally = Tally::default();
+    for t in tracks {
+        tally.add(t);
+    }
+    tally.finish()
+}
+
+// The first image in the directory
+fn art(dir: &Path) -> Option<String> {
+    let mut images: Vec<String> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|n| ART_EXTS.contains(&file_ext(n).as_str()))
+        .collect();
+    images.sort();
+    images.into_iter().next()
+}
+
+pub struct Album<'a> {
+    pub artist: &'a str,
+    pub title: &'a str,
+    pub tracks: Vec<&'a TrackInfo>,
+}
+
+impl Album<'_> {
+    // The largest total tracks tag of any track on a disc
+    pub fn disc_track_total(&self, disc: u32) -> Option<u32> {
+        self.disc_tracks(disc).filter_map(|t| t.track_total).max()
+    }
+
+    // Disc numbers that have tracks, in order
+    pub fn discs(&self) -> Vec<u32> {
+        let mut discs: Vec<u32> = self.tracks.iter().map(|t| disc(t)).collect();
+        discs.dedup();
+        discs
+    }
+
+    pub fn disc_tracks(&self, n: u32) -> impl Iterator<Item = &&TrackInfo> {
+        self.tracks.iter().filter(move |t| disc(t) == n)
+    }
+
+    // Discs up to the total discs tag that no track is on
+    pub fn missing_discs(&self) -> Vec<u32> {
+        let total = self
+            .tracks
+            .iter()
+            .filter_map(|t| t.disc_total)
+            .max()
+            .unwrap_or(0);
+        let discs = self.discs();
+        (1..=total).filter(|d| !discs.contains(d)).collect()
+    }
+
+    // Modification time of the newest track
+    pub fn modified(&self) -> u64 {
+        self.tracks.iter().map(|t| t.modified).max().unwrap_or(0)
+    }
+
+    // Distinct genres of the tracks, in track order
+    pub fn genres(&self) -> Vec<&str> {
+        let mut genres = Vec::new();
+        for g in self.tracks.iter().map(|t| &*t.genre) {
+            if !g.is_empty() && !genres.contains(&g) {
+                genres.push(g);
+            }
+        }
+        genres
+    }
+
+    // Track numbers up to the disc's total tracks tag that no track has
+    pub fn missing_tracks(&self, disc: u32) -> Vec<u32> {
+        let total = self.disc_track_total(disc).unwrap_or(0);
+        (1..=total)
+            .filter(|n| !self.disc_tracks(disc).any(|t| t.track == *n))
+            .collect()
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        !self.missing_discs().is_empty()
+            || self
+                .discs()
+                .into_iter()
+                .any(|d| !self.missing_tracks(d).is_empty())
+    }
+}
+
+// Tracks with an album tag, grouped by album artist, or artist when there
+// isn't one, and album, so a compilation is one album. Tracks without an
+// album tag aren't part of any album.
+pub fn albums(tracks: &[TrackInfo]) -> Vec<Album<'_>> {
+    let mut grouped: BTreeMap<(&str, &str), Vec<&TrackInfo>> = BTreeMap::new();
+    for t in tracks.iter().filter(|t| !t.album.is_empty()) {
+        let artist = t.album_artist.as_deref().unwrap_or(&t.artist);
+        grouped
+            .entry((artist, split_disc(&t.album).0))
+            .or_default()
+            .push(t);
+    }
+    grouped
+        .into_iter()
+        .map(|((artist, title), mut tracks)| {
+            tracks.sort_by_key(|t| (disc(t), t.track));
+            Album {
+                artist,
+                title,
+                tracks,
+            }
+        })
+        .collect()
+}
+
+// The disc a track is on: the disc number tag, then a disc in the album
+// tag, then a CD1/Disc 1 directory. Anything else is disc 1.
+pub fn disc(t: &TrackInfo) -> u32 {
+    t.disc
+        .filter(|&d| d > 0)
+        .or_else(|| split_disc(&t.album).1)
+        .or_else(|| {
+            let dir = Path::new(&t.path).parent()?.file_name()?.to_str()?;
+            let dir = dir.to_ascii_lowercase();
+            let rest = ["cd", "disc", "disk"]
+                .iter()
+                .find_map(|w| dir.strip_prefix(w))?;
+            rest.trim_start_matches([' ', '-', '_']).parse().ok()
+        })
+        .unwrap_or(1)
+}
+
+// Split "Album (CD1)", "Album [Disc 2 of 3]", "Album - Disk 1" and the like
+// into the album and the disc
+pub fn split_disc(album: &str) -> (&str, Option<u32>) {
+    let lower = album.to_ascii_lowercase();
+    let body = lower.trim_end_matches([')', ']']).trim_end();
+    for word in ["cd", "disc", "disk"] {
+        let i = match body.rfind(word) {
+            Some(i) => i,
+            None => continue,
+        };
+        let rest = body[i + word.len()..].trim_start();
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let after = rest[digits..].trim();
+        let before = &album[..i];
+        if digits == 0
+            || !(after.is_empty() || after.starts_with("of "))
+            || !before.ends_with([' ', '(', '[', '-', '_'])
+        {
+            continue;
+        }
+        let base = before.trim_end_matches([' ', '(', '[', '-', '_', ',', ':']);
+        if !base.is_empty() {
+            return (base, rest[..digits].parse().ok());
+        }
+    }
+    (album, None)
+}
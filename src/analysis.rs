@@ -0,0 +1,190 @@
+// Audio analysis. Decoding needs symphonia, so this is only built with
+// the bpm-analysis or fingerprint feature.
+use serde_derive::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    // Estimate the BPM of tracks that have no BPM tag
+    pub bpm: bool,
+}
+
+#[cfg(not(feature = "bpm-analysis"))]
+pub fn estimate_bpm(_file_name: &str) -> Option<f64> {
+    use std::sync::Once;
+    static WARN: Once = Once::new();
+    WARN.call_once(|| warn!("BPM analysis needs tag_test built with --features bpm-analysis"));
+    None
+}
+
+#[cfg(feature = "bpm-analysis")]
+pub use bpm::estimate_bpm;
+
+#[cfg(feature = "bpm-analysis")]
+mod bpm {
+    use super::decode::decode_mono;
+
+    // Only the first minute is decoded, that's plenty to find the beat
+    const MAX_SECONDS: usize = 60;
+    // Samples per onset envelope step
+    const HOP: usize = 512;
+    const MIN_BPM: f64 = 60.0;
+    const MAX_BPM: f64 = 200.0;
+
+    pub fn estimate_bpm(file_name: &str) -> Option<f64> {
+        let (samples, sample_rate) = decode_mono(file_name, MAX_SECONDS)?;
+        let envelope = onset_envelope(&samples);
+        let frame_rate = sample_rate as f64 / HOP as f64;
+
+        // Autocorrelate the onsets over the lags that make sense as beats
+        let min_lag = (frame_rate * 60.0 / MAX_BPM).floor() as usize;
+        let max_lag = (frame_rate * 60.0 / MIN_BPM).ceil() as usize;
+        if min_lag == 0 || envelope.len() <= max_lag + 1 {
+            return None;
+        }
+        let corr: Vec<f64> = (0..=max_lag + 1)
+            .map(|lag| {
+                envelope
+                    .iter()
+                    .zip(&envelope[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum()
+            })
+            .collect();
+        let best = (min_lag..=max_lag).max_by(|&a, &b| corr[a].total_cmp(&corr[b]))?;
+        if corr[best] <= 0.0 {
+            return None;
+        }
+
+        // Parabolic interpolation between neighbouring lags for a less
+        // coarse result
+        let (l, c, r) = (corr[best - 1], corr[best], corr[best + 1]);
+        let denom = l - 2.0 * c + r;
+        let offset = if denom != 0.0 {
+            0.5 * (l - r) / denom
+        } else {
+            0.0
+        };
+        Some(60.0 * frame_rate / (best as f64 + offset))
+    }
+
+    // Rises in energy from one hop to the next
+    fn onset_envelope(samples: &[f32]) -> Vec<f64> {
+        let energy: Vec<f64> = samples
+            .chunks(HOP)
+            .map(|c| c.iter().map(|&s| (s as f64) * (s as f64)).sum::<f64>())
+            .collect();
+        energy.windows(2).map(|w| (w[1] - w[0]).max(0.0)).collect()
+    }
+}
+
+#[cfg(not(any(feature = "bpm-analysis", feature = "fingerprint")))]
+pub fn audio_crc(_file_name: &str) -> Option<u32> {
+    use std::sync::Once;
+    static WARN: Once = Once::new();
+    WARN.call_once(|| warn!("Checking rip CRCs needs tag_test built with --features bpm-analysis"));
+    None
+}
+
+#[cfg(any(feature = "bpm-analysis", feature = "fingerprint"))]
+pub use decode::audio_crc;
+
+#[cfg(any(feature = "bpm-analysis", feature = "fingerprint"))]
+pub mod decode {
+    use std::fs::File;
+    use std::path::Path;
+    use symphonia::core::audio::{AudioBufferRef, SampleBuffer};
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    // Calls f with each decoded packet until it returns false or the file
+    // ends
+    fn decode(file_name: &str, mut f: impl FnMut(AudioBufferRef) -> bool) -> Option<()> {
+        let file = File::open(file_name).ok()?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = Path::new(file_name).extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .ok()?;
+        let mut format = probed.format;
+        let track = format.default_track()?;
+        let track_id = track.id;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .ok()?;
+
+        while let Ok(packet) = format.next_packet() {
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(d) => d,
+                Err(Error::DecodeError(_)) => continue,
+                Err(_) => break,
+            };
+            if !f(decoded) {
+                break;
+            }
+        }
+        Some(())
+    }
+
+    // Up to max_seconds of audio, mixed down to mono
+    pub fn decode_mono(file_name: &str, max_seconds: usize) -> Option<(Vec<f32>, u32)> {
+        let mut samples = Vec::new();
+        let mut sample_rate = 0;
+        decode(file_name, |decoded| {
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            sample_rate = spec.rate;
+            let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buf.copy_interleaved_ref(decoded);
+            samples.extend(
+                buf.samples()
+                    .chunks(channels)
+                    .map(|c| c.iter().sum::<f32>() / channels as f32),
+            );
+            samples.len() < sample_rate as usize * max_seconds
+        })?;
+        (sample_rate > 0).then_some((samples, sample_rate))
+    }
+
+    // CRC32 of the whole file's 16 bit samples, little endian, which is
+    // the Copy CRC in EAC, XLD and whipper logs
+    pub fn audio_crc(file_name: &str) -> Option<u32> {
+        let table: Vec<u32> = (0..256)
+            .map(|n| {
+                (0..8).fold(n, |c, _| {
+                    if c & 1 == 1 {
+                        0xedb88320 ^ (c >> 1)
+                    } else {
+                        c >> 1
+                    }
+                })
+            })
+            .collect();
+        let mut crc = !0u32;
+        decode(file_name, |decoded| {
+            let spec = *decoded.spec();
+            let mut buf = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+            buf.copy_interleaved_ref(decoded);
+            for b in buf.samples().iter().flat_map(|s| s.to_le_bytes()) {
+                crc = table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+            }
+            true
+        })?;
+        Some(!crc)
+    }
+}
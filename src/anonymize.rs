@@ -0,0 +1,78 @@
+// Exports that can be shared without giving away the collection, e.g. to
+// show a problem with the layout of a library. Each path part and each tag
+// text is swapped for a hash of it, so the same name is the same hash
+// everywhere and the directory tree stays as it was. Extensions, numbers,
+// durations, sizes, genres, dates and error messages are kept.
+use crate::xattrs::Xattrs;
+use crate::TrackInfo;
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+// FNV-1a, which is the same everywhere and in every version
+fn hash(salt: &str, s: &str) -> String {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in salt.bytes().chain([0]).chain(s.bytes()) {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    format!("{:08x}", h >> 32)
+}
+
+// Empty stays empty
+pub fn text(salt: &str, s: &str) -> String {
+    match s {
+        "" => String::new(),
+        s => hash(salt, s),
+    }
+}
+
+// The file's extension is kept
+pub fn path(salt: &str, p: &str) -> String {
+    let p = Path::new(p);
+    let last = p.components().count().saturating_sub(1);
+    p.components()
+        .enumerate()
+        .map(|(i, c)| match c {
+            Component::Normal(name) => {
+                let name = Path::new(name);
+                match name.extension() {
+                    Some(ext) if i == last => format!(
+                        "{}.{}",
+                        text(
+                            salt,
+                            &name.file_stem().unwrap_or_default().to_string_lossy()
+                        ),
+                        ext.to_string_lossy()
+                    ),
+                    _ => text(salt, &name.to_string_lossy()),
+                }
+            }
+            Component::RootDir => String::new(),
+            other => other.as_os_str().to_string_lossy().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn track(salt: &str, t: &TrackInfo) -> TrackInfo {
+    let opt = |s: &Option<String>| s.as_ref().map(|s| text(salt, s));
+    TrackInfo {
+        path: path(salt, &t.path),
+        title: text(salt, &t.title),
+        artist: Arc::from(text(salt, &t.artist)),
+        album: Arc::from(text(salt, &t.album)),
+        composer: opt(&t.composer),
+        conductor: opt(&t.conductor),
+        work: opt(&t.work),
+        movement: opt(&t.movement),
+        artist_sort: opt(&t.artist_sort),
+        album_sort: opt(&t.album_sort),
+        album_artist: opt(&t.album_artist),
+        xattrs: t.xattrs.as_ref().map(|x| Xattrs {
+            tags: x.tags.iter().map(|s| text(salt, s)).collect(),
+            comment: opt(&x.comment),
+            ..x.clone()
+        }),
+        ..t.clone()
+    }
+}
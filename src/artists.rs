@@ -0,0 +1,79 @@
+// What each artist adds up to, counted as the scan goes like the albums
+// are. Spellings that only differ in case, accents or punctuation are one
+// artist, named by the spelling most of their tracks have.
+use crate::albums::split_disc;
+use crate::spellings::normalize;
+use crate::TrackInfo;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ArtistInfo {
+    pub name: String,
+    // The other spellings
+    pub variants: Vec<String>,
+    pub albums: u32,
+    pub tracks: u32,
+    pub duration: Duration,
+    pub genres: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct Counts {
+    // Spelling -> tracks with it
+    names: BTreeMap<String, u32>,
+    albums: HashSet<String>,
+    tracks: u32,
+    duration: Duration,
+    genres: BTreeSet<String>,
+}
+
+#[derive(Default)]
+pub struct Tally(BTreeMap<String, Counts>);
+
+impl Tally {
+    pub fn add(&mut self, t: &TrackInfo) {
+        let key = normalize(&t.artist);
+        if key.is_empty() {
+            return;
+        }
+        let c = self.0.entry(key).or_default();
+        *c.names.entry(t.artist.to_string()).or_default() += 1;
+        if !t.album.is_empty() {
+            c.albums.insert(split_disc(&t.album).0.to_string());
+        }
+        c.tracks += 1;
+        c.duration += t.duration;
+        if !t.genre.is_empty() {
+            c.genres.insert(t.genre.to_string());
+        }
+    }
+
+    pub fn finish(self) -> Vec<ArtistInfo> {
+        self.0
+            .into_values()
+            .map(|c| {
+                let mut names: Vec<(String, u32)> = c.names.into_iter().collect();
+                names.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                let mut names = names.into_iter().map(|(n, _)| n);
+                ArtistInfo {
+                    name: names.next().unwrap_or_default(),
+                    variants: names.collect(),
+                    albums: c.albums.len() as u32,
+                    tracks: c.tracks,
+                    duration: c.duration,
+                    genres: c.genres,
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn info(tracks: &[TrackInfo]) -> Vec<ArtistInfo> {
+    let mut tally = Tally::default();
+    for t in tracks {
+        tally.add(t);
+    }
+    tally.finish()
+}
@@ -0,0 +1,84 @@
+// --interactive for the commands that fix tags one value at a time (dates,
+// featured, genres, infer, sortnames and spellings), for going through the
+// changes one at a time rather than trusting a bulk write. After each
+// change is shown, y takes it, n leaves it, e takes a value typed in
+// instead, a takes it and the rest without asking, and q leaves it and
+// the rest.
+use crate::term::{self, Mode};
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+
+const HELP: &str = "y - make this change
+n - leave it
+e - type in the value to use instead
+a - make this change and all the rest
+q - leave this change and all the rest";
+
+pub struct Prompt {
+    ask: bool,
+    quit: bool,
+}
+
+impl Prompt {
+    // Takes --interactive out of the arguments
+    pub fn new(args: &mut Vec<String>) -> Prompt {
+        let ask = args.iter().any(|a| a == "--interactive");
+        args.retain(|a| a != "--interactive");
+        if ask {
+            check_terminal("--interactive");
+        }
+        Prompt { ask, quit: false }
+    }
+
+    // The value to write for a change that's just been shown, None to
+    // leave it
+    pub fn check(&mut self, new: String) -> Option<String> {
+        if self.quit {
+            return None;
+        }
+        while self.ask {
+            match read_line("  Make this change [y,n,e,a,q,?]? ").as_deref() {
+                Some("y") => break,
+                Some("n") => return None,
+                Some("e") => return read_line("  New value: "),
+                Some("a") => self.ask = false,
+                Some("q") | None => {
+                    self.quit = true;
+                    return None;
+                }
+                _ => log!("{HELP}"),
+            }
+        }
+        Some(new)
+    }
+}
+
+// Questions are only asked with the normal output
+pub fn check_terminal(flag: &str) {
+    if term::mode() != Mode::Normal {
+        error!("{flag} can't be used with --quiet, --json or --stream");
+        exit(1);
+    }
+}
+
+// A yes or no before going ahead with something
+pub fn confirm(question: &str) -> bool {
+    loop {
+        match read_line(&format!("{question} [y,n]? ")).as_deref() {
+            Some("y") => return true,
+            Some("n") | None => return false,
+            _ => (),
+        }
+    }
+}
+
+// None at the end of the input
+fn read_line(prompt: &str) -> Option<String> {
+    print!("{prompt}");
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+    }
+}
@@ -0,0 +1,114 @@
+// Tags from earlier scans, so files that haven't changed since aren't
+// read again. A file counts as unchanged while its path, modification
+// time and size are the same. Backends implement Cache and are picked by
+// name in open().
+use crate::TrackInfo;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    // "flatfile" or "none"
+    pub backend: String,
+    // Where the flatfile backend keeps the tags
+    pub file: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            backend: String::from("none"),
+            file: String::from("cache.json"),
+        }
+    }
+}
+
+pub trait Cache: Send {
+    // The tags stored for path, if it had this modification time and size
+    // then
+    fn get(&self, path: &str, modified: u64, size: u64) -> Option<TrackInfo>;
+    // The tags stored for path whenever they were read
+    fn stored(&self, path: &str) -> Option<TrackInfo>;
+    fn put(&mut self, track: &TrackInfo);
+    // Everything stored, for the commands that work without the library
+    fn tracks(&self) -> Vec<TrackInfo>;
+    // Write out anything not stored yet, dropping files under roots that
+    // are gone
+    fn flush(&mut self, roots: &[&str]) -> Result<(), String>;
+}
+
+pub fn open(cc: &CacheConfig) -> Option<Box<dyn Cache>> {
+    match cc.backend.to_lowercase().as_str() {
+        "none" | "" => None,
+        "flatfile" => Some(Box::new(FlatFile::open(&cc.file))),
+        b => {
+            warn!("Unknown cache backend {b}, not caching");
+            None
+        }
+    }
+}
+
+// Everything in one JSON file, read at the start of the scan and written
+// at the end
+struct FlatFile {
+    file: String,
+    tracks: HashMap<String, TrackInfo>,
+    changed: bool,
+}
+
+impl FlatFile {
+    fn open(file: &str) -> FlatFile {
+        let tracks = match fs::read_to_string(file) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Ignoring {file}: {e}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        FlatFile {
+            file: file.to_string(),
+            tracks,
+            changed: false,
+        }
+    }
+}
+
+impl Cache for FlatFile {
+    fn get(&self, path: &str, modified: u64, size: u64) -> Option<TrackInfo> {
+        self.tracks
+            .get(path)
+            .filter(|t| t.modified == modified && t.size == size)
+            .cloned()
+    }
+
+    fn stored(&self, path: &str) -> Option<TrackInfo> {
+        self.tracks.get(path).cloned()
+    }
+
+    fn put(&mut self, track: &TrackInfo) {
+        self.tracks.insert(track.path.clone(), track.clone());
+        self.changed = true;
+    }
+
+    fn tracks(&self) -> Vec<TrackInfo> {
+        self.tracks.values().cloned().collect()
+    }
+
+    fn flush(&mut self, roots: &[&str]) -> Result<(), String> {
+        let before = self.tracks.len();
+        self.tracks.retain(|p, _| {
+            let path = Path::new(p);
+            !roots.iter().any(|r| path.starts_with(r)) || path.exists()
+        });
+        if !self.changed && self.tracks.len() == before {
+            return Ok(());
+        }
+        let json = serde_json::to_string(&self.tracks).map_err(|e| e.to_string())?;
+        fs::write(&self.file, json).map_err(|e| e.to_string())?;
+        self.changed = false;
+        Ok(())
+    }
+}
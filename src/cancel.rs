@@ -0,0 +1,72 @@
+// Ctrl-C during a scan stops it cleanly: no more files are started, the
+// ones already on their way are finished, exports are flushed and the
+// summary covers what was scanned. Where it stopped is saved, and
+// tag_test --resume carries on from there. A second Ctrl-C quits at once.
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+// The first file a cancelled scan didn't get to, and the index of its
+// scan root
+#[derive(Serialize, Deserialize)]
+pub struct ResumePoint {
+    pub root: usize,
+    pub path: String,
+}
+
+pub fn install() {
+    let res = ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            exit(130);
+        }
+        warn!("Stopping after the files in progress, Ctrl-C again to quit now");
+    });
+    if let Err(e) = res {
+        warn!("Can't catch Ctrl-C: {e}");
+    }
+}
+
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+impl ResumePoint {
+    // True if the walk had already got past this file, in the walk's
+    // order: roots in turn, and by path within a root
+    pub fn is_after(&self, root: usize, path: &Path) -> bool {
+        root > self.root || (root == self.root && path >= Path::new(&self.path))
+    }
+}
+
+pub fn load(file: &str) -> Option<ResumePoint> {
+    let json = fs::read_to_string(file).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            warn!("Ignoring {file}: {e}");
+            None
+        }
+    }
+}
+
+pub fn save(file: &str, point: &ResumePoint) {
+    let res = serde_json::to_string(point)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(file, json).map_err(|e| e.to_string()));
+    match res {
+        Ok(_) => warn!(
+            "Scan stopped, tag_test --resume carries on from {}",
+            point.path
+        ),
+        Err(e) => error!("Error writing {file}: {e}"),
+    }
+}
+
+// After a scan that ran to the end
+pub fn clear(file: &str) {
+    let _ = fs::remove_file(file);
+}
@@ -0,0 +1,172 @@
+// WAV and AIFF files can carry their tags in their own chunks (RIFF INFO,
+// AIFF text) or in an ID3v2 chunk, and players tend to read only one.
+// This lists which each file has, and with --copy-to fills in the one
+// asked for from the other.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::{file_ext, music_files, term, Config};
+use lofty::config::WriteOptions;
+use lofty::file::FileType;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{Tag, TagType};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::process::exit;
+
+pub const TARGETS: &[&str] = &["native", "id3"];
+
+pub const COMMAND: Command = Command {
+    name: "chunks",
+    usage: "[--dry-run] [--copy-to native|id3] [path...]",
+    about: "List WAV and AIFF files by the tag chunks they have",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--copy-to",
+            values: Some(TARGETS),
+            about: "Fill in the native (INFO or AIFF text) or the ID3 chunk from the other",
+        },
+    ],
+};
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut dry_run = false;
+    let mut copy_to = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--copy-to" => match args.next().map(String::as_str) {
+                Some(t) if TARGETS.contains(&t) => copy_to = Some(t == "id3"),
+                _ => usage(),
+            },
+            a if a.starts_with("--") => usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    // "INFO", "ID3", "INFO + ID3" or "none" -> files
+    let mut kinds: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let (mut changed, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        if !matches!(file_ext(&file_name).as_str(), "wav" | "aif" | "aiff") {
+            continue;
+        }
+        let res = Probe::open(&file_name)
+            .and_then(|p| p.read())
+            .map_err(|e| e.to_string())
+            .and_then(|f| {
+                let native = native_type(f.file_type());
+                let (n, i) = (f.tag(native), f.tag(TagType::Id3v2));
+                kinds
+                    .entry(kind(n, i, native))
+                    .or_default()
+                    .push(file_name.clone());
+                match copy_to {
+                    Some(to_id3) => copy(&file_name, n, i, native, to_id3, dry_run),
+                    None => Ok(false),
+                }
+            });
+        match res {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error in {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+
+    for (kind, files) in &kinds {
+        total!("{kind}: {}", files.len());
+        // Files with both are fine, list the rest
+        if !kind.ends_with(" + ID3") {
+            for f in files {
+                log!("  {f}");
+            }
+        }
+    }
+    let counts: BTreeMap<&String, usize> = kinds.iter().map(|(k, f)| (k, f.len())).collect();
+    if copy_to.is_some() {
+        total!(
+            "{} {}, Failed: {}",
+            if dry_run { "Would change" } else { "Changed" },
+            changed,
+            failed
+        );
+    }
+    term::event(
+        "summary",
+        json!({
+            "dry_run": dry_run,
+            "kinds": counts,
+            "changed": changed,
+            "failed": failed,
+        }),
+    );
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
+
+// The file type's own tag
+fn native_type(file_type: FileType) -> TagType {
+    match file_type {
+        FileType::Wav => TagType::RiffInfo,
+        _ => TagType::AiffText,
+    }
+}
+
+fn kind(native: Option<&Tag>, id3: Option<&Tag>, native_type: TagType) -> String {
+    let name = if native_type == TagType::RiffInfo {
+        "INFO"
+    } else {
+        "AIFF text"
+    };
+    let has = |t: Option<&Tag>| t.is_some_and(|t| !t.is_empty());
+    match (has(native), has(id3)) {
+        (true, true) => format!("{name} + ID3"),
+        (true, false) => name.to_string(),
+        (false, true) => String::from("ID3"),
+        (false, false) => String::from("none"),
+    }
+}
+
+// Add the fields the target tag is missing from the other one
+fn copy(
+    file_name: &str,
+    native: Option<&Tag>,
+    id3: Option<&Tag>,
+    native_type: TagType,
+    to_id3: bool,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let (from, to, to_type) = match to_id3 {
+        true => (native, id3, TagType::Id3v2),
+        false => (id3, native, native_type),
+    };
+    let from = match from {
+        Some(f) if !f.is_empty() => f,
+        _ => return Ok(false),
+    };
+    let mut to = to.cloned().unwrap_or_else(|| Tag::new(to_type));
+    let mut added = Vec::new();
+    for item in from.items() {
+        if to.get(item.key()).is_none() && to.insert(item.clone()) {
+            added.push(format!("{:?}", item.key()));
+        }
+    }
+    if added.is_empty() {
+        return Ok(false);
+    }
+    log!("{file_name}: {:?} + {}", to_type, added.join(", "));
+    if !dry_run {
+        to.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,133 @@
+// The commands and their flags. Each command's own module defines it next
+// to its argument handling, and main.rs checks the flags given against it
+// before running the command. The completions and man page are made from
+// the same definitions.
+use crate::{
+    chunks, dates, dedupe, edit, featured, fingerprint, genres, history, infer, inspect, m3u,
+    manifest, migrate, offline, orphans, quarantine, repair, search, snapshot, sortnames,
+    spellings, stream, strip, sync, transcode, xattrs,
+};
+use std::process::exit;
+
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub about: &'static str,
+    pub flags: &'static [Flag],
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    // Values the flag takes, None for a switch and empty for any value
+    pub values: Option<&'static [&'static str]>,
+    pub about: &'static str,
+}
+
+pub const GLOBAL_FLAGS: &[Flag] = &[
+    Flag {
+        name: "--quiet",
+        values: None,
+        about: "Only print totals, warnings and errors",
+    },
+    Flag {
+        name: "--json",
+        values: None,
+        about: "Print JSON events on stdout and everything else on stderr",
+    },
+    Flag {
+        name: "--stream",
+        values: None,
+        about: "Print each track on stdout as a line of JSON as it's scanned",
+    },
+    Flag {
+        name: "--resume",
+        values: None,
+        about: "Carry on a scan that was stopped with Ctrl-C",
+    },
+    Flag {
+        name: "--scope",
+        values: Some(&[]),
+        about: "Only scan a directory, the files in a playlist, or tracks matching an expression",
+    },
+    Flag {
+        name: "--stdin",
+        values: None,
+        about: "Scan the files listed on stdin, one per line, instead of the scan directories",
+    },
+    Flag {
+        name: "--no-recurse",
+        values: None,
+        about: "Only scan the files directly in each scan directory, not its subdirectories",
+    },
+];
+
+pub const DRY_RUN: Flag = Flag {
+    name: "--dry-run",
+    values: None,
+    about: "Show what would be changed without changing anything",
+};
+
+pub const INTERACTIVE: Flag = Flag {
+    name: "--interactive",
+    values: None,
+    about: "Ask before each change: y, n, e to type another value, a for all, q to quit. \
+            Only dates, featured, genres, infer, sortnames and spellings ask",
+};
+
+pub const COMMANDS: &[&Command] = &[
+    &inspect::COMMAND,
+    &stream::COMMAND,
+    &repair::COMMAND,
+    &migrate::COMMAND,
+    &strip::COMMAND,
+    &fingerprint::COMMAND,
+    &transcode::COMMAND,
+    &sync::COMMAND,
+    &orphans::COMMAND,
+    &quarantine::RESTORE,
+    &sortnames::COMMAND,
+    &featured::COMMAND,
+    &dates::COMMAND,
+    &spellings::COMMAND,
+    &chunks::COMMAND,
+    &snapshot::COMMAND,
+    &offline::STATS,
+    &offline::QUERY,
+    &offline::REPORT,
+    &history::COMMAND,
+    &history::ROLLBACK,
+    &m3u::COMMAND,
+    &search::COMMAND,
+    &genres::COMMAND,
+    &edit::EXPORT,
+    &edit::APPLY,
+    &xattrs::COMMAND,
+    &manifest::COMMAND,
+    &manifest::VERIFY,
+    &infer::COMMAND,
+    &dedupe::COMMAND,
+];
+
+impl Command {
+    pub fn usage(&self) -> String {
+        format!("Usage: tag_test {} {}", self.name, self.usage)
+            .trim_end()
+            .to_string()
+    }
+
+    // Anything that looks like a flag has to be one of the command's
+    pub fn check(&self, args: &[String]) {
+        if let Some(a) = args
+            .iter()
+            .find(|a| a.starts_with("--") && !self.flags.iter().any(|f| f.name == *a))
+        {
+            error!("Unknown option {a} for {}", self.name);
+            log!("{}", self.usage());
+            exit(1);
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name).copied()
+}
@@ -0,0 +1,219 @@
+// Shell completions and the man page, generated from the commands in
+// cli.rs, which each command's arguments are checked against too.
+use crate::cli::{Flag, COMMANDS, GLOBAL_FLAGS};
+use std::collections::HashSet;
+use std::process::exit;
+
+const SHELLS: &str = "bash, zsh or fish";
+
+pub fn completions(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("bash") => print!("{}", bash()),
+        Some("zsh") => print!("{}", zsh()),
+        Some("fish") => print!("{}", fish()),
+        _ => {
+            error!("Usage: tag_test completions <shell>, where shell is {SHELLS}");
+            exit(1);
+        }
+    }
+}
+
+pub fn mangen() {
+    print!("{}", man_page());
+}
+
+fn words<'a>(flags: impl Iterator<Item = &'a Flag>) -> String {
+    flags.map(|f| f.name).collect::<Vec<_>>().join(" ")
+}
+
+fn bash() -> String {
+    let mut s = String::from(
+        "_tag_test() {\n    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n",
+    );
+    s.push_str("    case \"$prev\" in\n");
+    // Only the first of the flags with the same name would match, so each
+    // is only given once
+    let mut seen = HashSet::new();
+    let flags = GLOBAL_FLAGS
+        .iter()
+        .chain(COMMANDS.iter().flat_map(|c| c.flags));
+    for f in flags.filter(|f| seen.insert(f.name)) {
+        match f.values {
+            Some([]) => s.push_str(&format!(
+                "        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n",
+                f.name
+            )),
+            Some(values) => s.push_str(&format!(
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+                f.name,
+                values.join(" ")
+            )),
+            None => (),
+        }
+    }
+    s.push_str("    esac\n");
+    let names: Vec<&str> = COMMANDS.iter().map(|c| c.name).collect();
+    s.push_str(&format!(
+        "    if [ \"$COMP_CWORD\" -eq 1 ]; then\n        COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\"))\n        return\n    fi\n",
+        names.join(" "),
+        words(GLOBAL_FLAGS.iter())
+    ));
+    s.push_str("    local opts\n    case \"${COMP_WORDS[1]}\" in\n");
+    for c in COMMANDS {
+        s.push_str(&format!(
+            "        {}) opts=\"{}\" ;;\n",
+            c.name,
+            words(c.flags.iter())
+        ));
+    }
+    s.push_str("    esac\n");
+    s.push_str(&format!(
+        "    if [[ \"$cur\" == -* ]]; then\n        COMPREPLY=($(compgen -W \"$opts {}\" -- \"$cur\"))\n    else\n        COMPREPLY=($(compgen -f -- \"$cur\"))\n    fi\n}}\ncomplete -o filenames -F _tag_test tag_test\n",
+        words(GLOBAL_FLAGS.iter())
+    ));
+    s
+}
+
+// zsh wants [ ] and : escaped in descriptions
+fn zsh_escape(s: &str) -> String {
+    s.replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+        .replace('\'', "'\\''")
+}
+
+fn zsh_flag(f: &Flag) -> String {
+    match f.values {
+        Some([]) => format!("'*{}[{}]:value:_files'", f.name, zsh_escape(f.about)),
+        Some(values) => format!(
+            "'*{}[{}]:value:({})'",
+            f.name,
+            zsh_escape(f.about),
+            values.join(" ")
+        ),
+        None => format!("'{}[{}]'", f.name, zsh_escape(f.about)),
+    }
+}
+
+fn zsh() -> String {
+    let mut s =
+        String::from("#compdef tag_test\n\n_tag_test() {\n    local -a commands\n    commands=(\n");
+    for c in COMMANDS {
+        s.push_str(&format!("        '{}:{}'\n", c.name, zsh_escape(c.about)));
+    }
+    s.push_str("    )\n    _arguments -C \\\n");
+    for f in GLOBAL_FLAGS {
+        s.push_str(&format!("        {} \\\n", zsh_flag(f)));
+    }
+    s.push_str("        '1: :->command' \\\n        '*:: :->args'\n");
+    s.push_str("    case $state in\n        command) _describe command commands ;;\n        args)\n            case $words[1] in\n");
+    for c in COMMANDS {
+        s.push_str(&format!("                {}) _arguments", c.name));
+        for f in c.flags {
+            s.push_str(&format!(" {}", zsh_flag(f)));
+        }
+        s.push_str(" '*:file:_files' ;;\n");
+    }
+    s.push_str("            esac\n            ;;\n    esac\n}\n\n_tag_test \"$@\"\n");
+    s
+}
+
+fn fish() -> String {
+    let mut s = String::from("complete -c tag_test -f\n");
+    for f in GLOBAL_FLAGS {
+        s.push_str(&format!(
+            "complete -c tag_test -l {}{} -d '{}'\n",
+            &f.name[2..],
+            fish_values(f),
+            f.about.replace('\'', "\\'")
+        ));
+    }
+    for c in COMMANDS {
+        s.push_str(&format!(
+            "complete -c tag_test -n __fish_use_subcommand -a {} -d '{}'\n",
+            c.name,
+            c.about.replace('\'', "\\'")
+        ));
+        s.push_str(&format!(
+            "complete -c tag_test -n '__fish_seen_subcommand_from {}' -F\n",
+            c.name
+        ));
+        for f in c.flags {
+            s.push_str(&format!(
+                "complete -c tag_test -n '__fish_seen_subcommand_from {}' -l {}{} -d '{}'\n",
+                c.name,
+                &f.name[2..],
+                fish_values(f),
+                f.about.replace('\'', "\\'")
+            ));
+        }
+    }
+    s
+}
+
+fn fish_values(f: &Flag) -> String {
+    match f.values {
+        Some([]) => String::from(" -r -F"),
+        Some(v) => format!(" -x -a '{}'", v.join(" ")),
+        None => String::new(),
+    }
+}
+
+// Backslashes and leading dots/quotes mean something to roff
+fn roff(s: &str) -> String {
+    let s = s.replace('\\', "\\e").replace('-', "\\-");
+    match s.starts_with(['.', '\'']) {
+        true => format!("\\&{s}"),
+        false => s,
+    }
+}
+
+// A flag that takes a value is shown with one, and its values if there
+// are only some
+fn man_flag(f: &Flag) -> String {
+    let (arg, values) = match f.values {
+        Some([]) => (" \\fIvalue\\fR", String::new()),
+        Some(v) => (" \\fIvalue\\fR", format!(" ({})", v.join(", "))),
+        None => ("", String::new()),
+    };
+    format!(
+        ".TP\n\\fB{}\\fR{}\n{}{}\n",
+        roff(f.name),
+        arg,
+        roff(f.about),
+        roff(&values)
+    )
+}
+
+fn man_page() -> String {
+    let mut s = format!(
+        ".TH TAG_TEST 1 \"\" \"tag_test {}\"\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    s.push_str(".SH NAME\ntag_test \\- scan a music library and check and fix its tags\n");
+    s.push_str(".SH SYNOPSIS\n.B tag_test\n[\\fB\\-\\-quiet\\fR | \\fB\\-\\-json\\fR] [\\fIcommand\\fR] [\\fIargs\\fR...]\n");
+    s.push_str(".SH DESCRIPTION\nWithout a command, tag_test scans the directories in config.toml, prints a summary and runs the reports, playlists, feed and exports switched on there.\nCommands that take paths use the configured scan directories when none are given.\n");
+    s.push_str(".SH OPTIONS\n");
+    for f in GLOBAL_FLAGS {
+        s.push_str(&man_flag(f));
+    }
+    s.push_str(".SH COMMANDS\n");
+    for c in COMMANDS {
+        s.push_str(&format!(
+            ".TP\n\\fB{}\\fR {}\n{}\n",
+            c.name,
+            roff(c.usage),
+            roff(c.about)
+        ));
+        if !c.flags.is_empty() {
+            s.push_str(".RS\n");
+            for f in c.flags {
+                s.push_str(&man_flag(f));
+            }
+            s.push_str(".RE\n");
+        }
+    }
+    s.push_str(".SH FILES\n.TP\n.I config.toml\nRead from the current directory. Every section is described in the example config.\n");
+    s.push_str(".SH ENVIRONMENT\n.TP\n.B NO_COLOR\nDon't colour the output.\n");
+    s
+}
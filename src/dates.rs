@@ -0,0 +1,207 @@
+// Date tags: check they are real dates, and rewrite them in one form at
+// one precision. Dates are read as year, year-month or year-month-day,
+// with -, / or . between, and anything after the day (a time) dropped.
+use crate::ask::Prompt;
+use crate::cli::{Command, DRY_RUN, INTERACTIVE};
+use crate::{music_files, raw, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::process::exit;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DatesConfig {
+    // "year", "month" or "day". tag_test dates cuts dates down to this,
+    // it can't make up the parts a date doesn't have.
+    pub precision: String,
+}
+
+impl Default for DatesConfig {
+    fn default() -> Self {
+        DatesConfig {
+            precision: String::from("year"),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub struct Date {
+    pub year: u32,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl Date {
+    // 1 for year only, 2 with the month, 3 with the day
+    pub fn precision(&self) -> usize {
+        1 + self.month.is_some() as usize + self.day.is_some() as usize
+    }
+
+    // As YYYY, YYYY-MM or YYYY-MM-DD, with no more than precision parts
+    pub fn format(&self, precision: usize) -> String {
+        let mut s = format!("{:04}", self.year);
+        for (i, part) in [self.month, self.day].iter().enumerate() {
+            match part {
+                Some(p) if precision > i + 1 => s.push_str(&format!("-{p:02}")),
+                _ => break,
+            }
+        }
+        s
+    }
+}
+
+pub fn parse(date: &str) -> Result<Date, String> {
+    let date = date.trim();
+    let date = date.split(['T', ' ']).next().unwrap_or(date);
+    let parts: Vec<&str> = date.split(['-', '/', '.']).collect();
+    if parts.len() > 3 || parts.iter().any(|p| p.is_empty()) {
+        return Err(String::from("not a date"));
+    }
+    let mut nums = Vec::new();
+    for p in &parts {
+        match p.parse::<u32>() {
+            Ok(n) if p.chars().all(|c| c.is_ascii_digit()) => nums.push(n),
+            _ => return Err(String::from("not a date")),
+        }
+    }
+    let d = Date {
+        year: nums[0],
+        month: nums.get(1).copied(),
+        day: nums.get(2).copied(),
+    };
+    if parts[0].len() != 4 || d.year == 0 {
+        return Err(String::from("no year"));
+    }
+    if d.month.is_some_and(|m| !(1..=12).contains(&m)) {
+        return Err(String::from("no such month"));
+    }
+    if let (Some(m), Some(day)) = (d.month, d.day) {
+        if day == 0 || day > days_in_month(d.year, m) {
+            return Err(String::from("no such day"));
+        }
+    }
+    Ok(d)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+pub fn precision(dc: &DatesConfig) -> usize {
+    match dc.precision.to_lowercase().as_str() {
+        "day" => 3,
+        "month" => 2,
+        _ => 1,
+    }
+}
+
+pub const COMMAND: Command = Command {
+    name: "dates",
+    usage: "[--dry-run] [--interactive] [path...]",
+    about: "Rewrite date tags in one form at the configured precision",
+    flags: &[DRY_RUN, INTERACTIVE],
+};
+
+// Rewrite valid dates in the standard form at the configured precision.
+// Invalid ones are left for the dates report to list.
+pub fn run(config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let mut prompt = Prompt::new(&mut args);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+
+    let precision = precision(&config.dates);
+    let (mut changed, mut invalid, mut failed) = (0, 0, 0);
+    for file_name in music_files(config, &paths) {
+        match fix_file(&file_name, precision, &mut prompt, dry_run) {
+            Ok(Some(true)) => changed += 1,
+            Ok(Some(false)) => (),
+            Ok(None) => invalid += 1,
+            Err(e) => {
+                error!("Error fixing the date in {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Invalid: {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        invalid,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({
+            "dry_run": dry_run,
+            "changed": changed,
+            "invalid": invalid,
+            "failed": failed,
+        }),
+    );
+}
+
+// Some(changed), or None if the date isn't valid
+fn fix_file(
+    file_name: &str,
+    precision: usize,
+    prompt: &mut Prompt,
+    dry_run: bool,
+) -> Result<Option<bool>, String> {
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = match tagged_file.primary_tag() {
+        Some(t) => t.clone(),
+        None => return Ok(Some(false)),
+    };
+    let (key, old) = match [ItemKey::RecordingDate, ItemKey::Year]
+        .into_iter()
+        .find_map(|k| tag.get_string(&k).map(|d| (k.clone(), d.to_string())))
+    {
+        Some(d) => d,
+        // A TDRC lofty couldn't parse is left out of the tag
+        None => match raw::id3v2_text(file_name, b"TDRC") {
+            Some(d) => (ItemKey::RecordingDate, d),
+            None => return Ok(Some(false)),
+        },
+    };
+    let date = match parse(&old) {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("{file_name}: {old:?} {e}");
+            return Ok(None);
+        }
+    };
+    let new = date.format(precision);
+    if new == old {
+        return Ok(Some(false));
+    }
+    log!("{file_name}: {old:?} -> {new:?}");
+    let new = match prompt.check(new) {
+        Some(d) => d,
+        None => return Ok(Some(false)),
+    };
+    if !dry_run {
+        tag.insert_text(key, new);
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(Some(true))
+}
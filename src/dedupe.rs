@@ -0,0 +1,226 @@
+// "tag_test dedupe": files that are byte for byte the same, e.g. an album
+// both in the library and in a downloads directory, made into hard links
+// to one copy so the space is only used once. With --reflink they're
+// reflinked instead (Btrfs, XFS), which shares the data but leaves them
+// separate files, so editing the tags of one doesn't change the others as
+// it does with hard links. Only files on the same filesystem can be
+// linked. Nothing is done unless asked for, and --dry-run shows what would
+// be.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::fields::Expr;
+use crate::manifest::crc32;
+use crate::{format, links, music_files, read_metadata, term, Config};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+pub const COMMAND: Command = Command {
+    name: "dedupe",
+    usage: "[--dry-run] [--reflink] [--where <expression>] [--exclude <path>]... [path...]",
+    about: "Replace files that are byte for byte the same with hard links to one copy",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--reflink",
+            values: None,
+            about: "Reflink rather than hard link, so the copies stay separate files",
+        },
+        Flag {
+            name: "--where",
+            values: Some(&[]),
+            about: "Only link the tracks matching this expression",
+        },
+        Flag {
+            name: "--exclude",
+            values: Some(&[]),
+            about: "Leave the files at or under this path alone",
+        },
+    ],
+};
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut dry_run = false;
+    let mut reflink = false;
+    let mut expr = None;
+    let mut excluded: Vec<PathBuf> = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--reflink" => reflink = true,
+            "--where" => match args.next().map(|e| Expr::try_from(e.clone())) {
+                Some(Ok(e)) => expr = Some(e),
+                Some(Err(e)) => {
+                    error!("Error in --where: {e}");
+                    exit(1);
+                }
+                None => usage(),
+            },
+            "--exclude" => match args.next() {
+                Some(p) => excluded.push(PathBuf::from(p)),
+                None => usage(),
+            },
+            a if a.starts_with("--") => usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let mut files = music_files(config, &paths);
+    files.retain(|f| !excluded.iter().any(|e| Path::new(f).starts_with(e)));
+    if let Some(e) = &expr {
+        files.retain(|f| read_metadata(f).is_ok_and(|t| e.matches(&t)));
+    }
+
+    // Only files of the same size on the same filesystem can be the same
+    // and be linked. Files already linked together are one file here.
+    let mut sizes: BTreeMap<(u64, u64), Vec<String>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for f in files {
+        if let Ok(m) = fs::metadata(&f) {
+            if m.len() > 0 && links::id(&m).is_none_or(|i| seen.insert(i)) {
+                sizes
+                    .entry((links::device(&m), m.len()))
+                    .or_default()
+                    .push(f);
+            }
+        }
+    }
+
+    let (mut linked, mut saved, mut failed) = (0, 0, 0);
+    for ((_, size), group) in sizes.into_iter().filter(|(_, g)| g.len() > 1) {
+        let mut crcs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for f in group {
+            match crc32(Path::new(&f)) {
+                Ok(crc) => crcs.entry(crc).or_default().push(f),
+                Err(e) => warn!("Error reading {f}: {e}"),
+            }
+        }
+        for mut same in crcs.into_values().filter(|g| g.len() > 1) {
+            same.sort();
+            let keep = &same[0];
+            for dup in &same[1..] {
+                // A CRC match is checked byte for byte before anything is
+                // replaced
+                match identical(keep, dup) {
+                    Ok(true) => (),
+                    Ok(false) => continue,
+                    Err(e) => {
+                        warn!("Error reading {dup}: {e}");
+                        continue;
+                    }
+                }
+                log!(
+                    "{} {dup} -> {keep}",
+                    if reflink { "Reflinking" } else { "Linking" }
+                );
+                let res = match dry_run {
+                    true => Ok(()),
+                    false => replace(Path::new(keep), Path::new(dup), reflink),
+                };
+                match res {
+                    Ok(_) => {
+                        linked += 1;
+                        saved += size;
+                        term::event("link", json!({ "path": dup, "to": keep, "size": size }));
+                    }
+                    Err(e) => {
+                        error!("Error linking {dup}: {e}");
+                        term::event("error", json!({ "path": dup, "message": e.to_string() }));
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+    total!(
+        "{} {}, {} {}, Failed: {}",
+        if dry_run { "Would link" } else { "Linked" },
+        linked,
+        if dry_run { "Would save" } else { "Saved" },
+        format::size(saved),
+        failed
+    );
+    term::event(
+        "summary",
+        json!({
+            "dry_run": dry_run,
+            "linked": linked,
+            "saved": saved,
+            "failed": failed,
+        }),
+    );
+}
+
+fn identical(a: &str, b: &str) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0; 1 << 16], vec![0; 1 << 16]);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut buf_b)? == 0);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+// The link is made next to the duplicate and renamed over it, so the
+// duplicate is never gone if linking fails
+fn replace(keep: &Path, dup: &Path, reflink: bool) -> io::Result<()> {
+    let name = dup.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dup.with_file_name(format!(".{name}.tag_test-link"));
+    let res = match reflink {
+        true => sys::reflink(keep, &tmp),
+        false => fs::hard_link(keep, &tmp),
+    };
+    if let Err(e) = res.and_then(|_| fs::rename(&tmp, dup)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs::{self, File};
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::path::Path;
+
+    pub fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+        let src = File::open(from)?;
+        let dest = File::create_new(to)?;
+        // SAFETY: both descriptors are open files for the whole call
+        let res = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+        if res == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        let _ = fs::remove_file(to);
+        Err(e)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    pub fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reflinks are only made on Linux",
+        ))
+    }
+}
@@ -0,0 +1,165 @@
+// DSD files from SACD rips, which lofty doesn't read: DSF, with an ID3v2
+// tag at the end, and DSDIFF (.dff), with an unofficial "ID3 " chunk
+// holding one. Only the headers are read, the audio isn't looked at.
+use lofty::config::ParseOptions;
+use lofty::error::LoftyError;
+use lofty::file::FileType;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::properties::FileProperties;
+use lofty::tag::Tag;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+pub fn is_dsd(file_name: &str) -> bool {
+    matches!(crate::file_ext(file_name).as_str(), "dsf" | "dff")
+}
+
+pub fn read(file_name: &str) -> Result<(Option<Tag>, FileProperties), LoftyError> {
+    let mut file = File::open(file_name)?;
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    let (id3, props) = match &magic {
+        b"DSD " => read_dsf(&mut file)?,
+        b"FRM8" => read_dff(&mut file)?,
+        _ => return Err(invalid("not a DSF or DSDIFF file")),
+    };
+    let tag = match id3 {
+        Some(bytes) => id3_tag(bytes)?,
+        None => None,
+    };
+    Ok((tag, props))
+}
+
+fn invalid(message: &str) -> LoftyError {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+fn u32_le(b: &[u8]) -> u32 {
+    u32::from_le_bytes(b[..4].try_into().unwrap())
+}
+
+fn u64_le(b: &[u8]) -> u64 {
+    u64::from_le_bytes(b[..8].try_into().unwrap())
+}
+
+fn properties(rate: u32, channels: u32, samples: u64, file_size: u64) -> FileProperties {
+    let duration = match rate {
+        0 => Duration::ZERO,
+        r => Duration::from_secs_f64(samples as f64 / r as f64),
+    };
+    let overall = match duration.as_millis() {
+        0 => None,
+        ms => Some((file_size * 8 / ms as u64) as u32),
+    };
+    FileProperties::new(
+        duration,
+        overall,
+        Some((rate as u64 * channels as u64 / 1000) as u32),
+        Some(rate),
+        Some(1),
+        u8::try_from(channels).ok(),
+        None,
+    )
+}
+
+// A DSD chunk (28 bytes) with the file size and where the ID3v2 tag is,
+// then a fmt chunk
+fn read_dsf(file: &mut File) -> Result<(Option<Vec<u8>>, FileProperties), LoftyError> {
+    let mut header = [0; 28 + 52];
+    file.read_exact(&mut header)?;
+    let (dsd, fmt) = header.split_at(28);
+    if &fmt[..4] != b"fmt " {
+        return Err(invalid("DSF file without a fmt chunk"));
+    }
+    let file_size = u64_le(&dsd[12..]);
+    let metadata = u64_le(&dsd[20..]);
+    let channels = u32_le(&fmt[24..]);
+    let rate = u32_le(&fmt[28..]);
+    let samples = u64_le(&fmt[36..]);
+
+    let mut id3 = None;
+    if metadata > 0 {
+        file.seek(SeekFrom::Start(metadata))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        id3 = Some(bytes);
+    }
+    Ok((id3, properties(rate, channels, samples, file_size)))
+}
+
+// An IFF style FRM8 form of big endian chunks. The sample rate and
+// channels are in the PROP chunk, the length comes from the size of the
+// DSD chunk (one bit per sample per channel).
+fn read_dff(file: &mut File) -> Result<(Option<Vec<u8>>, FileProperties), LoftyError> {
+    let mut header = [0; 16];
+    file.read_exact(&mut header)?;
+    if &header[12..16] != b"DSD " {
+        return Err(invalid("DSDIFF file that isn't DSD"));
+    }
+    let file_size = u64::from_be_bytes(header[4..12].try_into().unwrap()) + 12;
+    let (mut rate, mut channels, mut samples, mut id3) = (0, 0, 0, None);
+    loop {
+        let mut chunk = [0; 12];
+        if file.read_exact(&mut chunk).is_err() {
+            break;
+        }
+        let size = u64::from_be_bytes(chunk[4..12].try_into().unwrap());
+        let next = file.stream_position()? + size + size % 2;
+        match &chunk[..4] {
+            b"PROP" => {
+                let mut prop = vec![0; size.min(1 << 16) as usize];
+                file.read_exact(&mut prop)?;
+                (rate, channels) = dff_prop(&prop);
+            }
+            b"DSD " if channels > 0 => samples = size * 8 / channels as u64,
+            b"ID3 " => {
+                let mut bytes = vec![0; size.min(1 << 24) as usize];
+                file.read_exact(&mut bytes)?;
+                id3 = Some(bytes);
+            }
+            _ => (),
+        }
+        file.seek(SeekFrom::Start(next))?;
+    }
+    Ok((id3, properties(rate, channels, samples, file_size)))
+}
+
+// Sample rate and channels from the sub chunks of a PROP chunk
+fn dff_prop(prop: &[u8]) -> (u32, u32) {
+    let (mut rate, mut channels) = (0, 0);
+    // "SND " then chunks
+    let mut i = 4;
+    while let Some(chunk) = prop.get(i..i + 12) {
+        let size = u64::from_be_bytes(chunk[4..12].try_into().unwrap()) as usize;
+        let data = prop.get(i + 12..).unwrap_or_default();
+        match &chunk[..4] {
+            b"FS  " if data.len() >= 4 => rate = u32::from_be_bytes(data[..4].try_into().unwrap()),
+            b"CHNL" if data.len() >= 2 => {
+                channels = u16::from_be_bytes(data[..2].try_into().unwrap()) as u32
+            }
+            _ => (),
+        }
+        i += 12 + size + size % 2;
+    }
+    (rate, channels)
+}
+
+// lofty can't parse an ID3v2 tag on its own, so it's given one at the
+// start of a few silent MPEG frames and the tag taken from that
+fn id3_tag(mut bytes: Vec<u8>) -> Result<Option<Tag>, LoftyError> {
+    if !bytes.starts_with(b"ID3") {
+        return Ok(None);
+    }
+    for _ in 0..4 {
+        bytes.extend_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+        bytes.extend_from_slice(&[0; 413]);
+    }
+    let file = Probe::new(Cursor::new(bytes))
+        .set_file_type(FileType::Mpeg)
+        .options(ParseOptions::new().read_properties(false))
+        .read()?;
+    Ok(file.primary_tag().cloned())
+}
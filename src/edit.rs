@@ -0,0 +1,333 @@
+// Bulk corrections in a spreadsheet: export-edit writes a sheet of the
+// chosen fields for the files, one row each with the path first, and
+// apply-edit reads it back once it's been edited and writes the fields
+// that are different from the files' tags. A .tsv sheet is tab separated,
+// anything else CSV.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::fields::Expr;
+use crate::history::{self, item_key, Recorder};
+use crate::{dates, file_ext, music_files, read_metadata, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::exit;
+
+pub const EXPORT: Command = Command {
+    name: "export-edit",
+    usage: "[--field <name>]... [--where <expression>] --out <file> [path...]",
+    about: "Write a CSV or TSV sheet of fields to edit in a spreadsheet",
+    flags: &[
+        Flag {
+            name: "--field",
+            values: Some(&[]),
+            about: "A field to put in the sheet, artist, album, title and so on by default",
+        },
+        Flag {
+            name: "--where",
+            values: Some(&[]),
+            about: "Only files whose tags match this expression",
+        },
+        Flag {
+            name: "--out",
+            values: Some(&[]),
+            about: "The sheet to write, tab separated if it ends in .tsv",
+        },
+    ],
+};
+
+pub const APPLY: Command = Command {
+    name: "apply-edit",
+    usage: "[--dry-run] <file>",
+    about: "Write the fields changed in a sheet from export-edit to the files",
+    flags: &[DRY_RUN],
+};
+
+const DEFAULT_FIELDS: &[&str] = &[
+    "artist",
+    "album_artist",
+    "album",
+    "disc",
+    "track",
+    "title",
+    "date",
+    "genre",
+];
+
+const NUMBERS: &[&str] = &[
+    "track",
+    "track_total",
+    "disc",
+    "disc_total",
+    "movement_number",
+    "movement_total",
+];
+
+fn separator(file: &str) -> char {
+    if file_ext(file) == "tsv" {
+        '\t'
+    } else {
+        ','
+    }
+}
+
+pub fn export(config: &Config, args: &[String]) {
+    let mut fields = Vec::new();
+    let mut expr = None;
+    let mut out = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--field" => match args.next() {
+                Some(f) => fields.push(f.clone()),
+                None => export_usage(),
+            },
+            "--where" => match args.next().map(|e| Expr::try_from(e.clone())) {
+                Some(Ok(e)) => expr = Some(e),
+                Some(Err(e)) => {
+                    error!("Error in --where: {e}");
+                    exit(1);
+                }
+                None => export_usage(),
+            },
+            "--out" => out = args.next().cloned(),
+            a if a.starts_with("--") => export_usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let out = out.unwrap_or_else(|| export_usage());
+    if fields.is_empty() {
+        fields = DEFAULT_FIELDS.iter().map(|f| f.to_string()).collect();
+    }
+    if let Some(f) = fields.iter().find(|f| item_key(f).is_none()) {
+        error!("{f} can't be edited");
+        exit(1);
+    }
+
+    let sep = separator(&out);
+    let mut sheet = String::new();
+    sheet.push_str(&row(
+        sep,
+        ["path"]
+            .iter()
+            .copied()
+            .chain(fields.iter().map(String::as_str)),
+    ));
+    let (mut rows, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        let t = match read_metadata(&file_name) {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error reading {file_name}: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        if expr.as_ref().is_some_and(|e| !e.matches(&t)) {
+            continue;
+        }
+        let values: BTreeMap<&str, String> = history::fields(&t).into_iter().collect();
+        let cells = fields.iter().map(|f| values[f.as_str()].as_str());
+        sheet.push_str(&row(sep, [file_name.as_str()].into_iter().chain(cells)));
+        rows += 1;
+    }
+    if let Err(e) = fs::write(&out, sheet) {
+        error!("Error writing {out}: {e}");
+        exit(1);
+    }
+    total!("Rows written to {out}: {rows}, Failed: {failed}");
+    term::event(
+        "summary",
+        json!({ "file": out, "rows": rows, "failed": failed }),
+    );
+}
+
+fn export_usage() -> ! {
+    log!("{}", EXPORT.usage());
+    exit(1);
+}
+
+fn row<'a>(sep: char, cells: impl Iterator<Item = &'a str>) -> String {
+    let mut line = cells
+        .map(|c| {
+            if c.contains([sep, '"', '\n', '\r']) {
+                format!("\"{}\"", c.replace('"', "\"\""))
+            } else {
+                c.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&sep.to_string());
+    line.push('\n');
+    line
+}
+
+// The rows of a sheet, with quoted cells, which can have the separator
+// and new lines in them
+fn parse(text: &str, sep: char) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            c if quoted => cell.push(c),
+            c if c == sep => row.push(std::mem::take(&mut cell)),
+            '\r' => (),
+            '\n' => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+// Why a value can't go in a field, if it can't
+fn invalid(field: &str, value: &str) -> Option<String> {
+    if value.is_empty() {
+        return None;
+    }
+    let ok = if NUMBERS.contains(&field) {
+        value.parse::<u32>().is_ok()
+    } else if field == "bpm" {
+        value.parse::<f64>().is_ok_and(|b| b > 0.0)
+    } else if field == "date" {
+        dates::parse(value).is_ok()
+    } else {
+        true
+    };
+    (!ok).then(|| format!("{field} {value:?} isn't valid"))
+}
+
+pub fn apply(config: &Config, args: &[String]) {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let file = match &args
+        .iter()
+        .filter(|a| *a != "--dry-run")
+        .collect::<Vec<_>>()[..]
+    {
+        [f] if !f.starts_with("--") => f.to_string(),
+        _ => {
+            log!("{}", APPLY.usage());
+            exit(1);
+        }
+    };
+    let text = match fs::read_to_string(&file) {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Error reading {file}: {e}");
+            exit(1);
+        }
+    };
+    let rows = parse(&text, separator(&file));
+    let header = match rows.first() {
+        Some(h) if h.first().map(String::as_str) == Some("path") => h,
+        _ => {
+            error!("{file} has to start with a header row, path first");
+            exit(1);
+        }
+    };
+    if let Some(f) = header[1..].iter().find(|f| item_key(f).is_none()) {
+        error!("{file}: {f} can't be edited");
+        exit(1);
+    }
+
+    let mut history = Recorder::journal(config).filter(|_| !dry_run);
+    let (mut changed, mut failed) = (0, 0);
+    for (n, cells) in rows.iter().enumerate().skip(1) {
+        if cells.iter().all(|c| c.is_empty()) {
+            continue;
+        }
+        let res = if cells.len() != header.len() {
+            Err(format!("{} cells, not {}", cells.len(), header.len()))
+        } else {
+            let fields: Vec<(&str, &str)> = header[1..]
+                .iter()
+                .map(String::as_str)
+                .zip(cells[1..].iter().map(|c| c.trim()))
+                .collect();
+            match fields.iter().find_map(|(f, v)| invalid(f, v)) {
+                Some(e) => Err(e),
+                None => apply_file(&cells[0], &fields, dry_run, history.as_mut()),
+            }
+        };
+        match res {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("{file} row {}: {}: {e}", n + 1, cells[0]);
+                term::event("error", json!({ "path": cells[0], "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+    if let Some(h) = history {
+        h.finish();
+    }
+}
+
+fn apply_file(
+    file_name: &str,
+    fields: &[(&str, &str)],
+    dry_run: bool,
+    history: Option<&mut Recorder>,
+) -> Result<bool, String> {
+    let t = read_metadata(file_name).map_err(|e| e.to_string())?;
+    let current: BTreeMap<&str, String> = history::fields(&t).into_iter().collect();
+    let changes: Vec<&(&str, &str)> = fields.iter().filter(|(f, v)| current[f] != *v).collect();
+    if changes.is_empty() {
+        return Ok(false);
+    }
+
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = tagged_file
+        .primary_tag()
+        .cloned()
+        .ok_or_else(|| String::from("no tag"))?;
+    log!("{file_name}:");
+    for &&(field, value) in &changes {
+        let key = item_key(field).ok_or("can't be edited")?;
+        if !history::set(&mut tag, key, value) {
+            return Err(format!("{field} can't be written to this tag"));
+        }
+        log!("  {field}: {:?} -> {value:?}", current[field]);
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(h) = history {
+        for &(field, value) in changes {
+            h.change(file_name, field, current[field].clone(), value.to_string());
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,244 @@
+// Look up genre/tag suggestions and release details for each album, from
+// the providers built in: a file of known values, Last.fm and Discogs.
+// Nothing is ever written to the files. Each online provider is its own
+// feature (lastfm, discogs, or enrichment for both), and they're asked in
+// precedence order.
+use crate::albums::Album;
+use crate::{term, Config, ScanStats, TrackInfo};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+#[cfg(feature = "discogs")]
+mod discogs;
+mod file;
+#[cfg(feature = "lastfm")]
+mod lastfm;
+#[cfg(any(feature = "lastfm", feature = "discogs"))]
+mod lookup;
+mod sources;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EnrichConfig {
+    pub enabled: bool,
+    // From https://www.last.fm/api/account/create
+    pub lastfm_api_key: String,
+    // Most suggestions to show per album
+    pub max_tags: usize,
+    // Personal access token from https://www.discogs.com/settings/developers
+    pub discogs_token: String,
+    // Directory the responses of every service are cached in
+    pub cache: String,
+    // Days a response is kept for, 0 = for good
+    pub cache_days: u64,
+    // Times a busy service is asked again
+    pub retries: u32,
+    // Lookups left for next time when a service couldn't be reached
+    pub queue_file: String,
+    // JSON of known values by "artist - album" or "artist - title",
+    // empty = none
+    pub file: String,
+    // Where matched releases are written, as JSON
+    pub discogs_file: String,
+    // Where each album's fields are written with where each value came
+    // from, empty = not written
+    pub sources_file: String,
+    // Sources in the order they're trusted: providers are asked in this
+    // order, and the value picked for each field in sources_file is from
+    // the first that has one
+    pub precedence: Vec<String>,
+}
+
+impl Default for EnrichConfig {
+    fn default() -> Self {
+        EnrichConfig {
+            enabled: false,
+            lastfm_api_key: String::new(),
+            max_tags: 5,
+            discogs_token: String::new(),
+            cache: String::from("lookup_cache"),
+            cache_days: 30,
+            retries: 3,
+            queue_file: String::from("lookup_queue.json"),
+            file: String::new(),
+            discogs_file: String::from("discogs.json"),
+            sources_file: String::from("sources.json"),
+            precedence: vec![
+                String::from("file"),
+                String::from("tag"),
+                String::from("discogs"),
+                String::from("lastfm"),
+            ],
+        }
+    }
+}
+
+// A value a provider found for a field, e.g. a genre or a year
+#[derive(Serialize, Clone)]
+pub struct Candidate {
+    #[serde(skip)]
+    pub field: &'static str,
+    pub value: String,
+    pub source: &'static str,
+    // 0-1
+    pub confidence: f64,
+}
+
+pub enum ProviderError {
+    // Not looked up, it's been left for next time
+    #[cfg(any(feature = "lastfm", feature = "discogs"))]
+    Queued,
+    Failed(String),
+}
+
+impl From<String> for ProviderError {
+    fn from(e: String) -> Self {
+        ProviderError::Failed(e)
+    }
+}
+
+// Somewhere values can be looked up. Only lookup_album is needed, an
+// artist's values are used when the album has none, and tracks are looked
+// up when they're not on an album.
+pub trait EnrichmentProvider {
+    // Its name in precedence
+    fn name(&self) -> &'static str;
+    fn lookup_album(&mut self, album: &Album) -> Result<Vec<Candidate>, ProviderError>;
+    fn lookup_artist(&mut self, _artist: &str) -> Result<Vec<Candidate>, ProviderError> {
+        Ok(Vec::new())
+    }
+    fn lookup_track(&mut self, _track: &TrackInfo) -> Result<Vec<Candidate>, ProviderError> {
+        Ok(Vec::new())
+    }
+    // Called once everything's been looked up
+    fn finish(&mut self) {}
+}
+
+pub fn run(config: &Config, stats: &ScanStats) {
+    let ec = &config.enrichment;
+    if !ec.enabled {
+        return;
+    }
+    for (key, feature, built) in [
+        (&ec.lastfm_api_key, "lastfm", cfg!(feature = "lastfm")),
+        (&ec.discogs_token, "discogs", cfg!(feature = "discogs")),
+    ] {
+        if !key.is_empty() && !built {
+            warn!("The {feature} lookups need tag_test built with --features {feature}");
+        }
+    }
+
+    #[cfg(any(feature = "lastfm", feature = "discogs"))]
+    let lookups = {
+        let services = vec![
+            #[cfg(feature = "lastfm")]
+            lastfm::service(ec),
+            #[cfg(feature = "discogs")]
+            discogs::service(ec),
+        ];
+        let mut lookups = lookup::Lookups::new(ec, services);
+        lookups.retry_queued();
+        std::cell::RefCell::new(lookups)
+    };
+
+    let mut providers: Vec<Box<dyn EnrichmentProvider + '_>> = Vec::new();
+    if !ec.file.is_empty() {
+        match file::Provider::load(&ec.file) {
+            Ok(p) => providers.push(Box::new(p)),
+            Err(e) => error!("Unable to read {}: {e}", ec.file),
+        }
+    }
+    #[cfg(feature = "lastfm")]
+    if !ec.lastfm_api_key.is_empty() {
+        providers.push(Box::new(lastfm::Provider::new(ec, &lookups)));
+    }
+    #[cfg(feature = "discogs")]
+    if !ec.discogs_token.is_empty() {
+        providers.push(Box::new(discogs::Provider::new(ec, &lookups)));
+    }
+    let rank = |name: &str| {
+        ec.precedence
+            .iter()
+            .position(|p| p == name)
+            .unwrap_or(usize::MAX)
+    };
+    providers.sort_by_key(|p| rank(p.name()));
+
+    let albums = crate::albums::albums(&stats.tracks);
+    let singles: Vec<&TrackInfo> = stats
+        .tracks
+        .iter()
+        .filter(|t| t.album.is_empty() && !t.artist.is_empty() && !t.title.is_empty())
+        .collect();
+    let mut found: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+    let mut counts = Counts::default();
+    for p in &mut providers {
+        lookup_all(p.as_mut(), &albums, &singles, &mut found, &mut counts);
+    }
+    drop(providers);
+    total!(
+        "Looked up {}, Failed: {}, Left for next time: {}",
+        counts.found,
+        counts.failed,
+        counts.queued
+    );
+    term::event(
+        "enrichment",
+        json!({
+            "found": counts.found,
+            "failed": counts.failed,
+            "queued": counts.queued,
+        }),
+    );
+
+    #[cfg(any(feature = "lastfm", feature = "discogs"))]
+    lookups.into_inner().finish();
+    if !ec.sources_file.is_empty() {
+        sources::write(ec, &albums, &singles, &found);
+    }
+}
+
+// Lookups of albums and tracks, for the total
+#[derive(Default)]
+struct Counts {
+    found: u32,
+    failed: u32,
+    queued: u32,
+}
+
+// Everything a provider has for the albums and tracks, by "artist -
+// album" or "artist - title"
+fn lookup_all(
+    p: &mut dyn EnrichmentProvider,
+    albums: &[Album],
+    singles: &[&TrackInfo],
+    found: &mut BTreeMap<String, Vec<Candidate>>,
+    counts: &mut Counts,
+) {
+    log!("Looking up {}", p.name());
+    let mut add = |name: String, res: Result<Vec<Candidate>, ProviderError>| match res {
+        Ok(c) => {
+            counts.found += 1;
+            found.entry(name).or_default().extend(c);
+        }
+        // The provider has already said it can't be reached
+        #[cfg(any(feature = "lastfm", feature = "discogs"))]
+        Err(ProviderError::Queued) => counts.queued += 1,
+        Err(ProviderError::Failed(e)) => {
+            error!("  Error looking up {name}: {e}");
+            counts.failed += 1;
+        }
+    };
+    for album in albums {
+        let res = p.lookup_album(album).and_then(|c| match c.is_empty() {
+            true => p.lookup_artist(album.artist),
+            false => Ok(c),
+        });
+        add(format!("{} - {}", album.artist, album.title), res);
+    }
+    for t in singles {
+        add(format!("{} - {}", t.artist, t.title), p.lookup_track(t));
+    }
+    p.finish();
+}
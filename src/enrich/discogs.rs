@@ -0,0 +1,196 @@
+// Match albums to Discogs releases. Discogs only allows 60 authenticated
+// requests a minute.
+use super::lookup::{Auth, LookupError, Lookups, Service};
+use super::{Candidate, EnrichConfig, EnrichmentProvider, ProviderError};
+use crate::albums::Album;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+const API: &str = "https://api.discogs.com";
+const DELAY: Duration = Duration::from_millis(1100);
+// Search results whose tracklist is checked against the album
+const CANDIDATES: usize = 5;
+
+#[derive(Serialize, Deserialize)]
+struct Release {
+    id: u64,
+    title: String,
+    year: Option<u64>,
+    label: Option<String>,
+    // Whether its tracklist is as long as the album, rather than only
+    // the first search result
+    matched: bool,
+}
+
+pub fn service(ec: &EnrichConfig) -> Service {
+    Service {
+        name: "discogs",
+        base: API,
+        delay: DELAY,
+        auth: Auth::Header(
+            "Authorization",
+            format!("Discogs token={}", ec.discogs_token),
+        ),
+    }
+}
+
+pub struct Provider<'a, 'b> {
+    ec: &'a EnrichConfig,
+    lookups: &'a RefCell<Lookups<'b>>,
+    // The releases found, by "artist - album", for discogs_file. Those
+    // from earlier runs are kept.
+    found: BTreeMap<String, Release>,
+    new: usize,
+}
+
+impl<'a, 'b> Provider<'a, 'b> {
+    pub fn new(ec: &'a EnrichConfig, lookups: &'a RefCell<Lookups<'b>>) -> Self {
+        Provider {
+            ec,
+            lookups,
+            found: fs::read_to_string(&ec.discogs_file)
+                .ok()
+                .and_then(|j| serde_json::from_str(&j).ok())
+                .unwrap_or_default(),
+            new: 0,
+        }
+    }
+}
+
+// The year and label of the matching release. A release matched on its
+// tracklist is trusted more than the first search result.
+impl EnrichmentProvider for Provider<'_, '_> {
+    fn name(&self) -> &'static str {
+        "discogs"
+    }
+
+    fn lookup_album(&mut self, album: &Album) -> Result<Vec<Candidate>, ProviderError> {
+        let name = format!("{} - {}", album.artist, album.title);
+        let r = match find_release(&mut self.lookups.borrow_mut(), album)? {
+            Some(r) => r,
+            None => {
+                log!("  {name}: no match");
+                return Ok(Vec::new());
+            }
+        };
+        log!(
+            "  {}: {} ({}, {}) release {}",
+            name,
+            r.title,
+            r.year.map_or(String::from("no year"), |y| y.to_string()),
+            r.label.as_deref().unwrap_or("no label"),
+            r.id
+        );
+        let confidence = if r.matched { 0.9 } else { 0.5 };
+        let candidate = |field, value| Candidate {
+            field,
+            value,
+            source: "discogs",
+            confidence,
+        };
+        let mut found = Vec::new();
+        if let Some(year) = r.year {
+            found.push(candidate("year", year.to_string()));
+        }
+        if let Some(label) = &r.label {
+            found.push(candidate("label", label.clone()));
+        }
+        self.found.insert(name, r);
+        self.new += 1;
+        Ok(found)
+    }
+
+    fn finish(&mut self) {
+        if self.new == 0 {
+            return;
+        }
+        let json = serde_json::to_string_pretty(&self.found).expect("releases serialize");
+        match fs::write(&self.ec.discogs_file, json) {
+            Ok(_) => log!(
+                "  Wrote {} new releases to {}, {} in all",
+                self.new,
+                self.ec.discogs_file,
+                self.found.len()
+            ),
+            Err(e) => error!("  Unable to write {}: {e}", self.ec.discogs_file),
+        }
+    }
+}
+
+// The first search result with the same number of tracks as the album,
+// or the first result when none match
+fn find_release(lookups: &mut Lookups, album: &Album) -> Result<Option<Release>, ProviderError> {
+    let search = get(
+        lookups,
+        "/database/search",
+        &[
+            ("type", "release"),
+            ("artist", album.artist),
+            ("release_title", album.title),
+        ],
+    )?;
+    let ids: Vec<u64> = match search.get("results").and_then(|r| r.as_array()) {
+        Some(r) => r.iter().filter_map(|r| r.get("id")?.as_u64()).collect(),
+        None => return Ok(None),
+    };
+    // The tagged totals of every disc, or the tracks we have when
+    // there aren't any
+    let totals: Vec<u32> = album
+        .discs()
+        .into_iter()
+        .filter_map(|d| album.disc_track_total(d))
+        .collect();
+    let tracks = match totals.is_empty() {
+        true => album.tracks.len(),
+        false => totals.iter().sum::<u32>() as usize,
+    };
+    let mut first = None;
+    for id in ids.iter().take(CANDIDATES) {
+        let release = get(lookups, &format!("/releases/{id}"), &[])?;
+        let count = release
+            .get("tracklist")
+            .and_then(|t| t.as_array())
+            .map_or(0, |t| {
+                t.iter()
+                    .filter(|t| t.get("type_").and_then(|t| t.as_str()) == Some("track"))
+                    .count()
+            });
+        if count == tracks {
+            return Ok(Some(parse_release(*id, &release, true)));
+        }
+        first.get_or_insert((*id, release));
+    }
+    Ok(first.map(|(id, r)| parse_release(id, &r, false)))
+}
+
+fn get(lookups: &mut Lookups, path: &str, params: &[(&str, &str)]) -> Result<Value, ProviderError> {
+    lookups.get("discogs", path, params).map_err(|e| match e {
+        LookupError::Status(code, body) => ProviderError::Failed(
+            body.and_then(|j| j.get("message")?.as_str().map(String::from))
+                .unwrap_or_else(|| format!("HTTP {code}")),
+        ),
+        LookupError::Queued => ProviderError::Queued,
+        e => ProviderError::Failed(e.to_string()),
+    })
+}
+
+fn parse_release(id: u64, r: &Value, matched: bool) -> Release {
+    Release {
+        id,
+        title: r
+            .get("title")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        year: r.get("year").and_then(|y| y.as_u64()).filter(|&y| y > 0),
+        label: r
+            .pointer("/labels/0/name")
+            .and_then(|l| l.as_str())
+            .map(String::from),
+        matched,
+    }
+}
@@ -0,0 +1,139 @@
+// Genre/tag suggestions from Last.fm
+use super::lookup::{Auth, LookupError, Lookups, Service};
+use super::{Candidate, EnrichConfig, EnrichmentProvider, ProviderError};
+use crate::albums::Album;
+use crate::TrackInfo;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::time::Duration;
+
+const API: &str = "https://ws.audioscrobbler.com/2.0/";
+// Last.fm asks for no more than 5 requests a second
+const DELAY: Duration = Duration::from_millis(250);
+
+pub fn service(ec: &EnrichConfig) -> Service {
+    Service {
+        name: "lastfm",
+        base: API,
+        delay: DELAY,
+        auth: Auth::Query("api_key", ec.lastfm_api_key.clone()),
+    }
+}
+
+pub struct Provider<'a, 'b> {
+    ec: &'a EnrichConfig,
+    lookups: &'a RefCell<Lookups<'b>>,
+}
+
+impl<'a, 'b> Provider<'a, 'b> {
+    pub fn new(ec: &'a EnrichConfig, lookups: &'a RefCell<Lookups<'b>>) -> Self {
+        Provider { ec, lookups }
+    }
+
+    // Last.fm's weights (0-100) as confidences
+    fn get(&self, params: &[(&str, &str)]) -> Result<Vec<Candidate>, ProviderError> {
+        let tags = get(&mut self.lookups.borrow_mut(), params)?;
+        Ok(tags
+            .into_iter()
+            .map(|(name, count)| Candidate {
+                field: "genre",
+                value: name,
+                source: "lastfm",
+                confidence: count as f64 / 100.0,
+            })
+            .collect())
+    }
+
+    // The genre it has, if it's tagged with one
+    fn show(&self, name: &str, current: Option<&str>, tags: &[Candidate]) {
+        let suggested: Vec<String> = tags
+            .iter()
+            .take(self.ec.max_tags)
+            .map(|c| format!("{} ({})", c.value, (c.confidence * 100.0) as u64))
+            .collect();
+        log!(
+            "  {name}: {}suggested: {}",
+            current.map_or(String::new(), |g| format!("genre {g:?}, ")),
+            if suggested.is_empty() {
+                String::from("none")
+            } else {
+                suggested.join(", ")
+            }
+        );
+    }
+}
+
+// Genre suggestions, for an album from the tags people have given it
+impl EnrichmentProvider for Provider<'_, '_> {
+    fn name(&self) -> &'static str {
+        "lastfm"
+    }
+
+    fn lookup_album(&mut self, album: &Album) -> Result<Vec<Candidate>, ProviderError> {
+        let tags = self.get(&[
+            ("method", "album.gettoptags"),
+            ("artist", album.artist),
+            ("album", album.title),
+        ])?;
+        if !tags.is_empty() {
+            let name = format!("{} - {}", album.artist, album.title);
+            self.show(&name, Some(&album.genres().join(", ")), &tags);
+        }
+        Ok(tags)
+    }
+
+    // When nobody has tagged the album
+    fn lookup_artist(&mut self, artist: &str) -> Result<Vec<Candidate>, ProviderError> {
+        let tags = self.get(&[("method", "artist.gettoptags"), ("artist", artist)])?;
+        self.show(&format!("{artist} (the artist)"), None, &tags);
+        Ok(tags)
+    }
+
+    fn lookup_track(&mut self, t: &TrackInfo) -> Result<Vec<Candidate>, ProviderError> {
+        let tags = self.get(&[
+            ("method", "track.gettoptags"),
+            ("artist", &t.artist),
+            ("track", &t.title),
+        ])?;
+        self.show(
+            &format!("{} - {}", t.artist, t.title),
+            Some(&t.genre),
+            &tags,
+        );
+        Ok(tags)
+    }
+}
+
+fn get(
+    lookups: &mut Lookups,
+    params: &[(&str, &str)],
+) -> Result<Vec<(String, u64)>, ProviderError> {
+    let mut all = vec![("format", "json"), ("autocorrect", "1")];
+    all.extend_from_slice(params);
+    // Last.fm says what's wrong in the JSON
+    let json: Value = match lookups.get("lastfm", "", &all) {
+        Ok(j) | Err(LookupError::Status(_, Some(j))) => j,
+        Err(LookupError::Queued) => return Err(ProviderError::Queued),
+        Err(e) => return Err(ProviderError::Failed(e.to_string())),
+    };
+    // Error 6 is Last.fm for "not found"
+    if json.get("error").and_then(|e| e.as_u64()) == Some(6) {
+        return Ok(Vec::new());
+    }
+    if let Some(msg) = json.get("message").and_then(|m| m.as_str()) {
+        return Err(ProviderError::Failed(msg.to_string()));
+    }
+    let tags = match json.pointer("/toptags/tag").and_then(|t| t.as_array()) {
+        Some(t) => t,
+        None => return Ok(Vec::new()),
+    };
+    Ok(tags
+        .iter()
+        .filter_map(|t| {
+            let name = t.get("name")?.as_str()?.to_string();
+            let count = t.get("count")?.as_u64().unwrap_or(0);
+            Some((name, count))
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect())
+}
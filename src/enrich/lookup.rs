@@ -0,0 +1,262 @@
+// The HTTP side of every online lookup. Each service has its own pace,
+// responses are cached on disk for cache_days, and a busy service (429,
+// 503) is tried again after a wait. Once a service can't be reached the
+// run goes on from the cache, and the lookups it couldn't make are kept in
+// queue_file and made first next time.
+use super::EnrichConfig;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Statuses that mean try again later
+const BUSY: &[u16] = &[429, 500, 502, 503, 504];
+// Longest Retry-After that's waited for
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+// How a service takes its key, which is left out of the cache key and the
+// queue so it can change
+pub enum Auth {
+    #[cfg(feature = "lastfm")]
+    Query(&'static str, String),
+    #[cfg(feature = "discogs")]
+    Header(&'static str, String),
+}
+
+pub struct Service {
+    pub name: &'static str,
+    pub base: &'static str,
+    // Between requests that go to it
+    pub delay: Duration,
+    pub auth: Auth,
+}
+
+pub enum LookupError {
+    // With the response, if it was JSON
+    Status(u16, Option<Value>),
+    Failed(String),
+    // Not made, the service couldn't be reached this run
+    Queued,
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LookupError::Status(code, _) => write!(f, "HTTP {code}"),
+            LookupError::Failed(e) => write!(f, "{e}"),
+            LookupError::Queued => write!(f, "offline, queued for next time"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct Request {
+    service: String,
+    path: String,
+    params: Vec<(String, String)>,
+}
+
+pub struct Lookups<'a> {
+    ec: &'a EnrichConfig,
+    services: Vec<Service>,
+    // When each service was last asked
+    last: HashMap<&'static str, Instant>,
+    // Services that couldn't be reached this run
+    offline: Vec<&'static str>,
+    queue: Vec<Request>,
+}
+
+impl<'a> Lookups<'a> {
+    pub fn new(ec: &'a EnrichConfig, services: Vec<Service>) -> Self {
+        let queue = fs::read_to_string(&ec.queue_file)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Lookups {
+            ec,
+            services,
+            last: HashMap::new(),
+            offline: Vec::new(),
+            queue,
+        }
+    }
+
+    // The lookups left from last time, into the cache for this run
+    pub fn retry_queued(&mut self) {
+        let queued = std::mem::take(&mut self.queue);
+        if queued.is_empty() {
+            return;
+        }
+        log!("Looking up {} left from last time", queued.len());
+        for r in queued {
+            let params: Vec<(&str, &str)> = r
+                .params
+                .iter()
+                .map(|(n, v)| (n.as_str(), v.as_str()))
+                .collect();
+            match self.get(&r.service, &r.path, &params) {
+                Ok(_) | Err(LookupError::Queued) => (),
+                Err(e) => warn!("  Error looking up {}{}: {e}", r.service, r.path),
+            }
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        service: &str,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<Value, LookupError> {
+        let s = match self.services.iter().find(|s| s.name == service) {
+            Some(s) => s,
+            None => return Err(LookupError::Failed(format!("no service {service}"))),
+        };
+        let cached = self.cache_file(s.name, path, params);
+        if let Some(v) = self.cached(&cached) {
+            return Ok(v);
+        }
+        let request = || Request {
+            service: s.name.to_string(),
+            path: path.to_string(),
+            params: params
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+        };
+        if self.offline.contains(&s.name) {
+            enqueue(&mut self.queue, request());
+            return Err(LookupError::Queued);
+        }
+
+        let mut tries = 0;
+        let json = loop {
+            if let Some(wait) = self
+                .last
+                .get(s.name)
+                .and_then(|l| s.delay.checked_sub(l.elapsed()))
+            {
+                thread::sleep(wait);
+            }
+            self.last.insert(s.name, Instant::now());
+            let mut req = ureq::get(&format!("{}{path}", s.base)).set(
+                "User-Agent",
+                concat!("tag_test/", env!("CARGO_PKG_VERSION")),
+            );
+            req = match &s.auth {
+                #[cfg(feature = "lastfm")]
+                Auth::Query(name, value) => req.query(name, value),
+                #[cfg(feature = "discogs")]
+                Auth::Header(name, value) => req.set(name, value),
+            };
+            for (k, v) in params {
+                req = req.query(k, v);
+            }
+            // Errors are given without the URL, it can have the key in it
+            match req.call() {
+                Ok(r) => {
+                    break r
+                        .into_json::<Value>()
+                        .map_err(|e| LookupError::Failed(e.to_string()))?
+                }
+                Err(ureq::Error::Status(code, r))
+                    if BUSY.contains(&code) && tries < self.ec.retries =>
+                {
+                    tries += 1;
+                    let wait = r
+                        .header("Retry-After")
+                        .and_then(|w| w.trim().parse().ok())
+                        .map_or(Duration::from_secs(1 << tries), Duration::from_secs)
+                        .min(MAX_WAIT);
+                    warn!(
+                        "  {} is busy (HTTP {code}), trying again in {}s",
+                        s.name,
+                        wait.as_secs()
+                    );
+                    thread::sleep(wait);
+                }
+                Err(ureq::Error::Status(code, r)) => {
+                    if BUSY.contains(&code) {
+                        enqueue(&mut self.queue, request());
+                    }
+                    return Err(LookupError::Status(code, r.into_json().ok()));
+                }
+                Err(ureq::Error::Transport(t)) => {
+                    warn!(
+                        "  Can't reach {} ({}), its lookups are left for next time",
+                        s.name,
+                        t.message().unwrap_or("connection failed")
+                    );
+                    self.offline.push(s.name);
+                    enqueue(&mut self.queue, request());
+                    return Err(LookupError::Queued);
+                }
+            }
+        };
+
+        // A cache that can't be written just means asking again next time
+        if let Some(dir) = cached.parent() {
+            if fs::create_dir_all(dir).is_ok() {
+                let _ = fs::write(&cached, json.to_string());
+            }
+        }
+        Ok(json)
+    }
+
+    fn cache_file(&self, service: &str, path: &str, params: &[(&str, &str)]) -> PathBuf {
+        let key = params
+            .iter()
+            .fold(path.to_string(), |k, (n, v)| format!("{k}&{n}={v}"));
+        Path::new(&self.ec.cache)
+            .join(service)
+            .join(format!("{:016x}.json", fnv1a(&key)))
+    }
+
+    // None if it's not there or older than cache_days
+    fn cached(&self, file: &Path) -> Option<Value> {
+        let age = fs::metadata(file)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())?;
+        if self.ec.cache_days > 0 && age > Duration::from_secs(self.ec.cache_days * 24 * 60 * 60) {
+            return None;
+        }
+        serde_json::from_str(&fs::read_to_string(file).ok()?).ok()
+    }
+
+    // Saves what's left to look up, or removes the queue when there's
+    // nothing
+    pub fn finish(self) {
+        if self.queue.is_empty() {
+            let _ = fs::remove_file(&self.ec.queue_file);
+            return;
+        }
+        let res = serde_json::to_string_pretty(&self.queue)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&self.ec.queue_file, json).map_err(|e| e.to_string()));
+        match res {
+            Ok(_) => log!(
+                "{} lookups left for next time in {}",
+                self.queue.len(),
+                self.ec.queue_file
+            ),
+            Err(e) => error!("Unable to write {}: {e}", self.ec.queue_file),
+        }
+    }
+}
+
+fn enqueue(queue: &mut Vec<Request>, r: Request) {
+    if !queue.contains(&r) {
+        queue.push(r);
+    }
+}
+
+// Stable across runs and Rust versions, unlike DefaultHasher
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,111 @@
+// Every value found for each album field, with where it came from and how
+// far it can be trusted, so the tags and the lookups aren't mixed without
+// saying so. The value used is the first source in the precedence order
+// that has one.
+use super::{Candidate, EnrichConfig};
+use crate::albums::Album;
+use crate::{dates, TrackInfo};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+
+#[derive(Serialize)]
+struct Field {
+    // The value picked, and its source
+    value: String,
+    source: &'static str,
+    candidates: Vec<Candidate>,
+}
+
+// found is what the providers had, by "artist - album" or "artist -
+// title"
+pub fn write(
+    ec: &EnrichConfig,
+    albums: &[Album],
+    singles: &[&TrackInfo],
+    found: &BTreeMap<String, Vec<Candidate>>,
+) {
+    let mut out: BTreeMap<String, BTreeMap<&str, Field>> = BTreeMap::new();
+    let named = albums
+        .iter()
+        .map(|a| (format!("{} - {}", a.artist, a.title), a.tracks.clone()))
+        .chain(
+            singles
+                .iter()
+                .map(|t| (format!("{} - {}", t.artist, t.title), vec![*t])),
+        );
+    for (name, tracks) in named {
+        let mut fields: BTreeMap<&str, Vec<Candidate>> = BTreeMap::new();
+        for c in tagged(&tracks)
+            .into_iter()
+            .chain(found.get(&name).into_iter().flatten().cloned())
+        {
+            fields.entry(c.field).or_default().push(c);
+        }
+        let picked = fields
+            .into_iter()
+            .filter_map(|(field, candidates)| Some((field, pick(ec, candidates)?)))
+            .collect();
+        out.insert(name, picked);
+    }
+    let json = serde_json::to_string_pretty(&out).expect("sources serialize");
+    match fs::write(&ec.sources_file, json) {
+        Ok(_) => log!(
+            "Wrote the sources of {} albums and tracks to {}",
+            out.len(),
+            ec.sources_file
+        ),
+        Err(e) => error!("Unable to write {}: {e}", ec.sources_file),
+    }
+}
+
+// The year and genre most of the tracks have. The tags are as sure as can
+// be when every track agrees.
+fn tagged(tracks: &[&TrackInfo]) -> Vec<Candidate> {
+    let share = |n: usize| n as f64 / tracks.len() as f64;
+    let candidate = |field, value, n| Candidate {
+        field,
+        value,
+        source: "tag",
+        confidence: share(n),
+    };
+    let mut found = Vec::new();
+    let mut years: BTreeMap<u32, usize> = BTreeMap::new();
+    for t in tracks {
+        if let Some(Ok(d)) = t.date.as_deref().map(dates::parse) {
+            *years.entry(d.year).or_default() += 1;
+        }
+    }
+    if let Some((year, n)) = years.iter().max_by_key(|(_, n)| **n) {
+        found.push(candidate("year", year.to_string(), *n));
+    }
+    let mut genres: BTreeMap<&str, usize> = BTreeMap::new();
+    for g in tracks.iter().map(|t| &*t.genre).filter(|g| !g.is_empty()) {
+        *genres.entry(g).or_default() += 1;
+    }
+    if let Some((genre, n)) = genres.iter().max_by_key(|(_, n)| **n) {
+        found.push(candidate("genre", genre.to_string(), *n));
+    }
+    found
+}
+
+// Sources not in the precedence list come last
+fn pick(ec: &EnrichConfig, mut candidates: Vec<Candidate>) -> Option<Field> {
+    let rank = |source: &str| {
+        ec.precedence
+            .iter()
+            .position(|p| p == source)
+            .unwrap_or(usize::MAX)
+    };
+    candidates.sort_by(|a, b| {
+        rank(a.source)
+            .cmp(&rank(b.source))
+            .then(b.confidence.total_cmp(&a.confidence))
+    });
+    let best = candidates.iter().find(|c| !c.value.is_empty())?;
+    Some(Field {
+        value: best.value.clone(),
+        source: best.source,
+        candidates,
+    })
+}
@@ -0,0 +1,58 @@
+// The estimate walks the whole tree before the real scan does it again,
+// which doubles the directory reads on a network share. It can be skipped,
+// or taken from the counts the last scan saved.
+use crate::ScanStats;
+use serde_derive::Deserialize;
+use std::fs;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EstimateConfig {
+    // walk, cache or skip
+    pub mode: String,
+    // Where cache mode keeps the counts from the last scan
+    pub file: String,
+}
+
+impl Default for EstimateConfig {
+    fn default() -> Self {
+        EstimateConfig {
+            mode: String::from("walk"),
+            file: String::from("estimate.json"),
+        }
+    }
+}
+
+pub fn load(ec: &EstimateConfig) -> Option<ScanStats> {
+    let json = fs::read_to_string(&ec.file).ok()?;
+    match serde_json::from_str::<ScanStats>(&json) {
+        // The estimate doesn't read tags, so files that failed to read
+        // still count as valid
+        Ok(mut s) => {
+            s.valid_files += s.error_files;
+            s.error_files = 0;
+            for r in &mut s.roots {
+                r.valid_files += r.error_files;
+                r.error_files = 0;
+            }
+            Some(s)
+        }
+        Err(e) => {
+            warn!("Ignoring {}: {e}", ec.file);
+            None
+        }
+    }
+}
+
+// The counts of a real scan, for the next run's estimate
+pub fn save(ec: &EstimateConfig, stats: &ScanStats) {
+    if ec.mode != "cache" {
+        return;
+    }
+    let res = serde_json::to_string(stats)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&ec.file, json).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        error!("Error writing {}: {e}", ec.file);
+    }
+}
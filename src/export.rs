@@ -0,0 +1,268 @@
+// JSON lines and CSV exports of every track, written as the scan goes so
+// a big library never has to be held in memory just to export it
+use crate::albums::AlbumInfo;
+use crate::artists::ArtistInfo;
+use crate::{anonymize, fields, reports, Config, TrackInfo};
+use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    // Files to write, empty = don't
+    pub jsonl: String,
+    pub csv: String,
+    // Each album's and artist's totals as JSON, see albums.rs and
+    // artists.rs
+    pub albums: String,
+    pub artists: String,
+    // Everything for snapshot import, see snapshot.rs
+    pub snapshot: String,
+    // Hash the paths and tag text in all of them, see anonymize.rs. The
+    // salt stops the hashes of well known names being looked up.
+    pub anonymize: bool,
+    pub anonymize_salt: String,
+    // KiB of output held back before it's written to the files
+    pub buffer: usize,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            jsonl: String::new(),
+            csv: String::new(),
+            albums: String::new(),
+            artists: String::new(),
+            snapshot: String::new(),
+            anonymize: false,
+            anonymize_salt: String::new(),
+            buffer: 256,
+        }
+    }
+}
+
+const CSV_HEADER: &str = "path,artist,title,album,genre,track,track_total,disc,disc_total,\
+                          seconds,bitrate,rating,play_count,bpm,key,composer,work,language,date,sample_rate";
+
+struct Output {
+    file: String,
+    writer: BufWriter<File>,
+}
+
+pub struct Export {
+    jsonl: Option<Output>,
+    csv: Option<Output>,
+    tracks: u64,
+    // The salt, when anonymizing
+    anonymize: Option<String>,
+}
+
+impl Export {
+    pub fn new(ec: &ExportConfig) -> Export {
+        let mut export = Export {
+            jsonl: open(&ec.jsonl, ec.buffer),
+            csv: open(&ec.csv, ec.buffer),
+            tracks: 0,
+            anonymize: ec.anonymize.then(|| ec.anonymize_salt.clone()),
+        };
+        // [fields] go on the end
+        let header: String = fields::names().map(|n| format!(",{}", field(n))).collect();
+        export.write_csv(|w| writeln!(w, "{CSV_HEADER}{header}"));
+        export
+    }
+
+    pub fn track(&mut self, t: &TrackInfo) {
+        self.tracks += 1;
+        if self.jsonl.is_none() && self.csv.is_none() {
+            return;
+        }
+        let anonymized;
+        let t = match &self.anonymize {
+            Some(salt) => {
+                anonymized = anonymize::track(salt, t);
+                &anonymized
+            }
+            None => t,
+        };
+        if let Some(out) = self.jsonl.as_mut() {
+            let line = Line {
+                track: t,
+                fields: fields::values(t).into_iter().collect(),
+            };
+            let res = serde_json::to_writer(&mut out.writer, &line)
+                .map_err(std::io::Error::from)
+                .and_then(|_| writeln!(out.writer));
+            done(&mut self.jsonl, res);
+        }
+        if self.csv.is_some() {
+            self.write_csv(|w| writeln!(w, "{}", csv_row(t)));
+        }
+    }
+
+    pub fn albums(&self, ec: &ExportConfig, albums: &[AlbumInfo]) {
+        if ec.albums.is_empty() {
+            return;
+        }
+        let anonymized: Vec<AlbumInfo>;
+        let albums = match &self.anonymize {
+            Some(salt) => {
+                anonymized = albums
+                    .iter()
+                    .map(|a| AlbumInfo {
+                        artist: anonymize::text(salt, &a.artist),
+                        title: anonymize::text(salt, &a.title),
+                        art: a.art.as_ref().map(|f| anonymize::path(salt, f)),
+                        dir: anonymize::path(salt, &a.dir),
+                        ..a.clone()
+                    })
+                    .collect();
+                &anonymized
+            }
+            None => albums,
+        };
+        write_json(&ec.albums, albums, "albums");
+    }
+
+    pub fn artists(&self, ec: &ExportConfig, artists: &[ArtistInfo]) {
+        if ec.artists.is_empty() {
+            return;
+        }
+        let anonymized: Vec<ArtistInfo>;
+        let artists = match &self.anonymize {
+            Some(salt) => {
+                anonymized = artists
+                    .iter()
+                    .map(|a| ArtistInfo {
+                        name: anonymize::text(salt, &a.name),
+                        variants: a
+                            .variants
+                            .iter()
+                            .map(|v| anonymize::text(salt, v))
+                            .collect(),
+                        ..a.clone()
+                    })
+                    .collect();
+                &anonymized
+            }
+            None => artists,
+        };
+        write_json(&ec.artists, artists, "artists");
+    }
+
+    // Flush the files and say how much went into them
+    pub fn finish(self) {
+        for mut out in [self.jsonl, self.csv].into_iter().flatten() {
+            match out.writer.flush() {
+                Ok(_) => log!("Wrote {} ({} tracks)", out.file, self.tracks),
+                Err(e) => error!("Error writing {}: {e}", out.file),
+            }
+        }
+    }
+
+    fn write_csv(&mut self, f: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>) {
+        if let Some(out) = self.csv.as_mut() {
+            let res = f(&mut out.writer);
+            done(&mut self.csv, res);
+        }
+    }
+}
+
+// Whether anything after the scan needs all the tracks. If not, the scan
+// only keeps the counts.
+pub fn keep_tracks(config: &Config) -> bool {
+    reports::enabled(config).next().is_some()
+        || config.playlists.enabled
+        || config.feed.enabled
+        || config.mpd.enabled
+        || config.enrichment.enabled
+        || !config.hooks.new_album.is_empty()
+        || !config.mqtt.broker.is_empty()
+}
+
+fn open(file: &str, buffer: usize) -> Option<Output> {
+    if file.is_empty() {
+        return None;
+    }
+    match File::create(file) {
+        Ok(f) => Some(Output {
+            file: file.to_string(),
+            writer: BufWriter::with_capacity(buffer.max(1) * 1024, f),
+        }),
+        Err(e) => {
+            error!("Error creating {file}: {e}");
+            None
+        }
+    }
+}
+
+fn write_json<T: serde::Serialize>(file: &str, items: &[T], what: &str) {
+    let res = serde_json::to_string_pretty(items)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(file, json).map_err(|e| e.to_string()));
+    match res {
+        Ok(_) => log!("Wrote {file} ({} {what})", items.len()),
+        Err(e) => error!("Error writing {file}: {e}"),
+    }
+}
+
+// Give up on a file after an error rather than reporting it for every
+// track
+fn done(out: &mut Option<Output>, res: std::io::Result<()>) {
+    if let (Err(e), Some(o)) = (res, out.as_ref()) {
+        error!("Error writing {}: {e}", o.file);
+        *out = None;
+    }
+}
+
+// A JSON line, the track with its [fields] under "fields"
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    track: &'a TrackInfo,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<&'static str, String>,
+}
+
+fn csv_row(t: &TrackInfo) -> String {
+    let num = |n: Option<u32>| n.map_or(String::new(), |n| n.to_string());
+    let computed = fields::values(t).into_iter().map(|(_, v)| field(&v));
+    [
+        field(&t.path),
+        field(&t.artist),
+        field(&t.title),
+        field(&t.album),
+        field(&t.genre),
+        t.track.to_string(),
+        num(t.track_total),
+        num(t.disc),
+        num(t.disc_total),
+        t.duration.as_secs().to_string(),
+        num(t.bitrate),
+        t.rating.map_or(String::new(), |r| r.to_string()),
+        t.play_count.map_or(String::new(), |c| c.to_string()),
+        t.bpm
+            .or(t.estimated_bpm)
+            .map_or(String::new(), |b| format!("{b:.1}")),
+        field(t.key.as_deref().unwrap_or("")),
+        field(t.composer.as_deref().unwrap_or("")),
+        field(t.work.as_deref().unwrap_or("")),
+        field(t.language.as_deref().unwrap_or("")),
+        field(t.date.as_deref().unwrap_or("")),
+        num(t.sample_rate),
+    ]
+    .into_iter()
+    .chain(computed)
+    .join(",")
+}
+
+// Quote fields with commas, quotes or line breaks
+fn field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -0,0 +1,174 @@
+// Featured artists, "Artist feat. Guest" in the artist tag. The report
+// lists who features where, and tag_test featured moves the guests to
+// the title, "Song (feat. Guest)", leaving the artist tag to the main
+// artist so tracks group under them.
+use crate::ask::Prompt;
+use crate::cli::{Command, DRY_RUN, INTERACTIVE};
+use crate::{music_files, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::process::exit;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FeaturedConfig {
+    // Words that start the featured artists, matched without regard to
+    // case
+    pub separators: Vec<String>,
+    // Between featured artists, "Guest 1 & Guest 2"
+    pub joiners: Vec<String>,
+    // How the guests are written in the title
+    pub title_format: String,
+}
+
+impl Default for FeaturedConfig {
+    fn default() -> Self {
+        FeaturedConfig {
+            separators: ["feat.", "ft.", "featuring", "feat", "ft"]
+                .map(String::from)
+                .to_vec(),
+            joiners: [",", "&", " and "].map(String::from).to_vec(),
+            title_format: String::from("{title} (feat. {featured})"),
+        }
+    }
+}
+
+// The main artist and the featured ones, if artist has any
+pub fn split(fc: &FeaturedConfig, artist: &str) -> Option<(String, Vec<String>)> {
+    // Offsets in the lower case copy have to work in the original
+    let lower = match artist.to_lowercase() {
+        l if l.len() == artist.len() => l,
+        _ => artist.to_string(),
+    };
+    let (start, len) = fc
+        .separators
+        .iter()
+        .filter_map(|s| find_word(&lower, &s.to_lowercase()).map(|i| (i, s.len())))
+        .min()?;
+    let main = artist[..start]
+        .trim_end()
+        .trim_end_matches(['(', '['])
+        .trim_end();
+    let rest = artist[start + len..].trim().trim_end_matches([')', ']']);
+    let mut featured = vec![rest.to_string()];
+    for j in &fc.joiners {
+        featured = featured
+            .iter()
+            .flat_map(|f| f.split(j.as_str()))
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect();
+    }
+    if main.is_empty() || featured.is_empty() {
+        return None;
+    }
+    Some((main.to_string(), featured))
+}
+
+// Where word starts in text as a word of its own, after a space or
+// bracket
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    text.match_indices(word).map(|(i, _)| i).find(|&i| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + word.len()..].chars().next();
+        matches!(before, Some(' ' | '(' | '['))
+            && (word.ends_with('.') || matches!(after, None | Some(' ')))
+    })
+}
+
+pub const COMMAND: Command = Command {
+    name: "featured",
+    usage: "[--dry-run] [--interactive] [path...]",
+    about: "Move featured artists from the artist tag to the title",
+    flags: &[DRY_RUN, INTERACTIVE],
+};
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let mut prompt = Prompt::new(&mut args);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+
+    let (mut changed, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        match fix_file(&config.featured, &file_name, &mut prompt, dry_run) {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error moving featured artists in {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+}
+
+fn fix_file(
+    fc: &FeaturedConfig,
+    file_name: &str,
+    prompt: &mut Prompt,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = match tagged_file.primary_tag() {
+        Some(t) => t.clone(),
+        None => return Ok(false),
+    };
+    let artist = tag.artist().unwrap_or_default().to_string();
+    let (main, featured) = match split(fc, &artist) {
+        Some(s) => s,
+        None => return Ok(false),
+    };
+    let old_title = tag.title().unwrap_or_default().to_string();
+    // Leave the title alone if it names the guests already
+    let title = match split(fc, &old_title) {
+        Some(_) => old_title.clone(),
+        None => fc
+            .title_format
+            .replace("{title}", &old_title)
+            .replace("{featured}", &featured.join(" & ")),
+    };
+
+    log!("{file_name}:");
+    log!("  artist {artist:?} -> {main:?}");
+    let mut changed = false;
+    if let Some(main) = prompt.check(main) {
+        tag.insert_text(ItemKey::TrackArtist, main);
+        changed = true;
+    }
+    if title != old_title {
+        log!("  title {old_title:?} -> {title:?}");
+        if let Some(title) = prompt.check(title) {
+            tag.insert_text(ItemKey::TrackTitle, title);
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(false);
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,141 @@
+// RSS feed of recently added albums, so people can subscribe to new
+// music showing up in the library
+use crate::albums::albums;
+use crate::{format, Config, ScanStats};
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FeedConfig {
+    pub enabled: bool,
+    pub file: String,
+    pub title: String,
+    // Albums with a track changed within this many days are included
+    pub days: u64,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            enabled: false,
+            file: String::from("new_albums.xml"),
+            title: String::from("New music"),
+            days: 30,
+        }
+    }
+}
+
+pub fn run(config: &Config, stats: &ScanStats) {
+    let fc = &config.feed;
+    if !fc.enabled {
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let since = now.saturating_sub(fc.days * 24 * 60 * 60);
+
+    let mut new: Vec<_> = albums(&stats.tracks)
+        .into_iter()
+        .filter(|a| a.modified() >= since)
+        .collect();
+    new.sort_by_key(|a| std::cmp::Reverse(a.modified()));
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\">\n<channel>\n");
+    xml.push_str(&format!("  <title>{}</title>\n", escape(&fc.title)));
+    xml.push_str("  <description>Albums recently added to the library</description>\n");
+    xml.push_str(&format!(
+        "  <lastBuildDate>{}</lastBuildDate>\n",
+        rfc2822(now)
+    ));
+    for album in &new {
+        let dir = album
+            .tracks
+            .first()
+            .and_then(|t| Path::new(&t.path).parent())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let genres = album.genres();
+        let length: Duration = album.tracks.iter().map(|t| t.duration).sum();
+        xml.push_str("  <item>\n");
+        xml.push_str(&format!(
+            "    <title>{} - {}</title>\n",
+            escape(album.artist),
+            escape(album.title)
+        ));
+        xml.push_str(&format!(
+            "    <description>{} tracks, {}{}</description>\n",
+            album.tracks.len(),
+            format::duration(length),
+            if genres.is_empty() {
+                String::new()
+            } else {
+                escape(&format!(", {}", genres.join(", ")))
+            }
+        ));
+        xml.push_str(&format!(
+            "    <guid isPermaLink=\"false\">{}</guid>\n",
+            escape(&dir)
+        ));
+        xml.push_str(&format!(
+            "    <pubDate>{}</pubDate>\n",
+            rfc2822(album.modified())
+        ));
+        xml.push_str("  </item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+
+    match fs::write(&fc.file, xml) {
+        Ok(_) => log!("Wrote {} ({} albums)", fc.file, new.len()),
+        Err(e) => error!("Error writing {}: {e}", fc.file),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Seconds since the epoch as an RSS date, e.g. "Thu, 01 Jan 1970 00:00:00 GMT"
+pub fn rfc2822(secs: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = secs / 86400;
+    let rem = secs % 86400;
+    let (year, month, day) = civil(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// Year, month and day from days since the epoch (Howard Hinnant's
+// algorithm)
+pub fn civil(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,551 @@
+// Fields worked out from the tags by expressions in the [fields] section,
+// e.g. decade = "floor(year / 10) * 10". They can be used in templates,
+// queries and filters like the built in fields, are added to the exports,
+// and counted by the fields report.
+//
+// Expressions have numbers, "strings", true and false, the track's fields,
+// + - * / %, == != < <= > >=, and, or, not, brackets,
+// if <condition> <value> else <value>, and the functions floor, ceil,
+// round, abs, lower, upper and len. A field the track doesn't have is
+// empty, and so is any sum done with it or comparison other than == and
+// !=, so it's only found with == "".
+use crate::reports::LOSSLESS;
+use crate::{dates, file_ext, format, TrackInfo};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub type FieldsConfig = BTreeMap<String, Expr>;
+// Named expressions, so a filter used often can be given by its name in
+// queries, --scope and --where. They're read once the fields are in, so
+// they can't use each other.
+pub type FiltersConfig = BTreeMap<String, String>;
+
+// Fields expressions can use. Templates have most of them too.
+const VARS: &[&str] = &[
+    "path",
+    "file",
+    "ext",
+    "artist",
+    "albumartist",
+    "title",
+    "album",
+    "genre",
+    "track",
+    "track_total",
+    "disc",
+    "duration",
+    "seconds",
+    "bitrate",
+    "sample_rate",
+    "size",
+    "rating",
+    "play_count",
+    "bpm",
+    "key",
+    "composer",
+    "work",
+    "language",
+    "date",
+    "year",
+    "lossless",
+    "art",
+];
+
+const FUNCTIONS: &[&str] = &["floor", "ceil", "round", "abs", "lower", "upper", "len"];
+
+static FIELDS: OnceLock<Vec<(String, Expr)>> = OnceLock::new();
+static FILTERS: OnceLock<Vec<(String, Expr)>> = OnceLock::new();
+
+// Called once the config is loaded. The names can't be ones of the
+// track's own fields.
+pub fn init(fields: &FieldsConfig, filters: &FiltersConfig) -> Result<(), String> {
+    if let Some(name) = fields.keys().find(|n| VARS.contains(&n.as_str())) {
+        return Err(format!("[fields] {name} is a built in field"));
+    }
+    let _ = FIELDS.set(fields.iter().map(|(n, e)| (n.clone(), e.clone())).collect());
+    let mut parsed = Vec::new();
+    for (name, text) in filters {
+        if VARS.contains(&name.as_str()) || FUNCTIONS.contains(&name.as_str()) {
+            return Err(format!("[filters] {name} is a built in field"));
+        }
+        if fields.contains_key(name) {
+            return Err(format!("[filters] {name} is one of the [fields]"));
+        }
+        let e = Expr::try_from(text.clone()).map_err(|e| format!("[filters] {name}: {e}"))?;
+        parsed.push((name.clone(), e));
+    }
+    let _ = FILTERS.set(parsed);
+    Ok(())
+}
+
+// The [filters], in name order
+pub fn filters() -> &'static [(String, Expr)] {
+    // Not get_or_init, they're looked up while they're being parsed
+    FILTERS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn fields() -> &'static [(String, Expr)] {
+    FIELDS.get_or_init(Vec::new)
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    fields().iter().map(|(n, _)| n.as_str())
+}
+
+pub fn is_field(name: &str) -> bool {
+    names().any(|n| n == name)
+}
+
+// Empty if there is no such field
+pub fn value(t: &TrackInfo, name: &str) -> String {
+    fields()
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, e)| e.eval(t).to_string())
+        .unwrap_or_default()
+}
+
+// Every field's value, in name order
+pub fn values(t: &TrackInfo) -> Vec<(&'static str, String)> {
+    fields()
+        .iter()
+        .map(|(n, e)| (n.as_str(), e.eval(t).to_string()))
+        .collect()
+}
+
+#[derive(Clone, PartialEq)]
+enum Value {
+    Empty,
+    Num(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Empty => false,
+            Value::Num(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Empty => Ok(()),
+            Value::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Num(n) => {
+                let s = format!("{n:.2}");
+                write!(f, "{}", s.trim_end_matches('0').trim_end_matches('.'))
+            }
+            Value::Str(s) => write!(f, "{s}"),
+            Value::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct Expr(Node);
+
+#[derive(Clone)]
+enum Node {
+    Value(Value),
+    Var(&'static str),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    If(Box<Node>, Box<Node>, Box<Node>),
+    Call(&'static str, Box<Node>),
+    // Index in the [fields]
+    Field(usize),
+    // Index in the [filters]
+    Filter(usize),
+}
+
+impl TryFrom<String> for Expr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let tokens = tokenize(&s).map_err(|e| format!("{e} in {s:?}"))?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let node = parser.expr().map_err(|e| format!("{e} in {s:?}"))?;
+        match parser.peek() {
+            None => Ok(Expr(node)),
+            Some(t) => Err(format!("unexpected {t} in {s:?}")),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, t: &TrackInfo) -> Value {
+        self.0.eval(t)
+    }
+
+    // Whether the track's value counts as true, for --scope
+    pub fn matches(&self, t: &TrackInfo) -> bool {
+        self.eval(t).truthy()
+    }
+
+    // Sort by the value for each track: empty first, then false and true,
+    // numbers and text. Tracks with the same value stay in order.
+    pub fn sort(&self, tracks: &mut Vec<&TrackInfo>) {
+        let rank = |v: &Value| match v {
+            Value::Empty => 0,
+            Value::Bool(_) => 1,
+            Value::Num(_) => 2,
+            Value::Str(_) => 3,
+        };
+        let mut keyed: Vec<(Value, &TrackInfo)> =
+            tracks.drain(..).map(|t| (self.eval(t), t)).collect();
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Num(a), Value::Num(b)) => a.total_cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+            _ => rank(a).cmp(&rank(b)),
+        });
+        tracks.extend(keyed.into_iter().map(|(_, t)| t));
+    }
+}
+
+impl Node {
+    fn eval(&self, t: &TrackInfo) -> Value {
+        match self {
+            Node::Value(v) => v.clone(),
+            Node::Var(name) => var(t, name),
+            Node::Not(n) => Value::Bool(!n.eval(t).truthy()),
+            Node::Neg(n) => match n.eval(t) {
+                Value::Num(n) => Value::Num(-n),
+                _ => Value::Empty,
+            },
+            Node::Binary(Op::And, a, b) => Value::Bool(a.eval(t).truthy() && b.eval(t).truthy()),
+            Node::Binary(Op::Or, a, b) => Value::Bool(a.eval(t).truthy() || b.eval(t).truthy()),
+            Node::Binary(op, a, b) => binary(*op, a.eval(t), b.eval(t)),
+            Node::If(c, a, b) => match c.eval(t).truthy() {
+                true => a.eval(t),
+                false => b.eval(t),
+            },
+            Node::Call(f, arg) => call(f, arg.eval(t)),
+            Node::Field(i) => fields()[*i].1.eval(t),
+            Node::Filter(i) => Value::Bool(filters()[*i].1.matches(t)),
+        }
+    }
+}
+
+fn binary(op: Op, a: Value, b: Value) -> Value {
+    use std::cmp::Ordering;
+    let ord = match (&a, &b) {
+        (Value::Num(x), Value::Num(y)) => x.partial_cmp(y),
+        _ => Some(a.to_string().cmp(&b.to_string())),
+    };
+    let cmp = |f: fn(Ordering) -> bool| Value::Bool(ord.is_some_and(f));
+    match (op, &a, &b) {
+        (Op::Eq, ..) => cmp(|o| o == Ordering::Equal),
+        (Op::Ne, ..) => cmp(|o| o != Ordering::Equal),
+        // So year < 1990 isn't true of tracks with no year
+        (_, Value::Empty, _) | (_, _, Value::Empty) => Value::Empty,
+        (Op::Lt, ..) => cmp(|o| o == Ordering::Less),
+        (Op::Le, ..) => cmp(|o| o != Ordering::Greater),
+        (Op::Gt, ..) => cmp(|o| o == Ordering::Greater),
+        (Op::Ge, ..) => cmp(|o| o != Ordering::Less),
+        (Op::Add, Value::Num(x), Value::Num(y)) => Value::Num(x + y),
+        // Anything added to a string is joined on
+        (Op::Add, ..) => Value::Str(format!("{a}{b}")),
+        (Op::Sub, Value::Num(x), Value::Num(y)) => Value::Num(x - y),
+        (Op::Mul, Value::Num(x), Value::Num(y)) => Value::Num(x * y),
+        (Op::Div | Op::Rem, Value::Num(_), Value::Num(y)) if *y == 0.0 => Value::Empty,
+        (Op::Div, Value::Num(x), Value::Num(y)) => Value::Num(x / y),
+        (Op::Rem, Value::Num(x), Value::Num(y)) => Value::Num(x % y),
+        _ => Value::Empty,
+    }
+}
+
+fn call(f: &str, v: Value) -> Value {
+    match (f, v) {
+        ("floor", Value::Num(n)) => Value::Num(n.floor()),
+        ("ceil", Value::Num(n)) => Value::Num(n.ceil()),
+        ("round", Value::Num(n)) => Value::Num(n.round()),
+        ("abs", Value::Num(n)) => Value::Num(n.abs()),
+        ("lower", Value::Str(s)) => Value::Str(s.to_lowercase()),
+        ("upper", Value::Str(s)) => Value::Str(s.to_uppercase()),
+        ("len", Value::Empty) => Value::Num(0.0),
+        ("len", v) => Value::Num(v.to_string().chars().count() as f64),
+        _ => Value::Empty,
+    }
+}
+
+fn var(t: &TrackInfo, name: &str) -> Value {
+    let num = |n: Option<f64>| n.map_or(Value::Empty, Value::Num);
+    let text = |s: &str| match s {
+        "" => Value::Empty,
+        s => Value::Str(s.to_string()),
+    };
+    match name {
+        "path" => text(&t.path),
+        "file" => text(
+            &Path::new(&t.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+        ),
+        "ext" => text(&file_ext(&t.path)),
+        "artist" => text(&t.artist),
+        "albumartist" => text(t.album_artist.as_deref().unwrap_or(&t.artist)),
+        "title" => text(&t.title),
+        "album" => text(&t.album),
+        "genre" => text(&t.genre),
+        "track" => num(Some(t.track as f64).filter(|n| *n > 0.0)),
+        "track_total" => num(t.track_total.map(f64::from)),
+        "disc" => num(t.disc.map(f64::from)),
+        "duration" => text(&format::duration(t.duration)),
+        "seconds" => Value::Num(t.duration.as_secs() as f64),
+        "bitrate" => num(t.bitrate.map(f64::from)),
+        "sample_rate" => num(t.sample_rate.map(f64::from)),
+        "size" => Value::Num(t.size as f64),
+        "rating" => num(t.rating.map(f64::from)),
+        "play_count" => num(t.play_count.map(|c| c as f64)),
+        "bpm" => num(t.bpm.or(t.estimated_bpm)),
+        "key" => text(t.key.as_deref().unwrap_or("")),
+        "composer" => text(t.composer.as_deref().unwrap_or("")),
+        "work" => text(t.work.as_deref().unwrap_or("")),
+        "language" => text(t.language.as_deref().unwrap_or("")),
+        "date" => text(t.date.as_deref().unwrap_or("")),
+        "year" => num(t
+            .date
+            .as_deref()
+            .and_then(|d| dates::parse(d).ok())
+            .map(|d| d.year as f64)),
+        "lossless" => Value::Bool(LOSSLESS.contains(&file_ext(&t.path).as_str())),
+        "art" => num(t.pictures.map(f64::from)),
+        _ => Value::Empty,
+    }
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Word(String),
+    Sym(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{n}"),
+            Token::Str(s) => write!(f, "{s:?}"),
+            Token::Word(w) => write!(f, "{w}"),
+            Token::Sym(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+// Longest first, so <= isn't read as < and =
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "(", ")",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '"' {
+            let end = rest[1..].find('"').ok_or("unclosed \"")?;
+            tokens.push(Token::Str(rest[1..=end].to_string()));
+            end + 2
+        } else if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let n = rest[..len]
+                .parse()
+                .map_err(|_| format!("bad number {}", &rest[..len]))?;
+            tokens.push(Token::Num(n));
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..len].to_string()));
+            len
+        } else {
+            let sym = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(**s))
+                .ok_or_else(|| format!("unexpected {c}"))?;
+            tokens.push(Token::Sym(sym));
+            sym.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&Token, String> {
+        self.pos += 1;
+        self.tokens
+            .get(self.pos - 1)
+            .ok_or_else(|| String::from("unexpected end"))
+    }
+
+    // Takes the token if it's the given word or symbol
+    fn accept(&mut self, s: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Word(w)) => w == s,
+            Some(Token::Sym(sym)) => *sym == s,
+            _ => false,
+        };
+        self.pos += found as usize;
+        found
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        match self.accept(s) {
+            true => Ok(()),
+            false => Err(format!("expected {s}")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Node, String> {
+        if !self.accept("if") {
+            return self.or();
+        }
+        let cond = self.expr()?;
+        let then = self.expr()?;
+        self.expect("else")?;
+        let other = self.expr()?;
+        Ok(Node::If(Box::new(cond), Box::new(then), Box::new(other)))
+    }
+
+    // Operators from the loosest to the tightest
+    fn or(&mut self) -> Result<Node, String> {
+        self.binary(&[("or", Op::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        self.binary(&[("and", Op::And)], Parser::not)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        match self.accept("not") {
+            true => Ok(Node::Not(Box::new(self.not()?))),
+            false => self.compare(),
+        }
+    }
+
+    fn compare(&mut self) -> Result<Node, String> {
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        let left = self.sum()?;
+        match ops.iter().find(|(s, _)| self.accept(s)) {
+            Some((_, op)) => Ok(Node::Binary(*op, Box::new(left), Box::new(self.sum()?))),
+            None => Ok(left),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        self.binary(&[("+", Op::Add), ("-", Op::Sub)], Parser::product)
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)],
+            Parser::unary,
+        )
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, Op)],
+        operand: fn(&mut Parser) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let mut left = operand(self)?;
+        while let Some((_, op)) = ops.iter().find(|(s, _)| self.accept(s)) {
+            left = Node::Binary(*op, Box::new(left), Box::new(operand(self)?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.accept("-") {
+            true => Ok(Node::Neg(Box::new(self.unary()?))),
+            false => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let word = match self.next()? {
+            Token::Num(n) => return Ok(Node::Value(Value::Num(*n))),
+            Token::Str(s) => return Ok(Node::Value(Value::Str(s.clone()))),
+            Token::Sym("(") => {
+                let node = self.expr()?;
+                self.expect(")")?;
+                return Ok(node);
+            }
+            Token::Sym(s) => return Err(format!("unexpected {s}")),
+            Token::Word(w) => w.clone(),
+        };
+        match word.as_str() {
+            "true" => return Ok(Node::Value(Value::Bool(true))),
+            "false" => return Ok(Node::Value(Value::Bool(false))),
+            _ => (),
+        }
+        if let Some(f) = FUNCTIONS.iter().find(|f| **f == word) {
+            self.expect("(")?;
+            let arg = self.expr()?;
+            self.expect(")")?;
+            return Ok(Node::Call(f, Box::new(arg)));
+        }
+        if let Some(v) = VARS.iter().find(|v| **v == word) {
+            return Ok(Node::Var(v));
+        }
+        // Not set yet while the [fields] themselves are read, so they
+        // can't use each other
+        let fields = FIELDS.get().map(Vec::as_slice).unwrap_or_default();
+        if let Some(i) = fields.iter().position(|(n, _)| *n == word) {
+            return Ok(Node::Field(i));
+        }
+        match filters().iter().position(|(n, _)| *n == word) {
+            Some(i) => Ok(Node::Filter(i)),
+            None => Err(format!("unknown field {word}")),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+// Find the same recording in different files by Chromaprint fingerprint,
+// so re-encodes and retagged copies are found too. Fingerprints are kept
+// in a local file, nothing is looked up online. --plan and --resolve
+// then pick the copies to keep, see resolve.rs.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::resolve;
+use crate::{ask, music_files, Config, Types};
+use serde_derive::Deserialize;
+use std::path::Path;
+use std::process::exit;
+
+pub const COMMAND: Command = Command {
+    name: "duplicates",
+    usage: "[--plan <file>] [--resolve] [--permanent] [path...] | --apply <file> [--dry-run] [--permanent]",
+    about: "Find the same recording in different files by audio fingerprint",
+    flags: &[
+        Flag {
+            name: "--plan",
+            values: Some(&[]),
+            about: "Write which copies to keep and remove to this file",
+        },
+        Flag {
+            name: "--resolve",
+            values: None,
+            about: "Show which copies to keep and remove them once that's confirmed",
+        },
+        Flag {
+            name: "--apply",
+            values: Some(&[]),
+            about: "Remove the files in a plan written by --plan",
+        },
+        DRY_RUN,
+        Flag {
+            name: "--permanent",
+            values: None,
+            about: "Delete files for good rather than to the trash",
+        },
+    ],
+};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FingerprintConfig {
+    // Where fingerprints are stored between runs
+    pub file: String,
+    // Share of matching fingerprint bits (0.5 is chance) to call two files
+    // the same recording
+    pub threshold: f64,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        FingerprintConfig {
+            file: String::from("fingerprints.json"),
+            threshold: 0.9,
+        }
+    }
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let (mut plan_file, mut apply_file) = (None, None);
+    let (mut resolve, mut dry_run, mut permanent) = (false, false, false);
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--plan" => plan_file = Some(args.next().cloned().unwrap_or_else(|| usage())),
+            "--apply" => apply_file = Some(args.next().cloned().unwrap_or_else(|| usage())),
+            "--resolve" => resolve = true,
+            "--dry-run" => dry_run = true,
+            "--permanent" => permanent = true,
+            a if a.starts_with("--") => usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    // A saved plan doesn't need the fingerprints
+    if let Some(file) = apply_file {
+        if plan_file.is_some() || resolve || !paths.is_empty() {
+            usage();
+        }
+        match resolve::load(&file) {
+            Ok(plan) => resolve::apply(&plan, dry_run, permanent),
+            Err(e) => {
+                error!("Error reading {file}: {e}");
+                exit(1);
+            }
+        }
+        return;
+    }
+    if dry_run {
+        usage();
+    }
+    if resolve {
+        ask::check_terminal("--resolve");
+    }
+    // Fingerprints of files under a root that isn't mounted are kept
+    let roots: Vec<&str> = config
+        .directories
+        .scan
+        .iter()
+        .map(|r| r.path.as_str())
+        .filter(|r| Path::new(r).is_dir())
+        .collect();
+    let groups = duplicates(
+        &config.fingerprint,
+        &config.types,
+        &music_files(config, &paths),
+        &roots,
+    );
+    if plan_file.is_none() && !resolve {
+        return;
+    }
+    let plan = resolve::plan(&groups);
+    resolve::show(&plan);
+    if let Some(file) = &plan_file {
+        match resolve::save(&plan, file) {
+            Ok(_) => log!("Wrote {file}, carry it out with duplicates --apply {file}"),
+            Err(e) => error!("Error writing {file}: {e}"),
+        }
+    }
+    if resolve && !plan.groups.is_empty() && ask::confirm("Remove these files?") {
+        resolve::apply(&plan, false, permanent);
+    }
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
+
+#[cfg(not(feature = "fingerprint"))]
+fn duplicates(
+    _fc: &FingerprintConfig,
+    _types: &Types,
+    _files: &[String],
+    _roots: &[&str],
+) -> Vec<Vec<String>> {
+    warn!("Duplicate detection needs tag_test built with --features fingerprint");
+    exit(1);
+}
+
+#[cfg(feature = "fingerprint")]
+use store::duplicates;
+
+#[cfg(feature = "fingerprint")]
+mod store {
+    use super::{FingerprintConfig, Types};
+    use crate::analysis::decode::decode_mono;
+    use crate::{format, term};
+    use lofty::prelude::*;
+    use lofty::probe::Probe;
+    use rusty_chromaprint::{Configuration, Fingerprinter};
+    use serde_derive::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::collections::{BTreeMap, HashSet};
+    use std::fs;
+    use std::path::Path;
+    use std::time::UNIX_EPOCH;
+
+    // AcoustID fingerprints the first two minutes
+    const MAX_SECONDS: usize = 120;
+    // Files this much longer or shorter than each other aren't compared
+    const MAX_DURATION_DIFF: f64 = 3.0;
+    // Fingerprint items to shift by when lining up two fingerprints, for
+    // encoder delay and trimmed silence
+    const MAX_SHIFT: usize = 8;
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry {
+        // Modification time when fingerprinted, seconds since the epoch
+        modified: u64,
+        duration: f64,
+        fingerprint: Vec<u32>,
+    }
+
+    // The files in each group of copies
+    pub fn duplicates(
+        fc: &FingerprintConfig,
+        types: &Types,
+        files: &[String],
+        roots: &[&str],
+    ) -> Vec<Vec<String>> {
+        let mut stored: BTreeMap<String, Entry> = fs::read_to_string(&fc.file)
+            .ok()
+            .and_then(|j| serde_json::from_str(&j).ok())
+            .unwrap_or_default();
+
+        let (mut new, mut failed) = (0, 0);
+        for file_name in files {
+            let modified = match fs::metadata(file_name).and_then(|m| m.modified()) {
+                Ok(m) => m.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+                Err(_) => continue,
+            };
+            if stored
+                .get(file_name)
+                .is_some_and(|e| e.modified == modified)
+            {
+                continue;
+            }
+            match fingerprint(file_name) {
+                Some((duration, fingerprint)) => {
+                    stored.insert(
+                        file_name.clone(),
+                        Entry {
+                            modified,
+                            duration,
+                            fingerprint,
+                        },
+                    );
+                    new += 1;
+                }
+                None => {
+                    error!("Unable to fingerprint {file_name}");
+                    term::event(
+                        "error",
+                        json!({ "path": file_name, "message": "unable to fingerprint" }),
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        // Files that are gone from the roots that are there are dropped
+        // from the store
+        stored.retain(|f, _| {
+            let path = Path::new(f);
+            !roots.iter().any(|r| path.starts_with(r)) || path.exists()
+        });
+        match serde_json::to_string(&stored).map(|j| fs::write(&fc.file, j)) {
+            Ok(Ok(_)) => (),
+            _ => error!("Unable to write {}", fc.file),
+        }
+        total!(
+            "Fingerprinted {new}, Failed: {failed}, Stored: {}",
+            stored.len()
+        );
+
+        // Jingles and the like outside the types durations aren't compared
+        let files: HashSet<&String> = files.iter().collect();
+        let mut entries: Vec<(&String, &Entry)> = stored
+            .iter()
+            .filter(|(f, e)| files.contains(f) && types.duration_ok(e.duration))
+            .collect();
+        entries.sort_by(|a, b| a.1.duration.total_cmp(&b.1.duration));
+        let groups = group(&entries, fc.threshold);
+        for g in &groups {
+            let files: Vec<_> = g
+                .iter()
+                .map(|(f, s)| json!({ "path": f, "similarity": s }))
+                .collect();
+            term::event("duplicates", json!({ "files": files }));
+            log!("Same recording:");
+            for (f, similarity) in g {
+                match similarity {
+                    Some(s) => log!("  {f} ({}%)", format::decimal(s * 100.0, 0)),
+                    None => log!("  {f}"),
+                }
+            }
+        }
+        total!("Duplicate groups: {}", groups.len());
+        term::event(
+            "summary",
+            json!({ "fingerprinted": new, "failed": failed, "stored": stored.len() }),
+        );
+        groups
+            .into_iter()
+            .map(|g| g.into_iter().map(|(f, _)| f.clone()).collect())
+            .collect()
+    }
+
+    // Files matching the first file of each group, with how similar they are
+    fn group<'a>(
+        entries: &[(&'a String, &Entry)],
+        threshold: f64,
+    ) -> Vec<Vec<(&'a String, Option<f64>)>> {
+        let mut grouped = vec![false; entries.len()];
+        let mut groups = Vec::new();
+        for i in 0..entries.len() {
+            if grouped[i] {
+                continue;
+            }
+            let (file, first) = entries[i];
+            let mut g = vec![(file, None)];
+            // Sorted by duration, so only the next few can be close enough
+            for j in i + 1..entries.len() {
+                let (other, e) = entries[j];
+                if e.duration - first.duration > MAX_DURATION_DIFF {
+                    break;
+                }
+                if grouped[j] {
+                    continue;
+                }
+                let s = similarity(&first.fingerprint, &e.fingerprint);
+                if s >= threshold {
+                    grouped[j] = true;
+                    g.push((other, Some(s)));
+                }
+            }
+            if g.len() > 1 {
+                groups.push(g);
+            }
+        }
+        groups
+    }
+
+    fn fingerprint(file_name: &str) -> Option<(f64, Vec<u32>)> {
+        let (samples, sample_rate) = decode_mono(file_name, MAX_SECONDS)?;
+        // Only part of the file is decoded, so ask lofty for the length
+        let duration = Probe::open(file_name)
+            .and_then(|p| p.read())
+            .map(|f| f.properties().duration().as_secs_f64())
+            .unwrap_or(samples.len() as f64 / sample_rate as f64);
+        let pcm: Vec<i16> = samples
+            .iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect();
+        let mut printer = Fingerprinter::new(&Configuration::preset_test2());
+        printer.start(sample_rate, 1).ok()?;
+        printer.consume(&pcm);
+        printer.finish();
+        let fingerprint = printer.fingerprint().to_vec();
+        (!fingerprint.is_empty()).then_some((duration, fingerprint))
+    }
+
+    // Best share of equal bits over the overlap of two fingerprints,
+    // trying small shifts either way
+    fn similarity(a: &[u32], b: &[u32]) -> f64 {
+        let mut best = 0.0;
+        for shift in 0..=MAX_SHIFT {
+            for (x, y) in [(a, b), (b, a)] {
+                let pairs: Vec<(&u32, &u32)> = x.iter().skip(shift).zip(y).collect();
+                if pairs.is_empty() {
+                    continue;
+                }
+                let differing: u32 = pairs.iter().map(|(p, q)| (*p ^ *q).count_ones()).sum();
+                let s = 1.0 - differing as f64 / (pairs.len() * 32) as f64;
+                if s > best {
+                    best = s;
+                }
+            }
+        }
+        best
+    }
+}
@@ -0,0 +1,133 @@
+// Human readable durations, sizes and numbers for everything tag_test
+// prints. The locale only picks the decimal and thousands separators.
+use serde_derive::Deserialize;
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FormatConfig {
+    // Language code like "en" or "de_DE.UTF-8". Empty = LC_ALL,
+    // LC_NUMERIC or LANG from the environment.
+    pub locale: String,
+    // KiB/MiB/GiB (1024) rather than kB/MB/GB (1000)
+    pub binary_units: bool,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            locale: String::new(),
+            binary_units: true,
+        }
+    }
+}
+
+struct Settings {
+    decimal: char,
+    thousands: char,
+    binary_units: bool,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+// Called once the config is loaded, anything printed before that uses
+// the locale from the environment
+pub fn init(fc: &FormatConfig) {
+    let _ = SETTINGS.set(settings_for(&fc.locale, fc.binary_units));
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| settings_for("", true))
+}
+
+fn settings_for(locale: &str, binary_units: bool) -> Settings {
+    let locale = match locale {
+        "" => ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|v| env::var(v).ok())
+            .find(|v| !v.is_empty())
+            .unwrap_or_default(),
+        l => l.to_string(),
+    };
+    let lang = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    let (decimal, thousands) = match lang.as_str() {
+        "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (',', '.'),
+        "fr" | "ru" | "sv" | "nb" | "no" | "fi" | "cs" | "pl" | "uk" | "hu" | "sk" => {
+            (',', '\u{202f}')
+        }
+        _ => ('.', ','),
+    };
+    Settings {
+        decimal,
+        thousands,
+        binary_units,
+    }
+}
+
+// 4:13 under an hour, 1h 02m from then on
+pub fn duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs < 3600 {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+pub fn size(bytes: u64) -> String {
+    let (base, units) = if settings().binary_units {
+        (1024.0, ["KiB", "MiB", "GiB", "TiB"])
+    } else {
+        (1000.0, ["kB", "MB", "GB", "TB"])
+    };
+    if (bytes as f64) < base {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / base;
+    let mut unit = 0;
+    while value >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    format!("{} {}", decimal(value, 1), units[unit])
+}
+
+pub fn decimal(value: f64, places: usize) -> String {
+    let s = format!("{value:.places$}");
+    let (int, frac) = match s.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (s.as_str(), None),
+    };
+    let mut out = group(int);
+    if let Some(f) = frac {
+        out.push(settings().decimal);
+        out.push_str(f);
+    }
+    out
+}
+
+pub fn count(n: u64) -> String {
+    group(&n.to_string())
+}
+
+// Thousands separators in the integer part of a formatted number
+fn group(int: &str) -> String {
+    let (sign, digits) = match int.strip_prefix('-') {
+        Some(d) => ("-", d),
+        None => ("", int),
+    };
+    let mut out = String::from(sign);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(settings().thousands);
+        }
+        out.push(c);
+    }
+    out
+}
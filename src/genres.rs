@@ -0,0 +1,179 @@
+// Genres in a hierarchy, "Rock/Progressive Rock", from a taxonomy file
+// with one genre's path per line. The genres report rolls the tracks up
+// into the top genres, and "tag_test genres" rewrites flat genres that
+// are in the taxonomy, "Progressive Rock", to their path.
+use crate::ask::Prompt;
+use crate::cli::{Command, DRY_RUN, INTERACTIVE};
+use crate::{music_files, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::process::exit;
+
+pub const COMMAND: Command = Command {
+    name: "genres",
+    usage: "[--dry-run] [--interactive] [path...]",
+    about: "Rewrite genres in the [genres] taxonomy to their path, e.g. Rock/Progressive Rock",
+    flags: &[DRY_RUN, INTERACTIVE],
+};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GenresConfig {
+    // One path per line, # for comments, empty = no hierarchy
+    pub taxonomy: String,
+    // Between a genre and the one below it
+    pub separator: String,
+}
+
+impl Default for GenresConfig {
+    fn default() -> Self {
+        GenresConfig {
+            taxonomy: String::new(),
+            separator: String::from("/"),
+        }
+    }
+}
+
+pub struct Taxonomy {
+    // Lower case genre, and lower case path -> its path
+    paths: HashMap<String, String>,
+}
+
+impl Taxonomy {
+    // None when there's no taxonomy file
+    pub fn load(gc: &GenresConfig) -> Result<Option<Taxonomy>, String> {
+        if gc.taxonomy.is_empty() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&gc.taxonomy).map_err(|e| e.to_string())?;
+        let mut paths = HashMap::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // Each genre above is in it too
+            let parts: Vec<&str> = line.split(gc.separator.as_str()).map(str::trim).collect();
+            for i in 1..=parts.len() {
+                let path = parts[..i].join(&gc.separator);
+                paths.insert(path.to_lowercase(), path.clone());
+                paths.entry(parts[i - 1].to_lowercase()).or_insert(path);
+            }
+        }
+        Ok(Some(Taxonomy { paths }))
+    }
+
+    // The genre's path, whether it's tagged as a path or flat
+    pub fn path(&self, genre: &str) -> Option<&str> {
+        self.paths
+            .get(&genre.trim().to_lowercase())
+            .map(String::as_str)
+    }
+}
+
+// "Rock" for "Rock/Progressive Rock"
+pub fn top<'a>(gc: &GenresConfig, genre: &'a str) -> &'a str {
+    genre
+        .split(gc.separator.as_str())
+        .next()
+        .unwrap_or(genre)
+        .trim()
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let mut prompt = Prompt::new(&mut args);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+    let taxonomy = match Taxonomy::load(&config.genres) {
+        Ok(Some(t)) => t,
+        Ok(None) => {
+            error!("This needs a taxonomy file, set taxonomy in [genres]");
+            exit(1);
+        }
+        Err(e) => {
+            error!("Error reading {}: {e}", config.genres.taxonomy);
+            exit(1);
+        }
+    };
+
+    let (mut changed, mut unknown, mut failed) = (0, 0, 0);
+    for file_name in music_files(config, &paths) {
+        match fix_file(&taxonomy, &file_name, &mut prompt, dry_run) {
+            Ok(Some(true)) => changed += 1,
+            Ok(Some(false)) => (),
+            Ok(None) => unknown += 1,
+            Err(e) => {
+                error!("Error fixing the genre in {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Not in the taxonomy: {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        unknown,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({
+            "dry_run": dry_run,
+            "changed": changed,
+            "unknown": unknown,
+            "failed": failed,
+        }),
+    );
+}
+
+// Some(changed), or None if the genre isn't in the taxonomy
+fn fix_file(
+    taxonomy: &Taxonomy,
+    file_name: &str,
+    prompt: &mut Prompt,
+    dry_run: bool,
+) -> Result<Option<bool>, String> {
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = match tagged_file.primary_tag() {
+        Some(t) => t.clone(),
+        None => return Ok(Some(false)),
+    };
+    let old = match tag.genre() {
+        Some(g) if !g.trim().is_empty() => g.to_string(),
+        _ => return Ok(Some(false)),
+    };
+    let new = match taxonomy.path(&old) {
+        Some(p) => p.to_string(),
+        None => {
+            warn!("{file_name}: {old:?} isn't in the taxonomy");
+            return Ok(None);
+        }
+    };
+    if new == old {
+        return Ok(Some(false));
+    }
+    log!("{file_name}: {old:?} -> {new:?}");
+    let new = match prompt.check(new) {
+        Some(g) => g,
+        None => return Ok(Some(false)),
+    };
+    if !dry_run {
+        tag.insert_text(ItemKey::Genre, new);
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(Some(true))
+}
@@ -0,0 +1,457 @@
+// Tag changes seen between scans, so it's possible to find out when a
+// file's tags changed and what they were before, and put them back. When a
+// changed file is read again, its tags are compared with the ones in the
+// cache, and each field that's different is added to the history file as
+// a JSON line. Needs the cache, which is where the old tags come from.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::dates;
+use crate::feed::rfc2822;
+use crate::{read_metadata, term, Config, TrackInfo};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, Tag};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const COMMAND: Command = Command {
+    name: "history",
+    usage: "<path>...",
+    about: "Show the tag changes scans have seen in files at or under the paths",
+    flags: &[],
+};
+
+pub const ROLLBACK: Command = Command {
+    name: "rollback",
+    usage: "--since <date> [--dry-run] [path...]",
+    about: "Put back the tags files had before the changes in the history since a date",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--since",
+            values: Some(&[]),
+            about: "Undo the changes seen on or after this date, YYYY-MM-DD",
+        },
+    ],
+};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub file: String,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: false,
+            file: String::from("history.jsonl"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Change {
+    pub path: String,
+    // When the scan saw it, seconds since the epoch
+    pub time: u64,
+    pub field: String,
+    // Empty when the field wasn't set
+    pub old: String,
+    pub new: String,
+}
+
+fn opt<T: ToString>(v: &Option<T>) -> String {
+    v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+// The tag fields, not the audio properties, which only change when the
+// file's re-encoded
+pub fn fields(t: &TrackInfo) -> [(&'static str, String); 24] {
+    [
+        ("title", t.title.clone()),
+        ("artist", t.artist.to_string()),
+        ("album", t.album.to_string()),
+        ("album_artist", opt(&t.album_artist)),
+        ("genre", t.genre.to_string()),
+        ("track", opt(&Some(t.track).filter(|n| *n > 0))),
+        ("track_total", opt(&t.track_total)),
+        ("disc", opt(&t.disc)),
+        ("disc_total", opt(&t.disc_total)),
+        ("date", opt(&t.date)),
+        ("rating", opt(&t.rating)),
+        ("play_count", opt(&t.play_count)),
+        ("bpm", opt(&t.bpm)),
+        ("key", opt(&t.key)),
+        ("composer", opt(&t.composer)),
+        ("conductor", opt(&t.conductor)),
+        ("work", opt(&t.work)),
+        ("movement", opt(&t.movement)),
+        ("movement_number", opt(&t.movement_number)),
+        ("movement_total", opt(&t.movement_total)),
+        ("language", opt(&t.language)),
+        ("lyrics_language", opt(&t.lyrics_language)),
+        ("artist_sort", opt(&t.artist_sort)),
+        ("album_sort", opt(&t.album_sort)),
+    ]
+}
+
+pub struct Recorder {
+    file: String,
+    out: Option<BufWriter<File>>,
+    time: u64,
+    changes: u32,
+    // The last change to each file's fields, (path, field) -> (old, new)
+    last: HashMap<(String, String), (String, String)>,
+    failed: bool,
+}
+
+impl Recorder {
+    // For a scan. None unless it's switched on. The file is only opened
+    // once there's a change.
+    pub fn new(config: &Config) -> Option<Recorder> {
+        if !config.history.enabled {
+            return None;
+        }
+        if config.cache.backend == "none" {
+            warn!("The history needs the cache, set backend in [cache]");
+            return None;
+        }
+        let mut recorder = Recorder::journal(config)?;
+        // Changes made with tag_test are already in the file by the time a
+        // scan sees them
+        match read(&recorder.file) {
+            Ok(changes) => {
+                recorder.last = changes
+                    .into_iter()
+                    .map(|c| ((c.path, c.field), (c.old, c.new)))
+                    .collect()
+            }
+            Err(e) => error!("Error reading {}: {e}", recorder.file),
+        }
+        Some(recorder)
+    }
+
+    // For tag_test's own changes to tags, which don't need the cache
+    pub fn journal(config: &Config) -> Option<Recorder> {
+        config.history.enabled.then(|| Recorder {
+            file: config.history.file.clone(),
+            out: None,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            changes: 0,
+            last: HashMap::new(),
+            failed: false,
+        })
+    }
+
+    pub fn record(&mut self, old: &TrackInfo, new: &TrackInfo) {
+        for ((field, old), (_, new_value)) in fields(old).into_iter().zip(fields(new)) {
+            if old != new_value {
+                self.change(&new.path, field, old, new_value);
+            }
+        }
+    }
+
+    pub fn change(&mut self, path: &str, field: &str, old: String, new: String) {
+        let key = (path.to_string(), field.to_string());
+        if self.failed
+            || self
+                .last
+                .get(&key)
+                .is_some_and(|(o, n)| *o == old && *n == new)
+        {
+            return;
+        }
+        let change = Change {
+            path: key.0,
+            time: self.time,
+            field: key.1,
+            old,
+            new,
+        };
+        if let Err(e) = self.write(&change) {
+            error!("Error writing {}: {e}", self.file);
+            self.failed = true;
+            return;
+        }
+        self.changes += 1;
+    }
+
+    fn write(&mut self, change: &Change) -> Result<(), String> {
+        if self.out.is_none() {
+            let f = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.file)
+                .map_err(|e| e.to_string())?;
+            self.out = Some(BufWriter::new(f));
+        }
+        let out = self.out.as_mut().ok_or("not open")?;
+        serde_json::to_writer(&mut *out, change).map_err(|e| e.to_string())?;
+        writeln!(out).map_err(|e| e.to_string())
+    }
+
+    pub fn finish(mut self) {
+        match self.out.as_mut().map(|o| o.flush()) {
+            Some(Err(e)) => error!("Error writing {}: {e}", self.file),
+            Some(Ok(_)) => total!("Tag changes: {}", self.changes),
+            None => (),
+        }
+    }
+}
+
+// Every change in the file, oldest first
+pub fn read(file: &str) -> Result<Vec<Change>, String> {
+    let f = match File::open(file) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    BufReader::new(f)
+        .lines()
+        .enumerate()
+        .map(|(n, line)| {
+            let line = line.map_err(|e| e.to_string())?;
+            serde_json::from_str(&line).map_err(|e| format!("line {}: {e}", n + 1))
+        })
+        .collect()
+}
+
+// The changes to the files at or under the paths
+pub fn run(config: &Config, args: &[String]) {
+    if args.is_empty() || args.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+    let hc = &config.history;
+    if !hc.enabled && fs::metadata(&hc.file).is_err() {
+        warn!("No history yet, switch it on in [history]");
+    }
+    let changes = match read(&hc.file) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error reading {}: {e}", hc.file);
+            exit(1);
+        }
+    };
+    let mut last: Option<(&str, u64)> = None;
+    let mut shown = 0;
+    for c in changes
+        .iter()
+        .filter(|c| args.iter().any(|a| Path::new(&c.path).starts_with(a)))
+    {
+        if last != Some((&c.path, c.time)) {
+            log!("{} {}", rfc2822(c.time), c.path);
+            last = Some((&c.path, c.time));
+        }
+        log!("  {}: {:?} -> {:?}", c.field, c.old, c.new);
+        term::event("change", json!(c));
+        shown += 1;
+    }
+    total!("Changes: {shown}");
+}
+
+// Where each field is written back to. Ratings and play counts are kept by
+// players, and the lyrics language is part of the lyrics, so those aren't
+// rolled back.
+pub fn item_key(field: &str) -> Option<ItemKey> {
+    Some(match field {
+        "title" => ItemKey::TrackTitle,
+        "artist" => ItemKey::TrackArtist,
+        "album" => ItemKey::AlbumTitle,
+        "album_artist" => ItemKey::AlbumArtist,
+        "genre" => ItemKey::Genre,
+        "track" => ItemKey::TrackNumber,
+        "track_total" => ItemKey::TrackTotal,
+        "disc" => ItemKey::DiscNumber,
+        "disc_total" => ItemKey::DiscTotal,
+        "date" => ItemKey::RecordingDate,
+        "bpm" => ItemKey::Bpm,
+        "key" => ItemKey::InitialKey,
+        "composer" => ItemKey::Composer,
+        "conductor" => ItemKey::Conductor,
+        "work" => ItemKey::Work,
+        "movement" => ItemKey::Movement,
+        "movement_number" => ItemKey::MovementNumber,
+        "movement_total" => ItemKey::MovementTotal,
+        "language" => ItemKey::Language,
+        "artist_sort" => ItemKey::TrackArtistSortOrder,
+        "album_sort" => ItemKey::AlbumTitleSortOrder,
+        _ => return None,
+    })
+}
+
+// Midnight UTC at the start of a YYYY, YYYY-MM or YYYY-MM-DD date, in
+// seconds since the epoch
+fn start_of(date: &str) -> Result<u64, String> {
+    let d = dates::parse(date)?;
+    let (y, m, d) = (
+        d.year as i64,
+        d.month.unwrap_or(1) as i64,
+        d.day.unwrap_or(1) as i64,
+    );
+    // Days from the civil date (Howard Hinnant's algorithm)
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u64::try_from(days * 86400).map_err(|_| String::from("before 1970"))
+}
+
+// Put back the tags files had before the changes seen since a date: each
+// field gets the value it had before the first change to it since then
+pub fn rollback(config: &Config, args: &[String]) {
+    let mut dry_run = false;
+    let mut since = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--since" => since = args.next().cloned(),
+            a if a.starts_with("--") => rollback_usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let since = match since.map(|s| start_of(&s).map_err(|e| (s, e))) {
+        Some(Ok(s)) => s,
+        Some(Err((s, e))) => {
+            error!("Error in --since {s}: {e}");
+            exit(1);
+        }
+        None => rollback_usage(),
+    };
+    let changes = match read(&config.history.file) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error reading {}: {e}", config.history.file);
+            exit(1);
+        }
+    };
+
+    // File -> field -> the value before the first change since
+    let mut files: BTreeMap<&str, BTreeMap<&str, &str>> = BTreeMap::new();
+    for c in changes.iter().filter(|c| c.time >= since) {
+        if !paths.is_empty() && !paths.iter().any(|p| Path::new(&c.path).starts_with(p)) {
+            continue;
+        }
+        files
+            .entry(&c.path)
+            .or_default()
+            .entry(&c.field)
+            .or_insert(&c.old);
+    }
+
+    let mut history = Recorder::journal(config).filter(|_| !dry_run);
+    let (mut changed, mut failed) = (0, 0);
+    for (file, fields) in &files {
+        match restore(file, fields, dry_run, history.as_mut()) {
+            Ok(_) => changed += 1,
+            Err(e) => {
+                error!("Error rolling back {file}: {e}");
+                term::event("error", json!({ "path": file, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run {
+            "Would roll back"
+        } else {
+            "Rolled back"
+        },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+    if let Some(h) = history {
+        h.finish();
+    }
+}
+
+fn rollback_usage() -> ! {
+    log!("{}", ROLLBACK.usage());
+    exit(1);
+}
+
+fn restore(
+    file_name: &str,
+    fields: &BTreeMap<&str, &str>,
+    dry_run: bool,
+    history: Option<&mut Recorder>,
+) -> Result<(), String> {
+    let current: BTreeMap<&str, String> = read_metadata(file_name)
+        .map(|t| self::fields(&t).into_iter().collect())
+        .map_err(|e| e.to_string())?;
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = tagged_file
+        .primary_tag()
+        .cloned()
+        .ok_or_else(|| String::from("no tag"))?;
+    log!("{file_name}:");
+    let mut written = Vec::new();
+    for (field, old) in fields {
+        let key = match item_key(field) {
+            Some(k) => k,
+            None => {
+                log!("  {field}: can't be rolled back");
+                continue;
+            }
+        };
+        match (set(&mut tag, key, old), old.is_empty()) {
+            (true, true) => log!("  {field}: removed"),
+            (true, false) => log!("  {field}: -> {old:?}"),
+            (false, _) => {
+                log!("  {field}: can't be written to this tag");
+                continue;
+            }
+        }
+        written.push((*field, *old));
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(h) = history {
+        for (field, old) in written {
+            let now = current.get(field).cloned().unwrap_or_default();
+            h.change(file_name, field, now, old.to_string());
+        }
+    }
+    Ok(())
+}
+
+// Write a field's value, or remove it when it's empty. False if the tag
+// can't hold it.
+pub fn set(tag: &mut Tag, key: ItemKey, value: &str) -> bool {
+    if value.is_empty() {
+        tag.remove_key(&key);
+        if key == ItemKey::Bpm {
+            tag.remove_key(&ItemKey::IntegerBpm);
+        }
+        return true;
+    }
+    // Some tags, e.g. ID3v2, only have whole BPMs
+    tag.insert_text(key.clone(), value.to_string())
+        || (key == ItemKey::Bpm
+            && value.parse::<f64>().is_ok_and(|b| {
+                tag.insert_text(ItemKey::IntegerBpm, (b.round() as u64).to_string())
+            }))
+}
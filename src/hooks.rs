@@ -0,0 +1,148 @@
+// Commands run at points in the scan, so other tools can be tied in. Each
+// is run with sh -c, with what happened in TAG_TEST_* environment
+// variables. A hook failing is only a warning, it never stops the scan.
+// The same events go to MQTT too, see mqtt.rs.
+use crate::albums::albums;
+use crate::mqtt;
+use crate::term::{self, Mode};
+use crate::{ScanStats, TrackInfo};
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    // Empty = no hook
+    pub scan_start: String,
+    pub scan_finish: String,
+    pub file_error: String,
+    pub new_album: String,
+    // Albums seen by earlier scans, so new_album only runs for new ones
+    pub albums_file: String,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            scan_start: String::new(),
+            scan_finish: String::new(),
+            file_error: String::new(),
+            new_album: String::new(),
+            albums_file: String::from("albums.json"),
+        }
+    }
+}
+
+pub fn scan_start(hc: &HooksConfig) {
+    run(&hc.scan_start, "scan_start", &[]);
+    mqtt::publish(&[("scan_start", json!({}))]);
+}
+
+pub fn scan_finish(hc: &HooksConfig, stats: &ScanStats, cancelled: bool) {
+    run(
+        &hc.scan_finish,
+        "scan_finish",
+        &[
+            ("VALID", stats.valid_files.to_string()),
+            ("ERRORS", stats.error_files.to_string()),
+            ("CANCELLED", cancelled.to_string()),
+        ],
+    );
+    mqtt::publish(&[(
+        "scan_finish",
+        json!({
+            "valid": stats.valid_files,
+            "errors": stats.error_files,
+            "cancelled": cancelled,
+        }),
+    )]);
+}
+
+pub fn file_error(hc: &HooksConfig, path: &str, message: &str) {
+    run(
+        &hc.file_error,
+        "file_error",
+        &[("PATH", path.to_string()), ("MESSAGE", message.to_string())],
+    );
+}
+
+// Run new_album for each album not in albums_file, and add them to it.
+// The first scan only fills the file, or every album would be new.
+pub fn new_albums(hc: &HooksConfig, tracks: &[TrackInfo]) {
+    if hc.new_album.is_empty() && !mqtt::enabled() {
+        return;
+    }
+    let first = !Path::new(&hc.albums_file).exists();
+    let mut known: BTreeSet<(String, String)> = fs::read_to_string(&hc.albums_file)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    let mut new = 0;
+    let mut messages = Vec::new();
+    for a in albums(tracks) {
+        if !known.insert((a.artist.to_string(), a.title.to_string())) || first {
+            continue;
+        }
+        new += 1;
+        let dir = Path::new(&a.tracks[0].path)
+            .parent()
+            .map(|d| d.to_string_lossy().to_string())
+            .unwrap_or_default();
+        run(
+            &hc.new_album,
+            "new_album",
+            &[
+                ("ARTIST", a.artist.to_string()),
+                ("ALBUM", a.title.to_string()),
+                ("PATH", dir.clone()),
+                ("TRACKS", a.tracks.len().to_string()),
+            ],
+        );
+        messages.push((
+            "new_album",
+            json!({
+                "artist": a.artist,
+                "album": a.title,
+                "path": dir,
+                "tracks": a.tracks.len(),
+            }),
+        ));
+    }
+    mqtt::publish(&messages);
+    if first {
+        log!("Recorded {} albums in {}", known.len(), hc.albums_file);
+    } else {
+        total!("New albums: {new}");
+    }
+    let res = serde_json::to_string(&known)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&hc.albums_file, json).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        error!("Error writing {}: {e}", hc.albums_file);
+    }
+}
+
+// vars are set as TAG_TEST_<name>, with TAG_TEST_EVENT the hook's name
+fn run(command: &str, event: &str, vars: &[(&str, String)]) {
+    if command.is_empty() {
+        return;
+    }
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command).env("TAG_TEST_EVENT", event);
+    for (name, value) in vars {
+        cmd.env(format!("TAG_TEST_{name}"), value);
+    }
+    // Keep stdout for the JSON events or tracks
+    if matches!(term::mode(), Mode::Json | Mode::Stream) {
+        cmd.stdout(std::io::stderr());
+    }
+    match cmd.status() {
+        Ok(s) if s.success() => (),
+        Ok(s) => warn!("The {event} hook failed ({s})"),
+        Err(e) => warn!("Error running the {event} hook: {e}"),
+    }
+}
@@ -0,0 +1,349 @@
+// Tags guessed from where a file is, for files with no tags or missing the
+// main ones: "Artist/Album/03 - Title.flac" matches the pattern
+// "{artist}/{album}/{track} - {title}". The [infer] patterns are tried in
+// turn against the end of the path, and the first that fits is used. With
+// enabled on, the guesses fill in the missing fields during the scan and
+// are listed in the track's inferred fields in the cache, and "tag_test
+// infer" writes them to the files.
+use crate::ask::Prompt;
+use crate::cli::{Command, DRY_RUN, INTERACTIVE};
+use crate::history::{self, Recorder};
+use crate::{music_files, term, Config, TrackInfo};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::Tag;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::process::exit;
+use std::sync::{Arc, OnceLock};
+
+pub const COMMAND: Command = Command {
+    name: "infer",
+    usage: "[--dry-run] [--interactive] [path...]",
+    about: "Write the tags guessed from the files' paths for the fields they don't have",
+    flags: &[DRY_RUN, INTERACTIVE],
+};
+
+// Placeholders, named as in templates. {_} matches anything and is
+// dropped, e.g. for a label directory.
+const FIELDS: &[&str] = &[
+    "artist",
+    "albumartist",
+    "album",
+    "title",
+    "track",
+    "disc",
+    "year",
+    "genre",
+    "_",
+];
+// Only match digits
+const NUMBERS: &[&str] = &["track", "disc", "year"];
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct InferConfig {
+    pub enabled: bool,
+    pub patterns: Vec<Pattern>,
+}
+
+impl Default for InferConfig {
+    fn default() -> Self {
+        InferConfig {
+            enabled: false,
+            patterns: [
+                "{artist}/{album}/{track} - {title}",
+                "{artist}/{album}/{track}. {title}",
+                "{artist}/{album}/{track} {title}",
+                "{artist}/{album}/{title}",
+            ]
+            .into_iter()
+            .filter_map(|p| Pattern::try_from(p.to_string()).ok())
+            .collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    parts: Vec<Part>,
+    // Path components it's matched against
+    depth: usize,
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = s.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(e) => start + e,
+                None => return Err(format!("unclosed {{ in pattern {s:?}")),
+            };
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let name = &rest[start + 1..end];
+            match FIELDS.iter().find(|f| **f == name) {
+                Some(f) => parts.push(Part::Field(f)),
+                None => return Err(format!("unknown placeholder {{{name}}} in pattern {s:?}")),
+            }
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        // Two placeholders together can't be told apart
+        if parts
+            .windows(2)
+            .any(|w| matches!(w, [Part::Field(_), Part::Field(_)]))
+        {
+            return Err(format!("placeholders need text between them in {s:?}"));
+        }
+        Ok(Pattern {
+            depth: s.matches('/').count() + 1,
+            parts,
+        })
+    }
+}
+
+impl Pattern {
+    // Field -> value, for the path without its extension
+    fn matches(&self, path: &Path) -> Option<Vec<(&'static str, String)>> {
+        let path = path.with_extension("");
+        let names: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+        let tail = names.get(names.len().checked_sub(self.depth)?..)?.join("/");
+        let mut found = Vec::new();
+        fit(&self.parts, &tail, &mut found).then_some(found)
+    }
+}
+
+// Placeholders take as little as they can, the last one the rest. Values
+// don't go over a /.
+fn fit(parts: &[Part], s: &str, found: &mut Vec<(&'static str, String)>) -> bool {
+    match parts.split_first() {
+        None => s.is_empty(),
+        Some((Part::Text(t), rest)) => s
+            .strip_prefix(t.as_str())
+            .is_some_and(|s| fit(rest, s, found)),
+        Some((Part::Field(f), rest)) => {
+            for end in s.char_indices().skip(1).map(|(i, _)| i).chain([s.len()]) {
+                let value = &s[..end];
+                if value.contains('/')
+                    || (NUMBERS.contains(f) && !value.bytes().all(|b| b.is_ascii_digit()))
+                {
+                    break;
+                }
+                found.push((f, value.trim().to_string()));
+                if fit(rest, &s[end..], found) {
+                    return true;
+                }
+                found.pop();
+            }
+            false
+        }
+    }
+}
+
+// From the first pattern that fits, without {_} and empty values
+fn guess(ic: &InferConfig, path: &Path) -> Vec<(&'static str, String)> {
+    ic.patterns
+        .iter()
+        .find_map(|p| p.matches(path))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(f, v)| *f != "_" && !v.is_empty())
+        .map(|(f, v)| match NUMBERS.contains(&f) {
+            // "03" is track 3
+            true => (f, v.parse::<u32>().map_or(v, |n| n.to_string())),
+            false => (f, v),
+        })
+        .collect()
+}
+
+static CONFIG: OnceLock<InferConfig> = OnceLock::new();
+
+// Called once the config is loaded
+pub fn init(ic: &InferConfig) {
+    let _ = CONFIG.set(ic.clone());
+}
+
+// Files without tags are only read when there's something to guess
+pub fn enabled() -> bool {
+    CONFIG.get().is_some_and(|ic| ic.enabled)
+}
+
+// Fills in the track's empty fields from its path, and lists them in
+// inferred
+pub fn fill(t: &mut TrackInfo) {
+    let ic = match CONFIG.get().filter(|ic| ic.enabled) {
+        Some(ic) => ic,
+        None => return,
+    };
+    let mut inferred = Vec::new();
+    for (field, value) in guess(ic, Path::new(&t.path)) {
+        let filled = match field {
+            "artist" if t.artist.is_empty() => {
+                t.artist = Arc::from(value);
+                true
+            }
+            "album" if t.album.is_empty() => {
+                t.album = Arc::from(value);
+                true
+            }
+            "genre" if t.genre.is_empty() => {
+                t.genre = Arc::from(value);
+                true
+            }
+            "title" if t.title.is_empty() => {
+                t.title = value;
+                true
+            }
+            "track" if t.track == 0 => {
+                t.track = value.parse().unwrap_or(0);
+                true
+            }
+            "disc" if t.disc.is_none() => {
+                t.disc = value.parse().ok();
+                true
+            }
+            "albumartist" if t.album_artist.is_none() => {
+                t.album_artist = Some(value);
+                true
+            }
+            "year" if t.date.is_none() => {
+                t.date = Some(value);
+                true
+            }
+            _ => false,
+        };
+        if filled {
+            inferred.push(history_name(field).to_string());
+        }
+    }
+    if !inferred.is_empty() {
+        t.inferred = Some(inferred);
+    }
+}
+
+// As history.rs names the fields
+fn history_name(field: &str) -> &str {
+    match field {
+        "albumartist" => "album_artist",
+        "year" => "date",
+        f => f,
+    }
+}
+
+// Write the guesses for the fields the files don't have. The patterns are
+// used whether or not enabled is on.
+pub fn run(config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let mut prompt = Prompt::new(&mut args);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+
+    let mut history = Recorder::journal(config).filter(|_| !dry_run);
+    let (mut changed, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        match fix_file(
+            &config.infer,
+            &file_name,
+            &mut prompt,
+            dry_run,
+            history.as_mut(),
+        ) {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error writing the guessed tags of {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+    if let Some(h) = history {
+        h.finish();
+    }
+}
+
+fn fix_file(
+    ic: &InferConfig,
+    file_name: &str,
+    prompt: &mut Prompt,
+    dry_run: bool,
+    history: Option<&mut Recorder>,
+) -> Result<bool, String> {
+    let guessed = guess(ic, Path::new(file_name));
+    if guessed.is_empty() {
+        return Ok(false);
+    }
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    // A file with no tags gets its usual kind
+    let mut tag = match tagged_file.primary_tag() {
+        Some(t) => t.clone(),
+        None => Tag::new(tagged_file.primary_tag_type()),
+    };
+
+    let (mut shown, mut written) = (false, Vec::new());
+    for (field, value) in guessed {
+        let key = match history::item_key(history_name(field)) {
+            Some(k) => k,
+            None => continue,
+        };
+        let old = tag.get_string(&key).unwrap_or_default().to_string();
+        if !old.trim().is_empty() {
+            continue;
+        }
+        if !shown {
+            log!("{file_name}:");
+            shown = true;
+        }
+        log!("  + {key:?}: {value:?}");
+        if let Some(value) = prompt.check(value) {
+            if history::set(&mut tag, key, &value) {
+                written.push((history_name(field), old, value));
+            }
+        }
+    }
+    if written.is_empty() {
+        return Ok(false);
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(h) = history {
+        for (field, old, new) in written {
+            h.change(file_name, field, old, new);
+        }
+    }
+    Ok(true)
+}
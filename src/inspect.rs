@@ -0,0 +1,231 @@
+// Dump everything lofty knows about a single file. With --json it's
+// also given as an inspect event.
+use crate::cli::{Command, Flag};
+use crate::{file_ext, format, rating, raw, read_native, term};
+use lofty::error::LoftyError;
+use lofty::picture::PictureInformation;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, ItemValue, Tag};
+use serde_json::{json, Value};
+use std::fs;
+use std::process::exit;
+
+pub const COMMAND: Command = Command {
+    name: "inspect",
+    usage: "[--raw] <file or url>",
+    about: "Show everything lofty knows about one file",
+    flags: &[Flag {
+        name: "--raw",
+        values: None,
+        about: "Also dump the raw tag regions, even if lofty can't read the file",
+    }],
+};
+
+pub fn run(args: &[String]) {
+    let raw = args.iter().any(|a| a == "--raw");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--raw").collect();
+    let file_name = match files[..] {
+        [f] => f,
+        _ => {
+            log!("{}", COMMAND.usage());
+            exit(1);
+        }
+    };
+
+    let res = inspect(file_name);
+    if let Err(e) = &res {
+        error!("Error in {file_name}: {e}");
+        term::event(
+            "error",
+            json!({ "path": file_name, "message": e.to_string() }),
+        );
+    }
+    // Still dump the raw regions when lofty can't read the file, that's
+    // when they are needed most
+    if raw {
+        match fs::read(file_name) {
+            Ok(data) => {
+                log!("");
+                log!("Raw tag regions:");
+                raw::dump(&data);
+            }
+            Err(e) => error!("Error reading {file_name}: {e}"),
+        }
+    }
+    if res.is_err() {
+        exit(1);
+    }
+}
+
+fn inspect(file_name: &str) -> Result<(), LoftyError> {
+    let tagged_file;
+    let native;
+    let (file_type, p, primary, tags): (_, _, _, Vec<&Tag>) = match read_native(file_name) {
+        Some(res) => {
+            native = res?;
+            let tag = native.0.as_ref();
+            (
+                file_ext(file_name).to_uppercase(),
+                &native.1,
+                tag,
+                tag.into_iter().collect(),
+            )
+        }
+        None => {
+            tagged_file = Probe::open(file_name)?.read()?;
+            (
+                format!("{:?}", tagged_file.file_type()),
+                tagged_file.properties(),
+                tagged_file.primary_tag(),
+                tagged_file.tags().iter().collect(),
+            )
+        }
+    };
+
+    log!("File: {file_name}");
+    log!("Type: {file_type}");
+    let size = fs::metadata(file_name).ok().map(|m| m.len());
+    if let Some(size) = size {
+        log!(
+            "Size: {} ({} bytes)",
+            format::size(size),
+            format::count(size)
+        );
+    }
+
+    log!("");
+    log!("Properties:");
+    log!("  {:<16} {}", "Duration", format::duration(p.duration()));
+    print_opt(
+        "Overall bitrate",
+        p.overall_bitrate().map(|b| format!("{b} kbps")),
+    );
+    print_opt(
+        "Audio bitrate",
+        p.audio_bitrate().map(|b| format!("{b} kbps")),
+    );
+    print_opt("Sample rate", p.sample_rate().map(|r| format!("{r} Hz")));
+    print_opt("Bit depth", p.bit_depth().map(|b| format!("{b} bits")));
+    print_opt("Channels", p.channels().map(|c| c.to_string()));
+    print_opt(
+        "Channel mask",
+        p.channel_mask().map(|m| format!("{:#x}", m.bits())),
+    );
+
+    let rating = primary.and_then(rating::rating);
+    let play_count = primary.and_then(rating::play_count);
+    if primary.is_some() {
+        print_opt("Rating", rating.map(|r| format!("{r}/100")));
+        print_opt("Play count", play_count.map(|c| c.to_string()));
+    }
+
+    if tags.is_empty() {
+        log!("");
+        warn!("No tags found");
+    }
+    let tags: Vec<Value> = tags.into_iter().map(print_tag).collect();
+    term::event(
+        "inspect",
+        json!({
+            "path": file_name,
+            "type": file_type,
+            "size": size,
+            "duration": p.duration().as_secs_f64(),
+            "overall_bitrate": p.overall_bitrate(),
+            "audio_bitrate": p.audio_bitrate(),
+            "sample_rate": p.sample_rate(),
+            "bit_depth": p.bit_depth(),
+            "channels": p.channels(),
+            "channel_mask": p.channel_mask().map(|m| m.bits()),
+            "rating": rating,
+            "play_count": play_count,
+            "tags": tags,
+        }),
+    );
+    Ok(())
+}
+
+fn print_opt(label: &str, value: Option<String>) {
+    log!(
+        "  {:<16} {}",
+        label,
+        value.unwrap_or_else(|| String::from("-"))
+    );
+}
+
+// Printed, and as JSON for the inspect event
+fn print_tag(tag: &Tag) -> Value {
+    log!("");
+    log!(
+        "Tag: {:?} ({} items, {} pictures)",
+        tag.tag_type(),
+        tag.item_count(),
+        tag.picture_count()
+    );
+    let mut items = Vec::new();
+    for item in tag.items() {
+        // Show the frame/field name as stored in the file next to lofty's name
+        let native = item.key().map_key(tag.tag_type(), true).unwrap_or("?");
+        let name = match item.key() {
+            ItemKey::Unknown(k) => k.clone(),
+            k => format!("{k:?}"),
+        };
+        let (value, json_value) = match item.value() {
+            ItemValue::Text(t) => (format!("{t:?}"), json!(t)),
+            ItemValue::Locator(l) => (format!("<{l}>"), json!(l)),
+            ItemValue::Binary(b) => (
+                format!("{} of binary data", format::size(b.len() as u64)),
+                json!({ "binary": b.len() }),
+            ),
+        };
+        let mut extra = String::new();
+        let mut item_json = json!({ "native": native, "key": name, "value": json_value });
+        if !item.description().is_empty() {
+            extra.push_str(&format!(" desc={:?}", item.description()));
+            item_json["description"] = json!(item.description());
+        }
+        if item.lang() != b"XXX" && item.lang() != b"\0\0\0" {
+            let lang = String::from_utf8_lossy(item.lang());
+            extra.push_str(&format!(" lang={lang}"));
+            item_json["lang"] = json!(lang);
+        }
+        log!("  {native:<24} {name:<24} {value}{extra}");
+        items.push(item_json);
+    }
+    let mut pictures = Vec::new();
+    for (i, pic) in tag.pictures().iter().enumerate() {
+        let mime = pic.mime_type().map(|m| m.as_str()).unwrap_or("unknown");
+        let info = PictureInformation::from_picture(pic)
+            .ok()
+            .filter(|info| info.width > 0);
+        let dims = match &info {
+            Some(info) => format!(" {}x{}", info.width, info.height),
+            None => String::new(),
+        };
+        log!(
+            "  Picture {}: {:?} {} {}{}{}",
+            i + 1,
+            pic.pic_type(),
+            mime,
+            format::size(pic.data().len() as u64),
+            dims,
+            pic.description()
+                .map(|d| format!(" desc={d:?}"))
+                .unwrap_or_default()
+        );
+        pictures.push(json!({
+            "type": format!("{:?}", pic.pic_type()),
+            "mime": mime,
+            "size": pic.data().len(),
+            "width": info.as_ref().map(|i| i.width),
+            "height": info.as_ref().map(|i| i.height),
+            "description": pic.description(),
+        }));
+    }
+    json!({
+        "type": format!("{:?}", tag.tag_type()),
+        "items": items,
+        "pictures": pictures,
+    })
+}
@@ -0,0 +1,29 @@
+// Artist, album and genre names repeat on every track of an album, so the
+// scan keeps one copy of each and the tracks share it
+use crate::TrackInfo;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        match self.strings.get(s) {
+            Some(shared) => shared.clone(),
+            None => {
+                let shared: Arc<str> = Arc::from(s);
+                self.strings.insert(shared.clone());
+                shared
+            }
+        }
+    }
+
+    pub fn track(&mut self, t: &mut TrackInfo) {
+        t.artist = self.intern(&t.artist);
+        t.album = self.intern(&t.album);
+        t.genre = self.intern(&t.genre);
+    }
+}
@@ -0,0 +1,44 @@
+// Files other systems leave next to the music: Finder's .DS_Store and ._
+// AppleDouble files, Windows' Thumbs.db and desktop.ini. They get their
+// own count rather than filling up the other files and types.
+use crate::trash;
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct JunkConfig {
+    // count, ignore or delete
+    pub action: String,
+    // Delete for good rather than to the trash
+    pub permanent: bool,
+}
+
+impl Default for JunkConfig {
+    fn default() -> Self {
+        JunkConfig {
+            action: String::from("count"),
+            permanent: false,
+        }
+    }
+}
+
+const NAMES: &[&str] = &[".ds_store", "thumbs.db", "ehthumbs.db", "desktop.ini"];
+
+pub fn is_junk(name: &str) -> bool {
+    name.starts_with("._") || NAMES.contains(&name.to_ascii_lowercase().as_str())
+}
+
+// The file a ._ AppleDouble file holds the Mac resource fork and Finder
+// info of, whether it's still there or not
+pub fn apple_double_of(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?.strip_prefix("._")?;
+    Some(path.with_file_name(name))
+}
+
+pub fn delete(path: &Path, permanent: bool) {
+    match trash::remove(path, permanent) {
+        Ok(_) => log!("Deleted {}", path.display()),
+        Err(e) => error!("Error deleting {}: {e}", path.display()),
+    }
+}
@@ -0,0 +1,67 @@
+// Check album directories are where the [layout] pattern says they should
+// be, going by their tracks' tags, e.g. {albumartist}/{year} - {album}
+// under the scan directory. Disc subdirectories (CD1, Disc 2) are taken as
+// part of the album directory above them.
+use crate::albums::split_disc;
+use crate::template::Template;
+use crate::{Config, TrackInfo};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct LayoutConfig {
+    // Album directory under the scan directory
+    pub pattern: Option<Template>,
+}
+
+// Directory -> where it should be, for the ones that aren't there
+pub fn misplaced(config: &Config, tracks: &[TrackInfo]) -> Option<BTreeMap<PathBuf, PathBuf>> {
+    let pattern = config.layout.pattern.as_ref()?;
+    let mut dirs: BTreeMap<PathBuf, Vec<&TrackInfo>> = BTreeMap::new();
+    for t in tracks {
+        if let Some(dir) = Path::new(&t.path).parent() {
+            dirs.entry(album_dir(dir).to_path_buf())
+                .or_default()
+                .push(t);
+        }
+    }
+
+    let mut misplaced = BTreeMap::new();
+    for (dir, tracks) in dirs {
+        let root = match config.root_for(&dir.to_string_lossy()) {
+            Some(r) => Path::new(&r.path),
+            None => continue,
+        };
+        // The first track with a date, for {year}, without the disc in
+        // the album name
+        let t = tracks
+            .iter()
+            .find(|t| t.date.is_some())
+            .unwrap_or(&tracks[0]);
+        let mut t = (*t).clone();
+        t.album = split_disc(&t.album).0.into();
+        let expected = root.join(pattern.render_path(&t));
+        if expected != dir {
+            misplaced.insert(dir, expected);
+        }
+    }
+    Some(misplaced)
+}
+
+// The directory itself, or the one above for a CD1, Disc 2 and so on
+pub fn album_dir(dir: &Path) -> &Path {
+    let name = dir
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let word = name
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .trim_end();
+    match (dir.parent(), word.len() < name.len()) {
+        (Some(parent), true) if ["cd", "disc", "disk"].contains(&word) => parent,
+        _ => dir,
+    }
+}
@@ -0,0 +1,82 @@
+// Hard links: one file under more than one name, as torrent seeding setups
+// use to have the download and the library copy without the space twice.
+// Sizes count a linked file once, and the links report lists them.
+// Reflinked copies share their data too, but can only be told from real
+// copies by reading their extents, so they're still counted in full.
+use crate::{format, term, ScanStats, TrackInfo};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Metadata;
+
+// Device and inode, for a file with more than one link
+#[cfg(unix)]
+pub fn id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// The filesystem a file is on, as only files on the same one can be linked
+#[cfg(unix)]
+pub fn device(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.dev()
+}
+
+#[cfg(not(unix))]
+pub fn device(_meta: &Metadata) -> u64 {
+    0
+}
+
+// Bytes the tracks take on disk, with each linked file counted once
+pub fn size<'a>(tracks: impl IntoIterator<Item = &'a TrackInfo>) -> u64 {
+    let mut seen = HashSet::new();
+    tracks
+        .into_iter()
+        .filter(|t| t.inode.is_none_or(|i| seen.insert(i)))
+        .map(|t| t.size)
+        .sum()
+}
+
+pub fn report(stats: &ScanStats) {
+    let mut groups: BTreeMap<(u64, u64), Vec<&TrackInfo>> = BTreeMap::new();
+    for t in &stats.tracks {
+        if let Some(i) = t.inode {
+            groups.entry(i).or_default().push(t);
+        }
+    }
+    // Files linked to from outside the scan only show up once here
+    groups.retain(|_, g| g.len() > 1);
+    let mut groups: Vec<Vec<&TrackInfo>> = groups.into_values().collect();
+    for g in &mut groups {
+        g.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    groups.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+
+    let saved: u64 = groups
+        .iter()
+        .map(|g| g[0].size * (g.len() as u64 - 1))
+        .sum();
+    total!(
+        "Hard linked files: {}, Space saved: {}",
+        groups.len(),
+        format::size(saved)
+    );
+    for g in &groups {
+        log!("  {} ({})", g[0].path, format::size(g[0].size));
+        for t in &g[1..] {
+            log!("    = {}", t.path);
+        }
+        term::event(
+            "links",
+            json!({
+                "paths": g.iter().map(|t| &t.path).collect::<Vec<_>>(),
+                "size": g[0].size,
+            }),
+        );
+    }
+}
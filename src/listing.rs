@@ -0,0 +1,136 @@
+// Directory listings read in several threads at once, for the estimate.
+// On a big library on spinning disks or a network share the walk spends
+// most of its time waiting on each directory in turn, and the estimate
+// only needs the names. Only the listings are read, not each file's
+// metadata, unless a symlink has to be followed.
+use crate::cancel;
+use crate::scope::Scope;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+pub struct Listing {
+    pub dir: PathBuf,
+    // Names of the files in it
+    pub files: Vec<String>,
+}
+
+struct State {
+    queue: Vec<PathBuf>,
+    // Directories being read
+    busy: usize,
+    listings: Vec<Listing>,
+    // Where the directories reached through symlinks really are, so a
+    // link back up the tree isn't followed forever
+    seen: HashSet<PathBuf>,
+}
+
+// Every directory under root, root first and the rest in name order. Not
+// recursive = only root.
+pub fn list(
+    root: &Path,
+    threads: usize,
+    follow_links: bool,
+    recursive: bool,
+    scope: Option<&Scope>,
+) -> Vec<Listing> {
+    let state = Mutex::new(State {
+        queue: vec![root.to_path_buf()],
+        busy: 0,
+        listings: Vec::new(),
+        seen: HashSet::new(),
+    });
+    let wake = Condvar::new();
+    thread::scope(|s| {
+        for _ in 0..threads.max(1) {
+            s.spawn(|| work(&state, &wake, follow_links, recursive, scope));
+        }
+    });
+    let mut listings = state.into_inner().map(|s| s.listings).unwrap_or_default();
+    listings.sort_by(|a, b| a.dir.cmp(&b.dir));
+    listings
+}
+
+fn work(
+    state: &Mutex<State>,
+    wake: &Condvar,
+    follow_links: bool,
+    recursive: bool,
+    scope: Option<&Scope>,
+) {
+    loop {
+        let dir = {
+            let mut st = match state.lock() {
+                Ok(st) => st,
+                Err(_) => return,
+            };
+            loop {
+                if cancel::cancelled() {
+                    st.queue.clear();
+                }
+                if let Some(dir) = st.queue.pop() {
+                    st.busy += 1;
+                    break dir;
+                }
+                if st.busy == 0 {
+                    wake.notify_all();
+                    return;
+                }
+                st = match wake.wait(st) {
+                    Ok(st) => st,
+                    Err(_) => return,
+                };
+            }
+        };
+        let (subdirs, files) = read(&dir, follow_links, scope);
+        if let Ok(mut st) = state.lock() {
+            for sub in subdirs.into_iter().filter(|_| recursive) {
+                let linked = fs::symlink_metadata(&sub).is_ok_and(|m| m.file_type().is_symlink());
+                if linked && !fs::canonicalize(&sub).is_ok_and(|real| st.seen.insert(real)) {
+                    continue;
+                }
+                st.queue.push(sub);
+            }
+            st.listings.push(Listing { dir, files });
+            st.busy -= 1;
+        }
+        wake.notify_all();
+    }
+}
+
+// The subdirectories and file names in dir, going by the file types in
+// the listing
+fn read(dir: &Path, follow_links: bool, scope: Option<&Scope>) -> (Vec<PathBuf>, Vec<String>) {
+    let (mut subdirs, mut files) = (Vec::new(), Vec::new());
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return (subdirs, files),
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let mut file_type = match entry.file_type() {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        if file_type.is_symlink() {
+            if !follow_links {
+                continue;
+            }
+            file_type = match fs::metadata(entry.path()) {
+                Ok(m) => m.file_type(),
+                Err(_) => continue,
+            };
+        }
+        let path = entry.path();
+        if file_type.is_dir() {
+            if scope.is_none_or(|s| s.may_contain(&path)) {
+                subdirs.push(path);
+            }
+        } else if file_type.is_file() && scope.is_none_or(|s| s.includes(&path)) {
+            files.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    files.sort();
+    (subdirs, files)
+}
@@ -0,0 +1,291 @@
+// m3u playlists kept in the library, checked for entries whose files are
+// gone, files in twice, and absolute and relative paths mixed. After the
+// library's been reorganized, "tag_test m3u" points the dead entries at
+// the file with the same name, wherever it's moved to.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::{file_ext, music_files, term, Config};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::exit;
+use walkdir::WalkDir;
+
+pub const COMMAND: Command = Command {
+    name: "m3u",
+    usage: "[--dry-run] [--absolute | --relative] [playlist...]",
+    about: "Point dead m3u playlist entries at where their files have moved to",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--absolute",
+            values: None,
+            about: "Rewrite every entry as an absolute path",
+        },
+        Flag {
+            name: "--relative",
+            values: None,
+            about: "Rewrite every entry relative to the playlist",
+        },
+    ],
+};
+
+pub const EXTS: &[&str] = &["m3u", "m3u8"];
+
+pub struct Playlist {
+    pub file: PathBuf,
+    // As in the file, comments and all
+    pub lines: Vec<String>,
+}
+
+impl Playlist {
+    pub fn read(file: &Path) -> Result<Playlist, String> {
+        let contents = fs::read(file).map_err(|e| e.to_string())?;
+        let contents = String::from_utf8_lossy(&contents);
+        Ok(Playlist {
+            file: file.to_path_buf(),
+            lines: contents
+                .trim_start_matches('\u{feff}')
+                .lines()
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    // Index of each entry's line and the file it's for, relative ones
+    // going by the playlist's directory. URLs are left out.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, PathBuf)> + '_ {
+        let dir = self.file.parent().unwrap_or(Path::new(""));
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (i, l.trim()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#') && !l.contains("://"))
+            .map(move |(i, l)| (i, dir.join(l.replace('\\', "/"))))
+    }
+}
+
+#[derive(Default)]
+pub struct Problems {
+    pub dead: Vec<String>,
+    pub twice: Vec<String>,
+    pub absolute: usize,
+    pub relative: usize,
+}
+
+impl Problems {
+    pub fn any(&self) -> bool {
+        !self.dead.is_empty() || !self.twice.is_empty() || (self.absolute > 0 && self.relative > 0)
+    }
+}
+
+pub fn check(pl: &Playlist) -> Problems {
+    let mut problems = Problems::default();
+    let mut seen = HashSet::new();
+    for (i, path) in pl.entries() {
+        let line = pl.lines[i].trim().to_string();
+        if Path::new(&line).is_absolute() {
+            problems.absolute += 1;
+        } else {
+            problems.relative += 1;
+        }
+        if !path.is_file() {
+            problems.dead.push(line);
+        } else if !seen.insert(fs::canonicalize(&path).unwrap_or(path)) {
+            problems.twice.push(line);
+        }
+    }
+    problems
+}
+
+// The playlists under the scan directories, or the given paths
+pub fn find(config: &Config, paths: &[String]) -> Vec<PathBuf> {
+    let roots: Vec<&str> = if paths.is_empty() {
+        config
+            .directories
+            .scan
+            .iter()
+            .map(|r| r.path.as_str())
+            .collect()
+    } else {
+        paths.iter().map(String::as_str).collect()
+    };
+    roots
+        .iter()
+        .flat_map(|root| {
+            WalkDir::new(root)
+                .sort_by_file_name()
+                .follow_links(true)
+                .into_iter()
+                .filter_map(|e| e.ok())
+        })
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| EXTS.contains(&file_ext(&p.to_string_lossy()).as_str()))
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    // As each entry already is
+    Keep,
+    Absolute,
+    Relative,
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut dry_run = false;
+    let mut style = Style::Keep;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--absolute" => style = Style::Absolute,
+            "--relative" => style = Style::Relative,
+            a if a.starts_with("--") => {
+                log!("{}", COMMAND.usage());
+                exit(1);
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    // File name -> where it is in the library
+    let mut library: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+    for f in music_files(config, &[]) {
+        let path = PathBuf::from(f);
+        if let Some(name) = path.file_name() {
+            library.entry(name.to_os_string()).or_default().push(path);
+        }
+    }
+
+    let (mut changed, mut dead, mut failed) = (0, 0, 0);
+    for file in find(config, &paths) {
+        let mut pl = match Playlist::read(&file) {
+            Ok(pl) => pl,
+            Err(e) => {
+                error!("Error reading {}: {e}", file.display());
+                failed += 1;
+                continue;
+            }
+        };
+        let (fixed, left) = fix(&mut pl, &library, style);
+        dead += left;
+        if fixed == 0 {
+            continue;
+        }
+        if dry_run {
+            changed += 1;
+            continue;
+        }
+        let mut contents = pl.lines.join("\n");
+        contents.push('\n');
+        match fs::write(&file, contents) {
+            Ok(_) => changed += 1,
+            Err(e) => {
+                error!("Error writing {}: {e}", file.display());
+                term::event(
+                    "error",
+                    json!({ "path": file.to_string_lossy(), "message": e.to_string() }),
+                );
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Dead entries left: {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        dead,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "dead": dead, "failed": failed }),
+    );
+}
+
+// Point dead entries at the library file with the same name, and with a
+// style, rewrite the rest to it. Returns the entries changed and the dead
+// ones left.
+fn fix(pl: &mut Playlist, library: &HashMap<OsString, Vec<PathBuf>>, style: Style) -> (u32, u32) {
+    let dir = pl.file.parent().unwrap_or(Path::new("")).to_path_buf();
+    let entries: Vec<(usize, PathBuf)> = pl.entries().collect();
+    let (mut changed, mut dead) = (0, 0);
+    for (i, path) in entries {
+        let line = pl.lines[i].trim().to_string();
+        let target = if path.is_file() {
+            if style == Style::Keep {
+                continue;
+            }
+            path
+        } else {
+            match moved_to(&path, library) {
+                Some(p) => p.clone(),
+                None => {
+                    warn!("{}: {line} not found", pl.file.display());
+                    dead += 1;
+                    continue;
+                }
+            }
+        };
+        let absolute = match style {
+            Style::Keep => Path::new(&line).is_absolute(),
+            s => s == Style::Absolute,
+        };
+        let new = if absolute {
+            fs::canonicalize(&target).unwrap_or(target)
+        } else {
+            relative(&dir, &target)
+        };
+        let new = new.to_string_lossy().to_string();
+        if new != line {
+            log!("{}: {line} -> {new}", pl.file.display());
+            pl.lines[i] = new;
+            changed += 1;
+        }
+    }
+    (changed, dead)
+}
+
+// The library file with the dead entry's name. When there's more than one,
+// the one in a directory of the same name, if that's only one.
+fn moved_to<'a>(path: &Path, library: &'a HashMap<OsString, Vec<PathBuf>>) -> Option<&'a PathBuf> {
+    let found = library.get(path.file_name()?)?;
+    if let [one] = &found[..] {
+        return Some(one);
+    }
+    let dir_name = |p: &Path| {
+        p.parent()
+            .and_then(|d| d.file_name())
+            .map(|n| n.to_os_string())
+    };
+    let same_dir: Vec<_> = found
+        .iter()
+        .filter(|f| dir_name(f) == dir_name(path))
+        .collect();
+    match same_dir[..] {
+        [one] => Some(one),
+        _ => None,
+    }
+}
+
+// to as a path from dir, going by where they really are
+fn relative(dir: &Path, to: &Path) -> PathBuf {
+    let (dir, to) = match (fs::canonicalize(dir), fs::canonicalize(to)) {
+        (Ok(d), Ok(t)) => (d, t),
+        _ => return to.to_path_buf(),
+    };
+    let (d, t): (Vec<Component>, Vec<Component>) =
+        (dir.components().collect(), to.components().collect());
+    let common = d.iter().zip(&t).take_while(|(a, b)| a == b).count();
+    let mut rel = PathBuf::new();
+    for _ in common..d.len() {
+        rel.push("..");
+    }
+    for c in &t[common..] {
+        rel.push(c);
+    }
+    rel
+}
@@ -1,22 +1,183 @@
+use albums::AlbumInfo;
+use analysis::AnalysisConfig;
+use artists::ArtistInfo;
+use cache::CacheConfig;
+use dates::DatesConfig;
+use enrich::EnrichConfig;
+use estimate::EstimateConfig;
+use export::ExportConfig;
+use featured::FeaturedConfig;
+use feed::FeedConfig;
+use fields::{FieldsConfig, FiltersConfig};
+use fingerprint::FingerprintConfig;
+use format::FormatConfig;
+use genres::GenresConfig;
+use history::HistoryConfig;
+use hooks::HooksConfig;
+use infer::InferConfig;
 use itertools::Itertools;
+use junk::JunkConfig;
+use layout::LayoutConfig;
 use lofty::error::{ErrorKind, LoftyError};
 use lofty::prelude::*;
 use lofty::probe::Probe;
-use serde_derive::Deserialize;
+use lofty::properties::FileProperties;
+use lofty::tag::{Tag, TagType};
+use mpd::MpdConfig;
+use mqtt::MqttConfig;
+use overrides::Overrides;
+use placeholders::PlaceholdersConfig;
+use playlists::PlaylistsConfig;
+use quarantine::QuarantineConfig;
+use reports::ReportsConfig;
+use sandbox::SandboxConfig;
+use scan::{scan_dirs, PipelineConfig};
+use search::SearchConfig;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use sortnames::SortNamesConfig;
+use spellings::SpellingsConfig;
 use std::collections::HashMap;
 use std::fs;
+use std::io::{self, BufRead};
 use std::process::exit;
-use std::time::Duration;
-use toml;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use sync::SyncConfig;
+use template::TemplatesConfig;
+use term::TerminalConfig;
+use throttle::ThrottleConfig;
+use transcode::TranscodeConfig;
 use walkdir::WalkDir;
+use xattrs::{Xattrs, XattrsConfig};
 
+// First, so the output macros can be used everywhere
+#[macro_use]
+mod term;
+
+mod albums;
+mod analysis;
+mod anonymize;
+mod artists;
+mod ask;
+mod cache;
+mod cancel;
+mod chunks;
+mod cli;
+mod completions;
+mod dates;
+mod dedupe;
+mod dsd;
+mod edit;
+mod enrich;
+mod estimate;
+mod export;
+mod featured;
+mod feed;
+mod fields;
+mod fingerprint;
+mod format;
+mod genres;
+mod history;
+mod hooks;
+mod infer;
+mod inspect;
+mod intern;
+mod junk;
+mod layout;
+mod links;
+mod listing;
+mod m3u;
+mod manifest;
+mod migrate;
+mod mpd;
+mod mqtt;
+mod offline;
+mod ogg;
+mod orphans;
+mod overrides;
+mod placeholders;
+mod playlists;
+mod quarantine;
+mod rating;
+mod raw;
+mod remote;
+mod repair;
+mod reports;
+mod resolve;
+mod rips;
+mod sandbox;
+mod scan;
+mod scope;
+mod scripts;
+mod search;
+mod snapshot;
+mod sortnames;
+mod spellings;
+mod stream;
+mod strip;
+mod sync;
+mod systemd;
+mod template;
+mod throttle;
+mod tracker;
+mod transcode;
+mod trash;
+mod works;
+mod xattrs;
+
+#[derive(Clone, Serialize, Deserialize)]
 struct TrackInfo {
+    path: String,
     title: String,
-    artist: String,
-    album: String,
-    genre: String,
+    // Shared between tracks, see intern.rs
+    artist: Arc<str>,
+    album: Arc<str>,
+    genre: Arc<str>,
     track: u32,
+    track_total: Option<u32>,
+    disc: Option<u32>,
+    disc_total: Option<u32>,
     duration: Duration,
+    // Audio bitrate in kbps
+    bitrate: Option<u32>,
+    // Hz
+    sample_rate: Option<u32>,
+    // Modification time of the file, seconds since the epoch
+    modified: u64,
+    // File size in bytes
+    size: u64,
+    // Device and inode when the file has other hard links, see links.rs
+    inode: Option<(u64, u64)>,
+    // 0-100, see rating.rs
+    rating: Option<u8>,
+    play_count: Option<u64>,
+    bpm: Option<f64>,
+    key: Option<String>,
+    // Only set by BPM analysis, when the file has no BPM tag
+    estimated_bpm: Option<f64>,
+    composer: Option<String>,
+    conductor: Option<String>,
+    work: Option<String>,
+    movement: Option<String>,
+    movement_number: Option<u32>,
+    movement_total: Option<u32>,
+    // ISO 639-2 codes, e.g. "jpn"
+    language: Option<String>,
+    lyrics_language: Option<String>,
+    artist_sort: Option<String>,
+    album_sort: Option<String>,
+    album_artist: Option<String>,
+    // As tagged, see dates.rs
+    date: Option<String>,
+    // Embedded pictures, cover art and the like
+    pictures: Option<u32>,
+    // Streams in a chained Ogg file, see ogg.rs
+    chains: Option<u32>,
+    // From the file manager, see xattrs.rs
+    xattrs: Option<Xattrs>,
+    // Fields guessed from the path rather than tagged, see infer.rs
+    inferred: Option<Vec<String>>,
 }
 
 #[derive(Deserialize)]
@@ -24,6 +185,88 @@ struct Config {
     general: General,
     types: Types,
     directories: Directories,
+    #[serde(default)]
+    sandbox: SandboxConfig,
+    #[serde(default)]
+    reports: ReportsConfig,
+    #[serde(default)]
+    analysis: AnalysisConfig,
+    #[serde(default)]
+    playlists: PlaylistsConfig,
+    #[serde(default)]
+    feed: FeedConfig,
+    #[serde(default)]
+    mpd: MpdConfig,
+    #[serde(default)]
+    enrichment: EnrichConfig,
+    #[serde(default)]
+    fingerprint: FingerprintConfig,
+    #[serde(default)]
+    templates: TemplatesConfig,
+    #[serde(default)]
+    format: FormatConfig,
+    #[serde(default)]
+    terminal: TerminalConfig,
+    #[serde(default)]
+    throttle: ThrottleConfig,
+    #[serde(default)]
+    export: ExportConfig,
+    #[serde(default)]
+    estimate: EstimateConfig,
+    #[serde(default)]
+    junk: JunkConfig,
+    #[serde(default)]
+    transcode: TranscodeConfig,
+    #[serde(default)]
+    sync: SyncConfig,
+    #[serde(default)]
+    quarantine: QuarantineConfig,
+    #[serde(default)]
+    pipeline: PipelineConfig,
+    #[serde(default)]
+    cache: CacheConfig,
+    #[serde(default)]
+    sort_names: SortNamesConfig,
+    #[serde(default)]
+    featured: FeaturedConfig,
+    #[serde(default)]
+    dates: DatesConfig,
+    #[serde(default)]
+    placeholders: PlaceholdersConfig,
+    #[serde(default)]
+    fields: FieldsConfig,
+    #[serde(default)]
+    filters: FiltersConfig,
+    #[serde(default)]
+    hooks: HooksConfig,
+    #[serde(default)]
+    mqtt: MqttConfig,
+    #[serde(default)]
+    spellings: SpellingsConfig,
+    #[serde(default)]
+    layout: LayoutConfig,
+    #[serde(default)]
+    genres: GenresConfig,
+    #[serde(default)]
+    history: HistoryConfig,
+    #[serde(default)]
+    search: SearchConfig,
+    #[serde(default)]
+    xattrs: XattrsConfig,
+    #[serde(default)]
+    infer: InferConfig,
+    // From --scope, not the config file
+    #[serde(skip)]
+    scope: Option<scope::Scope>,
+    // From --stdin, scanned instead of the directories
+    #[serde(skip)]
+    files: Option<Vec<String>>,
+    // From --resume, when there's a point to resume from
+    #[serde(skip)]
+    resumed: bool,
+    // From --no-recurse
+    #[serde(skip)]
+    shallow: bool,
 }
 
 #[derive(Deserialize)]
@@ -34,152 +277,621 @@ struct General {
 
 #[derive(Deserialize)]
 struct Directories {
-    scan: Vec<String>,
+    scan: Vec<ScanRoot>,
+}
+
+// A directory to scan, either just the path or a table with the types
+// that are valid under it, a label to report it under, and whether to go
+// into its subdirectories
+#[derive(Deserialize)]
+#[serde(from = "RootEntry")]
+struct ScanRoot {
+    path: String,
+    types: Option<Vec<String>>,
+    label: Option<String>,
+    recursive: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RootEntry {
+    Path(String),
+    Table {
+        path: String,
+        types: Option<Vec<String>>,
+        label: Option<String>,
+        recursive: Option<bool>,
+    },
+}
+
+impl From<RootEntry> for ScanRoot {
+    fn from(e: RootEntry) -> Self {
+        match e {
+            RootEntry::Path(path) => ScanRoot {
+                path,
+                types: None,
+                label: None,
+                recursive: true,
+            },
+            RootEntry::Table {
+                path,
+                types,
+                label,
+                recursive,
+            } => ScanRoot {
+                path,
+                types,
+                label,
+                recursive: recursive.unwrap_or(true),
+            },
+        }
+    }
+}
+
+impl Config {
+    // True if the scan only covers part of the library
+    fn partial(&self) -> bool {
+        self.scope.is_some() || self.files.is_some() || self.resumed || self.shallow
+    }
+
+    // True if the scan saw every file in the library, so files it didn't
+    // see are gone: not partial, not cancelled, and no scan root missing,
+    // as when a NAS isn't mounted
+    fn scanned_all(&self) -> bool {
+        !self.partial()
+            && !cancel::cancelled()
+            && self
+                .directories
+                .scan
+                .iter()
+                .all(|r| std::path::Path::new(&r.path).is_dir())
+    }
+
+    // The scan root a path is under, if any
+    fn root_for(&self, path: &str) -> Option<&ScanRoot> {
+        self.directories
+            .scan
+            .iter()
+            .filter(|r| std::path::Path::new(path).starts_with(&r.path))
+            .max_by_key(|r| r.path.len())
+    }
 }
 
 #[derive(Deserialize)]
 struct Types {
     valid: Vec<String>,
+    // Seconds, files shorter or longer are counted as excluded rather
+    // than valid. 0 = no limit.
+    #[serde(default)]
+    min_duration: f64,
+    #[serde(default)]
+    max_duration: f64,
+}
+
+impl Types {
+    fn duration_ok(&self, seconds: f64) -> bool {
+        seconds >= self.min_duration && (self.max_duration <= 0.0 || seconds <= self.max_duration)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RootStats {
+    label: String,
+    #[serde(rename = "valid")]
+    valid_files: u32,
+    #[serde(rename = "error")]
+    error_files: u32,
 }
 
+#[derive(Serialize, Deserialize)]
 struct ScanStats {
     other_files: u32,
     directories: u32,
     error_files: u32,
     valid_files: u32,
+    #[serde(default)]
+    junk_files: u32,
+    // Outside the types durations
+    #[serde(default)]
+    excluded_files: u32,
     found_types: HashMap<String, u32>,
+    // Counts for each labelled scan root
+    #[serde(default)]
+    roots: Vec<RootStats>,
+    // m3u playlists found in the scan directories
+    #[serde(default)]
+    playlists: Vec<String>,
+    // Kept whether or not the tracks are
+    #[serde(default)]
+    albums: Vec<AlbumInfo>,
+    #[serde(default)]
+    artists: Vec<ArtistInfo>,
+    #[serde(skip)]
+    tracks: Vec<TrackInfo>,
+    // Where a cancelled scan stopped
+    #[serde(skip)]
+    stopped_at: Option<cancel::ResumePoint>,
 }
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    term::take_mode_args(&mut args);
+    if let Some(command) = args.first().and_then(|a| cli::find(a)) {
+        command.check(&args[1..]);
+    }
+    match args.first().map(String::as_str) {
+        Some("inspect") => {
+            inspect::run(&args[1..]);
+            return;
+        }
+        Some("probe-stream") => {
+            stream::run(&args[1..]);
+            return;
+        }
+        // Hidden, for packagers
+        Some("completions") => {
+            completions::completions(&args[1..]);
+            return;
+        }
+        Some("mangen") => {
+            completions::mangen();
+            return;
+        }
+        _ => (),
+    }
+
+    let mut config = load_config();
+    format::init(&config.format);
+    term::init(&config.terminal);
+    xattrs::init(&config.xattrs);
+    infer::init(&config.infer);
+    mqtt::init(&config.mqtt);
+    match args.first().map(String::as_str) {
+        // After the inits, files are read as they are in the scan
+        Some(sandbox::WORKER_ARG) => {
+            sandbox::run_worker();
+            return;
+        }
+        Some("repair") => {
+            repair::run(&config, &args[1..]);
+            return;
+        }
+        Some("migrate") => {
+            migrate::run(&config, &args[1..]);
+            return;
+        }
+        Some("strip") => {
+            strip::run(&config, &args[1..]);
+            return;
+        }
+        Some("duplicates") => {
+            fingerprint::run(&config, &args[1..]);
+            return;
+        }
+        Some("transcode") => {
+            transcode::run(&config, &args[1..]);
+            return;
+        }
+        Some("sync") => {
+            sync::run(&config, &args[1..]);
+            return;
+        }
+        Some("orphans") => {
+            orphans::run(&config, &args[1..]);
+            return;
+        }
+        Some("restore") => {
+            quarantine::restore(&config, &args[1..]);
+            return;
+        }
+        Some("sortnames") => {
+            sortnames::run(&config, &args[1..]);
+            return;
+        }
+        Some("featured") => {
+            featured::run(&config, &args[1..]);
+            return;
+        }
+        Some("dates") => {
+            dates::run(&config, &args[1..]);
+            return;
+        }
+        Some("spellings") => {
+            spellings::run(&config, &args[1..]);
+            return;
+        }
+        Some("chunks") => {
+            chunks::run(&config, &args[1..]);
+            return;
+        }
+        Some("stats") => {
+            offline::stats(&config);
+            return;
+        }
+        Some("query") => {
+            offline::query(&config, &args[1..]);
+            return;
+        }
+        Some("report") => {
+            offline::report(&config, &args[1..]);
+            return;
+        }
+        Some("history") => {
+            history::run(&config, &args[1..]);
+            return;
+        }
+        Some("rollback") => {
+            history::rollback(&config, &args[1..]);
+            return;
+        }
+        Some("m3u") => {
+            m3u::run(&config, &args[1..]);
+            return;
+        }
+        Some("search") => {
+            search::run(&config, &args[1..]);
+            return;
+        }
+        Some("genres") => {
+            genres::run(&config, &args[1..]);
+            return;
+        }
+        Some("export-edit") => {
+            edit::export(&config, &args[1..]);
+            return;
+        }
+        Some("apply-edit") => {
+            edit::apply(&config, &args[1..]);
+            return;
+        }
+        Some("manifest") => {
+            manifest::run(&config, &args[1..]);
+            return;
+        }
+        Some("verify-manifest") => {
+            manifest::verify(&config, &args[1..]);
+            return;
+        }
+        Some("xattrs") => {
+            xattrs::run(&config, &args[1..]);
+            return;
+        }
+        Some("infer") => {
+            infer::run(&config, &args[1..]);
+            return;
+        }
+        Some("dedupe") => {
+            dedupe::run(&config, &args[1..]);
+            return;
+        }
+        Some("snapshot") => match snapshot::run(&config, &args[1..]) {
+            Some(file) => config.export.snapshot = file,
+            None => return,
+        },
+        Some("--resume" | "--scope" | "--stdin" | "--no-recurse") | None => (),
+        Some(c) => {
+            error!("Unknown command {c}");
+            exit(1);
+        }
+    }
+    let mut resume = false;
+    if args.first().map(String::as_str) != Some("snapshot") {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--resume" => resume = true,
+                "--stdin" => config.files = Some(read_stdin()),
+                "--no-recurse" => {
+                    config.shallow = true;
+                    config
+                        .directories
+                        .scan
+                        .iter_mut()
+                        .for_each(|r| r.recursive = false)
+                }
+                "--scope" => match args.next().map(|s| (s, scope::Scope::parse(s))) {
+                    Some((_, Ok(s))) => config.scope = Some(s),
+                    Some((s, Err(e))) => {
+                        error!("Error in --scope {s}: {e}");
+                        exit(1);
+                    }
+                    None => {
+                        error!("--scope needs a directory, playlist or expression");
+                        exit(1);
+                    }
+                },
+                a => {
+                    error!("Unknown option {a}");
+                    exit(1);
+                }
+            }
+        }
+    }
+    let resume = match resume {
+        true if config.files.is_some() => {
+            error!("--resume can't be used with --stdin");
+            exit(1);
+        }
+        true if scan::newest_first(&config) => {
+            warn!("Can't resume with order = \"newest\", scanning everything");
+            None
+        }
+        true => {
+            let point = cancel::load(&config.pipeline.resume_file);
+            match &point {
+                Some(p) => log!("Resuming from {}", p.path),
+                None => log!("Nothing to resume, scanning everything"),
+            }
+            point
+        }
+        false => None,
+    };
+    config.resumed = resume.is_some();
+    cancel::install();
+    systemd::ready();
+
+    // Estimate files. Mainly for later use when I get a GUI working
+    let mode = match config.general.estimate_only {
+        true => "walk",
+        false => config.estimate.mode.as_str(),
+    };
+    let estimate = match mode {
+        "skip" => None,
+        "cache" => {
+            let cached = estimate::load(&config.estimate);
+            if cached.is_none() {
+                log!("No estimate from an earlier scan");
+            }
+            cached
+        }
+        _ => {
+            log!("Estimating files to scan");
+            Some(scan_dirs(&config, true, resume.as_ref()))
+        }
+    };
+    if let Some(estimate) = estimate {
+        print_types(&estimate.found_types);
+        total!(
+            "Valid {}, Other: {}, Junk: {}, Dirs: {}",
+            estimate.valid_files,
+            estimate.other_files,
+            estimate.junk_files,
+            estimate.directories
+        );
+        term::event(
+            "estimate",
+            json!({
+                "types": estimate.found_types,
+                "valid": estimate.valid_files,
+                "other": estimate.other_files,
+                "junk": estimate.junk_files,
+                "dirs": estimate.directories,
+                "roots": estimate.roots,
+            }),
+        );
+        print_roots(&estimate.roots);
+    }
+
+    if !config.general.estimate_only && !cancel::cancelled() {
+        // Do the real scan
+        log!("Scanning files for tags");
+        hooks::scan_start(&config.hooks);
+        let scan_results = scan_dirs(&config, false, resume.as_ref());
+        print_summary(&scan_results, cancel::cancelled());
+        systemd::finished(&format!(
+            "Scanned {} files, {} errors",
+            scan_results.valid_files, scan_results.error_files
+        ));
+        hooks::scan_finish(&config.hooks, &scan_results, cancel::cancelled());
+        // Reports on part of the library would be misleading, so a
+        // cancelled scan stops at the summary
+        if cancel::cancelled() {
+            match &scan_results.stopped_at {
+                Some(point) if !scan::newest_first(&config) => {
+                    cancel::save(&config.pipeline.resume_file, point)
+                }
+                _ => cancel::clear(&config.pipeline.resume_file),
+            }
+            return;
+        }
+        cancel::clear(&config.pipeline.resume_file);
+        reports::run(&config, &scan_results);
+        // These are for the whole library, so they're left as they were
+        // after a scan of part of it
+        if !config.partial() {
+            estimate::save(&config.estimate, &scan_results);
+            playlists::run(&config, &scan_results);
+            feed::run(&config, &scan_results);
+            mpd::run(&config, &scan_results);
+        }
+        enrich::run(&config, &scan_results);
+        hooks::new_albums(&config.hooks, &scan_results.tracks);
+    }
+}
+
+// File paths one per line, e.g. from find or fd
+fn read_stdin() -> Vec<String> {
+    let lines = io::stdin().lock().lines().map_while(Result::ok);
+    lines
+        .map(|l| l.trim_end_matches('\r').to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+// The counts at the end of a scan
+fn print_summary(stats: &ScanStats, cancelled: bool) {
+    print_types(&stats.found_types);
+    total!(
+        "Valid {}, Other: {}, Error: {}, Excluded: {}, Junk: {}, Dirs: {}",
+        stats.valid_files,
+        stats.other_files,
+        stats.error_files,
+        stats.excluded_files,
+        stats.junk_files,
+        stats.directories
+    );
+    term::event(
+        "summary",
+        json!({
+            "types": stats.found_types,
+            "valid": stats.valid_files,
+            "other": stats.other_files,
+            "error": stats.error_files,
+            "excluded": stats.excluded_files,
+            "junk": stats.junk_files,
+            "dirs": stats.directories,
+            "roots": stats.roots,
+            "cancelled": cancelled,
+        }),
+    );
+    print_roots(&stats.roots);
+}
+
+// Files found of each type, in aligned columns
+fn print_types(found_types: &HashMap<String, u32>) {
+    let width = found_types.keys().map(|k| k.len()).max().unwrap_or(0);
+    for key in found_types.keys().sorted() {
+        log!(
+            "  {:<width$}  {:>8}",
+            key,
+            format::count(found_types[key] as u64)
+        );
+    }
+}
+
+fn print_roots(roots: &[RootStats]) {
+    for r in roots {
+        match r.error_files {
+            0 => log!("  {}: Valid {}", r.label, r.valid_files),
+            e => log!("  {}: Valid {}, Error: {e}", r.label, r.valid_files),
+        }
+    }
+}
+
+fn load_config() -> Config {
     let config_file = "config.toml";
     let config_contents = match fs::read_to_string(config_file) {
         Ok(c) => c,
         Err(_) => {
-            println!("Error reading {config_file}");
+            error!("Error reading {config_file}");
             exit(1);
         }
     };
     let config: Config = match toml::from_str(&config_contents) {
         Ok(c) => c,
         Err(e) => {
-            println!("Error parsing {e}");
+            error!("Error parsing {e}");
             exit(1);
         }
     };
-
-    // Estimate files. Mainly for later use when I get a GUI working
-    println!("Estimating files to scan");
-    let estimate = scan_dirs(&config, true);
-    for key in estimate.found_types.keys().sorted() {
-        println!("{:?}: {:?}", key, estimate.found_types[key]);
-    }
-    println!(
-        "Valid {}, Other: {} Dirs: {}",
-        estimate.valid_files, estimate.other_files, estimate.directories
-    );
-
-    if !config.general.estimate_only {
-        // Do the real scan
-        println!("Scanning files for tags");
-        let scan_results = scan_dirs(&config, false);
-        for key in scan_results.found_types.keys().sorted() {
-            println!("{:?}: {:?}", key, scan_results.found_types[key]);
-        }
-        println!(
-            "Valid {}, Other: {}, Error: {}, Dirs: {}",
-            scan_results.valid_files,
-            scan_results.other_files,
-            scan_results.error_files,
-            scan_results.directories
-        );
+    // Templates can use [fields], so they're checked once those are in
+    let res = fields::init(&config.fields, &config.filters).and_then(|_| {
+        config
+            .templates
+            .line
+            .as_ref()
+            .map_or(Ok(()), template::Template::check)?;
+        config
+            .layout
+            .pattern
+            .as_ref()
+            .map_or(Ok(()), template::Template::check)
+    });
+    if let Err(e) = res {
+        error!("Error in {config_file}: {e}");
+        exit(1);
     }
+    config
 }
 
-fn scan_dirs(config: &Config, estimate: bool) -> ScanStats {
-    let mut scan_stats = ScanStats {
-        other_files: 0,
-        directories: 0,
-        error_files: 0,
-        valid_files: 0,
-        found_types: HashMap::new(),
-    };
+fn file_ext(f_name: &str) -> String {
+    f_name.split(".").last().unwrap_or("NONE").to_lowercase()
+}
 
-    for dir in &config.directories.scan {
-        for entry in WalkDir::new(dir)
+// Music files under the given paths, or the configured scan directories
+// if no paths are given. ._ AppleDouble files are never music even when
+// they end in .mp3.
+fn music_files(config: &Config, paths: &[String]) -> Vec<String> {
+    // Paths given are always gone into
+    let roots: Vec<(&str, bool)> = if paths.is_empty() {
+        config
+            .directories
+            .scan
+            .iter()
+            .map(|r| (r.path.as_str(), r.recursive))
+            .collect()
+    } else {
+        paths.iter().map(|p| (p.as_str(), true)).collect()
+    };
+    let mut files = Vec::new();
+    for (root, recursive) in roots {
+        let mut overrides = Overrides::new(config, config.root_for(root));
+        for entry in WalkDir::new(root)
             .sort_by_file_name()
-            .follow_links(true)
+            .follow_links(config.pipeline.follow_links)
+            .max_depth(if recursive { usize::MAX } else { 1 })
             .into_iter()
             .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| !junk::is_junk(&e.file_name().to_string_lossy()))
         {
-            if entry.file_type().is_dir() {
-                scan_stats.directories += 1;
-                if config.general.verbose {
-                    println!(
-                        "{} Dir: {:?}",
-                        if estimate { "Estimating" } else { "Scanning" },
-                        entry.path().to_string_lossy()
-                    );
-                };
-                continue;
-            }
-            let f_name = entry.file_name().to_string_lossy();
-            let f_ext = f_name.split(".").last().unwrap_or("NONE").to_lowercase();
-            scan_stats
-                .found_types
-                .entry(f_ext.clone())
-                .and_modify(|ext| *ext += 1)
-                .or_insert(1);
-
-            if config.types.valid.iter().any(|t| t == &f_ext) {
-                if !estimate {
-                    let full_path = entry.path().to_string_lossy();
-                    let res = read_metadata(&full_path);
-
-                    let t = match res {
-                        Ok(t) => t,
-                        Err(e) => {
-                            println!("Error in {}: {}", full_path, e);
-                            scan_stats.error_files += 1;
-                            continue;
-                        }
-                    };
-                    if config.general.verbose {
-                        println!(
-                            "{:?} {:?} {:?} {:?} {:?} {:?}",
-                            t.artist, t.title, t.album, t.genre, t.track, t.duration
-                        );
-                    }
-                }
-                scan_stats.valid_files += 1;
-            } else {
-                scan_stats.other_files += 1;
+            let f_ext = file_ext(&entry.file_name().to_string_lossy());
+            let dir = entry.path().parent().unwrap_or(entry.path());
+            if overrides.for_dir(dir).is_valid(&f_ext) {
+                files.push(entry.path().to_string_lossy().to_string());
             }
         }
     }
-    scan_stats
+    files
 }
 
-fn read_metadata(file_name: &str) -> Result<TrackInfo, LoftyError> {
-    let tagged_file_result = Probe::open(file_name)?.read();
+// Files lofty doesn't read, or not from a path, with their tag and
+// properties, or None for the ones it does
+fn read_native(file_name: &str) -> Option<Result<(Option<Tag>, FileProperties), LoftyError>> {
+    if remote::is_url(file_name) {
+        Some(remote::read(file_name))
+    } else if dsd::is_dsd(file_name) {
+        Some(dsd::read(file_name))
+    } else if tracker::is_tracker(file_name) {
+        Some(tracker::read(file_name))
+    } else {
+        None
+    }
+}
 
-    let tagged_file = match tagged_file_result {
-        Ok(tagged_file_result) => tagged_file_result,
-        Err(e) => return Err(e),
+fn read_metadata(file_name: &str) -> Result<TrackInfo, LoftyError> {
+    let tagged_file;
+    let native;
+    let (tag, properties) = match read_native(file_name) {
+        Some(res) => {
+            native = res?;
+            (native.0.as_ref(), &native.1)
+        }
+        None => {
+            tagged_file = Probe::open(file_name)?.read()?;
+            (tagged_file.primary_tag(), tagged_file.properties())
+        }
     };
 
-    let tag = match tagged_file.primary_tag() {
+    // With nothing tagged everything can still be guessed from the path
+    let empty;
+    let untagged = tag.is_none();
+    let tag = match tag {
         Some(primary_tag) => primary_tag,
+        None if infer::enabled() => {
+            empty = Tag::new(TagType::Ape);
+            &empty
+        }
         None => {
-            println!("No tags found in {file_name}");
+            warn!("No tags found in {file_name}");
             return Err(LoftyError::new(ErrorKind::FakeTag));
         }
     };
 
-    let properties = tagged_file.properties();
     /*let properties = match tagged_file.properties() {
         Ok(p) => p,
         Err(e) => {
-            println!("Error {e} in properties: {file_name}");
+            log!("Error {e} in properties: {file_name}");
             //return Err(e);
         }
     };*/
@@ -190,27 +902,93 @@ fn read_metadata(file_name: &str) -> Result<TrackInfo, LoftyError> {
     };
 
     let t_genre = match tag.genre() {
-        Some(genre) => genre.to_string(),
-        None => String::from(""),
+        Some(genre) => Arc::from(genre),
+        None => Arc::from(""),
     };
 
     // lofty does not handle track tags like 1/1, so just set the
     // track to 0 for now
     let t_track = match tag.track() {
         Some(track) => track,
+        None if untagged => 0,
         None => {
-            println!("Bad track info in {file_name}");
+            warn!("Bad track info in {file_name}");
             0
         }
     };
 
-    let t_info = TrackInfo {
+    let file_meta = fs::metadata(file_name).ok();
+    let mut t_info = TrackInfo {
+        path: file_name.to_string(),
         title: t_title,
-        artist: tag.artist().unwrap().to_string(),
-        album: tag.album().unwrap().to_string(),
+        artist: Arc::from(tag.artist().as_deref().unwrap_or("")),
+        album: Arc::from(tag.album().as_deref().unwrap_or("")),
         genre: t_genre,
         track: t_track,
+        track_total: tag.track_total(),
+        disc: tag.disk(),
+        disc_total: tag.disk_total(),
         duration: properties.duration(),
+        bitrate: properties.audio_bitrate(),
+        sample_rate: properties.sample_rate(),
+        modified: file_meta
+            .as_ref()
+            .and_then(|m| m.modified().ok())
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        inode: file_meta.as_ref().and_then(links::id),
+        size: file_meta.map_or(0, |m| m.len()),
+        rating: rating::rating(tag),
+        play_count: rating::play_count(tag),
+        bpm: tag
+            .get_string(&ItemKey::Bpm)
+            .or_else(|| tag.get_string(&ItemKey::IntegerBpm))
+            .and_then(|b| b.trim().parse().ok())
+            .filter(|&b: &f64| b > 0.0),
+        key: tag
+            .get_string(&ItemKey::InitialKey)
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty()),
+        estimated_bpm: None,
+        composer: text(tag, &ItemKey::Composer),
+        conductor: text(tag, &ItemKey::Conductor),
+        work: works::work_tag(tag),
+        movement: text(tag, &ItemKey::Movement),
+        movement_number: text(tag, &ItemKey::MovementNumber).and_then(|m| m.parse().ok()),
+        movement_total: text(tag, &ItemKey::MovementTotal).and_then(|m| m.parse().ok()),
+        language: text(tag, &ItemKey::Language),
+        lyrics_language: tag
+            .get(&ItemKey::Lyrics)
+            .map(|l| {
+                String::from_utf8_lossy(l.lang())
+                    .trim_matches('\0')
+                    .to_string()
+            })
+            .filter(|l| !l.is_empty() && l != "XXX"),
+        artist_sort: text(tag, &ItemKey::TrackArtistSortOrder),
+        album_sort: text(tag, &ItemKey::AlbumTitleSortOrder),
+        album_artist: text(tag, &ItemKey::AlbumArtist),
+        date: text(tag, &ItemKey::RecordingDate)
+            .or_else(|| text(tag, &ItemKey::Year))
+            .or_else(|| match tag.tag_type() {
+                // lofty drops dates it can't parse, which are the ones
+                // the dates report is after
+                TagType::Id3v2 => raw::id3v2_text(file_name, b"TDRC"),
+                _ => None,
+            }),
+        pictures: Some(tag.picture_count()),
+        chains: None,
+        xattrs: xattrs::read(file_name),
+        inferred: None,
     };
+    infer::fill(&mut t_info);
     Ok(t_info)
 }
+
+// A text item with the whitespace trimmed, if it isn't empty
+fn text(tag: &lofty::tag::Tag, key: &ItemKey) -> Option<String> {
+    tag.get_string(key)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
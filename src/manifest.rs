@@ -0,0 +1,204 @@
+// A list of the library's files with their sizes, CRC32 and a few tags,
+// for checking a backup against. "tag_test manifest" writes it, and
+// "tag_test verify-manifest" goes through a copy of the library, e.g. on
+// a backup drive, for files that are missing, have changed or shouldn't
+// be there. Paths are relative to the scan roots, like a mirror's.
+use crate::cli::{Command, Flag};
+use crate::transcode::mirror_path;
+use crate::{file_ext, music_files, read_metadata, term, Config};
+use flate2::Crc;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::exit;
+use walkdir::WalkDir;
+
+pub const COMMAND: Command = Command {
+    name: "manifest",
+    usage: "--out <file> [path...]",
+    about: "List the files with their sizes, checksums and tags, for checking backups",
+    flags: &[Flag {
+        name: "--out",
+        values: Some(&[]),
+        about: "The file to write",
+    }],
+};
+
+pub const VERIFY: Command = Command {
+    name: "verify-manifest",
+    usage: "[--quick] <file> <directory>",
+    about: "Check a copy of the library against a manifest for missing, changed and extra files",
+    flags: &[Flag {
+        name: "--quick",
+        values: None,
+        about: "Only compare sizes, not checksums",
+    }],
+};
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: String,
+    size: u64,
+    // Of the whole file, as 8 hex digits
+    crc32: String,
+    artist: String,
+    album: String,
+    title: String,
+}
+
+pub fn crc32(file: &Path) -> io::Result<String> {
+    let mut f = File::open(file)?;
+    let mut crc = Crc::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match f.read(&mut buf)? {
+            0 => break,
+            n => crc.update(&buf[..n]),
+        }
+    }
+    Ok(format!("{:08x}", crc.sum()))
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut out = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = args.next().cloned(),
+            a if a.starts_with("--") => usage(&COMMAND),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let out = out.unwrap_or_else(|| usage(&COMMAND));
+
+    let (mut entries, mut failed) = (Vec::new(), 0);
+    for file_name in music_files(config, &paths) {
+        let path = Path::new(&file_name);
+        let res = fs::metadata(path).and_then(|m| Ok((m.len(), crc32(path)?)));
+        let (size, crc32) = match res {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Error reading {file_name}: {e}");
+                term::event(
+                    "error",
+                    json!({ "path": file_name, "message": e.to_string() }),
+                );
+                failed += 1;
+                continue;
+            }
+        };
+        // Tags are only there to tell what a file was, so a file lofty
+        // can't read is still listed
+        let (artist, album, title) = match read_metadata(&file_name) {
+            Ok(t) => (t.artist.to_string(), t.album.to_string(), t.title),
+            Err(_) => Default::default(),
+        };
+        entries.push(Entry {
+            path: mirror_path(config, &file_name, "")
+                .to_string_lossy()
+                .to_string(),
+            size,
+            crc32,
+            artist,
+            album,
+            title,
+        });
+    }
+    let res = serde_json::to_string_pretty(&entries)
+        .map_err(|e| e.to_string())
+        .and_then(|j| fs::write(&out, j).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        error!("Error writing {out}: {e}");
+        exit(1);
+    }
+    total!("Listed {} in {out}, Failed: {failed}", entries.len());
+    term::event(
+        "summary",
+        json!({ "listed": entries.len(), "failed": failed, "file": out }),
+    );
+}
+
+// Exits with 1 if anything's wrong, for backup scripts. Only music files
+// of the valid types count as extra, not art and the like.
+pub fn verify(config: &Config, args: &[String]) {
+    let quick = args.iter().any(|a| a == "--quick");
+    let rest: Vec<&String> = args.iter().filter(|a| *a != "--quick").collect();
+    let (file, dir) = match rest.as_slice() {
+        [f, d] if !f.starts_with("--") && !d.starts_with("--") => (f.as_str(), Path::new(d)),
+        _ => usage(&VERIFY),
+    };
+    let entries: Vec<Entry> = match fs::read_to_string(file)
+        .map_err(|e| e.to_string())
+        .and_then(|j| serde_json::from_str(&j).map_err(|e| e.to_string()))
+    {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Error reading {file}: {e}");
+            exit(1);
+        }
+    };
+
+    let (mut ok, mut missing, mut changed, mut extra) = (0, 0, 0, 0);
+    let report = |path: &Path, status: &str| {
+        warn!("{}: {status}", path.display());
+        term::event("file", json!({ "path": path, "status": status }));
+    };
+    for e in &entries {
+        let path = dir.join(&e.path);
+        let status = match fs::metadata(&path) {
+            Err(_) => Some("missing"),
+            Ok(m) if m.len() != e.size => Some("changed"),
+            Ok(_) if quick => None,
+            Ok(_) => match crc32(&path) {
+                Ok(crc) if crc == e.crc32 => None,
+                Ok(_) => Some("changed"),
+                Err(_) => Some("missing"),
+            },
+        };
+        match status {
+            None => ok += 1,
+            Some(s) => {
+                report(&path, s);
+                match s {
+                    "missing" => missing += 1,
+                    _ => changed += 1,
+                }
+            }
+        }
+    }
+    let listed: HashSet<&Path> = entries.iter().map(|e| Path::new(&e.path)).collect();
+    for entry in WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            let ext = file_ext(&e.file_name().to_string_lossy());
+            config.types.valid.contains(&ext)
+        })
+    {
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        if !listed.contains(relative) {
+            report(entry.path(), "extra");
+            extra += 1;
+        }
+    }
+
+    total!("OK: {ok}, Missing: {missing}, Changed: {changed}, Extra: {extra}");
+    term::event(
+        "summary",
+        json!({ "ok": ok, "missing": missing, "changed": changed, "extra": extra }),
+    );
+    if missing + changed + extra > 0 {
+        exit(1);
+    }
+}
+
+fn usage(command: &Command) -> ! {
+    log!("{}", command.usage());
+    exit(1);
+}
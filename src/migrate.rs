@@ -0,0 +1,88 @@
+// Find MP3s that only have an ID3v1 tag and upgrade them to ID3v2.4.
+// ID3v1 text is read as Latin-1 and written back out as UTF-8.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::{music_files, term, Config};
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::id3::v2::Id3v2Tag;
+use lofty::mpeg::MpegFile;
+use lofty::prelude::*;
+use lofty::tag::{ItemKey, Tag, TagType};
+use serde_json::json;
+use std::fs::File;
+
+pub const COMMAND: Command = Command {
+    name: "migrate",
+    usage: "[--dry-run] [--remove-id3v1] [path...]",
+    about: "Upgrade MP3s with only an ID3v1 tag to ID3v2.4",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--remove-id3v1",
+            values: None,
+            about: "Remove the ID3v1 tag once the ID3v2 tag is written",
+        },
+    ],
+};
+
+pub fn run(config: &Config, args: &[String]) {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    // Remove the ID3v1 tag once the ID3v2 tag is written
+    let remove_v1 = args.iter().any(|a| a == "--remove-id3v1");
+    let paths: Vec<String> = args
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .cloned()
+        .collect();
+
+    let (mut found, mut migrated, mut failed) = (0, 0, 0);
+    for file_name in music_files(config, &paths) {
+        let mp3 = match File::open(&file_name)
+            .map_err(|e| e.into())
+            .and_then(|mut f| MpegFile::read_from(&mut f, ParseOptions::new()))
+        {
+            Ok(f) => f,
+            // Not an MP3, or unreadable, which repair deals with
+            Err(_) => continue,
+        };
+        let v1 = match (mp3.id3v1(), mp3.id3v2(), mp3.ape()) {
+            (Some(v1), None, None) => v1.clone(),
+            _ => continue,
+        };
+        found += 1;
+        log!("ID3v1 only: {file_name}");
+        if dry_run {
+            continue;
+        }
+
+        let mut tag = Tag::from(v1);
+        // ID3v2.4 keeps the year in TDRC, lofty won't map Year to it
+        let year = tag.take_strings(&ItemKey::Year).next();
+        tag.re_map(TagType::Id3v2);
+        if let Some(year) = year {
+            tag.insert_text(ItemKey::RecordingDate, year);
+        }
+        let v2 = Id3v2Tag::from(tag);
+        let res = v2
+            .save_to_path(&file_name, WriteOptions::default())
+            .and_then(|_| match remove_v1 {
+                true => TagType::Id3v1.remove_from_path(&file_name),
+                false => Ok(()),
+            });
+        match res {
+            Ok(_) => migrated += 1,
+            Err(e) => {
+                error!("Error migrating {file_name}: {e}");
+                term::event(
+                    "error",
+                    json!({ "path": file_name, "message": e.to_string() }),
+                );
+                failed += 1;
+            }
+        }
+    }
+    total!("ID3v1 only: {found}, Migrated: {migrated}, Failed: {failed}");
+    term::event(
+        "summary",
+        json!({ "id3v1_only": found, "migrated": migrated, "failed": failed }),
+    );
+}
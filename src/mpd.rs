@@ -0,0 +1,120 @@
+// Write the scan results as an MPD database, so MPD can use them instead
+// of scanning the same library again
+use crate::{Config, ScanStats, TrackInfo};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct MpdConfig {
+    pub enabled: bool,
+    pub file: String,
+    // MPD's music_directory. Tracks outside of it are left out.
+    pub music_directory: String,
+}
+
+#[derive(Default)]
+struct Dir<'a> {
+    dirs: BTreeMap<String, Dir<'a>>,
+    songs: BTreeMap<String, &'a TrackInfo>,
+}
+
+pub fn run(config: &Config, stats: &ScanStats) {
+    let mc = &config.mpd;
+    if !mc.enabled {
+        return;
+    }
+    let root_path = Path::new(&mc.music_directory);
+
+    let mut root = Dir::default();
+    let mut outside = 0;
+    for t in &stats.tracks {
+        let rel = match Path::new(&t.path).strip_prefix(root_path) {
+            Ok(r) => r,
+            Err(_) => {
+                outside += 1;
+                continue;
+            }
+        };
+        let parts: Vec<String> = rel
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        let (file, dirs) = match parts.split_last() {
+            Some(p) => p,
+            None => continue,
+        };
+        let mut dir = &mut root;
+        for d in dirs {
+            dir = dir.dirs.entry(d.clone()).or_default();
+        }
+        dir.songs.insert(file.clone(), t);
+    }
+
+    let mut out = String::new();
+    out.push_str("info_begin\nformat: 2\nmpd_version: 0.23.5\nfs_charset: UTF-8\n");
+    for tag in ["Artist", "Album", "Title", "Track", "Genre"] {
+        let _ = writeln!(out, "tag: {tag}");
+    }
+    out.push_str("info_end\n");
+    write_dir(&mut out, &root, root_path, "");
+
+    match fs::write(&mc.file, out) {
+        Ok(_) => log!(
+            "Wrote {} ({} tracks, {} outside {})",
+            mc.file,
+            stats.tracks.len() - outside,
+            outside,
+            mc.music_directory
+        ),
+        Err(e) => error!("Error writing {}: {e}", mc.file),
+    }
+}
+
+fn write_dir(out: &mut String, dir: &Dir, root_path: &Path, path: &str) {
+    for (name, child) in &dir.dirs {
+        let child_path = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{path}/{name}")
+        };
+        let _ = writeln!(out, "directory: {name}");
+        let _ = writeln!(out, "mtime: {}", mtime(&root_path.join(&child_path)));
+        let _ = writeln!(out, "begin: {child_path}");
+        write_dir(out, child, root_path, &child_path);
+        let _ = writeln!(out, "end: {child_path}");
+    }
+    for (name, t) in &dir.songs {
+        let _ = writeln!(out, "song_begin: {name}");
+        for (tag, value) in [
+            ("Artist", &*t.artist),
+            ("Album", &*t.album),
+            ("Title", t.title.as_str()),
+            ("Genre", &*t.genre),
+        ] {
+            // Values can't span lines in the database
+            if !value.is_empty() {
+                let _ = writeln!(out, "{tag}: {}", value.replace('\n', " "));
+            }
+        }
+        if t.track > 0 {
+            let _ = writeln!(out, "Track: {}", t.track);
+        }
+        let _ = writeln!(out, "Time: {:.6}", t.duration.as_secs_f64());
+        let _ = writeln!(out, "mtime: {}", t.modified);
+        out.push_str("song_end\n");
+    }
+}
+
+fn mtime(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
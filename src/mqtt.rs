@@ -0,0 +1,147 @@
+// Scan events published to an MQTT broker, for home automation, e.g. a
+// Home Assistant automation on new albums. The same points as the hooks:
+// <topic>/scan_start, <topic>/scan_finish with the counts and
+// <topic>/new_album for each album added, each with a JSON payload. Sent
+// at QoS 0 over plain TCP, connecting for each batch of messages, so a
+// broker that's down only costs a warning.
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MqttConfig {
+    // host:port, the port 1883 if left out. Empty = don't publish
+    pub broker: String,
+    pub topic: String,
+    pub client_id: String,
+    // Empty = connect without
+    pub username: String,
+    pub password: String,
+    // Have the broker keep the last message on each topic
+    pub retain: bool,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            broker: String::new(),
+            topic: String::from("tag_test"),
+            client_id: String::from("tag_test"),
+            username: String::new(),
+            password: String::new(),
+            retain: false,
+        }
+    }
+}
+
+static CONFIG: OnceLock<MqttConfig> = OnceLock::new();
+
+// Called once the config is loaded
+pub fn init(mc: &MqttConfig) {
+    let _ = CONFIG.set(mc.clone());
+}
+
+pub fn enabled() -> bool {
+    CONFIG.get().is_some_and(|mc| !mc.broker.is_empty())
+}
+
+// (event, payload) pairs, published to <topic>/<event>
+pub fn publish(messages: &[(&str, Value)]) {
+    let mc = match CONFIG.get().filter(|mc| !mc.broker.is_empty()) {
+        Some(mc) => mc,
+        None => return,
+    };
+    if messages.is_empty() {
+        return;
+    }
+    if let Err(e) = send(mc, messages) {
+        warn!("Error publishing to the MQTT broker {}: {e}", mc.broker);
+    }
+}
+
+fn send(mc: &MqttConfig, messages: &[(&str, Value)]) -> io::Result<()> {
+    let addr = match mc.broker.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => mc.broker.clone(),
+        _ => format!("{}:1883", mc.broker),
+    };
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other("broker not found"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(&connect(mc))?;
+
+    // CONNACK: 0x20, length 2, session present, return code
+    let mut ack = [0u8; 4];
+    stream.read_exact(&mut ack)?;
+    match ack {
+        [0x20, 2, _, 0] => (),
+        [0x20, 2, _, 4 | 5] => return Err(io::Error::other("not authorised")),
+        [0x20, 2, _, rc] => return Err(io::Error::other(format!("refused ({rc})"))),
+        _ => return Err(io::Error::other("not an MQTT broker")),
+    }
+
+    let mut packets = Vec::new();
+    for (event, payload) in messages {
+        let topic = format!("{}/{event}", mc.topic.trim_end_matches('/'));
+        let mut body = string(&topic);
+        body.extend_from_slice(payload.to_string().as_bytes());
+        packet(&mut packets, 0x30 | mc.retain as u8, &body);
+    }
+    // DISCONNECT
+    packet(&mut packets, 0xe0, &[]);
+    stream.write_all(&packets)
+}
+
+// MQTT 3.1.1, a clean session and no keep alive as it's closed again
+// straight away
+fn connect(mc: &MqttConfig) -> Vec<u8> {
+    let mut flags = 0x02;
+    let mut payload = string(&mc.client_id);
+    if !mc.username.is_empty() {
+        flags |= 0x80;
+        payload.extend(string(&mc.username));
+        if !mc.password.is_empty() {
+            flags |= 0x40;
+            payload.extend(string(&mc.password));
+        }
+    }
+    let mut body = string("MQTT");
+    body.extend_from_slice(&[4, flags, 0, 0]);
+    body.extend(payload);
+    let mut out = Vec::new();
+    packet(&mut out, 0x10, &body);
+    out
+}
+
+// Length prefixed UTF-8
+fn string(s: &str) -> Vec<u8> {
+    let mut out = (s.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(s.as_bytes());
+    out
+}
+
+// The fixed header, with the remaining length 7 bits a byte
+fn packet(out: &mut Vec<u8>, kind: u8, body: &[u8]) {
+    out.push(kind);
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        match len {
+            0 => {
+                out.push(byte);
+                break;
+            }
+            _ => out.push(byte | 0x80),
+        }
+    }
+    out.extend_from_slice(body);
+}
@@ -0,0 +1,253 @@
+// Commands on the tags stored in the cache rather than the files, so they
+// work while the library is asleep or unmounted. Only tracks under the
+// scan directories are used, as they were at the last scan.
+use crate::albums;
+use crate::artists;
+use crate::cache;
+use crate::cli::{Command, Flag};
+use crate::fields::Expr;
+use crate::intern::Interner;
+use crate::links;
+use crate::scan::new_stats;
+use crate::{file_ext, format, print_types, reports, term, Config, ScanStats, TrackInfo};
+use serde_json::json;
+use std::process::exit;
+use std::time::Duration;
+
+pub const STATS: Command = Command {
+    name: "stats",
+    usage: "",
+    about: "Count the tracks, artists and albums in the cache, without the library",
+    flags: &[],
+};
+
+pub const QUERY: Command = Command {
+    name: "query",
+    usage: "[--sort <expression>] [--reverse] [--offset <n>] [--limit <n>] [expression]",
+    about: "List the cached tracks matching a [fields] style expression",
+    flags: &[
+        Flag {
+            name: "--sort",
+            values: Some(&[]),
+            about: "Sort the tracks by this expression, e.g. year or artist",
+        },
+        Flag {
+            name: "--reverse",
+            values: None,
+            about: "Reverse the order",
+        },
+        Flag {
+            name: "--offset",
+            values: Some(&[]),
+            about: "Skip this many tracks, for the pages after the first",
+        },
+        Flag {
+            name: "--limit",
+            values: Some(&[]),
+            about: "Show at most this many tracks",
+        },
+    ],
+};
+
+pub const REPORT: Command = Command {
+    name: "report",
+    usage: "[--where <expression>] [report...]",
+    about: "Run the reports switched on, or the ones named, on the cached tracks",
+    flags: &[Flag {
+        name: "--where",
+        values: Some(&[]),
+        about: "Only report on the tracks matching this expression",
+    }],
+};
+
+// What the last scan found, less the errors and other files, which
+// aren't cached
+fn load(config: &Config) -> ScanStats {
+    load_where(config, None)
+}
+
+// Only the tracks matching the filter, as if they were all the scan found
+fn load_where(config: &Config, filter: Option<&Expr>) -> ScanStats {
+    let cache = match cache::open(&config.cache) {
+        Some(c) => c,
+        None => {
+            error!("This needs the cache, set backend in the [cache] section of the config");
+            exit(1);
+        }
+    };
+    let mut stats = new_stats();
+    let mut interner = Interner::default();
+    let mut tracks: Vec<TrackInfo> = cache
+        .tracks()
+        .into_iter()
+        .filter(|t| config.root_for(&t.path).is_some())
+        .filter(|t| filter.is_none_or(|e| e.matches(t)))
+        .collect();
+    tracks.sort_by(|a, b| a.path.cmp(&b.path));
+    for mut t in tracks {
+        *stats.found_types.entry(file_ext(&t.path)).or_default() += 1;
+        stats.valid_files += 1;
+        interner.track(&mut t);
+        stats.tracks.push(t);
+    }
+    stats.albums = albums::info(&stats.tracks);
+    stats.artists = artists::info(&stats.tracks);
+    if stats.tracks.is_empty() && filter.is_none() {
+        warn!(
+            "No tracks in {}, run a scan with the cache on first",
+            config.cache.file
+        );
+    }
+    stats
+}
+
+pub fn stats(config: &Config) {
+    let stats = load(config);
+    print_types(&stats.found_types);
+    let duration: Duration = stats.tracks.iter().map(|t| t.duration).sum();
+    let size = links::size(&stats.tracks);
+    total!(
+        "Tracks: {}, Artists: {}, Albums: {}, Length: {}, Size: {}",
+        format::count(stats.valid_files as u64),
+        format::count(stats.artists.len() as u64),
+        format::count(stats.albums.len() as u64),
+        format::duration(duration),
+        format::size(size)
+    );
+    term::event(
+        "library",
+        json!({
+            "types": stats.found_types,
+            "tracks": stats.valid_files,
+            "artists": stats.artists.len(),
+            "albums": stats.albums.len(),
+            "seconds": duration.as_secs(),
+            "size": size,
+        }),
+    );
+}
+
+// Tracks matching a [fields] style expression, with the line template.
+// For a client going through a big library a page at a time, they can be
+// sorted and a page of them taken; the summary event has the offset of the
+// next page.
+pub fn query(config: &Config, args: &[String]) {
+    let mut sort = None;
+    let mut reverse = false;
+    let (mut offset, mut limit) = (0, None);
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = || match args.next().map(|n| n.parse::<usize>()) {
+            Some(Ok(n)) => n,
+            _ => query_usage(),
+        };
+        match arg.as_str() {
+            "--sort" => sort = Some(expr(args.next().cloned().unwrap_or_else(|| query_usage()))),
+            "--reverse" => reverse = true,
+            "--offset" => offset = number(),
+            "--limit" => limit = Some(number()),
+            a if a.starts_with("--") => query_usage(),
+            _ => words.push(arg.clone()),
+        }
+    }
+    if words.is_empty() && sort.is_none() && limit.is_none() {
+        query_usage();
+    }
+    let filter = (!words.is_empty()).then(|| expr(words.join(" ")));
+
+    let stats = load(config);
+    let mut found: Vec<&TrackInfo> = stats
+        .tracks
+        .iter()
+        .filter(|t| filter.as_ref().is_none_or(|e| e.matches(t)))
+        .collect();
+    if let Some(s) = &sort {
+        s.sort(&mut found);
+    }
+    if reverse {
+        found.reverse();
+    }
+    let total = found.len();
+    let page: Vec<&TrackInfo> = found
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+    for t in &page {
+        match &config.templates.line {
+            Some(line) => log!("{}", line.render(t)),
+            None => log!("{}", t.path),
+        }
+        term::event("track", json!(t));
+    }
+    let next = offset + page.len();
+    match (offset, limit) {
+        (0, None) => total!("Tracks: {total}"),
+        _ => total!(
+            "Tracks: {total}, showing {}-{}",
+            (offset + 1).min(next),
+            next
+        ),
+    }
+    term::event(
+        "summary",
+        json!({
+            "total": total,
+            "offset": offset,
+            "count": page.len(),
+            "next": (next < total).then_some(next),
+        }),
+    );
+}
+
+fn expr(text: String) -> Expr {
+    match Expr::try_from(text) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Error in the query: {e}");
+            exit(1);
+        }
+    }
+}
+
+fn query_usage() -> ! {
+    log!("{}", QUERY.usage());
+    exit(1);
+}
+
+// The reports switched on in the config, or the ones named
+pub fn report(config: &Config, args: &[String]) {
+    let mut names = Vec::new();
+    let mut filter = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--where" => match args.next() {
+                Some(e) => filter = Some(expr(e.clone())),
+                None => {
+                    log!("{}", REPORT.usage());
+                    exit(1);
+                }
+            },
+            _ => names.push(arg),
+        }
+    }
+    let chosen: Vec<_> = names
+        .into_iter()
+        .map(|name| match reports::by_name(name) {
+            Some(r) => r,
+            None => {
+                error!("Unknown report {name}");
+                exit(1);
+            }
+        })
+        .collect();
+    let stats = load_where(config, filter.as_ref());
+    if chosen.is_empty() {
+        reports::run(config, &stats);
+    }
+    for r in chosen {
+        reports::run_one(r, config, &stats);
+    }
+}
@@ -0,0 +1,167 @@
+// Chained Ogg files, one logical stream after another as internet radio
+// rips are. lofty only looks at the first stream, so the length is that
+// of the first song. This walks the pages to find each stream, its
+// length and its title and artist. Only page headers and the first
+// packets of each stream are read.
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::time::Duration;
+
+pub struct Chain {
+    pub codec: &'static str,
+    pub duration: Duration,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+pub fn is_ogg(file_name: &str) -> bool {
+    matches!(crate::file_ext(file_name).as_str(), "ogg" | "oga" | "opus")
+}
+
+// Where a stream's sample rate and comments are still being read
+struct Stream {
+    serial: u32,
+    chain: Chain,
+    rate: u64,
+    pre_skip: u64,
+    granule: u64,
+    // Packets seen so far, and the one being put together
+    packets: usize,
+    packet: Vec<u8>,
+}
+
+pub fn chains(file_name: &str) -> std::io::Result<Vec<Chain>> {
+    let mut file = BufReader::new(File::open(file_name)?);
+    let mut chains = Vec::new();
+    let mut stream: Option<Stream> = None;
+    let mut header = [0; 27];
+    while file.read_exact(&mut header).is_ok() {
+        if &header[..4] != b"OggS" {
+            break;
+        }
+        let flags = header[5];
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let mut lacing = vec![0; header[26] as usize];
+        file.read_exact(&mut lacing)?;
+        let body_len: usize = lacing.iter().map(|&l| l as usize).sum();
+
+        if flags & 0x02 != 0 {
+            // Beginning of a stream. Only the first of a group of
+            // multiplexed streams is followed.
+            if stream.as_ref().is_some_and(|s| s.packets < 2) {
+                file.seek_relative(body_len as i64)?;
+                continue;
+            }
+            chains.extend(stream.take().map(finish));
+            stream = Some(Stream {
+                serial,
+                chain: Chain {
+                    codec: "unknown",
+                    duration: Duration::ZERO,
+                    title: None,
+                    artist: None,
+                },
+                rate: 0,
+                pre_skip: 0,
+                granule: 0,
+                packets: 0,
+                packet: Vec::new(),
+            });
+        }
+        let s = match stream.as_mut().filter(|s| s.serial == serial) {
+            Some(s) => s,
+            None => {
+                file.seek_relative(body_len as i64)?;
+                continue;
+            }
+        };
+        if granule != u64::MAX {
+            s.granule = granule;
+        }
+        if s.packets >= 2 {
+            file.seek_relative(body_len as i64)?;
+            continue;
+        }
+        let mut body = vec![0; body_len];
+        file.read_exact(&mut body)?;
+        let mut pos = 0;
+        for &l in &lacing {
+            s.packet.extend_from_slice(&body[pos..pos + l as usize]);
+            pos += l as usize;
+            if l < 255 && s.packets < 2 {
+                let packet = std::mem::take(&mut s.packet);
+                header_packet(s, &packet);
+                s.packets += 1;
+            }
+        }
+    }
+    chains.extend(stream.map(finish));
+    Ok(chains)
+}
+
+fn finish(s: Stream) -> Chain {
+    let mut chain = s.chain;
+    if s.rate > 0 {
+        let samples = s.granule.saturating_sub(s.pre_skip);
+        chain.duration = Duration::from_secs_f64(samples as f64 / s.rate as f64);
+    }
+    chain
+}
+
+// The identification header, then the comment header
+fn header_packet(s: &mut Stream, packet: &[u8]) {
+    let comments = if packet.starts_with(b"OpusHead") && packet.len() >= 12 {
+        s.chain.codec = "Opus";
+        // Opus granules always count at 48 kHz
+        s.rate = 48000;
+        s.pre_skip = u16::from_le_bytes([packet[10], packet[11]]) as u64;
+        return;
+    } else if packet.starts_with(b"\x01vorbis") && packet.len() >= 16 {
+        s.chain.codec = "Vorbis";
+        s.rate = u32::from_le_bytes(packet[12..16].try_into().unwrap()) as u64;
+        return;
+    } else if let Some(c) = packet.strip_prefix(b"OpusTags") {
+        c
+    } else if let Some(c) = packet.strip_prefix(b"\x03vorbis") {
+        c
+    } else {
+        return;
+    };
+    for (key, value) in vorbis_comments(comments) {
+        match key.to_uppercase().as_str() {
+            "TITLE" => s.chain.title = Some(value),
+            "ARTIST" => s.chain.artist = Some(value),
+            _ => (),
+        }
+    }
+}
+
+fn vorbis_comments(data: &[u8]) -> Vec<(String, String)> {
+    let u32_at = |i: usize| {
+        data.get(i..i + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+    };
+    let mut out = Vec::new();
+    let mut i = match u32_at(0) {
+        Some(vendor) => 4 + vendor,
+        None => return out,
+    };
+    let count = u32_at(i).unwrap_or(0);
+    i += 4;
+    for _ in 0..count {
+        let len = match u32_at(i) {
+            Some(l) => l,
+            None => break,
+        };
+        let comment = match data.get(i + 4..i + 4 + len) {
+            Some(c) => String::from_utf8_lossy(c),
+            None => break,
+        };
+        if let Some((k, v)) = comment.split_once('=') {
+            out.push((k.to_string(), v.to_string()));
+        }
+        i += 4 + len;
+    }
+    out
+}
@@ -0,0 +1,188 @@
+// Sidecar files left behind when the music they go with was deleted or
+// renamed, and empty directories. Lyrics (.lrc) go with the track of the
+// same name, a .cue sheet with the files it names, and art and .nfo files
+// with any music in their directory.
+use crate::cli::{Command, Flag};
+use crate::overrides::Overrides;
+use crate::transcode::mirror_path;
+use crate::trash;
+use crate::{file_ext, term, Config};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use walkdir::WalkDir;
+
+pub const COMMAND: Command = Command {
+    name: "orphans",
+    usage: "[--delete [--permanent] | --move <dir>] [path...]",
+    about: "List lyrics, cue sheets and art left without their music, and empty directories",
+    flags: &[
+        Flag {
+            name: "--delete",
+            values: None,
+            about: "Delete them, to the trash",
+        },
+        Flag {
+            name: "--permanent",
+            values: None,
+            about: "With --delete, delete them for good rather than to the trash",
+        },
+        Flag {
+            name: "--move",
+            values: Some(&[]),
+            about: "Move the files to this directory, keeping their paths, and remove the empty directories",
+        },
+    ],
+};
+
+const ALBUM_SIDECARS: &[&str] = &["jpg", "jpeg", "png", "gif", "nfo"];
+
+enum Cleanup {
+    Report,
+    // To the trash unless permanent
+    Delete(bool),
+    Move(String),
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut cleanup = Cleanup::Report;
+    let mut permanent = false;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--delete" => cleanup = Cleanup::Delete(false),
+            "--permanent" => permanent = true,
+            "--move" => match args.next() {
+                Some(d) => cleanup = Cleanup::Move(d.clone()),
+                None => usage(),
+            },
+            a if a.starts_with("--") => usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    match &mut cleanup {
+        Cleanup::Delete(p) => *p = permanent,
+        _ if permanent => usage(),
+        _ => (),
+    }
+    let roots: Vec<&str> = if paths.is_empty() {
+        config
+            .directories
+            .scan
+            .iter()
+            .map(|r| r.path.as_str())
+            .collect()
+    } else {
+        paths.iter().map(String::as_str).collect()
+    };
+
+    let (mut orphans, mut empty) = (Vec::new(), Vec::new());
+    for root in roots {
+        let mut overrides = Overrides::new(config, config.root_for(root));
+        for dir in WalkDir::new(root)
+            .sort_by_file_name()
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir())
+        {
+            let files: Vec<PathBuf> = match fs::read_dir(dir.path()) {
+                Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+                Err(e) => {
+                    error!("Error reading {}: {e}", dir.path().display());
+                    continue;
+                }
+            };
+            if files.is_empty() {
+                empty.push(dir.path().to_path_buf());
+                continue;
+            }
+            let settings = overrides.for_dir(dir.path());
+            let music: Vec<&PathBuf> = files
+                .iter()
+                .filter(|f| f.is_file() && settings.is_valid(&file_ext(&f.to_string_lossy())))
+                .collect();
+            for f in files.iter().filter(|f| f.is_file()) {
+                if is_orphan(f, &music) {
+                    orphans.push(f.clone());
+                }
+            }
+        }
+    }
+
+    total!("Orphaned sidecar files: {}", orphans.len());
+    for f in &orphans {
+        log!("  {}", f.display());
+        clean_file(config, f, &cleanup);
+    }
+    total!("Empty directories: {}", empty.len());
+    for d in &empty {
+        log!("  {}", d.display());
+        if !matches!(cleanup, Cleanup::Report) {
+            if let Err(e) = fs::remove_dir(d) {
+                error!("Error removing {}: {e}", d.display());
+            }
+        }
+    }
+    term::event(
+        "summary",
+        json!({
+            "orphans": orphans.iter().map(|f| f.to_string_lossy()).collect::<Vec<_>>(),
+            "empty_dirs": empty.iter().map(|d| d.to_string_lossy()).collect::<Vec<_>>(),
+        }),
+    );
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
+
+fn is_orphan(file: &Path, music: &[&PathBuf]) -> bool {
+    let ext = file_ext(&file.to_string_lossy());
+    let same_stem = || music.iter().any(|m| m.file_stem() == file.file_stem());
+    match ext.as_str() {
+        "lrc" => !same_stem(),
+        "cue" => !same_stem() && !cue_files(file).iter().any(|f| f.exists()),
+        e if ALBUM_SIDECARS.contains(&e) => music.is_empty(),
+        _ => false,
+    }
+}
+
+// Files named on FILE lines of a cue sheet, relative to the sheet
+fn cue_files(cue: &Path) -> Vec<PathBuf> {
+    let contents = match fs::read(cue) {
+        Ok(c) => String::from_utf8_lossy(&c).to_string(),
+        Err(_) => return Vec::new(),
+    };
+    let dir = cue.parent().unwrap_or(Path::new(""));
+    contents
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("FILE "))
+        .filter_map(|rest| match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split('"').next(),
+            None => rest.split_whitespace().next(),
+        })
+        .map(|name| dir.join(name))
+        .collect()
+}
+
+fn clean_file(config: &Config, file: &Path, cleanup: &Cleanup) {
+    let res = match cleanup {
+        Cleanup::Report => return,
+        Cleanup::Delete(permanent) => trash::remove(file, *permanent),
+        Cleanup::Move(dir) => {
+            let to = mirror_path(config, &file.to_string_lossy(), dir);
+            match to.parent() {
+                Some(parent) => fs::create_dir_all(parent),
+                None => Ok(()),
+            }
+            .and_then(|_| fs::rename(file, &to))
+        }
+    };
+    if let Err(e) = res {
+        error!("Error cleaning up {}: {e}", file.display());
+    }
+}
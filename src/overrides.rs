@@ -0,0 +1,118 @@
+// Settings for one part of the library, from a .tag_test.toml in a
+// directory. It applies to that directory and everything under it, over
+// the config.toml settings and any .tag_test.toml further up. For example
+// an audiobooks folder can have its own valid types:
+//
+//   [types]
+//   valid = ["m4b", "mp3"]
+use crate::template::Template;
+use crate::{Config, ScanRoot};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const FILE: &str = ".tag_test.toml";
+
+// What a .tag_test.toml can set. Anything it doesn't set comes from the
+// directory above.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DirFile {
+    types: DirTypes,
+    templates: DirTemplates,
+    analysis: DirAnalysis,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DirTypes {
+    valid: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DirTemplates {
+    line: Option<Template>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DirAnalysis {
+    bpm: Option<bool>,
+}
+
+#[derive(Clone)]
+pub struct DirSettings {
+    pub valid: Vec<String>,
+    pub line: Option<Template>,
+    pub bpm: bool,
+}
+
+impl DirSettings {
+    pub fn is_valid(&self, ext: &str) -> bool {
+        self.valid.iter().any(|t| t == ext)
+    }
+}
+
+pub struct Overrides {
+    base: Arc<DirSettings>,
+    dirs: HashMap<PathBuf, Arc<DirSettings>>,
+}
+
+impl Overrides {
+    // The settings for the root's types, or the config's if the root has
+    // none or there is no root
+    pub fn new(config: &Config, root: Option<&ScanRoot>) -> Overrides {
+        Overrides {
+            base: Arc::new(DirSettings {
+                valid: root
+                    .and_then(|r| r.types.clone())
+                    .unwrap_or_else(|| config.types.valid.clone()),
+                line: config.templates.line.clone(),
+                bpm: config.analysis.bpm,
+            }),
+            dirs: HashMap::new(),
+        }
+    }
+
+    // Settings for files in a directory. Each directory's .tag_test.toml
+    // is only read once.
+    pub fn for_dir(&mut self, dir: &Path) -> Arc<DirSettings> {
+        if let Some(s) = self.dirs.get(dir) {
+            return s.clone();
+        }
+        let parent = match dir.parent() {
+            Some(p) if !p.as_os_str().is_empty() => self.for_dir(p),
+            _ => self.base.clone(),
+        };
+        let settings = match read(&dir.join(FILE)) {
+            Some(f) => Arc::new(DirSettings {
+                valid: f.types.valid.unwrap_or_else(|| parent.valid.clone()),
+                line: f.templates.line.or_else(|| parent.line.clone()),
+                bpm: f.analysis.bpm.unwrap_or(parent.bpm),
+            }),
+            None => parent,
+        };
+        self.dirs.insert(dir.to_path_buf(), settings.clone());
+        settings
+    }
+}
+
+fn read(file: &Path) -> Option<DirFile> {
+    let contents = fs::read_to_string(file).ok()?;
+    match toml::from_str::<DirFile>(&contents) {
+        Ok(f) => match f.templates.line.as_ref().map_or(Ok(()), Template::check) {
+            Ok(_) => Some(f),
+            Err(e) => {
+                error!("Error in {}: {e}", file.display());
+                None
+            }
+        },
+        Err(e) => {
+            error!("Error parsing {}: {e}", file.display());
+            None
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// Values rippers and taggers write when they don't know the real one,
+// "Unknown Artist", "Track 01" and so on. The completeness report counts
+// them as missing.
+use serde_derive::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlaceholdersConfig {
+    // Matched without regard to case, # stands for a number
+    pub values: Vec<String>,
+}
+
+impl Default for PlaceholdersConfig {
+    fn default() -> Self {
+        PlaceholdersConfig {
+            values: [
+                "Unknown",
+                "Unknown Artist",
+                "Unknown Album",
+                "Unknown Title",
+                "Unknown Genre",
+                "Various",
+                "New Title",
+                "New Album",
+                "Untitled",
+                "No Title",
+                "Track #",
+                "Track#",
+                "Audio Track",
+                "Audio Track #",
+                "AudioTrack #",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+pub fn is_placeholder(pc: &PlaceholdersConfig, value: &str) -> bool {
+    let value = value.trim();
+    pc.values.iter().any(|p| matches(p, value))
+}
+
+// True if value is pattern, with each # in pattern matching one or more
+// digits
+fn matches(pattern: &str, value: &str) -> bool {
+    let mut v = value.chars().peekable();
+    for pc in pattern.chars() {
+        if pc == '#' {
+            if !v.peek().is_some_and(char::is_ascii_digit) {
+                return false;
+            }
+            while v.peek().is_some_and(char::is_ascii_digit) {
+                v.next();
+            }
+            continue;
+        }
+        match v.next() {
+            Some(vc) if vc.to_lowercase().eq(pc.to_lowercase()) => (),
+            _ => return false,
+        }
+    }
+    v.next().is_none()
+}
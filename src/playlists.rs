@@ -0,0 +1,112 @@
+// Smart playlists built from the scan results
+use crate::albums::albums;
+use crate::fields;
+use crate::{Config, ScanStats, TrackInfo};
+use serde_derive::Deserialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlaylistsConfig {
+    pub enabled: bool,
+    // Where the playlists are written
+    pub directory: String,
+    // m3u or json
+    pub format: String,
+    // Files modified within this many days count as recently added
+    pub recent_days: u64,
+    // Tracks at least this many minutes long count as long
+    pub long_minutes: u64,
+}
+
+impl Default for PlaylistsConfig {
+    fn default() -> Self {
+        PlaylistsConfig {
+            enabled: false,
+            directory: String::from("playlists"),
+            format: String::from("m3u"),
+            recent_days: 30,
+            long_minutes: 10,
+        }
+    }
+}
+
+pub fn run(config: &Config, stats: &ScanStats) {
+    let pl = &config.playlists;
+    if !pl.enabled {
+        return;
+    }
+    if let Err(e) = fs::create_dir_all(&pl.directory) {
+        error!("Error creating {}: {e}", pl.directory);
+        return;
+    }
+    let tracks = &stats.tracks;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let since = now.saturating_sub(pl.recent_days * 24 * 60 * 60);
+    let mut recent: Vec<&TrackInfo> = tracks.iter().filter(|t| t.modified >= since).collect();
+    recent.sort_by_key(|t| std::cmp::Reverse(t.modified));
+    write(config, "recently_added", &recent);
+
+    // Only meaningful if something in the library keeps play counts
+    if tracks.iter().any(|t| t.play_count.is_some()) {
+        let never: Vec<&TrackInfo> = tracks
+            .iter()
+            .filter(|t| t.play_count.unwrap_or(0) == 0)
+            .collect();
+        write(config, "never_played", &never);
+    }
+
+    let long: Vec<&TrackInfo> = tracks
+        .iter()
+        .filter(|t| t.duration.as_secs() >= pl.long_minutes * 60)
+        .collect();
+    write(config, "long_tracks", &long);
+
+    let incomplete: Vec<&TrackInfo> = albums(tracks)
+        .into_iter()
+        .filter(|a| a.is_incomplete())
+        .flat_map(|a| a.tracks)
+        .collect();
+    write(config, "incomplete_albums", &incomplete);
+
+    // One for each of the [filters], in the order of the scan
+    for (name, e) in fields::filters() {
+        let matching: Vec<&TrackInfo> = tracks.iter().filter(|t| e.matches(t)).collect();
+        write(config, name, &matching);
+    }
+}
+
+fn write(config: &Config, name: &str, tracks: &[&TrackInfo]) {
+    let pl = &config.playlists;
+    let json = pl.format.eq_ignore_ascii_case("json");
+    let path =
+        Path::new(&pl.directory).join(format!("{name}.{}", if json { "json" } else { "m3u" }));
+    let res = File::create(&path).and_then(|f| {
+        let mut out = BufWriter::new(f);
+        if json {
+            serde_json::to_writer_pretty(&mut out, tracks)?;
+        } else {
+            writeln!(out, "#EXTM3U")?;
+            for t in tracks {
+                let comment = match &config.templates.line {
+                    Some(line) => line.render(t),
+                    None => format!("{} - {}", t.artist, t.title),
+                };
+                writeln!(out, "#EXTINF:{},{}", t.duration.as_secs(), comment)?;
+                writeln!(out, "{}", t.path)?;
+            }
+        }
+        out.flush()
+    });
+    match res {
+        Ok(_) => log!("Wrote {} ({} tracks)", path.display(), tracks.len()),
+        Err(e) => error!("Error writing {}: {e}", path.display()),
+    }
+}
@@ -0,0 +1,195 @@
+// Move files the scan can't read out of the library, so they stop turning
+// up as errors on every scan, without losing them. A manifest records
+// where each one came from, and tag_test restore puts them back.
+use crate::cli::{Command, DRY_RUN};
+use crate::transcode::mirror_path;
+use crate::{term, Config};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    pub enabled: bool,
+    // Where broken files are moved to, laid out like the library
+    pub directory: String,
+    pub manifest: String,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        QuarantineConfig {
+            enabled: false,
+            directory: String::from("quarantine"),
+            manifest: String::from("quarantine.json"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    original: String,
+    quarantined: String,
+    reason: String,
+    // Seconds since the epoch
+    time: u64,
+}
+
+pub struct Quarantine<'a> {
+    config: &'a Config,
+    entries: Vec<Entry>,
+    moved: u32,
+}
+
+impl Quarantine<'_> {
+    pub fn new(config: &Config) -> Quarantine<'_> {
+        Quarantine {
+            config,
+            entries: read_manifest(&config.quarantine.manifest),
+            moved: 0,
+        }
+    }
+
+    pub fn add(&mut self, file_name: &str, reason: &str) {
+        let qc = &self.config.quarantine;
+        let to = mirror_path(self.config, file_name, &qc.directory);
+        if to.exists() {
+            error!("Not quarantining {file_name}, {} exists", to.display());
+            return;
+        }
+        if let Err(e) = move_file(Path::new(file_name), &to) {
+            error!("Error quarantining {file_name}: {e}");
+            return;
+        }
+        log!("Quarantined {file_name}");
+        self.entries.push(Entry {
+            original: file_name.to_string(),
+            quarantined: to.to_string_lossy().to_string(),
+            reason: reason.to_string(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        });
+        self.moved += 1;
+    }
+
+    pub fn finish(self) {
+        if self.moved == 0 {
+            return;
+        }
+        total!("Quarantined: {}", self.moved);
+        write_manifest(&self.config.quarantine.manifest, &self.entries);
+    }
+}
+
+pub const RESTORE: Command = Command {
+    name: "restore",
+    usage: "[--dry-run] [path...]",
+    about: "Move quarantined files back where they came from",
+    flags: &[DRY_RUN],
+};
+
+// Move quarantined files back, all of them or the ones given by their
+// original or quarantined path
+pub fn restore(config: &Config, args: &[String]) {
+    let qc = &config.quarantine;
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<&String> = args.iter().filter(|a| *a != "--dry-run").collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", RESTORE.usage());
+        exit(1);
+    }
+
+    let (mut restored, mut failed) = (0, 0);
+    let mut kept = Vec::new();
+    for e in read_manifest(&qc.manifest) {
+        let wanted = paths.is_empty()
+            || paths
+                .iter()
+                .any(|p| **p == e.original || **p == e.quarantined);
+        if !wanted {
+            kept.push(e);
+            continue;
+        }
+        log!(
+            "{} {}",
+            if dry_run {
+                "Would restore"
+            } else {
+                "Restoring"
+            },
+            e.original
+        );
+        if dry_run {
+            restored += 1;
+            kept.push(e);
+            continue;
+        }
+        let res = match Path::new(&e.original).exists() {
+            true => Err(String::from("a file is there already")),
+            false => move_file(Path::new(&e.quarantined), Path::new(&e.original)),
+        };
+        match res {
+            Ok(_) => restored += 1,
+            Err(err) => {
+                error!("Error restoring {}: {err}", e.original);
+                term::event("error", json!({ "path": e.original, "message": err }));
+                failed += 1;
+                kept.push(e);
+            }
+        }
+    }
+    if !dry_run {
+        write_manifest(&qc.manifest, &kept);
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would restore" } else { "Restored" },
+        restored,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "restored": restored, "failed": failed }),
+    );
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    // rename doesn't work across file systems, so copy and delete then
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to).map_err(|e| e.to_string())?;
+        fs::remove_file(from).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn read_manifest(file: &str) -> Vec<Entry> {
+    let json = match fs::read_to_string(file) {
+        Ok(j) => j,
+        Err(_) => return Vec::new(),
+    };
+    match serde_json::from_str(&json) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Error parsing {file}: {e}");
+            exit(1);
+        }
+    }
+}
+
+fn write_manifest(file: &str, entries: &[Entry]) {
+    let res = serde_json::to_string_pretty(entries)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(file, json).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        error!("Error writing {file}: {e}");
+    }
+}
@@ -0,0 +1,98 @@
+// Ratings and play counts. Every player stores these differently, so
+// ratings are normalized to 0-100 (20 per star).
+use lofty::id3::v2::{Frame, Id3v2Tag};
+use lofty::tag::{ItemKey, Tag, TagType};
+
+// Email WMP uses in POPM frames, its rating wins over other players'
+const WMP_EMAIL: &str = "Windows Media Player 9 Series";
+
+pub fn rating(tag: &Tag) -> Option<u8> {
+    if tag.tag_type() == TagType::Id3v2 {
+        let popm = popularimeters(tag)
+            .into_iter()
+            .filter(|(_, rating, _)| *rating > 0)
+            .max_by_key(|(email, _, _)| email == WMP_EMAIL);
+        if let Some((_, rating, _)) = popm {
+            return Some(popm_rating(rating));
+        }
+    }
+    if let Some(r) = tag.get_string(&ItemKey::Popularimeter) {
+        if let Some(r) = text_rating(tag.tag_type(), r) {
+            return Some(r);
+        }
+    }
+    // FMPS_Rating is 0.0 - 1.0, in a Vorbis comment or an ID3v2 TXXX frame
+    unknown_text(tag, "FMPS_RATING")
+        .and_then(|r| r.trim().parse::<f64>().ok())
+        .filter(|r| (0.0..=1.0).contains(r))
+        .map(|r| (r * 100.0).round() as u8)
+}
+
+pub fn play_count(tag: &Tag) -> Option<u64> {
+    if tag.tag_type() == TagType::Id3v2 {
+        let id3v2 = Id3v2Tag::from(tag.clone());
+        for frame in &id3v2 {
+            match frame {
+                Frame::Popularimeter(p) if p.counter > 0 => return Some(p.counter),
+                Frame::Binary(b) if frame.id_str() == "PCNT" => {
+                    return Some(b.data.iter().fold(0, |acc, &x| (acc << 8) | x as u64))
+                }
+                _ => (),
+            }
+        }
+    }
+    ["FMPS_PLAYCOUNT", "PLAYCOUNT", "PLAY_COUNT"]
+        .iter()
+        .find_map(|k| unknown_text(tag, k).and_then(|c| c.trim().parse().ok()))
+}
+
+// (email, rating, counter) of every POPM frame. lofty keeps these in the
+// ID3v2 tag behind the generic one, so convert back to get at them.
+fn popularimeters(tag: &Tag) -> Vec<(String, u8, u64)> {
+    let id3v2 = Id3v2Tag::from(tag.clone());
+    let mut found = Vec::new();
+    for frame in &id3v2 {
+        if let Frame::Popularimeter(p) = frame {
+            found.push((p.email.clone(), p.rating, p.counter));
+        }
+    }
+    found
+}
+
+// POPM is 1-255, WMP, foobar2000, MediaMonkey and Kodi all agree on
+// these ranges for stars
+fn popm_rating(rating: u8) -> u8 {
+    let stars = match rating {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    };
+    stars * 20
+}
+
+fn text_rating(tag_type: TagType, value: &str) -> Option<u8> {
+    let v: f64 = value.trim().parse().ok()?;
+    let percent = match tag_type {
+        // iTunes and Windows (IRTD) use 0-100 already
+        TagType::Mp4Ilst | TagType::RiffInfo => v,
+        // Vorbis RATING has no standard: 0.0-1.0 (Quod Libet), 1-5
+        // (foobar2000), 0-10 (Kodi) or 0-100 (MusicBee)
+        _ if value.contains('.') && v <= 1.0 => v * 100.0,
+        _ if v <= 5.0 => v * 20.0,
+        _ if v <= 10.0 => v * 10.0,
+        _ => v,
+    };
+    (0.0..=100.0)
+        .contains(&percent)
+        .then_some(percent.round() as u8)
+}
+
+fn unknown_text<'a>(tag: &'a Tag, key: &str) -> Option<&'a str> {
+    tag.items().find_map(|i| match i.key() {
+        ItemKey::Unknown(k) if k.eq_ignore_ascii_case(key) => i.value().text(),
+        _ => None,
+    })
+}
@@ -0,0 +1,503 @@
+// Locate tag regions in the raw bytes of a file without going through
+// lofty, so files that lofty rejects can still be looked at
+use crate::term;
+use serde_json::json;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::Read;
+
+// Most bytes of a single region to hex dump
+const MAX_DUMP: usize = 512;
+
+pub struct Id3v2Header {
+    pub offset: usize,
+    pub major: u8,
+    pub revision: u8,
+    pub flags: u8,
+    // Size of the tag, excluding the 10 byte header and any footer
+    pub size: usize,
+}
+
+impl Id3v2Header {
+    // Offset of the first byte after the tag
+    pub fn end(&self) -> usize {
+        let footer = if self.flags & 0x10 != 0 { 10 } else { 0 };
+        self.offset + 10 + self.size + footer
+    }
+}
+
+pub struct FlacBlock {
+    pub offset: usize,
+    pub block_type: u8,
+    pub last: bool,
+    pub size: usize,
+}
+
+pub fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |acc, &x| (acc << 7) | (x & 0x7f) as usize)
+}
+
+pub fn be(b: &[u8]) -> usize {
+    b.iter().fold(0, |acc, &x| (acc << 8) | x as usize)
+}
+
+fn le(b: &[u8]) -> usize {
+    b.iter().rev().fold(0, |acc, &x| (acc << 8) | x as usize)
+}
+
+// Parse an ID3v2 header at offset, if there is a valid one
+pub fn id3v2_at(data: &[u8], offset: usize) -> Option<Id3v2Header> {
+    let h = data.get(offset..offset + 10)?;
+    if &h[0..3] != b"ID3" || h[3] == 0xff || h[4] == 0xff || h[6..10].iter().any(|b| b & 0x80 != 0)
+    {
+        return None;
+    }
+    Some(Id3v2Header {
+        offset,
+        major: h[3],
+        revision: h[4],
+        flags: h[5],
+        size: syncsafe(&h[6..10]),
+    })
+}
+
+// Like id3v2_at, but also accepts a size that isn't syncsafe, which some
+// broken taggers write
+pub fn id3v2_at_lenient(data: &[u8], offset: usize) -> Option<Id3v2Header> {
+    let h = data.get(offset..offset + 10)?;
+    if &h[0..3] != b"ID3" || !(2..=4).contains(&h[3]) {
+        return None;
+    }
+    let size_bytes = &h[6..10];
+    Some(Id3v2Header {
+        offset,
+        major: h[3],
+        revision: h[4],
+        flags: h[5],
+        size: if size_bytes.iter().any(|b| b & 0x80 != 0) {
+            be(size_bytes)
+        } else {
+            syncsafe(size_bytes)
+        },
+    })
+}
+
+// Find every ID3v2 header within the first `limit` bytes, including ones
+// that follow garbage or another tag
+pub fn find_id3v2(data: &[u8], limit: usize) -> Vec<Id3v2Header> {
+    let end = data.len().min(limit);
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i + 10 <= end {
+        match id3v2_at(data, i) {
+            Some(h) => {
+                i = h.end().max(i + 1);
+                found.push(h);
+            }
+            None => i += 1,
+        }
+    }
+    found
+}
+
+// Offset of an ID3v1 tag at the end of the file
+pub fn id3v1_offset(data: &[u8]) -> Option<usize> {
+    let offset = data.len().checked_sub(128)?;
+    (&data[offset..offset + 3] == b"TAG").then_some(offset)
+}
+
+// Offset and total size (including header) of an APEv2 tag at the end
+// of the file, before any ID3v1 tag
+pub fn ape_region(data: &[u8]) -> Option<(usize, usize)> {
+    let end = id3v1_offset(data).unwrap_or(data.len());
+    let footer = end.checked_sub(32)?;
+    let f = &data[footer..end];
+    if &f[0..8] != b"APETAGEX" {
+        return None;
+    }
+    let size = le(&f[12..16]);
+    let has_header = f[23] & 0x80 != 0;
+    let total = size + if has_header { 32 } else { 0 };
+    Some((end.checked_sub(total)?, total))
+}
+
+// The metadata blocks of a FLAC stream starting at offset
+pub fn flac_blocks(data: &[u8], offset: usize) -> Vec<FlacBlock> {
+    let mut blocks = Vec::new();
+    if data.get(offset..offset + 4) != Some(b"fLaC") {
+        return blocks;
+    }
+    let mut i = offset + 4;
+    while let Some(h) = data.get(i..i + 4) {
+        let block = FlacBlock {
+            offset: i,
+            block_type: h[0] & 0x7f,
+            last: h[0] & 0x80 != 0,
+            size: be(&h[1..4]),
+        };
+        i += 4 + block.size;
+        let last = block.last;
+        blocks.push(block);
+        if last {
+            break;
+        }
+    }
+    blocks
+}
+
+// The comment header of an Ogg Vorbis or Opus file: the second packet of
+// the first stream, which can be split over several pages
+pub struct OggPacket {
+    // Where its first and last bytes are in the file
+    pub offset: usize,
+    pub end: usize,
+    pub pages: usize,
+    pub bytes: Vec<u8>,
+}
+
+pub fn ogg_comments(data: &[u8]) -> Option<OggPacket> {
+    let mut packet = OggPacket {
+        offset: 0,
+        end: 0,
+        pages: 0,
+        bytes: Vec::new(),
+    };
+    let (mut pos, mut serial, mut packets) = (0, None, 0);
+    while data.get(pos..pos + 4) == Some(b"OggS") {
+        let header = data.get(pos..pos + 27)?;
+        let lacing = data.get(pos + 27..pos + 27 + header[26] as usize)?;
+        let mut at = pos + 27 + lacing.len();
+        pos = at + lacing.iter().map(|&l| l as usize).sum::<usize>();
+        // Only the first stream
+        if *serial.get_or_insert(le(&header[14..18])) != le(&header[14..18]) {
+            continue;
+        }
+        let mut on_page = false;
+        for &l in lacing {
+            let segment = data.get(at..at + l as usize)?;
+            if packets == 1 {
+                if packet.bytes.is_empty() {
+                    packet.offset = at;
+                }
+                packet.bytes.extend_from_slice(segment);
+                packet.end = at + segment.len();
+                on_page = true;
+            }
+            at += segment.len();
+            if l < 255 {
+                packets += 1;
+                if packets == 2 {
+                    packet.pages += 1;
+                    return (packet.bytes.starts_with(b"\x03vorbis")
+                        || packet.bytes.starts_with(b"OpusTags"))
+                    .then_some(packet);
+                }
+            }
+        }
+        packet.pages += on_page as usize;
+    }
+    None
+}
+
+pub fn flac_block_name(block_type: u8) -> &'static str {
+    match block_type {
+        0 => "STREAMINFO",
+        1 => "PADDING",
+        2 => "APPLICATION",
+        3 => "SEEKTABLE",
+        4 => "VORBIS_COMMENT",
+        5 => "CUESHEET",
+        6 => "PICTURE",
+        _ => "UNKNOWN",
+    }
+}
+
+// Classic hex dump, with offsets relative to the start of the file
+pub fn hexdump(data: &[u8], start: usize, len: usize) -> String {
+    let end = data.len().min(start + len);
+    let shown = end.min(start + MAX_DUMP);
+    let mut out = String::new();
+    for (n, chunk) in data[start..shown].chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        let text: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "    {:08x}  {:<48}  {}",
+            start + n * 16,
+            hex.join(" "),
+            text
+        );
+    }
+    if end > shown {
+        let _ = writeln!(out, "    ... {} more bytes", end - shown);
+    }
+    out
+}
+
+pub fn dump(data: &[u8]) {
+    let mut found = false;
+
+    for h in find_id3v2(data, data.len().min(1 << 20)) {
+        found = true;
+        log!("");
+        log!(
+            "ID3v2.{}.{} at {:#x}, flags {:#04x}, size {} bytes",
+            h.major,
+            h.revision,
+            h.offset,
+            h.flags,
+            h.size
+        );
+        show(hexdump(data, h.offset, 10));
+        region("id3v2", h.offset, h.size + 10);
+        dump_id3v2_frames(data, &h);
+    }
+
+    let flac_start = match data.get(0..4) {
+        Some(b"fLaC") => Some(0),
+        _ => find_id3v2(data, 10)
+            .first()
+            .map(|h| h.end())
+            .filter(|&e| data.get(e..e + 4) == Some(b"fLaC")),
+    };
+    if let Some(start) = flac_start {
+        for b in flac_blocks(data, start) {
+            found = true;
+            log!("");
+            log!(
+                "FLAC {} block at {:#x}, size {} bytes{}",
+                flac_block_name(b.block_type),
+                b.offset,
+                b.size,
+                if b.last { " (last)" } else { "" }
+            );
+            let kind = flac_block_name(b.block_type).to_lowercase();
+            region(&format!("flac_{kind}"), b.offset, b.size + 4);
+            match b.block_type {
+                // Padding is just zeros, and pictures are too big to be useful
+                1 | 6 => show(hexdump(data, b.offset, 4)),
+                4 => {
+                    show(hexdump(data, b.offset, 4 + b.size));
+                    dump_vorbis_comments(data, b.offset + 4, b.size);
+                }
+                _ => show(hexdump(data, b.offset, 4 + b.size)),
+            }
+        }
+    }
+
+    if let Some(p) = ogg_comments(data) {
+        found = true;
+        log!("");
+        log!(
+            "Ogg comment header at {:#x}, {} bytes in {} page{}",
+            p.offset,
+            p.bytes.len(),
+            p.pages,
+            if p.pages == 1 { "" } else { "s" }
+        );
+        region("ogg_comments", p.offset, p.end - p.offset);
+        // Past "\x03vorbis" or "OpusTags"
+        let skip = if p.bytes.starts_with(b"OpusTags") {
+            8
+        } else {
+            7
+        };
+        if p.pages == 1 {
+            show(hexdump(data, p.offset, p.bytes.len()));
+            dump_vorbis_comments(data, p.offset + skip, p.bytes.len() - skip);
+        } else {
+            // The page headers in between would be in the way
+            log!("  Offsets are within the packet, not the file");
+            show(hexdump(&p.bytes, 0, p.bytes.len()));
+            dump_vorbis_comments(&p.bytes, skip, p.bytes.len() - skip);
+        }
+    }
+
+    if let Some((offset, size)) = ape_region(data) {
+        found = true;
+        log!("");
+        log!("APEv2 tag at {offset:#x}, size {size} bytes");
+        show(hexdump(data, offset, size));
+        region("ape", offset, size);
+    }
+
+    if let Some(offset) = id3v1_offset(data) {
+        found = true;
+        log!("");
+        log!("ID3v1 tag at {offset:#x}");
+        show(hexdump(data, offset, 128));
+        region("id3v1", offset, 128);
+    }
+
+    if !found {
+        log!("");
+        log!("No raw tag regions found");
+    }
+}
+
+// Where a tag region is, for --json
+fn region(kind: &str, offset: usize, size: usize) {
+    term::event(
+        "region",
+        json!({ "kind": kind, "offset": offset, "size": size }),
+    );
+}
+
+// A hexdump, without its last newline as log! adds one
+fn show(dump: String) {
+    if !dump.is_empty() {
+        log!("{}", dump.trim_end_matches('\n'));
+    }
+}
+
+fn dump_id3v2_frames(data: &[u8], h: &Id3v2Header) {
+    // ID3v2.2 uses 3 character ids and 3 byte sizes
+    let (id_len, size_len, header_len) = if h.major == 2 { (3, 3, 6) } else { (4, 4, 10) };
+    let mut i = h.offset + 10;
+    if h.flags & 0x40 != 0 && h.major > 2 {
+        let ext = match data.get(i..i + 4) {
+            Some(b) if h.major == 4 => syncsafe(b),
+            Some(b) => be(b) + 4,
+            None => return,
+        };
+        log!("  Extended header at {i:#x}, size {ext} bytes");
+        i += ext;
+    }
+    let end = (h.offset + 10 + h.size).min(data.len());
+    while i + header_len <= end {
+        let fh = &data[i..i + header_len];
+        if fh[0] == 0 {
+            log!("  Padding at {:#x}, {} bytes", i, end - i);
+            break;
+        }
+        let id = String::from_utf8_lossy(&fh[..id_len]);
+        let size_bytes = &fh[id_len..id_len + size_len];
+        let size = if h.major == 4 {
+            syncsafe(size_bytes)
+        } else {
+            be(size_bytes)
+        };
+        let flags = if header_len == 10 { be(&fh[8..10]) } else { 0 };
+        log!("  Frame {id} at {i:#x}, size {size} bytes, flags {flags:#06x}");
+        if i + header_len + size > end {
+            log!("    Frame runs past the end of the tag");
+        }
+        show(hexdump(data, i, (header_len + size).min(end - i)));
+        i += header_len + size;
+    }
+}
+
+// The text of the first id frame in an ID3v2 tag at the start of the
+// file. For frames lofty drops because it can't parse them, like a TDRC
+// that isn't a real date.
+pub fn id3v2_text(file_name: &str, id: &[u8; 4]) -> Option<String> {
+    let mut file = File::open(file_name).ok()?;
+    let mut header = [0; 10];
+    file.read_exact(&mut header).ok()?;
+    let h = id3v2_at(&header, 0)?;
+    if h.major < 3 {
+        return None;
+    }
+    let mut data = vec![0; h.size];
+    file.read_exact(&mut data).ok()?;
+
+    let mut i = 0;
+    if h.flags & 0x40 != 0 {
+        i += match data.get(0..4) {
+            Some(b) if h.major == 4 => syncsafe(b),
+            Some(b) => be(b) + 4,
+            None => return None,
+        };
+    }
+    while let Some(fh) = data.get(i..i + 10) {
+        if fh[0] == 0 {
+            return None;
+        }
+        let size = if h.major == 4 {
+            syncsafe(&fh[4..8])
+        } else {
+            be(&fh[4..8])
+        };
+        if &fh[..4] == id {
+            let body = data.get(i + 10..i + 10 + size)?;
+            return Some(decode_text(body));
+        }
+        i += 10 + size;
+    }
+    None
+}
+
+// An ID3v2 text frame body: an encoding byte, then the text
+fn decode_text(body: &[u8]) -> String {
+    let (encoding, text) = match body.split_first() {
+        Some((e, t)) => (*e, t),
+        None => return String::new(),
+    };
+    let text = match encoding {
+        1 | 2 => {
+            // UTF-16, with a BOM for 1 and big endian for 2
+            let (big, text) = match text {
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                _ => (encoding == 2, text),
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|c| match big {
+                    true => u16::from_be_bytes([c[0], c[1]]),
+                    false => u16::from_le_bytes([c[0], c[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).to_string(),
+        _ => text.iter().map(|&b| b as char).collect(),
+    };
+    text.trim_end_matches('\0').to_string()
+}
+
+fn dump_vorbis_comments(data: &[u8], start: usize, size: usize) {
+    let end = (start + size).min(data.len());
+    let mut i = start;
+    let read_len = |i: &mut usize| -> Option<usize> {
+        let n = le(data.get(*i..*i + 4)?);
+        *i += 4;
+        Some(n)
+    };
+    let vendor_len = match read_len(&mut i) {
+        Some(n) => n,
+        None => return,
+    };
+    if let Some(v) = data.get(i..i + vendor_len) {
+        log!("  Vendor at {:#x}: {:?}", i, String::from_utf8_lossy(v));
+    }
+    i += vendor_len;
+    let count = match read_len(&mut i) {
+        Some(n) => n,
+        None => return,
+    };
+    for _ in 0..count {
+        let at = i;
+        let len = match read_len(&mut i) {
+            Some(n) => n,
+            None => break,
+        };
+        match data.get(i..i + len).filter(|_| i + len <= end) {
+            Some(c) => log!("  Comment at {:#x}: {:?}", at, String::from_utf8_lossy(c)),
+            None => {
+                log!("  Comment at {at:#x} claims {len} bytes, past the end of the block");
+                break;
+            }
+        }
+        i += len;
+    }
+}
@@ -0,0 +1,89 @@
+// Files on a web server, read with range requests for the start and end
+// of the file rather than downloading all of it, so "tag_test inspect
+// <url>" or a URL in a --stdin list shows a remote file's tags. Tags at
+// the start (ID3v2, FLAC, Vorbis) or the end (ID3v1, APE, an MP4 moov
+// box that's small enough) are found; the length is worked out from what
+// lofty gets. Only built with the remote feature.
+
+pub fn is_url(file_name: &str) -> bool {
+    file_name.starts_with("http://") || file_name.starts_with("https://")
+}
+
+#[cfg(not(feature = "remote"))]
+pub fn read(
+    _url: &str,
+) -> Result<(Option<lofty::tag::Tag>, lofty::properties::FileProperties), lofty::error::LoftyError>
+{
+    let msg = "needs tag_test built with --features remote";
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, msg).into())
+}
+
+#[cfg(feature = "remote")]
+pub use fetch::read;
+
+#[cfg(feature = "remote")]
+mod fetch {
+    use lofty::error::LoftyError;
+    use lofty::prelude::*;
+    use lofty::probe::Probe;
+    use lofty::properties::FileProperties;
+    use lofty::tag::Tag;
+    use std::io::{self, Cursor, Read};
+
+    // Bytes fetched from the start and the end
+    const HEAD: u64 = 512 * 1024;
+    const TAIL: u64 = 128 * 1024;
+    // Servers that ignore Range send the whole file, up to this much is
+    // read of it
+    const MAX_WHOLE: u64 = 256 * 1024 * 1024;
+
+    // The bytes, and the file's size if the server sent only part of it
+    fn get(url: &str, range: &str) -> io::Result<(Vec<u8>, Option<u64>)> {
+        let resp = ureq::get(url)
+            .set("Range", &format!("bytes={range}"))
+            .set(
+                "User-Agent",
+                concat!("tag_test/", env!("CARGO_PKG_VERSION")),
+            )
+            .call()
+            .map_err(|e| match e {
+                ureq::Error::Status(code, _) => io::Error::other(format!("HTTP {code}")),
+                ureq::Error::Transport(t) => {
+                    io::Error::other(t.message().unwrap_or("connection failed").to_string())
+                }
+            })?;
+        // "bytes 0-524287/7340032"
+        let size = match resp.status() {
+            206 => resp
+                .header("Content-Range")
+                .and_then(|r| r.rsplit('/').next())
+                .and_then(|s| s.parse().ok()),
+            _ => None,
+        };
+        let mut data = Vec::new();
+        resp.into_reader().take(MAX_WHOLE).read_to_end(&mut data)?;
+        Ok((data, size))
+    }
+
+    pub fn read(url: &str) -> Result<(Option<Tag>, FileProperties), LoftyError> {
+        let (head, size) = get(url, &format!("0-{}", HEAD - 1))?;
+        // The parts in place in a file of the right size, zeros between
+        let data = match size {
+            Some(size) if size > head.len() as u64 => {
+                let size = usize::try_from(size).map_err(io::Error::other)?;
+                let (tail, _) = get(url, &format!("-{TAIL}"))?;
+                let mut data = vec![0; size];
+                data[..head.len()].copy_from_slice(&head);
+                let start = size.saturating_sub(tail.len());
+                data[start..].copy_from_slice(&tail[tail.len().saturating_sub(size)..]);
+                data
+            }
+            _ => head,
+        };
+        let tagged_file = Probe::new(Cursor::new(data)).guess_file_type()?.read()?;
+        Ok((
+            tagged_file.primary_tag().cloned(),
+            tagged_file.properties().clone(),
+        ))
+    }
+}
@@ -0,0 +1,246 @@
+// Try known fixes on files that lofty can't read. The original file is
+// always copied to <file>.bak before anything is written.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::raw::{self, Id3v2Header};
+use crate::{music_files, read_metadata, term, Config};
+use lofty::config::{ParseOptions, ParsingMode, WriteOptions};
+use lofty::id3::v2::Id3v2Tag;
+use lofty::mpeg::MpegFile;
+use lofty::prelude::*;
+use serde_json::json;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+pub const COMMAND: Command = Command {
+    name: "repair",
+    usage: "[--dry-run] [--all] [path...]",
+    about: "Fix files lofty can't read, keeping a .bak copy",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--all",
+            values: None,
+            about: "Also check files lofty can read",
+        },
+    ],
+};
+
+// How far into a file to look for an ID3v2 header after garbage
+const MAX_GARBAGE: usize = 64 * 1024;
+
+pub fn run(config: &Config, args: &[String]) {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    // Also check files lofty can read, it silently drops duplicate tags
+    let all = args.iter().any(|a| a == "--all");
+    let paths: Vec<String> = args
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .cloned()
+        .collect();
+
+    let (mut repaired, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        // Only touch files that can't be read as they are
+        if !all && read_metadata(&file_name).is_ok() {
+            continue;
+        }
+        match repair_file(&file_name, dry_run) {
+            Ok(true) => repaired += 1,
+            Ok(false) => {
+                if !all {
+                    log!("No known problems in {file_name}");
+                }
+            }
+            Err(e) => {
+                error!("Unable to repair {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Repairable" } else { "Repaired" },
+        repaired,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "repaired": repaired, "failed": failed }),
+    );
+}
+
+// Returns Ok(false) if no known problem was found
+fn repair_file(file_name: &str, dry_run: bool) -> Result<bool, String> {
+    let mut data = fs::read(file_name).map_err(|e| e.to_string())?;
+    let mut fixes = Vec::new();
+
+    // Garbage before the first ID3v2 header
+    if !starts_with_known_magic(&data) {
+        if let Some(offset) =
+            (1..data.len().min(MAX_GARBAGE)).find(|&i| raw::id3v2_at_lenient(&data, i).is_some())
+        {
+            fixes.push(format!(
+                "strip {offset} bytes of garbage before the ID3v2 tag"
+            ));
+            data.drain(..offset);
+        }
+    }
+
+    // Every ID3v2 tag at the start of the file, with sizes corrected
+    let mut tags = Vec::new();
+    let mut pos = 0;
+    while let Some(h) = raw::id3v2_at_lenient(&data, pos) {
+        let actual_end = skip_padding(&data, frames_end(&data, &h)).min(data.len());
+        // A truncated file can end inside the tag
+        let end = if h.end() > data.len() {
+            fixes.push(format!(
+                "fix size of ID3v2 tag at {:#x} from {} to {} bytes, the file ends before it",
+                h.offset,
+                h.size,
+                actual_end - h.offset - 10
+            ));
+            actual_end
+        } else if is_audio_start(&data, h.end()) || !is_audio_start(&data, actual_end) {
+            h.end()
+        } else {
+            fixes.push(format!(
+                "fix size of ID3v2 tag at {:#x} from {} to {} bytes",
+                h.offset,
+                h.size,
+                actual_end - h.offset - 10
+            ));
+            actual_end
+        };
+        tags.push((h, end));
+        pos = skip_padding(&data, end);
+    }
+    if tags.len() > 1 {
+        fixes.push(format!("merge {} ID3v2 tags into one", tags.len()));
+    }
+
+    if fixes.is_empty() {
+        return Ok(false);
+    }
+    log!("{file_name}:");
+    for fix in &fixes {
+        log!("  {fix}");
+    }
+    if dry_run {
+        return Ok(true);
+    }
+
+    // Rebuild one tag from whatever frames could be salvaged, the first
+    // tag wins when the same frame is in more than one
+    let audio = pos;
+    let mut merged: Option<Id3v2Tag> = None;
+    for (h, end) in &tags {
+        match salvage_tag(&data, h, *end, audio) {
+            Some(tag) => match merged.as_mut() {
+                Some(m) => {
+                    for frame in tag {
+                        if m.get(frame.id()).is_none() {
+                            m.insert(frame);
+                        }
+                    }
+                }
+                None => merged = Some(tag),
+            },
+            None => log!("  Nothing salvageable in the ID3v2 tag at {:#x}", h.offset),
+        }
+    }
+
+    let backup = format!("{file_name}.bak");
+    if Path::new(&backup).exists() {
+        return Err(format!("backup {backup} already exists"));
+    }
+    fs::copy(file_name, &backup).map_err(|e| format!("unable to write backup {backup}: {e}"))?;
+
+    fs::write(file_name, &data[audio..]).map_err(|e| e.to_string())?;
+    if let Some(tag) = merged {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| format!("unable to write rebuilt tag: {e}"))?;
+    }
+
+    match read_metadata(file_name) {
+        Ok(_) => log!("  Repaired, original saved as {backup}"),
+        Err(e) => error!("  Still unreadable ({e}), original saved as {backup}"),
+    }
+    Ok(true)
+}
+
+fn starts_with_known_magic(data: &[u8]) -> bool {
+    [
+        &b"ID3"[..],
+        b"fLaC",
+        b"OggS",
+        b"RIFF",
+        b"FORM",
+        b"MAC ",
+        b"wvpk",
+    ]
+    .iter()
+    .any(|m| data.starts_with(m))
+        || data.get(4..8) == Some(b"ftyp")
+        || is_mpeg_sync(data, 0)
+}
+
+fn is_mpeg_sync(data: &[u8], pos: usize) -> bool {
+    matches!(data.get(pos..pos + 2), Some([0xff, b]) if b & 0xe0 == 0xe0)
+}
+
+// Whether the audio (or another tag) starts at pos
+fn is_audio_start(data: &[u8], pos: usize) -> bool {
+    is_mpeg_sync(data, pos)
+        || data.get(pos..pos + 4) == Some(b"fLaC")
+        || raw::id3v2_at_lenient(data, pos).is_some()
+}
+
+fn skip_padding(data: &[u8], pos: usize) -> usize {
+    pos + data.iter().skip(pos).take_while(|&&b| b == 0).count()
+}
+
+// Where the frames of a tag really end, ignoring the size in the header
+fn frames_end(data: &[u8], h: &Id3v2Header) -> usize {
+    let (id_len, header_len) = if h.major == 2 { (3, 6) } else { (4, 10) };
+    let mut i = h.offset + 10;
+    while let Some(fh) = data.get(i..i + header_len) {
+        if !fh[..id_len]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            break;
+        }
+        let size_bytes = &fh[id_len..id_len * 2];
+        let size = if h.major == 4 {
+            raw::syncsafe(size_bytes)
+        } else {
+            raw::be(size_bytes)
+        };
+        if i + header_len + size > data.len() {
+            break;
+        }
+        i += header_len + size;
+    }
+    i
+}
+
+// Parse a single tag by handing lofty the tag followed by the audio
+fn salvage_tag(data: &[u8], h: &Id3v2Header, end: usize, audio: usize) -> Option<Id3v2Tag> {
+    let size = end - h.offset - 10;
+    let mut buf = data[h.offset..end].to_vec();
+    buf[5] &= !0x10;
+    let size_bytes: Vec<u8> = (0..4)
+        .rev()
+        .map(|n| ((size >> (n * 7)) & 0x7f) as u8)
+        .collect();
+    buf[6..10].copy_from_slice(&size_bytes);
+    buf.extend_from_slice(&data[audio..data.len().min(audio + MAX_GARBAGE)]);
+
+    let options = ParseOptions::new()
+        .parsing_mode(ParsingMode::Relaxed)
+        .read_properties(false);
+    let mut file = MpegFile::read_from(&mut Cursor::new(buf), options).ok()?;
+    file.remove_id3v2().filter(|t| !t.is_empty())
+}
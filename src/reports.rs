@@ -0,0 +1,782 @@
+// Reports printed after the scan summary, each one switched on in the
+// [reports] section of the config
+use crate::albums::albums;
+use crate::dates;
+use crate::featured::{self, FeaturedConfig};
+use crate::genres::{self, Taxonomy};
+use crate::layout;
+use crate::links;
+use crate::m3u::{self, Playlist};
+use crate::placeholders::{self, PlaceholdersConfig};
+use crate::rips;
+use crate::sortnames::{self, SortNamesConfig};
+use crate::spellings;
+use crate::works::works;
+use crate::{fields, file_ext, format, ogg, scripts, term, Config, ScanStats, TrackInfo};
+use itertools::Itertools;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ReportsConfig {
+    // List tracks without a BPM or initial key tag
+    pub missing_bpm_key: bool,
+    // List albums missing tracks, going by the total tracks tags
+    pub missing_tracks: bool,
+    // List classical tracks by composer and work, and check works for
+    // missing movements rather than albums for missing tracks
+    pub classical: bool,
+    // List albums that have both lossy and lossless copies of tracks, and
+    // the space the lossy copies take
+    pub mixed_formats: bool,
+    // Show the space used by artist, album, genre and format
+    pub space: bool,
+    // Rows in each part of the space report, 0 = all
+    pub space_top: usize,
+    // size, tracks or name
+    pub space_sort: String,
+    // Count tracks by language tag, and list tracks with text in other
+    // scripts than these or that looks mis-encoded
+    pub languages: bool,
+    pub scripts: Vec<String>,
+    // List artists and albums without sort names that should have one,
+    // going by the [sort_names] rules
+    pub sort_names: bool,
+    // List featured artists and the tracks with them in the artist tag
+    pub featured: bool,
+    // List invalid date tags, and albums whose tracks have different
+    // dates or dates at different precisions
+    pub dates: bool,
+    // Count tracks missing each of the main fields, with placeholder
+    // values counted as missing, and list the placeholders
+    pub completeness: bool,
+    // Find chained Ogg files, e.g. radio rips, during the scan, count all
+    // their streams in the length, and list the streams
+    pub chained: bool,
+    // Count tracks by the value of each of the [fields]
+    pub fields: bool,
+    // List artists and albums spelt more than one way, see [spellings]
+    pub spellings: bool,
+    // List album directories not where the [layout] pattern puts them
+    pub layout: bool,
+    // Check album directories against their cue sheets and rip logs, for
+    // missing or doubled tracks, and list the logs' rip quality
+    pub rips: bool,
+    // Seconds an album's length can be off from its rip log's
+    pub rips_tolerance: f64,
+    // Check lossless tracks' audio against the logs' CRCs
+    pub rips_verify: bool,
+    // Check the m3u playlists in the scan directories for missing files,
+    // files in twice, and absolute and relative paths mixed
+    pub m3u: bool,
+    // Count tracks by top genre, and list genres not in the [genres]
+    // taxonomy
+    pub genres: bool,
+    // List files hard linked to each other, and the space that saves
+    pub links: bool,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        ReportsConfig {
+            missing_bpm_key: false,
+            missing_tracks: false,
+            classical: false,
+            mixed_formats: false,
+            space: false,
+            space_top: 10,
+            space_sort: String::from("size"),
+            languages: false,
+            scripts: vec![String::from("Latin")],
+            sort_names: false,
+            featured: false,
+            dates: false,
+            completeness: false,
+            chained: false,
+            fields: false,
+            spellings: false,
+            layout: false,
+            rips: false,
+            rips_tolerance: 5.0,
+            rips_verify: false,
+            m3u: false,
+            genres: false,
+            links: false,
+        }
+    }
+}
+
+// A report on the scan's tracks, run after the summary when it's switched
+// on. Add new ones to REPORTS, behind a feature if they need more crates.
+pub trait Report {
+    // Its switch in [reports]
+    fn name(&self) -> &'static str;
+    fn enabled(&self, config: &Config) -> bool;
+    fn run(&self, config: &Config, stats: &ScanStats);
+}
+
+struct Builtin {
+    name: &'static str,
+    enabled: fn(&ReportsConfig) -> bool,
+    run: fn(&Config, &ScanStats),
+}
+
+impl Report for Builtin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        (self.enabled)(&config.reports)
+    }
+
+    fn run(&self, config: &Config, stats: &ScanStats) {
+        (self.run)(config, stats)
+    }
+}
+
+// In the order they're printed
+const REPORTS: &[&dyn Report] = &[
+    &Builtin {
+        name: "missing_bpm_key",
+        enabled: |r| r.missing_bpm_key,
+        run: |_, s| missing_bpm_key(s),
+    },
+    &Builtin {
+        name: "missing_tracks",
+        enabled: |r| r.missing_tracks,
+        run: |c, s| missing_tracks(s, c.reports.classical),
+    },
+    &Builtin {
+        name: "classical",
+        enabled: |r| r.classical,
+        run: |_, s| classical(s),
+    },
+    &Builtin {
+        name: "mixed_formats",
+        enabled: |r| r.mixed_formats,
+        run: |_, s| mixed_formats(s),
+    },
+    &Builtin {
+        name: "space",
+        enabled: |r| r.space,
+        run: |c, s| space(s, &c.reports),
+    },
+    &Builtin {
+        name: "languages",
+        enabled: |r| r.languages,
+        run: |c, s| languages(s, &c.reports),
+    },
+    &Builtin {
+        name: "sort_names",
+        enabled: |r| r.sort_names,
+        run: |c, s| sort_names(s, &c.sort_names),
+    },
+    &Builtin {
+        name: "featured",
+        enabled: |r| r.featured,
+        run: |c, s| featured(s, &c.featured),
+    },
+    &Builtin {
+        name: "dates",
+        enabled: |r| r.dates,
+        run: |_, s| dates(s),
+    },
+    &Builtin {
+        name: "completeness",
+        enabled: |r| r.completeness,
+        run: |c, s| completeness(s, &c.placeholders),
+    },
+    &Builtin {
+        name: "chained",
+        enabled: |r| r.chained,
+        run: |_, s| chained(s),
+    },
+    &Builtin {
+        name: "fields",
+        enabled: |r| r.fields,
+        run: |_, s| computed_fields(s),
+    },
+    &Builtin {
+        name: "spellings",
+        enabled: |r| r.spellings,
+        run: |c, s| spellings(s, c.spellings.similarity),
+    },
+    &Builtin {
+        name: "layout",
+        enabled: |r| r.layout,
+        run: layout,
+    },
+    &Builtin {
+        name: "rips",
+        enabled: |r| r.rips,
+        run: |c, s| rips(s, &c.reports),
+    },
+    &Builtin {
+        name: "m3u",
+        enabled: |r| r.m3u,
+        run: |_, s| m3u(s),
+    },
+    &Builtin {
+        name: "genres",
+        enabled: |r| r.genres,
+        run: genres,
+    },
+    &Builtin {
+        name: "links",
+        enabled: |r| r.links,
+        run: |_, s| links::report(s),
+    },
+];
+
+pub fn by_name(name: &str) -> Option<&'static dyn Report> {
+    REPORTS.iter().copied().find(|r| r.name() == name)
+}
+
+// The reports switched on
+pub fn enabled(config: &Config) -> impl Iterator<Item = &'static dyn Report> + '_ {
+    REPORTS.iter().copied().filter(|r| r.enabled(config))
+}
+
+pub fn run(config: &Config, stats: &ScanStats) {
+    for report in enabled(config) {
+        run_one(report, config, stats);
+    }
+}
+
+pub fn run_one(report: &dyn Report, config: &Config, stats: &ScanStats) {
+    term::event("report", json!({ "name": report.name() }));
+    report.run(config, stats);
+}
+
+fn missing_bpm_key(stats: &ScanStats) {
+    let no_bpm: Vec<_> = stats.tracks.iter().filter(|t| t.bpm.is_none()).collect();
+    total!("Tracks missing BPM: {}", no_bpm.len());
+    for t in no_bpm {
+        match t.estimated_bpm {
+            Some(b) => log!("  {} (estimated {} BPM)", t.path, format::decimal(b, 1)),
+            None => log!("  {}", t.path),
+        }
+    }
+
+    let no_key: Vec<_> = stats.tracks.iter().filter(|t| t.key.is_none()).collect();
+    total!("Tracks missing key: {}", no_key.len());
+    for t in no_key {
+        log!("  {}", t.path);
+    }
+}
+
+fn missing_tracks(stats: &ScanStats, classical: bool) {
+    let incomplete: Vec<_> = albums(&stats.tracks)
+        .into_iter()
+        .filter(|a| a.is_incomplete())
+        .filter(|a| !classical || a.tracks.iter().any(|t| t.work.is_none()))
+        .collect();
+    total!("Albums missing tracks: {}", incomplete.len());
+    for a in incomplete {
+        log!("  {} - {}", a.artist, a.title);
+        let discs = a.discs();
+        for &d in &discs {
+            let missing = a.missing_tracks(d);
+            if missing.is_empty() {
+                continue;
+            }
+            let mut have: Vec<u32> = a
+                .disc_tracks(d)
+                .map(|t| t.track)
+                .filter(|&n| n > 0)
+                .collect();
+            have.dedup();
+            log!(
+                "    {}have {} of {}, missing {}",
+                if discs.len() > 1 {
+                    format!("disc {d}: ")
+                } else {
+                    String::new()
+                },
+                ranges(&have),
+                a.disc_track_total(d).unwrap_or(0),
+                ranges(&missing)
+            );
+        }
+        let missing = a.missing_discs();
+        if !missing.is_empty() {
+            log!("    missing discs {}", ranges(&missing));
+        }
+    }
+}
+
+fn classical(stats: &ScanStats) {
+    let works = works(&stats.tracks);
+    total!("Classical works: {}", works.len());
+    let mut composer = "";
+    for w in &works {
+        if w.composer != composer {
+            composer = w.composer;
+            log!("  {composer}");
+        }
+        let conductors = w.conductors();
+        log!(
+            "    {}{}",
+            w.title,
+            if conductors.is_empty() {
+                String::new()
+            } else {
+                format!(" (cond. {})", conductors.join(", "))
+            }
+        );
+        for t in &w.tracks {
+            log!(
+                "      {}{}",
+                t.movement_number
+                    .map_or(String::new(), |n| format!("{n}. ")),
+                t.movement.as_deref().unwrap_or(&t.title)
+            );
+        }
+        let missing = w.missing_movements();
+        if !missing.is_empty() {
+            log!(
+                "      missing movements {} of {}",
+                ranges(&missing),
+                w.movement_total().unwrap_or(0)
+            );
+        }
+    }
+}
+
+// m4a can be AAC or ALAC, so it's in neither list
+pub const LOSSLESS: &[&str] = &["flac", "wav", "aiff", "aif", "ape", "wv"];
+const LOSSY: &[&str] = &["mp3", "mp3a", "ogg", "opus", "aac", "wma"];
+
+fn mixed_formats(stats: &ScanStats) {
+    let mut mixed = Vec::new();
+    for a in albums(&stats.tracks) {
+        // The same track is the same disc, number and title
+        let mut copies: BTreeMap<_, Vec<&TrackInfo>> = BTreeMap::new();
+        for &t in &a.tracks {
+            let key = (crate::albums::disc(t), t.track, t.title.to_lowercase());
+            copies.entry(key).or_default().push(t);
+        }
+        let lossy: Vec<&TrackInfo> = copies
+            .into_values()
+            .filter(|c| {
+                c.iter()
+                    .any(|t| LOSSLESS.contains(&file_ext(&t.path).as_str()))
+            })
+            .flat_map(|c| c.into_iter())
+            .filter(|t| LOSSY.contains(&file_ext(&t.path).as_str()))
+            .collect();
+        if !lossy.is_empty() {
+            let bytes = links::size(lossy.iter().copied());
+            mixed.push((a.artist, a.title, lossy.len(), bytes));
+        }
+    }
+    let total: u64 = mixed.iter().map(|m| m.3).sum();
+    total!(
+        "Albums with lossy and lossless copies: {}, lossy copies take {}",
+        mixed.len(),
+        format::size(total)
+    );
+    for (artist, title, tracks, bytes) in mixed {
+        log!(
+            "  {artist} - {title}: {tracks} lossy copies, {}",
+            format::size(bytes)
+        );
+    }
+}
+
+fn space(stats: &ScanStats, rc: &ReportsConfig) {
+    let total = links::size(&stats.tracks);
+    total!(
+        "Space used: {} in {} tracks",
+        format::size(total),
+        format::count(stats.tracks.len() as u64)
+    );
+    let by = |name: &str, key: fn(&TrackInfo) -> String| {
+        // name -> tracks
+        let mut groups: BTreeMap<String, Vec<&TrackInfo>> = BTreeMap::new();
+        for t in &stats.tracks {
+            groups.entry(key(t)).or_default().push(t);
+        }
+        let mut rows: Vec<(String, (u64, u64))> = groups
+            .into_iter()
+            .map(|(k, g)| (k, (links::size(g.iter().copied()), g.len() as u64)))
+            .collect();
+        match rc.space_sort.as_str() {
+            "name" => (),
+            "tracks" => rows.sort_by_key(|r| std::cmp::Reverse(r.1 .1)),
+            _ => rows.sort_by_key(|r| std::cmp::Reverse(r.1 .0)),
+        }
+        if rc.space_top > 0 {
+            rows.truncate(rc.space_top);
+        }
+        log!("  By {name}:");
+        for (key, (bytes, tracks)) in rows {
+            log!(
+                "    {:>10}  {:>7}  {}",
+                format::size(bytes),
+                format::count(tracks),
+                if key.is_empty() { "(none)" } else { &key }
+            );
+        }
+    };
+    by("artist", |t| t.artist.to_string());
+    by("album", |t| {
+        format!("{} - {}", t.artist, crate::albums::split_disc(&t.album).0)
+    });
+    by("genre", |t| t.genre.to_string());
+    by("format", |t| file_ext(&t.path));
+}
+
+fn languages(stats: &ScanStats, rc: &ReportsConfig) {
+    let count = |key: fn(&TrackInfo) -> &Option<String>| {
+        let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+        for t in &stats.tracks {
+            *counts
+                .entry(key(t).as_deref().unwrap_or("(none)"))
+                .or_default() += 1;
+        }
+        counts
+    };
+    for (name, counts) in [
+        ("Language tags", count(|t| &t.language)),
+        ("Lyrics languages", count(|t| &t.lyrics_language)),
+    ] {
+        total!("{name}: {}", counts.len());
+        for (language, tracks) in counts {
+            log!("  {:>7}  {language}", format::count(tracks));
+        }
+    }
+
+    let text = |t: &TrackInfo| format!("{} {} {}", t.title, t.artist, t.album);
+    let mut other = Vec::new();
+    for t in &stats.tracks {
+        let found: Vec<_> = scripts::scripts(&text(t))
+            .into_iter()
+            .filter(|s| !rc.scripts.iter().any(|e| e.eq_ignore_ascii_case(s)))
+            .collect();
+        if !found.is_empty() {
+            other.push((t, found));
+        }
+    }
+    total!("Tracks in other scripts: {}", other.len());
+    for (t, found) in other {
+        log!("  {} ({})", t.path, found.join(", "));
+    }
+
+    let garbled: Vec<_> = stats
+        .tracks
+        .iter()
+        .filter(|t| scripts::mis_encoded(&text(t)))
+        .collect();
+    total!("Tracks that look mis-encoded: {}", garbled.len());
+    for t in garbled {
+        log!("  {}: {} - {}", t.path, t.artist, t.title);
+    }
+}
+
+fn sort_names(stats: &ScanStats, sc: &SortNamesConfig) {
+    // name -> sort name
+    let mut artists = BTreeMap::new();
+    let mut albums = BTreeMap::new();
+    for t in &stats.tracks {
+        if t.artist_sort.is_none() {
+            if let Some(s) = sortnames::sort_name(sc, &t.artist) {
+                artists.insert(t.artist.to_string(), s);
+            }
+        }
+        if t.album_sort.is_none() {
+            if let Some(s) = sortnames::sort_name(sc, &t.album) {
+                albums.insert(format!("{} - {}", t.artist, t.album), s);
+            }
+        }
+    }
+    for (name, missing) in [("Artists", artists), ("Albums", albums)] {
+        total!("{name} missing sort names: {}", missing.len());
+        for (n, s) in missing {
+            log!("  {n} ({s})");
+        }
+    }
+}
+
+fn featured(stats: &ScanStats, fc: &FeaturedConfig) {
+    // Guest -> main artists they feature with
+    let mut guests: BTreeMap<String, BTreeMap<String, u64>> = BTreeMap::new();
+    let mut tracks = Vec::new();
+    for t in &stats.tracks {
+        if let Some((main, featured)) = featured::split(fc, &t.artist) {
+            for f in featured {
+                *guests
+                    .entry(f)
+                    .or_default()
+                    .entry(main.clone())
+                    .or_default() += 1;
+            }
+            tracks.push(t);
+        }
+    }
+    total!("Featured artists: {}", guests.len());
+    for (guest, with) in guests {
+        let with: Vec<String> = with
+            .into_iter()
+            .map(|(main, n)| format!("{main} ({n})"))
+            .collect();
+        log!("  {guest}: {}", with.join(", "));
+    }
+    total!("Tracks with features in the artist tag: {}", tracks.len());
+    for t in tracks {
+        log!("  {}: {}", t.path, t.artist);
+    }
+}
+
+fn dates(stats: &ScanStats) {
+    let invalid: Vec<_> = stats
+        .tracks
+        .iter()
+        .filter_map(|t| {
+            let d = t.date.as_ref()?;
+            dates::parse(d).err().map(|e| (t, d, e))
+        })
+        .collect();
+    total!("Tracks with invalid dates: {}", invalid.len());
+    for (t, d, e) in invalid {
+        log!("  {}: {d:?} {e}", t.path);
+    }
+
+    let mut mixed = Vec::new();
+    for a in albums(&stats.tracks) {
+        let dates: Vec<_> = a
+            .tracks
+            .iter()
+            .filter_map(|t| t.date.as_deref().and_then(|d| dates::parse(d).ok()))
+            .collect();
+        let precisions: Vec<_> = dates.iter().map(|d| d.precision()).unique().collect();
+        let values: Vec<_> = dates.iter().map(|d| d.format(3)).unique().collect();
+        if precisions.len() > 1 || values.len() > 1 {
+            mixed.push((a.artist, a.title, values));
+        }
+    }
+    total!("Albums with mixed dates: {}", mixed.len());
+    for (artist, title, values) in mixed {
+        log!("  {artist} - {title}: {}", values.join(", "));
+    }
+}
+
+type Field = fn(&TrackInfo) -> Option<&str>;
+
+fn completeness(stats: &ScanStats, pc: &PlaceholdersConfig) {
+    let fields: [(&str, Field); 5] = [
+        ("title", |t| Some(&t.title)),
+        ("artist", |t| Some(&t.artist)),
+        ("album", |t| Some(&t.album)),
+        ("genre", |t| Some(&t.genre)),
+        ("date", |t| t.date.as_deref()),
+    ];
+    let mut placeholders = Vec::new();
+    let mut missing = vec![0; fields.len()];
+    let mut no_track = 0;
+    for t in &stats.tracks {
+        for (i, (name, field)) in fields.iter().enumerate() {
+            match field(t).map(str::trim) {
+                None | Some("") => missing[i] += 1,
+                Some(v) if placeholders::is_placeholder(pc, v) => {
+                    missing[i] += 1;
+                    placeholders.push((t, *name, v));
+                }
+                _ => (),
+            }
+        }
+        if t.track == 0 {
+            no_track += 1;
+        }
+    }
+    total!("Tracks missing fields, placeholders included:");
+    for ((name, _), n) in fields.iter().zip(missing) {
+        log!("  {:>7}  {name}", format::count(n));
+    }
+    log!("  {:>7}  track number", format::count(no_track));
+    total!("Placeholder values: {}", placeholders.len());
+    for (t, name, v) in placeholders {
+        log!("  {}: {name} {v:?}", t.path);
+    }
+}
+
+fn chained(stats: &ScanStats) {
+    let chained: Vec<_> = stats.tracks.iter().filter(|t| t.chains.is_some()).collect();
+    total!("Chained Ogg files, to split: {}", chained.len());
+    for t in chained {
+        log!("  {} ({})", t.path, format::duration(t.duration));
+        // Read again for the per stream details, there aren't many
+        for (i, c) in ogg::chains(&t.path).unwrap_or_default().iter().enumerate() {
+            log!(
+                "    {}. {} {} - {} ({})",
+                i + 1,
+                format::duration(c.duration),
+                c.artist.as_deref().unwrap_or("?"),
+                c.title.as_deref().unwrap_or("?"),
+                c.codec
+            );
+        }
+    }
+}
+
+// Sorted numbers as "1-3, 5, 7-9"
+fn ranges(numbers: &[u32]) -> String {
+    let mut out: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < numbers.len() {
+        let start = numbers[i];
+        while i + 1 < numbers.len() && numbers[i + 1] == numbers[i] + 1 {
+            i += 1;
+        }
+        match numbers[i] {
+            end if end == start => out.push(start.to_string()),
+            end => out.push(format!("{start}-{end}")),
+        }
+        i += 1;
+    }
+    if out.is_empty() {
+        String::from("none")
+    } else {
+        out.join(", ")
+    }
+}
+
+fn computed_fields(stats: &ScanStats) {
+    for name in fields::names() {
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for t in &stats.tracks {
+            *counts.entry(fields::value(t, name)).or_default() += 1;
+        }
+        total!("Tracks by {name}: {} values", counts.len());
+        for (value, tracks) in counts {
+            let value = if value.is_empty() { "(none)" } else { &value };
+            log!("  {:>7}  {value}", format::count(tracks));
+        }
+    }
+}
+
+fn spellings(stats: &ScanStats, similarity: f64) {
+    let show = |c: &spellings::Cluster| {
+        c.others
+            .iter()
+            .map(|(name, tracks)| format!("{name:?} ({tracks})"))
+            .join(", ")
+    };
+    let artists = spellings::artist_clusters(&stats.tracks, similarity);
+    total!("Artists spelt more than one way: {}", artists.len());
+    for c in &artists {
+        log!("  {:?}: {}", c.canonical, show(c));
+    }
+    let albums = spellings::album_clusters(&stats.tracks, similarity);
+    total!("Albums spelt more than one way: {}", albums.len());
+    for (artist, c) in &albums {
+        log!("  {artist} - {:?}: {}", c.canonical, show(c));
+    }
+}
+
+fn layout(config: &Config, stats: &ScanStats) {
+    let misplaced = match layout::misplaced(config, &stats.tracks) {
+        Some(m) => m,
+        None => {
+            warn!("The layout report needs a pattern in [layout]");
+            return;
+        }
+    };
+    total!("Directories not following the layout: {}", misplaced.len());
+    for (dir, expected) in &misplaced {
+        log!("  {} -> {}", dir.display(), expected.display());
+    }
+}
+
+fn rips(stats: &ScanStats, rc: &ReportsConfig) {
+    let tolerance = Duration::from_secs_f64(rc.rips_tolerance.max(0.0));
+    let rips = rips::check(&stats.tracks, tolerance, rc.rips_verify);
+    let problems = rips.values().filter(|r| !r.problems.is_empty()).count();
+    total!("Rips: {}, with problems: {problems}", rips.len());
+    for (dir, rip) in &rips {
+        log!("  {}", dir.display());
+        for line in rip.quality.iter().chain(&rip.problems) {
+            log!("    {line}");
+        }
+    }
+}
+
+fn m3u(stats: &ScanStats) {
+    let mut bad = 0;
+    for file in &stats.playlists {
+        let pl = match Playlist::read(Path::new(file)) {
+            Ok(pl) => pl,
+            Err(e) => {
+                error!("Error reading {file}: {e}");
+                continue;
+            }
+        };
+        let p = m3u::check(&pl);
+        if !p.any() {
+            continue;
+        }
+        bad += 1;
+        log!("  {file}");
+        for line in &p.dead {
+            log!("    missing: {line}");
+        }
+        for line in &p.twice {
+            log!("    in twice: {line}");
+        }
+        if p.absolute > 0 && p.relative > 0 {
+            log!(
+                "    {} absolute and {} relative paths",
+                p.absolute,
+                p.relative
+            );
+        }
+    }
+    total!("Playlists: {}, with problems: {bad}", stats.playlists.len());
+}
+
+fn genres(config: &Config, stats: &ScanStats) {
+    let taxonomy = match Taxonomy::load(&config.genres) {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Error reading {}: {e}", config.genres.taxonomy);
+            return;
+        }
+    };
+    // Top genre -> tracks and length
+    let mut tops: BTreeMap<&str, (u64, Duration)> = BTreeMap::new();
+    let mut unknown: BTreeMap<&str, u64> = BTreeMap::new();
+    for t in &stats.tracks {
+        let genre = t.genre.trim();
+        let path = match taxonomy.as_ref().map(|tx| tx.path(genre)) {
+            _ if genre.is_empty() => "(none)",
+            Some(Some(p)) => p,
+            Some(None) => {
+                *unknown.entry(genre).or_default() += 1;
+                genre
+            }
+            None => genre,
+        };
+        let top = tops.entry(genres::top(&config.genres, path)).or_default();
+        top.0 += 1;
+        top.1 += t.duration;
+    }
+    total!("Top genres: {}", tops.len());
+    for (genre, (tracks, length)) in &tops {
+        log!(
+            "  {:>7}  {:>9}  {genre}",
+            format::count(*tracks),
+            format::duration(*length)
+        );
+    }
+    if taxonomy.is_some() {
+        total!("Genres not in the taxonomy: {}", unknown.len());
+        for (genre, tracks) in unknown {
+            log!("  {genre} ({tracks})");
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Which copies of a recording to keep, for duplicates --plan and
+// --resolve. A lossless copy beats a lossy one, then the higher bitrate
+// wins, then the copy with more of its tags filled in. A plan can be
+// saved, looked over or edited, and carried out later with --apply.
+use crate::history;
+use crate::reports::LOSSLESS;
+use crate::{file_ext, read_metadata, term, trash};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize)]
+pub struct Plan {
+    pub groups: Vec<Group>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Group {
+    pub keep: String,
+    pub remove: Vec<String>,
+}
+
+// Best first. Files that can't be read go last.
+fn rank(files: &[String]) -> Vec<String> {
+    let mut ranked: Vec<(String, (bool, bool, u32, usize))> = files
+        .iter()
+        .map(|f| {
+            let key = match read_metadata(f) {
+                Ok(t) => (
+                    true,
+                    LOSSLESS.contains(&file_ext(f).as_str()),
+                    t.bitrate.unwrap_or(0),
+                    history::fields(&t)
+                        .iter()
+                        .filter(|(_, v)| !v.is_empty())
+                        .count(),
+                ),
+                Err(_) => (false, false, 0, 0),
+            };
+            (f.clone(), key)
+        })
+        .collect();
+    ranked.sort_by_key(|(f, key)| (Reverse(*key), f.clone()));
+    ranked.into_iter().map(|(f, _)| f).collect()
+}
+
+pub fn plan(groups: &[Vec<String>]) -> Plan {
+    let groups = groups
+        .iter()
+        .map(|g| {
+            let mut ranked = rank(g).into_iter();
+            Group {
+                keep: ranked.next().unwrap_or_default(),
+                remove: ranked.collect(),
+            }
+        })
+        .collect();
+    Plan { groups }
+}
+
+pub fn show(plan: &Plan) {
+    for g in &plan.groups {
+        log!("Keep {}", g.keep);
+        for f in &g.remove {
+            log!("  remove {f}");
+        }
+        term::event("plan", json!(g));
+    }
+    total!(
+        "Groups: {}, To remove: {}",
+        plan.groups.len(),
+        plan.groups.iter().map(|g| g.remove.len()).sum::<usize>()
+    );
+}
+
+pub fn save(plan: &Plan, file: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(plan).map_err(|e| e.to_string())?;
+    fs::write(file, json).map_err(|e| e.to_string())
+}
+
+pub fn load(file: &str) -> Result<Plan, String> {
+    let json = fs::read_to_string(file).map_err(|e| e.to_string())?;
+    serde_json::from_str(&json).map_err(|e| e.to_string())
+}
+
+// Removes the files, to the trash unless permanent. A group whose kept
+// copy has gone is left alone, so an edited plan can't remove every copy.
+pub fn apply(plan: &Plan, dry_run: bool, permanent: bool) {
+    let (mut removed, mut skipped, mut failed) = (0, 0, 0);
+    for g in &plan.groups {
+        if !Path::new(&g.keep).is_file() {
+            warn!("Skipping the copies of {}, it isn't there", g.keep);
+            skipped += g.remove.len();
+            continue;
+        }
+        for f in g.remove.iter().filter(|f| **f != g.keep) {
+            log!("Removing {f}");
+            if dry_run {
+                removed += 1;
+                continue;
+            }
+            match trash::remove(Path::new(f), permanent) {
+                Ok(_) => removed += 1,
+                Err(e) => {
+                    error!("Error removing {f}: {e}");
+                    term::event("error", json!({ "path": f, "message": e.to_string() }));
+                    failed += 1;
+                }
+            }
+        }
+    }
+    total!(
+        "{} {}, Skipped: {}, Failed: {}",
+        if dry_run { "Would remove" } else { "Removed" },
+        removed,
+        skipped,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({
+            "dry_run": dry_run,
+            "removed": removed,
+            "skipped": skipped,
+            "failed": failed,
+        }),
+    );
+}
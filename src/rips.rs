@@ -0,0 +1,400 @@
+// Check ripped albums against the cue sheets and rip logs next to them. A
+// rip log's table of contents has the length of every track on the CD, so
+// an album directory adding up to much more or less than that has tracks
+// missing or in twice. A cue sheet names the files of a rip, or the
+// track starts in a single image file. The logs also say how good the rip
+// was, AccurateRip's verdict and any read errors, and have each track's
+// CRC, which the files' decoded audio can be checked against.
+use crate::albums::disc;
+use crate::analysis::audio_crc;
+use crate::reports::LOSSLESS;
+use crate::{file_ext, format, TrackInfo};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// CD frames (sectors) a second
+const FRAMES: u64 = 75;
+
+#[derive(Default)]
+pub struct Cue {
+    // FILE lines, in order
+    pub files: Vec<String>,
+    pub tracks: u32,
+    // Where the last track starts in its file
+    pub last_start: Duration,
+}
+
+// mm:ss:ff, with ff in frames
+fn cue_time(s: &str) -> Option<Duration> {
+    let mut parts = s.split(':').map(|p| p.parse::<u64>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(Duration::from_millis(
+        (m * 60 + s) * 1000 + f * 1000 / FRAMES,
+    ))
+}
+
+pub fn read_cue(file: &Path) -> Option<Cue> {
+    let contents = String::from_utf8_lossy(&fs::read(file).ok()?).to_string();
+    let mut cue = Cue::default();
+    for line in contents.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("FILE ") {
+            let name = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next(),
+                None => rest.split_whitespace().next(),
+            };
+            cue.files.extend(name.map(String::from));
+        } else if line.starts_with("TRACK ") && line.ends_with("AUDIO") {
+            cue.tracks += 1;
+        } else if let Some(time) = line.strip_prefix("INDEX 01 ") {
+            cue.last_start = cue_time(time.trim()).unwrap_or_default();
+        }
+    }
+    Some(cue)
+}
+
+// EAC logs are usually UTF-16
+fn read_text(file: &Path) -> Option<String> {
+    let bytes = fs::read(file).ok()?;
+    match bytes.strip_prefix(&[0xff, 0xfe]) {
+        Some(utf16) => Some(String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>(),
+        )),
+        None => Some(String::from_utf8_lossy(&bytes).to_string()),
+    }
+}
+
+// Track lengths from the log's table of contents, going by the start and
+// end sectors. EAC and XLD have a table, with the sectors in the last two
+// columns, and whipper has "Start sector:" and "End sector:" lines.
+pub fn log_lengths(file: &Path) -> Vec<Duration> {
+    let text = match read_text(file) {
+        Some(t) => t,
+        None => return Vec::new(),
+    };
+    let sectors = |start: u64, end: u64| {
+        Duration::from_millis((end.saturating_sub(start) + 1) * 1000 / FRAMES)
+    };
+    let mut lengths = Vec::new();
+    let mut start = None;
+    for line in text.lines().map(str::trim) {
+        let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+        if let [n, _, _, s, e] = columns[..] {
+            if let (Ok(_), Ok(s), Ok(e)) = (n.parse::<u32>(), s.parse(), e.parse()) {
+                lengths.push(sectors(s, e));
+            }
+        } else if let Some(s) = line.strip_prefix("Start sector:") {
+            start = s.trim().parse().ok();
+        } else if let Some(e) = line.strip_prefix("End sector:") {
+            if let (Some(s), Ok(e)) = (start.take(), e.trim().parse()) {
+                lengths.push(sectors(s, e));
+            }
+        }
+    }
+    lengths
+}
+
+#[derive(Default, PartialEq, PartialOrd)]
+pub enum Accurate {
+    #[default]
+    Unknown,
+    NotInDatabase,
+    No,
+    // With the confidence, how many other rips matched
+    Yes(u32),
+}
+
+#[derive(Default)]
+pub struct LogTrack {
+    pub number: u32,
+    // File name without the directory. EAC logs name the .wav ripped to,
+    // so it's matched by the stem.
+    pub file: Option<String>,
+    pub crc: Option<u32>,
+    pub accurate: Accurate,
+    // Suspicious positions and read errors
+    pub errors: u32,
+}
+
+pub struct RipLog {
+    pub ripper: &'static str,
+    pub tracks: Vec<LogTrack>,
+}
+
+// The number after the last ':' on the line, or 0
+fn count(line: &str) -> u32 {
+    line.rsplit(':')
+        .next()
+        .and_then(|n| n.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+// The tracks of an EAC, XLD or whipper log. EAC and XLD start each track
+// with "Track  1", whipper has "1:" under "Tracks:". AccurateRip v1 and
+// v2 results are both in some logs, and the best one is kept.
+pub fn read_log(file: &Path) -> Option<RipLog> {
+    let text = read_text(file)?;
+    let mut log = RipLog {
+        ripper: "",
+        tracks: Vec::new(),
+    };
+    let mut whipper_tracks = false;
+    for line in text.lines().map(str::trim) {
+        let lower = line.to_lowercase();
+        if log.ripper.is_empty() {
+            log.ripper = if lower.contains("exact audio copy") {
+                "EAC"
+            } else if lower.contains("x lossless decoder") {
+                "XLD"
+            } else if lower.contains("whipper") {
+                "whipper"
+            } else {
+                ""
+            };
+        }
+        if line == "Tracks:" {
+            whipper_tracks = true;
+            continue;
+        }
+        let number = match line.strip_prefix("Track ") {
+            Some(n) => n.trim().parse().ok(),
+            None if whipper_tracks => line.strip_suffix(':').and_then(|n| n.parse().ok()),
+            None => None,
+        };
+        if let Some(number) = number {
+            log.tracks.push(LogTrack {
+                number,
+                ..LogTrack::default()
+            });
+            continue;
+        }
+        let track = match log.tracks.last_mut() {
+            Some(t) => t,
+            None => continue,
+        };
+        if let Some(name) = line.strip_prefix("Filename") {
+            let name = name.trim_start_matches([' ', ':']);
+            track.file = name.rsplit(['/', '\\']).next().map(String::from);
+        } else if let Some(crc) = line.strip_prefix("Copy CRC").or_else(|| {
+            line.strip_prefix("CRC32 hash")
+                .filter(|r| r.trim_start().starts_with(':'))
+        }) {
+            let crc = crc.trim_start_matches([' ', ':']);
+            track.crc = u32::from_str_radix(crc.split_whitespace().next().unwrap_or(""), 16).ok();
+        } else if lower.contains("accurately ripped") || lower.contains("found, exact match") {
+            let confidence = lower
+                .split("confidence")
+                .nth(1)
+                .map(|c| {
+                    c.trim_start()
+                        .chars()
+                        .take_while(|c| c.is_ascii_digit())
+                        .collect::<String>()
+                })
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(0);
+            track.accurate = Accurate::Yes(confidence);
+        } else if let (Some(c), Accurate::Yes(n)) =
+            (line.strip_prefix("Confidence:"), &mut track.accurate)
+        {
+            *n = (*n).max(c.trim().parse().unwrap_or(0));
+        } else if lower.contains("not exact match")
+            || lower.contains("cannot be verified as accurate")
+            || lower.contains("may not be accurate")
+        {
+            if track.accurate < Accurate::No {
+                track.accurate = Accurate::No;
+            }
+        } else if lower.contains("not present in accuraterip database") {
+            if track.accurate < Accurate::NotInDatabase {
+                track.accurate = Accurate::NotInDatabase;
+            }
+        } else if lower.starts_with("suspicious position") {
+            track.errors += 1;
+        } else if [
+            "read error",
+            "damaged sectors",
+            "skipped (treated as error)",
+        ]
+        .iter()
+        .any(|p| lower.starts_with(p))
+        {
+            track.errors += count(line);
+        }
+    }
+    (!log.tracks.is_empty()).then_some(log)
+}
+
+#[derive(Default)]
+pub struct Rip {
+    // A line on each log's rip
+    pub quality: Vec<String>,
+    pub problems: Vec<String>,
+}
+
+// Each directory with a cue sheet or rip log, checked against the tracks
+// in it. tolerance is how far the total length can be out, and verify
+// decodes lossless tracks to check their CRCs against the log's.
+pub fn check(tracks: &[TrackInfo], tolerance: Duration, verify: bool) -> BTreeMap<PathBuf, Rip> {
+    let mut dirs: BTreeMap<&Path, Vec<&TrackInfo>> = BTreeMap::new();
+    for t in tracks {
+        if let Some(dir) = Path::new(&t.path).parent() {
+            dirs.entry(dir).or_default().push(t);
+        }
+    }
+
+    let mut rips = BTreeMap::new();
+    for (dir, tracks) in dirs {
+        let (cues, logs) = (sidecars(dir, "cue"), sidecars(dir, "log"));
+        if cues.is_empty() && logs.is_empty() {
+            continue;
+        }
+        let mut rip = Rip::default();
+        let found = &mut rip.problems;
+
+        for cue in &cues {
+            let c = match read_cue(cue) {
+                Some(c) => c,
+                None => continue,
+            };
+            let name = cue.file_name().unwrap_or_default().to_string_lossy();
+            match &c.files[..] {
+                // An image and its track starts
+                [image] if c.tracks > 1 => {
+                    let t = tracks
+                        .iter()
+                        .find(|t| Path::new(&t.path).file_name() == Some(image.as_ref()));
+                    if let Some(t) = t.filter(|t| t.duration < c.last_start) {
+                        found.push(format!(
+                            "{image} is {} but {name} has a track starting at {}",
+                            format::duration(t.duration),
+                            format::duration(c.last_start)
+                        ));
+                    }
+                }
+                files => {
+                    let here = files.iter().filter(|f| dir.join(f).exists()).count();
+                    if here != files.len() {
+                        found.push(format!(
+                            "{name} names {} files, {here} of them here",
+                            files.len()
+                        ));
+                    }
+                }
+            }
+        }
+
+        // An image is one track against the log's many, so only the
+        // lengths are compared then
+        let total: Duration = tracks.iter().map(|t| t.duration).sum();
+        for log in &logs {
+            let name = log.file_name().unwrap_or_default().to_string_lossy();
+            let lengths = log_lengths(log);
+            if !lengths.is_empty() {
+                let expected: Duration = lengths.iter().sum();
+                let count_off = tracks.len() > 1 && lengths.len() != tracks.len();
+                if count_off || expected.abs_diff(total) > tolerance {
+                    found.push(format!(
+                        "{name} has {} tracks, {}, the directory has {}, {}",
+                        lengths.len(),
+                        format::duration(expected),
+                        tracks.len(),
+                        format::duration(total)
+                    ));
+                }
+            }
+            if let Some(l) = read_log(log) {
+                rip.quality.push(quality(&name, &l));
+                found.extend(log_problems(&l, &tracks, verify));
+            }
+        }
+
+        let mut numbers: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+        for t in tracks.iter().filter(|t| t.track > 0) {
+            *numbers.entry((disc(t), t.track)).or_default() += 1;
+        }
+        let twice: Vec<String> = numbers
+            .iter()
+            .filter(|(_, n)| **n > 1)
+            .map(|((_, track), _)| track.to_string())
+            .collect();
+        if !twice.is_empty() {
+            rip.problems
+                .push(format!("more than one track {}", twice.join(", ")));
+        }
+
+        rips.insert(dir.to_path_buf(), rip);
+    }
+    rips
+}
+
+fn quality(name: &str, log: &RipLog) -> String {
+    let n = |f: &dyn Fn(&LogTrack) -> bool| log.tracks.iter().filter(|t| f(t)).count();
+    format!(
+        "{name}: {}, {} tracks, {} accurately ripped, {} not in AccurateRip, {} with errors",
+        if log.ripper.is_empty() {
+            "unknown ripper"
+        } else {
+            log.ripper
+        },
+        log.tracks.len(),
+        n(&|t| matches!(t.accurate, Accurate::Yes(_))),
+        n(&|t| t.accurate == Accurate::NotInDatabase),
+        n(&|t| t.errors > 0)
+    )
+}
+
+// Tracks the log has doubts about, and with verify, ones whose audio
+// isn't what was ripped
+fn log_problems(log: &RipLog, tracks: &[&TrackInfo], verify: bool) -> Vec<String> {
+    let mut found = Vec::new();
+    for lt in &log.tracks {
+        if lt.accurate == Accurate::No {
+            found.push(format!("track {} not accurately ripped", lt.number));
+        }
+        if lt.errors > 0 {
+            found.push(format!(
+                "track {} had read errors ({})",
+                lt.number, lt.errors
+            ));
+        }
+        let expected = match lt.crc {
+            Some(c) if verify => c,
+            _ => continue,
+        };
+        let stem = |p: &str| Path::new(p).file_stem().map(|s| s.to_os_string());
+        let t = tracks.iter().find(|t| match &lt.file {
+            Some(f) => stem(&t.path) == stem(f),
+            None => t.track == lt.number,
+        });
+        let t = match t {
+            Some(t) if LOSSLESS.contains(&file_ext(&t.path).as_str()) => t,
+            _ => continue,
+        };
+        match audio_crc(&t.path) {
+            Some(crc) if crc != expected => found.push(format!(
+                "track {}: the audio's CRC is {crc:08X}, the log has {expected:08X}",
+                lt.number
+            )),
+            _ => (),
+        }
+    }
+    found
+}
+
+// Files in dir with the extension, in order
+fn sidecars(dir: &Path, ext: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| file_ext(&p.to_string_lossy()) == ext)
+        .collect();
+    files.sort();
+    files
+}
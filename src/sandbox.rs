@@ -0,0 +1,210 @@
+// Probe files in a separate worker process so that a parser crash,
+// runaway allocation or hang on a bad file only takes down the worker
+// and not the whole scan.
+use crate::scan::transient;
+use crate::{read_metadata, TrackInfo};
+use serde_derive::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// Argument used to start the binary as a probe worker
+pub const WORKER_ARG: &str = "--probe-worker";
+
+// read_metadata prints its own messages to stdout, so replies from the
+// worker are marked to tell them apart
+const REPLY_PREFIX: &str = "tag_test-reply:";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    // Seconds to wait for a single file before the worker is killed
+    pub timeout: u64,
+    // MiB of address space the worker can have, 0 = no limit
+    pub memory_limit: u64,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            enabled: false,
+            timeout: 30,
+            memory_limit: 2048,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Reply {
+    Ok(Box<TrackInfo>),
+    // And whether it's worth trying again, see scan::transient
+    Err(String, bool),
+}
+
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+pub struct Sandbox {
+    timeout: Duration,
+    memory_limit: u64,
+    worker: Option<Worker>,
+}
+
+impl Sandbox {
+    pub fn new(config: &SandboxConfig) -> Sandbox {
+        Sandbox {
+            timeout: Duration::from_secs(config.timeout),
+            memory_limit: config.memory_limit,
+            worker: None,
+        }
+    }
+
+    // Read the metadata of file_name in the worker, starting a new worker
+    // if there is none or the last one died. An error comes with whether
+    // it's worth trying again, which a worker that died or hung isn't.
+    pub fn probe(&mut self, file_name: &str) -> Result<TrackInfo, (String, bool)> {
+        if self.worker.is_none() {
+            self.worker = Some(
+                spawn_worker(self.memory_limit)
+                    .map_err(|e| (format!("Unable to start worker: {e}"), false))?,
+            );
+        }
+        let worker = self.worker.as_mut().unwrap();
+
+        if writeln!(worker.stdin, "{file_name}").is_err() {
+            self.kill_worker();
+            return Err((String::from("Worker exited unexpectedly"), false));
+        }
+
+        loop {
+            match worker.lines.recv_timeout(self.timeout) {
+                Ok(line) => match line.strip_prefix(REPLY_PREFIX) {
+                    Some(reply) => {
+                        return match serde_json::from_str(reply) {
+                            Ok(Reply::Ok(t)) => Ok(*t),
+                            Ok(Reply::Err(e, transient)) => Err((e, transient)),
+                            Err(e) => Err((format!("Bad reply from worker: {e}"), false)),
+                        }
+                    }
+                    // Pass through anything the worker printed itself, which
+                    // is only ever warnings
+                    None => warn!("{line}"),
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    self.kill_worker();
+                    let e = format!("Worker timed out after {} seconds", self.timeout.as_secs());
+                    return Err((e, false));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = worker.child.wait();
+                    self.worker = None;
+                    let e = match status {
+                        Ok(s) => format!("Worker crashed ({s})"),
+                        Err(e) => format!("Worker crashed ({e})"),
+                    };
+                    return Err((e, false));
+                }
+            }
+        }
+    }
+
+    fn kill_worker(&mut self) {
+        if let Some(mut worker) = self.worker.take() {
+            let _ = worker.child.kill();
+            let _ = worker.child.wait();
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        self.kill_worker();
+    }
+}
+
+fn spawn_worker(memory_limit: u64) -> io::Result<Worker> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg(WORKER_ARG)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    // In its own process group Ctrl-C doesn't reach the worker, so it can
+    // finish the file it's on while the scan stops
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    // A runaway allocation fails in the worker, which then crashes, rather
+    // than bringing in the OOM killer
+    #[cfg(unix)]
+    if memory_limit > 0 {
+        let bytes = memory_limit.saturating_mul(1024 * 1024) as libc::rlim_t;
+        let limit = libc::rlimit {
+            rlim_cur: bytes,
+            rlim_max: bytes,
+        };
+        // SAFETY: setrlimit is async-signal-safe, and only the copied
+        // limit is used between fork and exec
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(
+                &mut command,
+                move || match libc::setrlimit(libc::RLIMIT_AS, &limit) {
+                    0 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                },
+            );
+        }
+    }
+    let mut child = command.spawn()?;
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    // Read the replies on a thread so probe() can give up on a hung worker
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(l) => {
+                    if tx.send(l).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    Ok(Worker {
+        child,
+        stdin,
+        lines: rx,
+    })
+}
+
+// Entry point when started with WORKER_ARG. Reads one file name per line
+// from stdin and writes one reply per line to stdout.
+pub fn run_worker() {
+    let stdout = io::stdout();
+    for line in io::stdin().lock().lines() {
+        let file_name = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let reply = match read_metadata(&file_name) {
+            Ok(t) => Reply::Ok(Box::new(t)),
+            Err(e) => Reply::Err(e.to_string(), transient(&e)),
+        };
+        let reply = serde_json::to_string(&reply).expect("Unable to encode reply");
+        let mut out = stdout.lock();
+        if writeln!(out, "{REPLY_PREFIX}{reply}")
+            .and_then(|_| out.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
@@ -0,0 +1,725 @@
+// The scan as a pipeline: one thread walks the directories, probe threads
+// read the tags, analysis threads estimate BPMs, and this thread prints,
+// exports and keeps the results. The stages are joined by bounded queues,
+// so a slow stage holds up the ones before it once its queue is full
+// rather than piling up files in memory, and a fast one never waits on a
+// slow one further down until then.
+use crate::albums::Tally;
+use crate::artists;
+use crate::cache::{self, Cache};
+use crate::cancel::{self, ResumePoint};
+use crate::export::{self, Export};
+use crate::history::Recorder;
+use crate::intern::Interner;
+use crate::listing;
+use crate::m3u;
+use crate::overrides::{self, DirSettings, Overrides};
+use crate::quarantine::Quarantine;
+use crate::remote;
+use crate::sandbox::Sandbox;
+use crate::search::Index;
+use crate::throttle::Throttle;
+use crate::{
+    analysis, file_ext, format, hooks, junk, ogg, read_metadata, snapshot, systemd, term, Config,
+    RootStats, ScanStats, TrackInfo,
+};
+use lofty::error::{ErrorKind, LoftyError};
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use walkdir::{DirEntry, WalkDir};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    // Threads reading tags. With the sandbox on each has its own worker.
+    pub probe_threads: usize,
+    // Threads estimating BPMs
+    pub analysis_threads: usize,
+    // Files each queue between the stages holds
+    pub queue: usize,
+    // Where a cancelled scan saves the point to resume from
+    pub resume_file: String,
+    // "name" to walk each directory in name order, "newest" to take its
+    // most recently modified subdirectories first
+    pub order: String,
+    // Times a file is read again after an IO error before it's counted as
+    // an error, and seconds to wait before the first retry, doubled for
+    // each one after
+    pub retries: u32,
+    pub retry_delay: f64,
+    // Threads reading directories for the estimate, see listing.rs
+    pub walk_threads: usize,
+    // Follow symlinks to files and directories. Costs a stat of every
+    // file as well as reading the directories.
+    pub follow_links: bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            probe_threads: 1,
+            analysis_threads: 1,
+            queue: 64,
+            resume_file: String::from("resume.json"),
+            order: String::from("name"),
+            retries: 2,
+            retry_delay: 0.5,
+            walk_threads: 1,
+            follow_links: true,
+        }
+    }
+}
+
+// A music file on its way through the pipeline
+struct Job {
+    path: String,
+    // Settings for the file's directory
+    settings: Arc<DirSettings>,
+    // Index of the scan root the file is under
+    root: usize,
+    result: Option<Result<TrackInfo, String>>,
+    // True if the result came from the cache
+    cached: bool,
+}
+
+// Shared by the threads of one stage, the first free one takes the job
+type Queue = Arc<Mutex<Receiver<Job>>>;
+
+type SharedCache<'a> = Option<&'a Mutex<Box<dyn Cache>>>;
+
+// With resume, files the walk had got to before it was cancelled are
+// skipped
+pub fn scan_dirs(config: &Config, estimate: bool, resume: Option<&ResumePoint>) -> ScanStats {
+    if estimate {
+        return walk(config, true, None, resume);
+    }
+    let pc = &config.pipeline;
+    let queue = pc.queue.max(1);
+    let opened = cache::open(&config.cache).map(Mutex::new);
+    let cache = opened.as_ref();
+    let mut snapshot = snapshot::Writer::create(config, &config.export.snapshot);
+    let scan_stats = thread::scope(|s| {
+        let (walk_tx, walk_rx) = sync_channel(queue);
+        let (probe_tx, probe_rx) = sync_channel(queue);
+        let (analysis_tx, analysis_rx) = sync_channel(queue);
+
+        let walk_rx: Queue = Arc::new(Mutex::new(walk_rx));
+        for _ in 0..pc.probe_threads.max(1) {
+            let (rx, tx) = (walk_rx.clone(), probe_tx.clone());
+            s.spawn(move || probe(config, cache, rx, tx));
+        }
+        drop(probe_tx);
+        let probe_rx: Queue = Arc::new(Mutex::new(probe_rx));
+        for _ in 0..pc.analysis_threads.max(1) {
+            let (rx, tx) = (probe_rx.clone(), analysis_tx.clone());
+            s.spawn(move || analyse(rx, tx));
+        }
+        drop(analysis_tx);
+        let walker = s.spawn(move || walk(config, false, Some(walk_tx), resume));
+
+        let mut scan_stats = sink(config, cache, analysis_rx, snapshot.as_mut());
+        let walked = walker.join().expect("walker thread panicked");
+        scan_stats.directories = walked.directories;
+        scan_stats.other_files = walked.other_files;
+        scan_stats.junk_files = walked.junk_files;
+        scan_stats.found_types = walked.found_types;
+        scan_stats.playlists = walked.playlists;
+        scan_stats.stopped_at = walked.stopped_at;
+        scan_stats
+    });
+    if let Some(c) = opened {
+        // Only roots that were scanned in full and are there, so a NAS
+        // that isn't mounted keeps its files
+        let roots: Vec<&str> = match config.partial() || cancel::cancelled() {
+            true => Vec::new(),
+            false => config
+                .directories
+                .scan
+                .iter()
+                .map(|r| r.path.as_str())
+                .filter(|r| Path::new(r).is_dir())
+                .collect(),
+        };
+        let res = c
+            .into_inner()
+            .map_err(|e| e.to_string())
+            .and_then(|mut c| c.flush(&roots));
+        if let Err(e) = res {
+            error!("Error writing {}: {e}", config.cache.file);
+        }
+    }
+    // Once the walk's counts are in
+    if let Some(s) = snapshot {
+        s.finish(&scan_stats);
+    }
+    scan_stats
+}
+
+pub fn new_stats() -> ScanStats {
+    ScanStats {
+        other_files: 0,
+        directories: 0,
+        error_files: 0,
+        valid_files: 0,
+        junk_files: 0,
+        excluded_files: 0,
+        found_types: HashMap::new(),
+        roots: Vec::new(),
+        playlists: Vec::new(),
+        albums: Vec::new(),
+        artists: Vec::new(),
+        tracks: Vec::new(),
+        stopped_at: None,
+    }
+}
+
+// Count what's in the scan directories and send the music files on. An
+// estimate counts the music files as valid instead. Once the scan is
+// cancelled no more files are sent, and the first one left is kept to
+// resume from.
+fn walk(
+    config: &Config,
+    estimate: bool,
+    tx: Option<SyncSender<Job>>,
+    resume: Option<&ResumePoint>,
+) -> ScanStats {
+    if let Some(files) = &config.files {
+        return walk_files(config, files, tx);
+    }
+    if estimate && config.pipeline.walk_threads > 1 && resume.is_none() {
+        return estimate_listed(config);
+    }
+    let mut scan_stats = new_stats();
+    let mut throttle = Throttle::new(&config.throttle);
+    for (i, root) in config.directories.scan.iter().enumerate() {
+        let mut overrides = Overrides::new(config, Some(root));
+        let valid = scan_stats.valid_files;
+        let walker = WalkDir::new(&root.path).follow_links(config.pipeline.follow_links);
+        let walker = match newest_first(config) {
+            true => walker.sort_by(newest_dir),
+            false => walker.sort_by_file_name(),
+        };
+        let in_scope = |e: &DirEntry| match &config.scope {
+            _ if e.file_type().is_dir() && e.depth() > 0 && !root.recursive => false,
+            Some(s) if e.file_type().is_dir() => s.may_contain(e.path()),
+            Some(s) => s.includes(e.path()),
+            None => true,
+        };
+        for entry in walker
+            .into_iter()
+            .filter_entry(in_scope)
+            .filter_map(|e| e.ok())
+        {
+            if resume.is_some_and(|r| !r.is_after(i, entry.path())) {
+                continue;
+            }
+            if entry.file_type().is_dir() {
+                scan_stats.directories += 1;
+                systemd::progress(|| {
+                    let dirs = scan_stats.directories;
+                    match estimate {
+                        true => format!("Estimating, {dirs} directories"),
+                        false => format!("Scanning, {dirs} directories"),
+                    }
+                });
+                if config.general.verbose {
+                    log!(
+                        "{} Dir: {:?}",
+                        if estimate { "Estimating" } else { "Scanning" },
+                        entry.path().to_string_lossy()
+                    );
+                };
+                continue;
+            }
+            let f_name = entry.file_name().to_string_lossy();
+            if junk::is_junk(&f_name) {
+                junk_file(config, entry.path(), estimate, &mut scan_stats);
+                continue;
+            }
+            if f_name == overrides::FILE {
+                continue;
+            }
+            let settings = overrides.for_dir(entry.path().parent().unwrap_or(entry.path()));
+            let f_ext = file_ext(&f_name);
+            scan_stats
+                .found_types
+                .entry(f_ext.clone())
+                .and_modify(|ext| *ext += 1)
+                .or_insert(1);
+
+            if !settings.is_valid(&f_ext) {
+                if m3u::EXTS.contains(&f_ext.as_str()) {
+                    scan_stats
+                        .playlists
+                        .push(entry.path().to_string_lossy().to_string());
+                }
+                scan_stats.other_files += 1;
+                continue;
+            }
+            if cancel::cancelled() {
+                scan_stats.stopped_at = Some(ResumePoint {
+                    root: i,
+                    path: entry.path().to_string_lossy().to_string(),
+                });
+                return scan_stats;
+            }
+            match &tx {
+                Some(tx) => {
+                    throttle.wait();
+                    let job = Job {
+                        path: entry.path().to_string_lossy().to_string(),
+                        settings,
+                        root: i,
+                        result: None,
+                        cached: false,
+                    };
+                    // Only fails if the pipeline has stopped
+                    if tx.send(job).is_err() {
+                        return scan_stats;
+                    }
+                }
+                None => scan_stats.valid_files += 1,
+            }
+        }
+        if let Some(label) = &root.label {
+            scan_stats.roots.push(RootStats {
+                label: label.clone(),
+                valid_files: scan_stats.valid_files - valid,
+                error_files: 0,
+            });
+        }
+    }
+    scan_stats
+}
+
+// Count, ignore or delete a junk file as [junk] says. Only the scan
+// deletes, not the estimate.
+fn junk_file(config: &Config, path: &Path, estimate: bool, scan_stats: &mut ScanStats) {
+    if config.general.verbose {
+        if let Some(data) = junk::apple_double_of(path) {
+            log!(
+                "AppleDouble file {:?} {} {:?}",
+                path.to_string_lossy(),
+                if data.exists() { "for" } else { "without" },
+                data.to_string_lossy()
+            );
+        }
+    }
+    match config.junk.action.as_str() {
+        "ignore" => (),
+        "delete" if !estimate => {
+            junk::delete(path, config.junk.permanent);
+            scan_stats.junk_files += 1;
+        }
+        _ => scan_stats.junk_files += 1,
+    }
+}
+
+// walk for the files given on stdin rather than the scan directories.
+// Files outside them are scanned too, with the settings in [types], and
+// aren't counted under any root.
+fn walk_files(config: &Config, files: &[String], tx: Option<SyncSender<Job>>) -> ScanStats {
+    let mut scan_stats = new_stats();
+    let mut throttle = Throttle::new(&config.throttle);
+    let mut overrides = Overrides::new(config, None);
+    for file in files {
+        let path = Path::new(file);
+        if !path.is_file() && !remote::is_url(file) {
+            // Once, not for the estimate as well
+            if tx.is_some() {
+                warn!("Skipping {file}, it isn't a file");
+            }
+            continue;
+        }
+        if config.scope.as_ref().is_some_and(|s| !s.includes(path)) {
+            continue;
+        }
+        let f_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        if junk::is_junk(&f_name) {
+            junk_file(config, path, tx.is_none(), &mut scan_stats);
+            continue;
+        }
+        let settings = overrides.for_dir(path.parent().unwrap_or(path));
+        let f_ext = file_ext(&f_name);
+        *scan_stats.found_types.entry(f_ext.clone()).or_default() += 1;
+        if !settings.is_valid(&f_ext) {
+            scan_stats.other_files += 1;
+            continue;
+        }
+        if cancel::cancelled() {
+            return scan_stats;
+        }
+        match &tx {
+            Some(tx) => {
+                throttle.wait();
+                // The innermost root, as for the other counts
+                let root = config
+                    .root_for(file)
+                    .and_then(|r| config.directories.scan.iter().position(|s| ptr::eq(s, r)))
+                    .unwrap_or(usize::MAX);
+                let job = Job {
+                    path: file.clone(),
+                    settings,
+                    root,
+                    result: None,
+                    cached: false,
+                };
+                if tx.send(job).is_err() {
+                    return scan_stats;
+                }
+            }
+            None => scan_stats.valid_files += 1,
+        }
+    }
+    scan_stats
+}
+
+// The estimate from directory listings read in walk_threads threads. Only
+// counts, nothing's sent on, so the order doesn't matter.
+fn estimate_listed(config: &Config) -> ScanStats {
+    let pc = &config.pipeline;
+    let mut scan_stats = new_stats();
+    for root in &config.directories.scan {
+        let mut overrides = Overrides::new(config, Some(root));
+        let valid = scan_stats.valid_files;
+        let listings = listing::list(
+            Path::new(&root.path),
+            pc.walk_threads,
+            pc.follow_links,
+            root.recursive,
+            config.scope.as_ref(),
+        );
+        for l in listings {
+            scan_stats.directories += 1;
+            if config.general.verbose {
+                log!("Estimating Dir: {:?}", l.dir.to_string_lossy());
+            }
+            let settings = overrides.for_dir(&l.dir);
+            for f_name in &l.files {
+                if junk::is_junk(f_name) {
+                    junk_file(config, &l.dir.join(f_name), true, &mut scan_stats);
+                    continue;
+                }
+                if f_name == overrides::FILE {
+                    continue;
+                }
+                let f_ext = file_ext(f_name);
+                *scan_stats.found_types.entry(f_ext.clone()).or_default() += 1;
+                if settings.is_valid(&f_ext) {
+                    scan_stats.valid_files += 1;
+                } else {
+                    scan_stats.other_files += 1;
+                }
+            }
+        }
+        if let Some(label) = &root.label {
+            scan_stats.roots.push(RootStats {
+                label: label.clone(),
+                valid_files: scan_stats.valid_files - valid,
+                error_files: 0,
+            });
+        }
+    }
+    scan_stats
+}
+
+pub fn newest_first(config: &Config) -> bool {
+    config.pipeline.order.eq_ignore_ascii_case("newest")
+}
+
+// Subdirectories newest first, then the files by name
+fn newest_dir(a: &DirEntry, b: &DirEntry) -> Ordering {
+    let modified = |e: &DirEntry| match e.file_type().is_dir() {
+        true => e.metadata().ok().and_then(|m| m.modified().ok()),
+        false => None,
+    };
+    let dir = |e: &DirEntry| e.file_type().is_dir();
+    dir(b)
+        .cmp(&dir(a))
+        .then_with(|| modified(b).cmp(&modified(a)))
+        .then_with(|| a.file_name().cmp(b.file_name()))
+}
+
+fn probe(config: &Config, cache: SharedCache, rx: Queue, tx: SyncSender<Job>) {
+    let mut sandbox = if config.sandbox.enabled {
+        Some(Sandbox::new(&config.sandbox))
+    } else {
+        None
+    };
+    while let Some(mut job) = next(&rx) {
+        if let Some(t) = cache.and_then(|c| cached(c, &job.path)) {
+            job.result = Some(Ok(t));
+            job.cached = true;
+            if tx.send(job).is_err() {
+                return;
+            }
+            continue;
+        }
+        job.result = Some(read_retrying(&config.pipeline, &job.path, sandbox.as_mut()));
+        if let Some(Ok(t)) = job.result.as_mut() {
+            if config.reports.chained && ogg::is_ogg(&t.path) {
+                count_chains(t);
+            }
+        }
+        if tx.send(job).is_err() {
+            return;
+        }
+    }
+}
+
+// Read a file's tags, in the sandbox if there is one, trying again after
+// IO errors, which on network storage are often gone a moment later.
+// Errors in the file itself, and a file cut short, aren't tried again.
+fn read_retrying(
+    pc: &PipelineConfig,
+    path: &str,
+    mut sandbox: Option<&mut Sandbox>,
+) -> Result<TrackInfo, String> {
+    let mut delay = Duration::from_secs_f64(pc.retry_delay.max(0.0));
+    let mut tries = 0;
+    loop {
+        let res = match sandbox.as_deref_mut() {
+            Some(s) => s.probe(path),
+            None => read_metadata(path).map_err(|e| (e.to_string(), transient(&e))),
+        };
+        match res {
+            Err((e, true)) if tries < pc.retries && !cancel::cancelled() => {
+                tries += 1;
+                warn!(
+                    "Error reading {path}: {e}, trying again ({tries} of {})",
+                    pc.retries
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            res => return res.map_err(|(e, _)| e),
+        }
+    }
+}
+
+pub fn transient(e: &LoftyError) -> bool {
+    match e.kind() {
+        ErrorKind::Io(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData | io::ErrorKind::Unsupported
+        ),
+        _ => false,
+    }
+}
+
+// lofty only reads the first stream of a chained Ogg file, so the length
+// is the sum of the streams'
+fn count_chains(t: &mut TrackInfo) {
+    match ogg::chains(&t.path) {
+        Ok(chains) if chains.len() > 1 => {
+            t.duration = chains.iter().map(|c| c.duration).sum();
+            t.chains = Some(chains.len() as u32);
+        }
+        Ok(_) => (),
+        Err(e) => warn!("Error reading the Ogg streams in {}: {e}", t.path),
+    }
+}
+
+// The cached tags for file if it hasn't changed since
+fn cached(cache: &Mutex<Box<dyn Cache>>, file: &str) -> Option<TrackInfo> {
+    let meta = fs::metadata(file).ok()?;
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    cache.lock().ok()?.get(file, modified.as_secs(), meta.len())
+}
+
+fn analyse(rx: Queue, tx: SyncSender<Job>) {
+    while let Some(mut job) = next(&rx) {
+        if let Some(Ok(t)) = job.result.as_mut() {
+            // Cancelling skips the slow part for the files still queued
+            let missing = t.bpm.is_none() && t.estimated_bpm.is_none();
+            if job.settings.bpm && missing && !cancel::cancelled() {
+                t.estimated_bpm = analysis::estimate_bpm(&job.path);
+            }
+        }
+        if tx.send(job).is_err() {
+            return;
+        }
+    }
+}
+
+// What went wrong reading a file, going by the message as that's all the
+// sink has; errors from the sandbox come from another process
+pub fn error_kind(message: &str) -> &'static str {
+    if message.starts_with("Worker crashed") {
+        "crash"
+    } else if message.starts_with("Worker ") || message.starts_with("Unable to start worker") {
+        "sandbox"
+    } else if message.contains("failed to fill whole buffer") {
+        "truncated"
+    } else if message.contains("os error") {
+        "io"
+    } else if message.contains("Expected a tag") {
+        "no_tags"
+    } else if message.contains("No format could be determined") {
+        "unknown_format"
+    } else {
+        "invalid"
+    }
+}
+
+// The file itself is broken: the parser crashed on it, it's cut short, or
+// it doesn't hold what its name says
+fn broken(message: &str) -> bool {
+    matches!(
+        error_kind(message),
+        "crash" | "truncated" | "unknown_format" | "invalid"
+    )
+}
+
+fn next(rx: &Queue) -> Option<Job> {
+    rx.lock().ok()?.recv().ok()
+}
+
+// Print, export and keep the results as they come out of the pipeline
+fn sink(
+    config: &Config,
+    cache: SharedCache,
+    rx: Receiver<Job>,
+    mut snapshot: Option<&mut snapshot::Writer>,
+) -> ScanStats {
+    let mut scan_stats = new_stats();
+    let mut export = Export::new(&config.export);
+    let keep_tracks = export::keep_tracks(config);
+    let mut interner = Interner::default();
+    let mut quarantine = if config.quarantine.enabled {
+        Some(Quarantine::new(config))
+    } else {
+        None
+    };
+    let mut history = Recorder::new(config);
+    let mut index = Index::open(config);
+    let mut albums = Tally::default();
+    let mut artists = artists::Tally::default();
+    // Valid and error files under each scan root
+    let mut roots = vec![(0, 0); config.directories.scan.len()];
+
+    for job in rx {
+        systemd::progress(|| {
+            let files = scan_stats.valid_files + scan_stats.error_files;
+            format!("Scanning, {files} files read")
+        });
+        let mut t = match job.result {
+            Some(Ok(t)) => t,
+            Some(Err(e)) => {
+                // The journal gets it with the path as a field instead
+                if !systemd::file_error(&job.path, &e) {
+                    error!("Error in {}: {}", job.path, e);
+                }
+                term::event("error", json!({ "path": job.path, "message": e }));
+                scan_stats.error_files += 1;
+                if let Some(r) = roots.get_mut(job.root) {
+                    r.1 += 1;
+                }
+                hooks::file_error(&config.hooks, &job.path, &e);
+                if let Some(s) = snapshot.as_mut() {
+                    s.error(&job.path, &e);
+                }
+                // Not files that are fine but untagged, or that couldn't
+                // be read this time
+                if let Some(q) = quarantine.as_mut().filter(|_| broken(&e)) {
+                    q.add(&job.path, &e);
+                }
+                continue;
+            }
+            None => continue,
+        };
+        // Not cached or indexed either, as if it wasn't there
+        if !config.types.duration_ok(t.duration.as_secs_f64()) {
+            if config.general.verbose {
+                log!("Excluded {} ({})", job.path, format::duration(t.duration));
+            }
+            scan_stats.excluded_files += 1;
+            continue;
+        }
+        if let Some(c) = cache.filter(|_| !job.cached) {
+            if let Ok(mut c) = c.lock() {
+                if let (Some(h), Some(old)) = (history.as_mut(), c.stored(&t.path)) {
+                    h.record(&old, &t);
+                }
+                c.put(&t);
+            }
+        }
+        if let Some(i) = index.as_mut() {
+            i.track(&t);
+        }
+        if !config.scope.as_ref().is_none_or(|s| s.matches(&t)) {
+            continue;
+        }
+        if config.general.verbose {
+            match &job.settings.line {
+                Some(line) => log!("{}", line.render(&t)),
+                None => log!(
+                    "{:?} {:?} {:?} {:?} {:?} {} {:?} {:?}",
+                    t.artist,
+                    t.title,
+                    t.album,
+                    t.genre,
+                    t.track,
+                    format::duration(t.duration),
+                    t.rating,
+                    t.play_count
+                ),
+            }
+        }
+        let value = json!(t);
+        term::stream(&value);
+        term::event("track", value);
+        export.track(&t);
+        albums.add(&t);
+        artists.add(&t);
+        if let Some(s) = snapshot.as_mut() {
+            s.track(&t);
+        }
+        if keep_tracks {
+            interner.track(&mut t);
+            scan_stats.tracks.push(t);
+        }
+        scan_stats.valid_files += 1;
+        if let Some(r) = roots.get_mut(job.root) {
+            r.0 += 1;
+        }
+    }
+
+    for (root, (valid, errors)) in config.directories.scan.iter().zip(roots) {
+        if let Some(label) = &root.label {
+            scan_stats.roots.push(RootStats {
+                label: label.clone(),
+                valid_files: valid,
+                error_files: errors,
+            });
+        }
+    }
+    scan_stats.albums = albums.finish();
+    scan_stats.artists = artists.finish();
+    export.albums(&config.export, &scan_stats.albums);
+    export.artists(&config.export, &scan_stats.artists);
+    export.finish();
+    if let Some(h) = history {
+        h.finish();
+    }
+    if let Some(i) = index {
+        i.finish(config.scanned_all());
+    }
+    if let Some(q) = quarantine {
+        q.finish();
+    }
+    scan_stats
+}
@@ -0,0 +1,69 @@
+// --scope, to scan part of the library again, e.g. the album just fixed,
+// rather than the whole of it. The scope is a directory, a playlist, or a
+// [fields] style expression the tracks have to match, like
+// 'artist == "Queen" and year < 1980'. Directories and playlists limit
+// what's walked; an expression still reads every file, from the cache
+// where it can, and keeps the ones that match.
+use crate::fields::Expr;
+use crate::m3u;
+use crate::sync::read_playlist;
+use crate::{file_ext, TrackInfo};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub enum Scope {
+    Under(PathBuf),
+    // Where the files really are, symlinks followed
+    Files(HashSet<PathBuf>),
+    Query(Expr),
+}
+
+impl Scope {
+    pub fn parse(arg: &str) -> Result<Scope, String> {
+        let path = Path::new(arg);
+        let ext = file_ext(arg);
+        if path.is_file() && (m3u::EXTS.contains(&ext.as_str()) || ext == "json") {
+            let files = read_playlist(arg)?
+                .iter()
+                .filter_map(|f| fs::canonicalize(f).ok())
+                .collect();
+            Ok(Scope::Files(files))
+        } else if path.exists() {
+            Ok(Scope::Under(path.to_path_buf()))
+        } else if arg.contains('/') && !arg.contains('"') {
+            Err(String::from("no such file or directory"))
+        } else {
+            Expr::try_from(arg.to_string()).map(Scope::Query)
+        }
+    }
+
+    // Whether the walk should go into dir
+    pub fn may_contain(&self, dir: &Path) -> bool {
+        match self {
+            Scope::Under(p) => dir.starts_with(p) || p.starts_with(dir),
+            Scope::Files(files) => match fs::canonicalize(dir) {
+                Ok(dir) => files.iter().any(|f| f.starts_with(&dir)),
+                Err(_) => false,
+            },
+            Scope::Query(_) => true,
+        }
+    }
+
+    // Whether the walk should send file on
+    pub fn includes(&self, file: &Path) -> bool {
+        match self {
+            Scope::Under(p) => file.starts_with(p),
+            Scope::Files(files) => fs::canonicalize(file).is_ok_and(|f| files.contains(&f)),
+            Scope::Query(_) => true,
+        }
+    }
+
+    // Whether a track that's been read is kept
+    pub fn matches(&self, t: &TrackInfo) -> bool {
+        match self {
+            Scope::Query(e) => e.matches(t),
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Which writing systems text is in, and whether it looks like it was
+// decoded with the wrong character set. Only the scripts likely in a
+// music library are told apart, anything else is "Other".
+
+// Unicode blocks of each script. Digits, punctuation and symbols belong
+// to none, so "1999" or "!!!" never count against a title.
+const SCRIPTS: &[(&str, &[(char, char)])] = &[
+    (
+        "Latin",
+        &[
+            ('A', 'Z'),
+            ('a', 'z'),
+            ('\u{c0}', '\u{24f}'),
+            ('\u{1e00}', '\u{1eff}'),
+        ],
+    ),
+    ("Greek", &[('\u{370}', '\u{3ff}')]),
+    ("Cyrillic", &[('\u{400}', '\u{52f}')]),
+    ("Hebrew", &[('\u{590}', '\u{5ff}')]),
+    ("Arabic", &[('\u{600}', '\u{6ff}')]),
+    ("Thai", &[('\u{e00}', '\u{e7f}')]),
+    (
+        "Hangul",
+        &[
+            ('\u{1100}', '\u{11ff}'),
+            ('\u{3130}', '\u{318f}'),
+            ('\u{ac00}', '\u{d7af}'),
+        ],
+    ),
+    ("Hiragana", &[('\u{3040}', '\u{309f}')]),
+    (
+        "Katakana",
+        &[('\u{30a0}', '\u{30ff}'), ('\u{ff66}', '\u{ff9f}')],
+    ),
+    ("Han", &[('\u{3400}', '\u{4dbf}'), ('\u{4e00}', '\u{9fff}')]),
+];
+
+// Windows-1252 characters that Shift-JIS and other double byte text
+// mostly turns into when read as Latin-1, and that real titles hardly
+// ever have
+const MOJIBAKE: &[char] = &['‚', 'ƒ', 'ˆ', '‰', '‹', '›'];
+
+pub fn script(c: char) -> Option<&'static str> {
+    if !c.is_alphabetic() {
+        return None;
+    }
+    let name = SCRIPTS
+        .iter()
+        .find(|(_, ranges)| ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)))
+        .map_or("Other", |(name, _)| *name);
+    Some(name)
+}
+
+// The scripts used in text, in the order they first turn up
+pub fn scripts(text: &str) -> Vec<&'static str> {
+    let mut found = Vec::new();
+    for s in text.chars().filter_map(script) {
+        if !found.contains(&s) {
+            found.push(s);
+        }
+    }
+    found
+}
+
+// True if text looks like it went through the wrong character set:
+// replacement or control characters, UTF-8 read as Latin-1 ("Ã©" for
+// "é"), or double byte text read as Latin-1
+pub fn mis_encoded(text: &str) -> bool {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .iter()
+        .any(|&c| c == '\u{fffd}' || ('\u{80}'..='\u{9f}').contains(&c))
+        || chars
+            .windows(2)
+            .any(|w| matches!(w[0], 'Â' | 'Ã') && ('\u{80}'..='\u{bf}').contains(&w[1]))
+        || text.contains("â€")
+        || chars.iter().filter(|c| MOJIBAKE.contains(c)).count() >= 2
+}
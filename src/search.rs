@@ -0,0 +1,243 @@
+// A word index of the tags, so "tag_test search" finds tracks by any
+// words of their title, artist, album and the rest without reading the
+// library. It's kept up to date by the scan: only tracks whose words have
+// changed are re-indexed, and after a whole, finished scan files that are
+// gone are dropped. Words match by their start, and words of four or more
+// letters with one letter wrong.
+use crate::cli::{Command, Flag};
+use crate::spellings::{distance, normalize};
+use crate::{term, Config, TrackInfo};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::process::exit;
+
+pub const COMMAND: Command = Command {
+    name: "search",
+    usage: "[--limit <n>] <word>...",
+    about: "Find tracks by words in their tags, from the [search] index",
+    flags: &[Flag {
+        name: "--limit",
+        values: Some(&[]),
+        about: "Show at most this many tracks, 50 by default",
+    }],
+};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub enabled: bool,
+    pub file: String,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: false,
+            file: String::from("search.json"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    // Path -> its words, to take them out again when they change
+    tracks: BTreeMap<String, BTreeSet<String>>,
+    // Word -> the paths with it
+    words: BTreeMap<String, BTreeSet<String>>,
+}
+
+pub struct Index {
+    file: String,
+    stored: Stored,
+    seen: HashSet<String>,
+    changed: u32,
+}
+
+fn words(t: &TrackInfo) -> BTreeSet<String> {
+    let text = [
+        Some(t.title.as_str()),
+        Some(&*t.artist),
+        Some(&*t.album),
+        Some(&*t.genre),
+        t.album_artist.as_deref(),
+        t.composer.as_deref(),
+        t.conductor.as_deref(),
+        t.work.as_deref(),
+    ];
+    text.into_iter()
+        .flatten()
+        .flat_map(|s| {
+            normalize(s)
+                .split(' ')
+                .filter(|w| !w.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn read(file: &str) -> Result<Stored, String> {
+    match fs::read_to_string(file) {
+        Ok(s) => serde_json::from_str(&s).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Stored::default()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl Index {
+    // None unless it's switched on. A broken index is built again.
+    pub fn open(config: &Config) -> Option<Index> {
+        let sc = &config.search;
+        if !sc.enabled {
+            return None;
+        }
+        let stored = read(&sc.file).unwrap_or_else(|e| {
+            warn!("Error reading {}, building it again: {e}", sc.file);
+            Stored::default()
+        });
+        Some(Index {
+            file: sc.file.clone(),
+            stored,
+            seen: HashSet::new(),
+            changed: 0,
+        })
+    }
+
+    pub fn track(&mut self, t: &TrackInfo) {
+        self.seen.insert(t.path.clone());
+        let new = words(t);
+        if self.stored.tracks.get(&t.path) == Some(&new) {
+            return;
+        }
+        self.remove(&t.path);
+        for w in &new {
+            self.stored
+                .words
+                .entry(w.clone())
+                .or_default()
+                .insert(t.path.clone());
+        }
+        self.stored.tracks.insert(t.path.clone(), new);
+        self.changed += 1;
+    }
+
+    fn remove(&mut self, path: &str) {
+        for w in self.stored.tracks.remove(path).unwrap_or_default() {
+            if let Some(paths) = self.stored.words.get_mut(&w) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.stored.words.remove(&w);
+                }
+            }
+        }
+    }
+
+    // Saves it if anything changed. complete = the whole library was
+    // scanned, so files not seen are gone.
+    pub fn finish(mut self, complete: bool) {
+        if complete {
+            let gone: Vec<String> = self
+                .stored
+                .tracks
+                .keys()
+                .filter(|p| !self.seen.contains(*p))
+                .cloned()
+                .collect();
+            for path in &gone {
+                self.remove(path);
+            }
+            self.changed += gone.len() as u32;
+        }
+        if self.changed == 0 {
+            return;
+        }
+        let res = serde_json::to_string(&self.stored)
+            .map_err(|e| e.to_string())
+            .and_then(|s| fs::write(&self.file, s).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            error!("Error writing {}: {e}", self.file);
+        }
+    }
+}
+
+// Whether a word in the index matches one searched for
+fn word_matches(word: &str, wanted: &[char], prefix: &str) -> bool {
+    if word.starts_with(prefix) {
+        return true;
+    }
+    let chars: Vec<char> = word.chars().collect();
+    wanted.len() >= 4 && chars.len().abs_diff(wanted.len()) <= 1 && distance(&chars, wanted) <= 1
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut limit = 50;
+    let mut wanted = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--limit" => match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => limit = n,
+                _ => usage(),
+            },
+            a if a.starts_with("--") => usage(),
+            _ => wanted.extend(normalize(arg).split(' ').map(String::from)),
+        }
+    }
+    wanted.retain(|w| !w.is_empty());
+    if wanted.is_empty() {
+        usage();
+    }
+    let sc = &config.search;
+    if !sc.enabled {
+        warn!("The index is only kept up to date with enabled in [search]");
+    }
+    let stored = match read(&sc.file) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Error reading {}: {e}", sc.file);
+            exit(1);
+        }
+    };
+
+    // Path -> words it has exactly, for the order. Every word searched for
+    // has to match.
+    let mut found: Option<BTreeMap<&str, u32>> = None;
+    for w in &wanted {
+        let chars: Vec<char> = w.chars().collect();
+        let mut paths: BTreeMap<&str, u32> = BTreeMap::new();
+        for (word, with) in stored
+            .words
+            .iter()
+            .filter(|(word, _)| word_matches(word, &chars, w))
+        {
+            for p in with {
+                let exact = paths.entry(p.as_str()).or_default();
+                *exact = (*exact).max((word == w) as u32);
+            }
+        }
+        found = Some(match found {
+            None => paths,
+            Some(before) => before
+                .into_iter()
+                .filter_map(|(p, n)| Some((p, n + paths.get(p)?)))
+                .collect(),
+        });
+    }
+    let mut found: Vec<(&str, u32)> = found.unwrap_or_default().into_iter().collect();
+    found.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    for (path, _) in found.iter().take(limit) {
+        log!("{path}");
+        term::event("found", json!({ "path": path }));
+    }
+    match found.len() > limit {
+        true => total!("Found: {}, showing {limit}", found.len()),
+        false => total!("Found: {}", found.len()),
+    }
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
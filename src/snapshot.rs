@@ -0,0 +1,350 @@
+// A snapshot is the whole scan, every track and the counts, in one
+// gzipped file, so the reports can be run somewhere without the music,
+// e.g. a laptop away from the NAS, or with other machines' snapshots. It's
+// JSON lines: a header, one line per track, and the counts last. Like the
+// exports it's written as the scan goes.
+use crate::albums::{self, albums};
+use crate::anonymize;
+use crate::artists;
+use crate::cli::Command;
+use crate::feed::rfc2822;
+use crate::intern::Interner;
+use crate::scan::new_stats;
+use crate::{cancel, print_summary, reports, term, Config, RootStats, ScanStats, TrackInfo};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const VERSION: u32 = 1;
+
+pub const COMMAND: Command = Command {
+    name: "snapshot",
+    usage: "export|import <file> | merge <file>...",
+    about: "Save a scan to a file, run the reports on one, or on several machines' together",
+    flags: &[],
+};
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    // Seconds since the epoch
+    created: u64,
+    // The scan directories
+    roots: Vec<String>,
+    // Machine the scan was done on
+    #[serde(default)]
+    host: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Line {
+    Header(Header),
+    Track(Box<TrackInfo>),
+    // Files the scan couldn't read
+    Error { path: String, message: String },
+    Stats { counts: ScanStats, cancelled: bool },
+}
+
+#[derive(Serialize)]
+struct TrackLine<'a> {
+    track: &'a TrackInfo,
+}
+
+pub struct Writer {
+    file: String,
+    out: Option<GzEncoder<BufWriter<File>>>,
+    // The salt, when anonymizing
+    anonymize: Option<String>,
+}
+
+impl Writer {
+    // None if file is empty
+    pub fn create(config: &Config, file: &str) -> Option<Writer> {
+        if file.is_empty() {
+            return None;
+        }
+        let out = match File::create(file) {
+            Ok(f) => GzEncoder::new(BufWriter::new(f), Compression::default()),
+            Err(e) => {
+                error!("Error creating {file}: {e}");
+                return None;
+            }
+        };
+        let ec = &config.export;
+        let anonymize = ec.anonymize.then(|| ec.anonymize_salt.clone());
+        let mut header = Header {
+            version: VERSION,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            roots: config
+                .directories
+                .scan
+                .iter()
+                .map(|r| r.path.clone())
+                .collect(),
+            host: host_name(),
+        };
+        if let Some(salt) = &anonymize {
+            header.roots = header
+                .roots
+                .iter()
+                .map(|r| anonymize::path(salt, r))
+                .collect();
+            header.host = anonymize::text(salt, &header.host);
+        }
+        let mut writer = Writer {
+            file: file.to_string(),
+            out: Some(out),
+            anonymize,
+        };
+        writer.line(&json!({ "header": header }));
+        Some(writer)
+    }
+
+    pub fn track(&mut self, t: &TrackInfo) {
+        match &self.anonymize {
+            Some(salt) => {
+                let track = anonymize::track(salt, t);
+                self.line(&TrackLine { track: &track })
+            }
+            None => self.line(&TrackLine { track: t }),
+        }
+    }
+
+    pub fn error(&mut self, path: &str, message: &str) {
+        let path = match &self.anonymize {
+            Some(salt) => anonymize::path(salt, path),
+            None => path.to_string(),
+        };
+        self.line(&json!({ "error": { "path": path, "message": message } }));
+    }
+
+    pub fn finish(mut self, stats: &ScanStats) {
+        self.line(&json!({ "stats": { "counts": stats, "cancelled": cancel::cancelled() } }));
+        if let Some(out) = self.out.take() {
+            match out.finish().and_then(|mut w| w.flush()) {
+                Ok(_) => log!("Wrote {} ({} tracks)", self.file, stats.valid_files),
+                Err(e) => error!("Error writing {}: {e}", self.file),
+            }
+        }
+    }
+
+    // Gives up on the file after an error
+    fn line(&mut self, value: &impl serde::Serialize) {
+        if let Some(out) = self.out.as_mut() {
+            let res = serde_json::to_writer(&mut *out, value)
+                .map_err(std::io::Error::from)
+                .and_then(|_| writeln!(out));
+            if let Err(e) = res {
+                error!("Error writing {}: {e}", self.file);
+                self.out = None;
+            }
+        }
+    }
+}
+
+// snapshot export is a normal scan that also writes the snapshot, so main
+// only needs the file. The rest run here and return None.
+pub fn run(config: &Config, args: &[String]) -> Option<String> {
+    match args {
+        [cmd, file] if cmd == "export" => Some(file.clone()),
+        [cmd, file] if cmd == "import" => {
+            import(config, file);
+            None
+        }
+        [cmd, files @ ..] if cmd == "merge" && !files.is_empty() => {
+            merge(config, files);
+            None
+        }
+        _ => {
+            log!("{}", COMMAND.usage());
+            exit(1);
+        }
+    }
+}
+
+struct Snapshot {
+    header: Header,
+    stats: ScanStats,
+    cancelled: bool,
+    // Unreadable files and why
+    errors: Vec<(String, String)>,
+}
+
+// Print the snapshot's summary and run the reports on its tracks
+fn import(config: &Config, file: &str) {
+    let Snapshot {
+        header,
+        mut stats,
+        cancelled,
+        errors,
+    } = open(file);
+    log!("{}", describe(&header));
+    for (path, message) in errors {
+        error!("Error in {path}: {message}");
+    }
+    print_summary(&stats, cancelled);
+    if cancelled {
+        warn!("The scan in {file} was stopped before the end, not running the reports");
+        return;
+    }
+    let mut interner = Interner::default();
+    for t in &mut stats.tracks {
+        interner.track(t);
+    }
+    reports::run(config, &stats);
+}
+
+// Snapshots from several machines as one library: each one's summary,
+// then the reports on all their tracks together, and which albums are on
+// which machines. Machines are named by their host name, or the snapshot
+// file if that's missing or the same as another's.
+fn merge(config: &Config, files: &[String]) {
+    let mut sources: Vec<(String, Snapshot)> = Vec::new();
+    for file in files {
+        let snapshot = open(file);
+        let mut name = snapshot.header.host.clone();
+        if name.is_empty() || sources.iter().any(|(n, _)| *n == name) {
+            name = file.clone();
+        }
+        total!("{name}: {}", describe(&snapshot.header));
+        print_summary(&snapshot.stats, snapshot.cancelled);
+        if snapshot.cancelled {
+            warn!("The scan in {file} was stopped before the end");
+        }
+        sources.push((name, snapshot));
+    }
+
+    let mut all = new_stats();
+    let mut interner = Interner::default();
+    // artist and album -> the machines with it
+    let mut albums_on: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+    for (name, s) in &mut sources {
+        all.valid_files += s.stats.valid_files;
+        all.error_files += s.stats.error_files;
+        all.other_files += s.stats.other_files;
+        all.excluded_files += s.stats.excluded_files;
+        all.junk_files += s.stats.junk_files;
+        all.directories += s.stats.directories;
+        for (ext, n) in &s.stats.found_types {
+            *all.found_types.entry(ext.clone()).or_default() += n;
+        }
+        all.playlists.extend(s.stats.playlists.iter().cloned());
+        all.albums.extend(s.stats.albums.iter().cloned());
+        all.artists.extend(s.stats.artists.iter().cloned());
+        all.roots.extend(s.stats.roots.iter().map(|r| RootStats {
+            label: format!("{name}: {}", r.label),
+            valid_files: r.valid_files,
+            error_files: r.error_files,
+        }));
+        for a in albums(&s.stats.tracks) {
+            albums_on
+                .entry((a.artist.to_string(), a.title.to_string()))
+                .or_default()
+                .push(name.clone());
+        }
+        for mut t in std::mem::take(&mut s.stats.tracks) {
+            interner.track(&mut t);
+            all.tracks.push(t);
+        }
+    }
+    total!("All machines:");
+    print_summary(&all, sources.iter().any(|(_, s)| s.cancelled));
+    reports::run(config, &all);
+
+    let shared: Vec<_> = albums_on.iter().filter(|(_, on)| on.len() > 1).collect();
+    total!("Albums on more than one machine: {}", shared.len());
+    for ((artist, album), on) in &shared {
+        log!("  {artist} - {album}: {}", on.join(", "));
+    }
+    for (name, _) in &sources {
+        let only = albums_on
+            .values()
+            .filter(|on| on.len() == 1 && on[0] == *name)
+            .count();
+        total!("Albums only on {name}: {only}");
+    }
+    term::event(
+        "machines",
+        json!({
+            "shared": shared
+                .iter()
+                .map(|((artist, album), on)| json!({ "artist": artist, "album": album, "on": on }))
+                .collect::<Vec<_>>(),
+        }),
+    );
+}
+
+fn open(file: &str) -> Snapshot {
+    match read(file) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Error reading {file}: {e}");
+            exit(1);
+        }
+    }
+}
+
+fn describe(h: &Header) -> String {
+    format!(
+        "Snapshot of {} from {}",
+        h.roots.join(", "),
+        rfc2822(h.created)
+    )
+}
+
+fn read(file: &str) -> Result<Snapshot, String> {
+    let f = File::open(file).map_err(|e| e.to_string())?;
+    let mut tracks = Vec::new();
+    let mut errors = Vec::new();
+    let mut header = None;
+    for (n, line) in BufReader::new(GzDecoder::new(f)).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        match serde_json::from_str(&line).map_err(|e| format!("line {}: {e}", n + 1))? {
+            Line::Header(h) if h.version > VERSION => {
+                return Err(format!("made by a newer tag_test (version {})", h.version))
+            }
+            Line::Header(h) => header = Some(h),
+            Line::Track(t) => tracks.push(*t),
+            Line::Error { path, message } => errors.push((path, message)),
+            Line::Stats {
+                mut counts,
+                cancelled,
+            } => {
+                // Snapshots from before albums were counted
+                if counts.albums.is_empty() {
+                    counts.albums = albums::info(&tracks);
+                    counts.artists = artists::info(&tracks);
+                }
+                counts.tracks = tracks;
+                return Ok(Snapshot {
+                    header: header.ok_or("no header")?,
+                    stats: counts,
+                    cancelled,
+                    errors,
+                });
+            }
+        }
+    }
+    Err(String::from("cut short, the scan may not have finished"))
+}
+
+// The machine's name, for snapshot merge
+fn host_name() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|f| fs::read_to_string(f).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,140 @@
+// Sort names for artists and albums, so "The Beatles" files under B.
+// Names starting with one of the articles get it moved to the end, and
+// names in the names table get the sort name given there, e.g. for
+// people listed by surname.
+use crate::ask::Prompt;
+use crate::cli::{Command, DRY_RUN, INTERACTIVE};
+use crate::{music_files, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::process::exit;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SortNamesConfig {
+    // Leading words moved to the end, matched without regard to case
+    pub articles: Vec<String>,
+    // Sort names for particular artists or albums
+    pub names: BTreeMap<String, String>,
+}
+
+impl Default for SortNamesConfig {
+    fn default() -> Self {
+        SortNamesConfig {
+            articles: vec![String::from("The"), String::from("A"), String::from("An")],
+            names: BTreeMap::new(),
+        }
+    }
+}
+
+// The sort name for name, if it should have one different from the name
+pub fn sort_name(sc: &SortNamesConfig, name: &str) -> Option<String> {
+    if let Some(s) = sc.names.get(name) {
+        return Some(s.clone());
+    }
+    let (first, rest) = name.split_once(' ')?;
+    let rest = rest.trim_start();
+    if rest.is_empty() || !sc.articles.iter().any(|a| a.eq_ignore_ascii_case(first)) {
+        return None;
+    }
+    Some(format!("{rest}, {first}"))
+}
+
+pub const COMMAND: Command = Command {
+    name: "sortnames",
+    usage: "[--dry-run] [--interactive] [path...]",
+    about: "Add missing artist and album sort names",
+    flags: &[DRY_RUN, INTERACTIVE],
+};
+
+// Add the missing artist and album sort names to files. Sort names
+// already there are left alone.
+pub fn run(config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let mut prompt = Prompt::new(&mut args);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+
+    let (mut changed, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        match fix_file(&config.sort_names, &file_name, &mut prompt, dry_run) {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error adding sort names to {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+}
+
+fn fix_file(
+    sc: &SortNamesConfig,
+    file_name: &str,
+    prompt: &mut Prompt,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = match tagged_file.primary_tag() {
+        Some(t) => t.clone(),
+        None => return Ok(false),
+    };
+
+    let mut sort_names = Vec::new();
+    for (key, sort_key) in [
+        (ItemKey::TrackArtist, ItemKey::TrackArtistSortOrder),
+        (ItemKey::AlbumTitle, ItemKey::AlbumTitleSortOrder),
+    ] {
+        if tag.get_string(&sort_key).is_some() {
+            continue;
+        }
+        let sort = match tag.get_string(&key).and_then(|n| sort_name(sc, n.trim())) {
+            Some(s) => s,
+            None => continue,
+        };
+        sort_names.push((sort_key, sort));
+    }
+
+    if sort_names.is_empty() {
+        return Ok(false);
+    }
+    log!("{file_name}:");
+    let mut changed = false;
+    for (sort_key, sort) in sort_names {
+        log!("  + {sort_key:?}: {sort:?}");
+        if let Some(sort) = prompt.check(sort) {
+            tag.insert_text(sort_key, sort);
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(false);
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,292 @@
+// Artist and album names spelt more than one way, "Beyoncé", "Beyonce"
+// and "Beyoncé ". Names are compared without case, accents, punctuation or
+// extra spaces, and names still a letter or two apart (by edit distance)
+// are put together too. The spelling on the most tracks is taken as the
+// right one.
+use crate::albums::split_disc;
+use crate::ask::Prompt;
+use crate::cli::{Command, DRY_RUN, INTERACTIVE};
+use crate::{music_files, read_metadata, term, Config, TrackInfo};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::process::exit;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SpellingsConfig {
+    // How alike two names have to be to count as the same, 1 = only
+    // differing in case, accents, punctuation and spaces
+    pub similarity: f64,
+}
+
+impl Default for SpellingsConfig {
+    fn default() -> Self {
+        SpellingsConfig { similarity: 0.9 }
+    }
+}
+
+pub struct Cluster {
+    // The spelling with the most tracks
+    pub canonical: String,
+    // The other spellings and their tracks
+    pub others: Vec<(String, u64)>,
+}
+
+const ACCENTED: &str =
+    "ÀÁÂÃÄÅàáâãäåÇçĆćČčĎďÈÉÊËèéêëĚěÌÍÎÏìíîïŁłÑñŃńŇňÒÓÔÕÖØòóôõöøŘřŚśŠšŤťÙÚÛÜùúûüŮůÝýÿŹźŻżŽž";
+const PLAIN: &str =
+    "AAAAAAaaaaaaCcCcCcDdEEEEeeeeEeIIIIiiiiLlNnNnNnOOOOOOooooooRrSsSsTtUUUUuuuuUuYyyZzZzZz";
+
+// The name to compare, "Beyoncé " -> "beyonce"
+pub fn normalize(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        let c = match ACCENTED.chars().position(|a| a == c) {
+            Some(i) => PLAIN.chars().nth(i).unwrap_or(c),
+            None => c,
+        };
+        if c.is_alphanumeric() {
+            out.extend(c.to_lowercase());
+        } else if c.is_whitespace() && !out.is_empty() && !out.ends_with(' ') {
+            out.push(' ');
+        }
+    }
+    out.trim_end().to_string()
+}
+
+// Edits to turn one into the other
+pub fn distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = prev + (ca != cb) as usize;
+            prev = row[j + 1];
+            row[j + 1] = cost.min(prev + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Names with their track counts, grouped into the ones with more than one
+// spelling
+pub fn clusters(names: &BTreeMap<String, u64>, similarity: f64) -> Vec<Cluster> {
+    // normalized -> spellings
+    let mut keys: BTreeMap<String, Vec<(&String, u64)>> = BTreeMap::new();
+    for (name, n) in names.iter().filter(|(n, _)| !n.trim().is_empty()) {
+        keys.entry(normalize(name)).or_default().push((name, *n));
+    }
+    let keys: Vec<_> = keys.into_iter().collect();
+    let chars: Vec<Vec<char>> = keys.iter().map(|(k, _)| k.chars().collect()).collect();
+
+    // Join up keys close enough to each other
+    let mut group: Vec<usize> = (0..keys.len()).collect();
+    fn root(group: &mut [usize], mut i: usize) -> usize {
+        while group[i] != i {
+            group[i] = group[group[i]];
+            i = group[i];
+        }
+        i
+    }
+    let max_edits = |len: usize| ((1.0 - similarity) * len as f64).floor() as usize;
+    for i in 0..keys.len() {
+        for j in i + 1..keys.len() {
+            let (a, b) = (&chars[i], &chars[j]);
+            let edits = max_edits(a.len().max(b.len()));
+            if edits == 0 || a.len().abs_diff(b.len()) > edits {
+                continue;
+            }
+            if distance(a, b) <= edits {
+                let (ri, rj) = (root(&mut group, i), root(&mut group, j));
+                group[rj] = ri;
+            }
+        }
+    }
+
+    let mut grouped: BTreeMap<usize, Vec<(&String, u64)>> = BTreeMap::new();
+    for (i, (_, spellings)) in keys.iter().enumerate() {
+        let r = root(&mut group, i);
+        grouped
+            .entry(r)
+            .or_default()
+            .extend(spellings.iter().copied());
+    }
+    grouped
+        .into_values()
+        .filter(|s| s.len() > 1)
+        .map(|mut s| {
+            // On a tie, no stray spaces, then the longest, which has the
+            // accents or the letter the others are missing
+            s.sort_by_key(|(name, n)| {
+                (
+                    Reverse(*n),
+                    name.trim() != name.as_str(),
+                    Reverse(name.len()),
+                    name.to_string(),
+                )
+            });
+            Cluster {
+                canonical: s[0].0.clone(),
+                others: s[1..].iter().map(|(n, c)| (n.to_string(), *c)).collect(),
+            }
+        })
+        .collect()
+}
+
+pub fn artist_clusters(tracks: &[TrackInfo], similarity: f64) -> Vec<Cluster> {
+    let mut names: BTreeMap<String, u64> = BTreeMap::new();
+    for t in tracks {
+        *names.entry(t.artist.to_string()).or_default() += 1;
+    }
+    clusters(&names, similarity)
+}
+
+// Albums are only compared with the same artist's, once the artists'
+// spellings are sorted out. Returns the artist with each cluster.
+pub fn album_clusters(tracks: &[TrackInfo], similarity: f64) -> Vec<(String, Cluster)> {
+    let artists = canonical(&artist_clusters(tracks, similarity));
+    let mut by_artist: BTreeMap<&str, BTreeMap<String, u64>> = BTreeMap::new();
+    for t in tracks {
+        let artist = artists.get(&*t.artist).map_or(&*t.artist, |a| a.as_str());
+        *by_artist
+            .entry(artist)
+            .or_default()
+            .entry(split_disc(&t.album).0.to_string())
+            .or_default() += 1;
+    }
+    by_artist
+        .into_iter()
+        .flat_map(|(artist, albums)| {
+            clusters(&albums, similarity)
+                .into_iter()
+                .map(move |c| (artist.to_string(), c))
+        })
+        .collect()
+}
+
+// Other spelling -> canonical
+fn canonical(clusters: &[Cluster]) -> HashMap<String, String> {
+    clusters
+        .iter()
+        .flat_map(|c| {
+            c.others
+                .iter()
+                .map(|(n, _)| (n.clone(), c.canonical.clone()))
+        })
+        .collect()
+}
+
+pub const COMMAND: Command = Command {
+    name: "spellings",
+    usage: "[--dry-run] [--interactive] [path...]",
+    about: "Rewrite other spellings of artists and albums to the most used one",
+    flags: &[DRY_RUN, INTERACTIVE],
+};
+
+// Rewrite the other spellings of artists and albums to the canonical ones
+pub fn run(config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let mut prompt = Prompt::new(&mut args);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--dry-run").cloned().collect();
+    if paths.iter().any(|a| a.starts_with("--")) {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+
+    let tracks: Vec<TrackInfo> = music_files(config, &paths)
+        .iter()
+        .filter_map(|f| read_metadata(f).ok())
+        .collect();
+    let similarity = config.spellings.similarity;
+    let artists = canonical(&artist_clusters(&tracks, similarity));
+    // (artist, other spelling) -> canonical
+    let mut albums: HashMap<(String, String), String> = HashMap::new();
+    for (artist, c) in album_clusters(&tracks, similarity) {
+        for (other, _) in c.others {
+            albums.insert((artist.clone(), other), c.canonical.clone());
+        }
+    }
+
+    let (mut changed, mut failed) = (0, 0);
+    for t in &tracks {
+        let artist = artists.get(&*t.artist);
+        let key = (
+            artist.map_or(t.artist.to_string(), |a| a.clone()),
+            split_disc(&t.album).0.to_string(),
+        );
+        let album = albums.get(&key);
+        if artist.is_none() && album.is_none() {
+            continue;
+        }
+        match fix_file(&t.path, artist, album.map(|a| (t, a)), &mut prompt, dry_run) {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error fixing the spelling in {}: {e}", t.path);
+                term::event("error", json!({ "path": t.path, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+}
+
+// The album's disc suffix, e.g. " (Disc 2)", is kept
+fn fix_file(
+    file_name: &str,
+    artist: Option<&String>,
+    album: Option<(&TrackInfo, &String)>,
+    prompt: &mut Prompt,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = tagged_file
+        .primary_tag()
+        .cloned()
+        .ok_or_else(|| String::from("no tag"))?;
+    log!("{file_name}:");
+    let mut changed = false;
+    if let Some(a) = artist {
+        log!("  {:?} -> {a:?}", tag.artist().unwrap_or_default());
+        if let Some(a) = prompt.check(a.clone()) {
+            tag.insert_text(ItemKey::TrackArtist, a);
+            changed = true;
+        }
+    }
+    if let Some((t, a)) = album {
+        let title = split_disc(&t.album).0;
+        let new = format!("{a}{}", &t.album[title.len()..]);
+        log!("  {:?} -> {new:?}", t.album);
+        if let Some(new) = prompt.check(new) {
+            tag.insert_text(ItemKey::AlbumTitle, new);
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(false);
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
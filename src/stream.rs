@@ -0,0 +1,201 @@
+// "tag_test probe-stream <url>": what an Icecast or SHOUTcast station
+// says about itself in its response headers, and the title playing now
+// from the ICY metadata sent in between the audio. Only plain http, which
+// is what most stations still serve.
+use crate::cli::Command;
+use crate::term;
+use serde_json::{Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::exit;
+use std::time::Duration;
+
+pub const COMMAND: Command = Command {
+    name: "probe-stream",
+    usage: "<url>",
+    about: "Show an Icecast or SHOUTcast station's name, codec, bitrate and current title",
+    flags: &[],
+};
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: u32 = 3;
+
+// Header -> what it's shown as
+const HEADERS: &[(&str, &str)] = &[
+    ("icy-name", "Station"),
+    ("icy-description", "Description"),
+    ("icy-genre", "Genre"),
+    ("icy-url", "Website"),
+    ("icy-br", "Bitrate"),
+    ("icy-sr", "Sample rate"),
+    ("ice-audio-info", "Audio info"),
+    ("server", "Server"),
+];
+
+struct Response {
+    status: u32,
+    // Names in lower case
+    headers: Vec<(String, String)>,
+    body: BufReader<TcpStream>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn run(args: &[String]) {
+    let url = match args {
+        [u] if !u.starts_with("--") => u,
+        _ => {
+            log!("{}", COMMAND.usage());
+            exit(1);
+        }
+    };
+    let mut resp = match open(url) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Error connecting to {url}: {e}");
+            exit(1);
+        }
+    };
+
+    let mut info = Map::new();
+    info.insert(String::from("url"), Value::from(url.as_str()));
+    for (header, label) in HEADERS {
+        if let Some(v) = resp.header(header).filter(|v| !v.is_empty()) {
+            log!("{label}: {v}");
+            info.insert(header.replace('-', "_"), Value::from(v));
+        }
+    }
+    let content_type = resp.header("content-type").unwrap_or("").to_string();
+    log!("Codec: {}", codec(&content_type));
+    info.insert(String::from("codec"), Value::from(codec(&content_type)));
+    info.insert(String::from("content_type"), Value::from(content_type));
+
+    match resp
+        .header("icy-metaint")
+        .map(|m| m.trim().parse::<usize>())
+    {
+        Some(Ok(metaint)) => match title(&mut resp.body, metaint) {
+            Ok(Some(t)) => {
+                log!("Now playing: {t}");
+                info.insert(String::from("title"), Value::from(t));
+            }
+            Ok(None) => log!("Now playing: not sent"),
+            Err(e) => warn!("Error reading the stream metadata: {e}"),
+        },
+        _ => log!("Now playing: the station doesn't send titles"),
+    }
+    term::event("stream", Value::Object(info));
+}
+
+// The stream's response, after any redirects
+fn open(url: &str) -> Result<Response, String> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let resp = get(&url)?;
+        match (resp.status, resp.header("location")) {
+            (200, _) => return Ok(resp),
+            (301 | 302 | 303 | 307 | 308, Some(to)) => url = to.to_string(),
+            (status, _) => return Err(format!("HTTP {status}")),
+        }
+    }
+    Err(String::from("too many redirects"))
+}
+
+fn get(url: &str) -> Result<Response, String> {
+    let rest = match url.strip_prefix("http://") {
+        Some(r) => r,
+        None if url.starts_with("https://") => return Err(String::from("https isn't supported")),
+        None => return Err(String::from("not an http URL")),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{host}:80"),
+    };
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{host} not found"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let request = format!(
+        "GET {path} HTTP/1.0\r\nHost: {host}\r\nIcy-MetaData: 1\r\nUser-Agent: tag_test/{}\r\n\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    // SHOUTcast 1 answers "ICY 200 OK" rather than "HTTP/1.0 200 OK"
+    let mut body = BufReader::new(stream);
+    let mut line = String::new();
+    body.read_line(&mut line).map_err(|e| e.to_string())?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("bad response {:?}", line.trim()))?;
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        body.read_line(&mut line).map_err(|e| e.to_string())?;
+        let l = line.trim_end();
+        if l.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = l.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+// StreamTitle from the first metadata block, which comes after metaint
+// bytes of audio
+fn title(body: &mut impl Read, metaint: usize) -> Result<Option<String>, String> {
+    let mut skip = body.take(metaint as u64);
+    let skipped = std::io::copy(&mut skip, &mut std::io::sink()).map_err(|e| e.to_string())?;
+    if skipped < metaint as u64 {
+        return Err(String::from("the stream ended"));
+    }
+    let mut len = [0u8];
+    body.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let mut block = vec![0u8; len[0] as usize * 16];
+    body.read_exact(&mut block).map_err(|e| e.to_string())?;
+    // StreamTitle='Artist - Title';StreamUrl='';
+    let text = String::from_utf8_lossy(&block);
+    Ok(text
+        .split("StreamTitle='")
+        .nth(1)
+        .and_then(|t| t.split("';").next())
+        .filter(|t| !t.is_empty())
+        .map(String::from))
+}
+
+fn codec(content_type: &str) -> &'static str {
+    match content_type.split(';').next().unwrap_or("").trim() {
+        "audio/mpeg" | "audio/mp3" => "MP3",
+        "audio/aac" | "audio/aacp" | "audio/x-aac" => "AAC",
+        "audio/ogg" | "application/ogg" => "Ogg",
+        "audio/opus" => "Opus",
+        "audio/flac" => "FLAC",
+        "" => "unknown",
+        _ => "other",
+    }
+}
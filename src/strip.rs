@@ -0,0 +1,186 @@
+// Remove unwanted fields, or whole tags, from files
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::{music_files, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagType};
+use serde_json::json;
+use std::process::exit;
+
+pub const TAG_TYPES: &[&str] = &["id3v1", "id3v2", "ape", "vorbis", "riff", "aiff", "mp4"];
+// Friendly names for fields, see field_keys
+pub const FIELD_ALIASES: &[&str] = &["comment", "encoded-by", "encoder", "lyrics", "rating"];
+
+pub const COMMAND: Command = Command {
+    name: "strip",
+    usage: "[--dry-run] [--field <name>]... [--tag <type>]... [path...]",
+    about: "Remove fields, or whole tags, from files",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--field",
+            values: Some(FIELD_ALIASES),
+            about: "Field to remove, by name or by the tag's own key",
+        },
+        Flag {
+            name: "--tag",
+            values: Some(TAG_TYPES),
+            about: "Tag type to remove completely",
+        },
+    ],
+};
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut dry_run = false;
+    let mut fields = Vec::new();
+    let mut tag_types = Vec::new();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--field" => match args.next() {
+                Some(f) => fields.push(f.clone()),
+                None => usage(),
+            },
+            "--tag" => match args.next().and_then(|t| tag_type(t.as_str())) {
+                Some(t) => tag_types.push(t),
+                None => {
+                    error!("--tag needs one of {}", TAG_TYPES.join(", "));
+                    exit(1);
+                }
+            },
+            a if a.starts_with("--") => usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if fields.is_empty() && tag_types.is_empty() {
+        usage();
+    }
+
+    let (mut changed, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        match strip_file(&file_name, &fields, &tag_types, dry_run) {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error stripping {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
+
+fn tag_type(name: &str) -> Option<TagType> {
+    match name.to_lowercase().as_str() {
+        "id3v1" => Some(TagType::Id3v1),
+        "id3v2" => Some(TagType::Id3v2),
+        "ape" | "apev2" => Some(TagType::Ape),
+        "vorbis" => Some(TagType::VorbisComments),
+        "riff" => Some(TagType::RiffInfo),
+        "aiff" => Some(TagType::AiffText),
+        "mp4" => Some(TagType::Mp4Ilst),
+        _ => None,
+    }
+}
+
+// Friendly field names cover the usual suspects in every tag type,
+// anything else is taken as the frame/field name used by the tag itself
+fn field_keys(field: &str, tag_type: TagType) -> Vec<ItemKey> {
+    match field.to_lowercase().as_str() {
+        "comment" | "comments" => vec![ItemKey::Comment],
+        "encoded-by" => vec![ItemKey::EncodedBy],
+        "encoder" => vec![ItemKey::EncoderSoftware, ItemKey::EncoderSettings],
+        "lyrics" => vec![ItemKey::Lyrics],
+        "rating" | "ratings" => vec![
+            ItemKey::Popularimeter,
+            ItemKey::from_key(tag_type, "RATING"),
+            ItemKey::from_key(tag_type, "FMPS_RATING"),
+        ],
+        _ => vec![ItemKey::from_key(tag_type, field)],
+    }
+}
+
+fn strip_file(
+    file_name: &str,
+    fields: &[String],
+    tag_types: &[TagType],
+    dry_run: bool,
+) -> Result<bool, String> {
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut diff = Vec::new();
+
+    let mut whole = Vec::new();
+    for t in tag_types {
+        if let Some(tag) = tagged_file.tag(*t) {
+            diff.push(format!("- {:?} tag ({} items)", t, tag.item_count()));
+            whole.push(*t);
+        }
+    }
+
+    let mut modified: Vec<Tag> = Vec::new();
+    for tag in tagged_file.tags() {
+        if whole.contains(&tag.tag_type()) {
+            continue;
+        }
+        let keys: Vec<ItemKey> = fields
+            .iter()
+            .flat_map(|f| field_keys(f, tag.tag_type()))
+            .collect();
+        let mut tag = tag.clone();
+        let before = diff.len();
+        for key in &keys {
+            for item in tag.get_items(key) {
+                let value = match item.value() {
+                    ItemValue::Text(t) | ItemValue::Locator(t) => format!("{t:?}"),
+                    ItemValue::Binary(b) => format!("{} bytes", b.len()),
+                };
+                diff.push(format!("- {:?} {:?}: {}", tag.tag_type(), key, value));
+            }
+            tag.remove_key(key);
+        }
+        if diff.len() > before {
+            modified.push(tag);
+        }
+    }
+
+    if diff.is_empty() {
+        return Ok(false);
+    }
+    log!("{file_name}:");
+    for line in &diff {
+        log!("  {line}");
+    }
+    if dry_run {
+        return Ok(true);
+    }
+
+    for t in whole {
+        t.remove_from_path(file_name).map_err(|e| e.to_string())?;
+    }
+    for tag in modified {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
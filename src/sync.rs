@@ -0,0 +1,310 @@
+// Copy part of the library to a player or SD card: the files in a
+// playlist or under the given paths, with formats that are too big
+// transcoded, the cover art copied along, and anything else on the target
+// deleted. Files already on the target and newer than in the library are
+// left alone, so a second sync only does the changes.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::fields::Expr;
+use crate::m3u::Playlist;
+use crate::transcode::{ffmpeg, mirror_path, up_to_date};
+use crate::{file_ext, music_files, read_metadata, term, Config};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use walkdir::WalkDir;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    // Directory to sync to, e.g. where the player is mounted
+    pub target: String,
+    // Types transcoded with the [transcode] settings rather than copied
+    pub transcode: Vec<String>,
+    // Cover art files copied from each album directory
+    pub art: Vec<String>,
+    // Delete files on the target that aren't part of the sync
+    pub delete: bool,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            target: String::new(),
+            transcode: vec![String::from("flac"), String::from("wav")],
+            art: vec![String::from("cover.jpg"), String::from("folder.jpg")],
+            delete: true,
+        }
+    }
+}
+
+pub const COMMAND: Command = Command {
+    name: "sync",
+    usage: "[--dry-run] [--playlist <file>] [--where <expression>] [path...]",
+    about: "Copy a playlist or part of the library to a player, deleting what else is there",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--playlist",
+            values: Some(&[]),
+            about: "Sync the files in this m3u or JSON playlist",
+        },
+        Flag {
+            name: "--where",
+            values: Some(&[]),
+            about: "Only files whose tags match this expression",
+        },
+    ],
+};
+
+#[derive(Default)]
+struct Counts {
+    copied: u32,
+    transcoded: u32,
+    deleted: u32,
+    failed: u32,
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let sc = &config.sync;
+    let mut dry_run = false;
+    let mut playlist = None;
+    let mut expr = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--playlist" => match args.next() {
+                Some(p) => playlist = Some(p.clone()),
+                None => usage(),
+            },
+            "--where" => match args.next().map(|e| Expr::try_from(e.clone())) {
+                Some(Ok(e)) => expr = Some(e),
+                Some(Err(e)) => {
+                    error!("Error in --where: {e}");
+                    exit(1);
+                }
+                None => usage(),
+            },
+            a if a.starts_with("--") => usage(),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if sc.target.is_empty() {
+        error!("Set target in the [sync] section of the config first");
+        exit(1);
+    }
+    // Deleting what isn't part of the sync would delete the library
+    if let Some(root) = config
+        .directories
+        .scan
+        .iter()
+        .find(|r| overlaps(Path::new(&r.path), Path::new(&sc.target)))
+    {
+        error!(
+            "The sync target {} overlaps the scan directory {}",
+            sc.target, root.path
+        );
+        exit(1);
+    }
+
+    let mut files = match &playlist {
+        Some(p) => match read_playlist(p) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Error reading {p}: {e}");
+                exit(1);
+            }
+        },
+        None => music_files(config, &paths),
+    };
+    // Files that can't be read are left out, like ones that don't match
+    if let Some(e) = &expr {
+        files.retain(|f| read_metadata(f).is_ok_and(|t| e.matches(&t)));
+    }
+
+    let mut counts = Counts::default();
+    let mut wanted = HashSet::new();
+    let mut art_dirs = HashSet::new();
+    for file in &files {
+        let mut target = mirror_path(config, file, &sc.target);
+        let transcode = sc.transcode.contains(&file_ext(file));
+        if transcode {
+            target.set_extension(&config.transcode.to);
+        }
+        let target_name = target.to_string_lossy().to_string();
+        wanted.insert(target.clone());
+        if let Some(dir) = Path::new(file).parent() {
+            if art_dirs.insert(dir.to_path_buf()) {
+                for name in &sc.art {
+                    let art = dir.join(name);
+                    if art.is_file() {
+                        let art = art.to_string_lossy().to_string();
+                        let to = mirror_path(config, &art, &sc.target);
+                        wanted.insert(to.clone());
+                        copy(&art, &to, dry_run, &mut counts);
+                    }
+                }
+            }
+        }
+        if !transcode {
+            copy(file, &target, dry_run, &mut counts);
+            continue;
+        }
+        if up_to_date(file, &target_name) {
+            continue;
+        }
+        log!(
+            "{} {file}",
+            if dry_run {
+                "Would transcode"
+            } else {
+                "Transcoding"
+            }
+        );
+        if dry_run {
+            counts.transcoded += 1;
+            continue;
+        }
+        match ffmpeg(&config.transcode, file, &target_name) {
+            Ok(_) => counts.transcoded += 1,
+            Err(e) => failed(file, e, &mut counts),
+        }
+    }
+    if sc.delete {
+        // With nothing to sync, a library that isn't mounted or a failed
+        // copy, deleting would take what's on the target for no reason
+        let missing = config
+            .directories
+            .scan
+            .iter()
+            .find(|r| !Path::new(&r.path).is_dir());
+        if files.is_empty() {
+            warn!("Nothing to sync, not deleting anything on the target");
+        } else if let Some(root) = missing {
+            warn!(
+                "{} isn't there, not deleting anything on the target",
+                root.path
+            );
+        } else if counts.failed > 0 {
+            warn!("Not deleting anything on the target after errors");
+        } else {
+            delete_unwanted(&sc.target, &wanted, dry_run, &mut counts);
+        }
+    }
+
+    total!(
+        "{} {}, Transcoded: {}, Deleted: {}, Failed: {}",
+        if dry_run { "Would copy" } else { "Copied" },
+        counts.copied,
+        counts.transcoded,
+        counts.deleted,
+        counts.failed
+    );
+    term::event(
+        "summary",
+        json!({
+            "dry_run": dry_run,
+            "copied": counts.copied,
+            "transcoded": counts.transcoded,
+            "deleted": counts.deleted,
+            "failed": counts.failed,
+        }),
+    );
+}
+
+// Either is the other or under it, going by where they really are when
+// they're there
+fn overlaps(a: &Path, b: &Path) -> bool {
+    let real = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+    let (a, b) = (real(a), real(b));
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
+fn usage() -> ! {
+    log!("{}", COMMAND.usage());
+    exit(1);
+}
+
+// Paths from an m3u playlist, relative ones going by the playlist's
+// directory, or from a JSON playlist as written by the playlists option
+pub fn read_playlist(file: &str) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(file).map_err(|e| e.to_string())?;
+    if file_ext(file) == "json" {
+        let tracks: Vec<Value> = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        return Ok(tracks
+            .iter()
+            .filter_map(|t| t["path"].as_str().map(String::from))
+            .collect());
+    }
+    let pl = Playlist::read(Path::new(file))?;
+    Ok(pl
+        .entries()
+        .map(|(_, path)| path.to_string_lossy().to_string())
+        .collect())
+}
+
+fn copy(from: &str, to: &Path, dry_run: bool, counts: &mut Counts) {
+    let to_name = to.to_string_lossy();
+    if up_to_date(from, &to_name) {
+        return;
+    }
+    log!("{} {from}", if dry_run { "Would copy" } else { "Copying" });
+    if dry_run {
+        counts.copied += 1;
+        return;
+    }
+    let res = match to.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+    .and_then(|_| fs::copy(from, to));
+    match res {
+        Ok(_) => counts.copied += 1,
+        Err(e) => failed(from, e.to_string(), counts),
+    }
+}
+
+fn failed(file: &str, e: String, counts: &mut Counts) {
+    error!("Error syncing {file}: {e}");
+    term::event("error", json!({ "path": file, "message": e }));
+    counts.failed += 1;
+}
+
+// Delete files on the target that the sync didn't put there, and the
+// directories that leaves empty
+fn delete_unwanted(target: &str, wanted: &HashSet<PathBuf>, dry_run: bool, counts: &mut Counts) {
+    for entry in WalkDir::new(target)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if entry.file_type().is_dir() {
+            if !dry_run && path != Path::new(target) {
+                // Fails unless it's empty, which is what's wanted
+                let _ = fs::remove_dir(path);
+            }
+            continue;
+        }
+        if wanted.contains(path) {
+            continue;
+        }
+        log!(
+            "{} {}",
+            if dry_run { "Would delete" } else { "Deleting" },
+            path.display()
+        );
+        if dry_run {
+            counts.deleted += 1;
+            continue;
+        }
+        match fs::remove_file(path) {
+            Ok(_) => counts.deleted += 1,
+            Err(e) => failed(&path.to_string_lossy(), e.to_string(), counts),
+        }
+    }
+}
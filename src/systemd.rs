@@ -0,0 +1,151 @@
+// Running as a systemd service. With Type=notify the scan tells systemd
+// when it's started and how far it's got, and keeps WatchdogSec= fed
+// while it's getting somewhere, so a scan stuck on a hung network mount
+// is restarted. When the output goes to the journal, files that can't be
+// read are logged with TAG_TEST_PATH and TAG_TEST_ERROR_KIND fields, for
+// "journalctl TAG_TEST_ERROR_KIND=io" and the like. Nothing happens
+// outside systemd, it goes by the variables systemd sets.
+#[cfg(target_os = "linux")]
+pub use linux::{file_error, finished, progress, ready};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::env;
+    use std::os::unix::net::UnixDatagram;
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{Duration, Instant};
+
+    const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+    // How often the status is updated when there's no watchdog
+    const STATUS_EVERY: Duration = Duration::from_secs(5);
+
+    // READY=1, STATUS=... and so on, one per line
+    fn notify(state: &str) {
+        let path = match env::var("NOTIFY_SOCKET") {
+            Ok(p) if !p.is_empty() => p,
+            _ => return,
+        };
+        let socket = match UnixDatagram::unbound() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        // @ is an abstract socket
+        let res = match path.strip_prefix('@') {
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                std::os::unix::net::SocketAddr::from_abstract_name(name)
+                    .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+            }
+            _ => socket.send_to(state.as_bytes(), &path),
+        };
+        if let Err(e) = res {
+            warn!("Error notifying systemd: {e}");
+        }
+    }
+
+    pub fn ready() {
+        notify("READY=1\nSTATUS=Starting");
+    }
+
+    pub fn finished(status: &str) {
+        notify(&format!("STOPPING=1\nSTATUS={status}"));
+    }
+
+    // Half the watchdog timeout, or how often to update the status
+    fn interval() -> Duration {
+        static INTERVAL: OnceLock<Duration> = OnceLock::new();
+        *INTERVAL.get_or_init(|| {
+            env::var("WATCHDOG_USEC")
+                .ok()
+                .and_then(|u| u.parse::<u64>().ok())
+                .filter(|u| *u > 0)
+                .map_or(STATUS_EVERY, |u| Duration::from_micros(u / 2))
+        })
+    }
+
+    // Called as the scan gets somewhere. The status is only made when it's
+    // time to send it.
+    pub fn progress(status: impl FnOnce() -> String) {
+        static LAST: Mutex<Option<Instant>> = Mutex::new(None);
+        if env::var_os("NOTIFY_SOCKET").is_none() {
+            return;
+        }
+        let mut last = match LAST.lock() {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        if last.is_some_and(|l| l.elapsed() < interval()) {
+            return;
+        }
+        *last = Some(Instant::now());
+        let watchdog = match env::var_os("WATCHDOG_USEC") {
+            Some(_) => "WATCHDOG=1\n",
+            None => "",
+        };
+        notify(&format!("{watchdog}STATUS={}", status()));
+    }
+
+    // True if the output goes to the journal: JOURNAL_STREAM is the device
+    // and inode of stdout's or stderr's connection to it
+    fn journal() -> bool {
+        static JOURNAL: OnceLock<bool> = OnceLock::new();
+        *JOURNAL.get_or_init(|| {
+            use std::os::unix::fs::MetadataExt;
+            let stream = env::var("JOURNAL_STREAM").unwrap_or_default();
+            let ids = stream
+                .split_once(':')
+                .and_then(|(d, i)| Some((d.parse::<u64>().ok()?, i.parse::<u64>().ok()?)));
+            ids.is_some_and(|ids| {
+                ["/proc/self/fd/1", "/proc/self/fd/2"]
+                    .iter()
+                    .any(|fd| std::fs::metadata(fd).is_ok_and(|m| (m.dev(), m.ino()) == ids))
+            })
+        })
+    }
+
+    // A file the scan can't read, to the journal with its fields. False if
+    // the output isn't going to the journal, and it should be printed.
+    pub fn file_error(path: &str, message: &str) -> bool {
+        if !journal() {
+            return false;
+        }
+        let mut entry = Vec::new();
+        for (name, value) in [
+            ("MESSAGE", format!("Error in {path}: {message}").as_str()),
+            ("PRIORITY", "3"),
+            ("SYSLOG_IDENTIFIER", "tag_test"),
+            ("TAG_TEST_PATH", path),
+            ("TAG_TEST_ERROR_KIND", crate::scan::error_kind(message)),
+        ] {
+            // Values with a newline are given with their length
+            entry.extend_from_slice(name.as_bytes());
+            if value.contains('\n') {
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                entry.push(b'=');
+            }
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(b'\n');
+        }
+        UnixDatagram::unbound()
+            .and_then(|s| s.send_to(&entry, JOURNAL_SOCKET))
+            .is_ok()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod stubs {
+    pub fn ready() {}
+
+    pub fn finished(_status: &str) {}
+
+    pub fn progress(_status: impl FnOnce() -> String) {}
+
+    pub fn file_error(_path: &str, _message: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub use stubs::{file_error, finished, progress, ready};
@@ -0,0 +1,175 @@
+// Output line templates like "{artist} - {title} [{duration}]". Templates
+// are checked when the config is loaded, so a typo in a placeholder stops
+// tag_test before the scan rather than printing it on every line.
+use crate::{dates, fields, format, TrackInfo};
+use serde_derive::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    // Used for verbose scan output and playlist comments instead of the
+    // built in formats
+    pub line: Option<Template>,
+}
+
+const FIELDS: &[&str] = &[
+    "path",
+    "file",
+    "artist",
+    "albumartist",
+    "title",
+    "album",
+    "genre",
+    "track",
+    "track_total",
+    "disc",
+    "duration",
+    "seconds",
+    "bitrate",
+    "rating",
+    "play_count",
+    "bpm",
+    "key",
+    "composer",
+    "work",
+    "year",
+];
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+    // From [fields], checked once they're loaded
+    Computed(String),
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    // {{ and }} are a literal { and }
+    fn try_from(s: String) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{ in template {s:?}")),
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(match FIELDS.iter().find(|f| **f == name) {
+                        Some(field) => Part::Field(field),
+                        None => Part::Computed(name),
+                    });
+                }
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    // Placeholders that aren't built in have to be in [fields]
+    pub fn check(&self) -> Result<(), String> {
+        for part in &self.parts {
+            match part {
+                Part::Computed(name) if !fields::is_field(name) => {
+                    return Err(format!("unknown placeholder {{{name}}} in template"))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Missing values are left empty
+    pub fn render(&self, t: &TrackInfo) -> String {
+        self.render_with(t, |v| v)
+    }
+
+    // For a path, with the characters that can't go in a file name
+    // replaced in the values, so an album "AC/DC Live" is one directory
+    pub fn render_path(&self, t: &TrackInfo) -> String {
+        self.render_with(t, |v| {
+            v.replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_")
+                .trim_end_matches(['.', ' '])
+                .to_string()
+        })
+    }
+
+    fn render_with(&self, t: &TrackInfo, value: impl Fn(String) -> String) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(s) => out.push_str(s),
+                Part::Field(f) => out.push_str(&value(field(t, f))),
+                Part::Computed(f) => out.push_str(&value(fields::value(t, f))),
+            }
+        }
+        out
+    }
+}
+
+fn field(t: &TrackInfo, name: &str) -> String {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    match name {
+        "path" => t.path.clone(),
+        "file" => opt(Path::new(&t.path)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())),
+        "artist" => t.artist.to_string(),
+        // The artist when there's no album artist tag
+        "albumartist" => t
+            .album_artist
+            .clone()
+            .unwrap_or_else(|| t.artist.to_string()),
+        "title" => t.title.clone(),
+        "album" => t.album.to_string(),
+        "genre" => t.genre.to_string(),
+        "track" => t.track.to_string(),
+        "track_total" => opt(t.track_total.map(|n| n.to_string())),
+        "disc" => opt(t.disc.map(|n| n.to_string())),
+        "duration" => format::duration(t.duration),
+        "seconds" => t.duration.as_secs().to_string(),
+        "bitrate" => opt(t.bitrate.map(|b| b.to_string())),
+        "rating" => opt(t.rating.map(|r| r.to_string())),
+        "play_count" => opt(t.play_count.map(|c| c.to_string())),
+        "bpm" => opt(t.bpm.or(t.estimated_bpm).map(|b| format::decimal(b, 0))),
+        "key" => opt(t.key.clone()),
+        "composer" => opt(t.composer.clone()),
+        "work" => opt(t.work.clone()),
+        "year" => opt(t
+            .date
+            .as_deref()
+            .and_then(|d| dates::parse(d).ok())
+            .map(|d| d.year.to_string())),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,148 @@
+// Everything tag_test prints goes through here. Errors are red, warnings
+// yellow and totals bold, unless the output isn't a terminal or NO_COLOR
+// is set. --quiet only prints totals, warnings and errors, and --json
+// prints JSON events on stdout with everything else on stderr. --stream
+// is for piping the scan into jq and the like: each track goes to stdout
+// as a line of JSON as soon as it's read, with nothing else there.
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::fmt::{self, Display};
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TerminalConfig {
+    // auto, always or never
+    pub color: String,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        TerminalConfig {
+            color: String::from("auto"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Quiet,
+    Json,
+    Stream,
+}
+
+pub enum Kind {
+    Log,
+    Warning,
+    Error,
+    Total,
+}
+
+static COLOR: OnceLock<bool> = OnceLock::new();
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+// Called once the config is loaded, anything printed before that uses
+// auto
+pub fn init(tc: &TerminalConfig) {
+    let color = match tc.color.to_lowercase().as_str() {
+        "always" => true,
+        "never" => false,
+        _ => auto(),
+    };
+    let _ = COLOR.set(color);
+}
+
+// Take --quiet, --json and --stream out of the arguments, they work with
+// every command
+pub fn take_mode_args(args: &mut Vec<String>) {
+    let mode = if args.iter().any(|a| a == "--stream") {
+        Mode::Stream
+    } else if args.iter().any(|a| a == "--json") {
+        Mode::Json
+    } else if args.iter().any(|a| a == "--quiet") {
+        Mode::Quiet
+    } else {
+        Mode::Normal
+    };
+    args.retain(|a| a != "--json" && a != "--quiet" && a != "--stream");
+    let _ = MODE.set(mode);
+}
+
+pub fn mode() -> Mode {
+    *MODE.get_or_init(|| Mode::Normal)
+}
+
+fn auto() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+fn paint(code: &str, s: impl Display) -> String {
+    if *COLOR.get_or_init(auto) {
+        format!("\x1b[{code}m{s}\x1b[0m")
+    } else {
+        s.to_string()
+    }
+}
+
+// Use the log!, warn!, error! and total! macros rather than this
+pub fn print(kind: Kind, args: fmt::Arguments) {
+    match (mode(), kind) {
+        (Mode::Normal, Kind::Log) => println!("{args}"),
+        (Mode::Normal, Kind::Warning) => println!("{}", paint("33", args)),
+        (Mode::Normal, Kind::Error) => println!("{}", paint("31", args)),
+        (Mode::Normal | Mode::Quiet, Kind::Total) => println!("{}", paint("1", args)),
+        (Mode::Quiet, Kind::Log) => (),
+        (Mode::Quiet | Mode::Json | Mode::Stream, _) => eprintln!("{args}"),
+    }
+}
+
+// One JSON object per line, with the event name in "event". Nothing is
+// printed unless --json was given.
+pub fn event(name: &str, value: Value) {
+    if mode() != Mode::Json {
+        return;
+    }
+    let mut event = serde_json::Map::new();
+    event.insert(String::from("event"), Value::from(name));
+    match value {
+        Value::Object(fields) => event.extend(fields),
+        v => {
+            event.insert(String::from("value"), v);
+        }
+    }
+    println!("{}", Value::Object(event));
+}
+
+// A track read by the scan, only printed with --stream
+pub fn stream(track: &Value) {
+    if mode() == Mode::Stream {
+        println!("{track}");
+    }
+}
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::term::print($crate::term::Kind::Log, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::term::print($crate::term::Kind::Warning, format_args!($($arg)*))
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::term::print($crate::term::Kind::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! total {
+    ($($arg:tt)*) => {
+        $crate::term::print($crate::term::Kind::Total, format_args!($($arg)*))
+    };
+}
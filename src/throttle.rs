@@ -0,0 +1,136 @@
+// Slow down or pause the scan while the machine is busy or running on
+// battery, so a scan left running in the background doesn't get in the
+// way. Load and power come from /proc and /sys, so this only does
+// anything on Linux.
+use crate::cancel;
+use serde_derive::Deserialize;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    pub enabled: bool,
+    // Pause while the 1 minute load average is above this. 0 = the number
+    // of CPUs.
+    pub max_load: f64,
+    // What to do on battery: "slow", "pause" or "ignore"
+    pub battery: String,
+    // Milliseconds to wait between files when slowed down
+    pub delay: u64,
+    // Seconds between checks of the load and power
+    pub interval: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            enabled: false,
+            max_load: 0.0,
+            battery: String::from("slow"),
+            delay: 100,
+            interval: 5,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum State {
+    Run,
+    Slow,
+    Pause,
+}
+
+pub struct Throttle<'a> {
+    config: &'a ThrottleConfig,
+    max_load: f64,
+    state: State,
+    checked: Option<Instant>,
+}
+
+impl Throttle<'_> {
+    pub fn new(config: &ThrottleConfig) -> Throttle<'_> {
+        let max_load = match config.max_load {
+            l if l > 0.0 => l,
+            _ => thread::available_parallelism().map_or(1, |n| n.get()) as f64,
+        };
+        Throttle {
+            config,
+            max_load,
+            state: State::Run,
+            checked: None,
+        }
+    }
+
+    // Called before each file. Returns straight away unless the scan
+    // should slow down, and blocks while it should pause or until the scan
+    // is cancelled.
+    pub fn wait(&mut self) {
+        if !self.config.enabled {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.interval.max(1));
+        loop {
+            if self.checked.is_none_or(|c| c.elapsed() >= interval) {
+                self.check();
+            }
+            match self.state {
+                State::Run => return,
+                State::Slow => {
+                    thread::sleep(Duration::from_millis(self.config.delay));
+                    return;
+                }
+                State::Pause if cancel::cancelled() => return,
+                // Short naps so Ctrl-C is noticed, the load is still only
+                // checked every interval
+                State::Pause => thread::sleep(Duration::from_millis(250)),
+            }
+        }
+    }
+
+    fn check(&mut self) {
+        self.checked = Some(Instant::now());
+        let load = load_average();
+        let battery = on_battery();
+        let (state, reason) = match load {
+            Some(l) if l > self.max_load => (State::Pause, format!("load {l:.2}")),
+            _ if battery && self.config.battery == "pause" => {
+                (State::Pause, String::from("on battery"))
+            }
+            _ if battery && self.config.battery == "slow" => {
+                (State::Slow, String::from("on battery"))
+            }
+            _ => (State::Run, String::new()),
+        };
+        if state != self.state {
+            match state {
+                State::Run => log!("Resuming scan"),
+                State::Slow => log!("Slowing scan, {reason}"),
+                State::Pause => log!("Pausing scan, {reason}"),
+            }
+            self.state = state;
+        }
+    }
+}
+
+fn load_average() -> Option<f64> {
+    fs::read_to_string("/proc/loadavg")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+// True if a battery is discharging
+fn on_battery() -> bool {
+    let supplies = match fs::read_dir("/sys/class/power_supply") {
+        Ok(d) => d,
+        Err(_) => return false,
+    };
+    supplies.filter_map(|e| e.ok()).any(|e| {
+        let read = |name| fs::read_to_string(e.path().join(name)).unwrap_or_default();
+        read("type").trim() == "Battery" && read("status").trim() == "Discharging"
+    })
+}
@@ -0,0 +1,210 @@
+// Tracker modules (.mod, .xm, .it, .s3m) and game music rips (.spc, .vgm,
+// .nsf), which lofty doesn't read. Only built with the tracker feature.
+// Titles come from the file headers, plus the game and artist where the
+// format has them. Lengths come from .spc and .vgm headers, and for .mod
+// by stepping through the patterns; the other formats get none.
+
+pub const TYPES: &[&str] = &["mod", "xm", "it", "s3m", "spc", "vgm", "nsf"];
+
+pub fn is_tracker(file_name: &str) -> bool {
+    TYPES.contains(&crate::file_ext(file_name).as_str())
+}
+
+#[cfg(not(feature = "tracker"))]
+pub fn read(
+    _file_name: &str,
+) -> Result<(Option<lofty::tag::Tag>, lofty::properties::FileProperties), lofty::error::LoftyError>
+{
+    Err(std::io::Error::other("needs tag_test built with --features tracker").into())
+}
+
+#[cfg(feature = "tracker")]
+pub use formats::read;
+
+#[cfg(feature = "tracker")]
+mod formats {
+    use lofty::error::LoftyError;
+    use lofty::properties::FileProperties;
+    use lofty::tag::{ItemKey, Tag, TagType};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Info {
+        title: String,
+        artist: String,
+        game: String,
+        date: String,
+        seconds: f64,
+    }
+
+    pub fn read(file_name: &str) -> Result<(Option<Tag>, FileProperties), LoftyError> {
+        let data = fs::read(file_name)?;
+        let info = match crate::file_ext(file_name).as_str() {
+            "mod" => module(&data),
+            "xm" if data.starts_with(b"Extended Module: ") => Some(Info {
+                title: text(&data, 17, 20),
+                ..Info::default()
+            }),
+            "it" if data.starts_with(b"IMPM") => Some(Info {
+                title: text(&data, 4, 26),
+                ..Info::default()
+            }),
+            "s3m" if data.get(44..48) == Some(b"SCRM") => Some(Info {
+                title: text(&data, 0, 28),
+                ..Info::default()
+            }),
+            "spc" => spc(&data),
+            "vgm" => vgm(&data),
+            "nsf" if data.starts_with(b"NESM\x1a") => Some(Info {
+                title: text(&data, 0x0e, 32),
+                artist: text(&data, 0x2e, 32),
+                ..Info::default()
+            }),
+            _ => None,
+        };
+        let info = info.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad header"))?;
+
+        // The items are stored as if they were Vorbis comments, the
+        // closest thing to the free form text these formats have
+        let mut tag = Tag::new(TagType::VorbisComments);
+        for (key, value) in [
+            (ItemKey::TrackTitle, info.title),
+            (ItemKey::TrackArtist, info.artist),
+            (ItemKey::AlbumTitle, info.game),
+            (ItemKey::RecordingDate, info.date),
+        ] {
+            if !value.is_empty() {
+                tag.insert_text(key, value);
+            }
+        }
+        let props = FileProperties::new(
+            Duration::from_secs_f64(info.seconds),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        Ok((Some(tag), props))
+    }
+
+    // Fixed length text padded with nulls or spaces
+    fn text(data: &[u8], offset: usize, len: usize) -> String {
+        let bytes = data.get(offset..offset + len).unwrap_or_default();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+    }
+
+    fn u32_le(data: &[u8], offset: usize) -> usize {
+        data.get(offset..offset + 4)
+            .map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+    }
+
+    // Protracker and friends. The length is worked out by playing the
+    // order list, following speed changes, pattern breaks and position
+    // jumps until it ends or loops.
+    fn module(data: &[u8]) -> Option<Info> {
+        let signature = data.get(1080..1084)?;
+        let channels = match signature {
+            b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => 4,
+            [n @ b'1'..=b'9', b'C', b'H', b'N'] => (n - b'0') as usize,
+            [a @ b'1'..=b'9', b @ b'0'..=b'9', b'C', b'H'] => ((a - b'0') * 10 + b - b'0') as usize,
+            b"FLT8" => 8,
+            _ => return None,
+        };
+        let length = (*data.get(950)? as usize).min(128);
+        let orders = data.get(952..952 + length)?;
+        let row_size = channels * 4;
+        let pattern_size = 64 * row_size;
+
+        let (mut speed, mut bpm) = (6.0, 125.0);
+        let mut seconds = 0.0;
+        let mut seen = HashSet::new();
+        let (mut order, mut row) = (0, 0);
+        while order < length && seen.insert((order, row)) {
+            let start = 1084 + orders[order] as usize * pattern_size + row * row_size;
+            let cells = data.get(start..start + row_size)?;
+            let mut next = None;
+            for cell in cells.chunks_exact(4) {
+                let param = cell[3];
+                match cell[2] & 0x0f {
+                    0x0f if param == 0 => (),
+                    0x0f if param < 32 => speed = param as f64,
+                    0x0f => bpm = param as f64,
+                    0x0b => next = Some((param as usize, 0)),
+                    0x0d => {
+                        let to = ((param >> 4) * 10 + (param & 0x0f)) as usize;
+                        next = Some((next.map_or(order + 1, |n: (usize, usize)| n.0), to.min(63)));
+                    }
+                    _ => (),
+                }
+            }
+            seconds += speed * 2.5 / bpm;
+            (order, row) = match next {
+                Some(n) => n,
+                None if row == 63 => (order + 1, 0),
+                None => (order, row + 1),
+            };
+        }
+        Some(Info {
+            title: text(data, 0, 20),
+            seconds,
+            ..Info::default()
+        })
+    }
+
+    // Super Nintendo sound dumps, with an ID666 tag in the text layout
+    fn spc(data: &[u8]) -> Option<Info> {
+        if !data.starts_with(b"SNES-SPC700 Sound File Data") || data.get(0x23) != Some(&26) {
+            return None;
+        }
+        Some(Info {
+            title: text(data, 0x2e, 32),
+            game: text(data, 0x4e, 32),
+            date: text(data, 0x9e, 11),
+            seconds: text(data, 0xa9, 3).parse().unwrap_or(0.0),
+            artist: text(data, 0xb1, 32),
+        })
+    }
+
+    // Video game music logs. The sample count is at 44.1 kHz, and the GD3
+    // tag is UTF-16 strings: title, game, system and author in English
+    // then Japanese, then the date.
+    fn vgm(data: &[u8]) -> Option<Info> {
+        if !data.starts_with(b"Vgm ") {
+            return None;
+        }
+        let mut info = Info {
+            seconds: u32_le(data, 0x18) as f64 / 44100.0,
+            ..Info::default()
+        };
+        let gd3 = match u32_le(data, 0x14) {
+            0 => return Some(info),
+            o => o + 0x14,
+        };
+        if data.get(gd3..gd3 + 4) != Some(b"Gd3 ") {
+            return Some(info);
+        }
+        let len = u32_le(data, gd3 + 8);
+        let units: Vec<u16> = data
+            .get(gd3 + 12..gd3 + 12 + len)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        let strings: Vec<String> = units
+            .split(|&u| u == 0)
+            .map(String::from_utf16_lossy)
+            .collect();
+        let get = |i: usize| strings.get(i).cloned().unwrap_or_default();
+        info.title = get(0);
+        info.game = get(2);
+        info.artist = get(6);
+        info.date = get(8);
+        Some(info)
+    }
+}
@@ -0,0 +1,234 @@
+// Work out which files to transcode, write them to a job file, and with
+// --run have ffmpeg do them into a mirror of the library. Tags are copied
+// over by ffmpeg.
+use crate::cli::{self, Flag};
+use crate::{file_ext, music_files, term, Config};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+use std::sync::Mutex;
+use std::thread;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TranscodeConfig {
+    // Format to transcode to, also the extension of the new files
+    pub to: String,
+    // Only transcode these types. Empty = everything not already in the
+    // to format.
+    pub types: Vec<String>,
+    // Only transcode files with at least this bit depth or sample rate,
+    // 0 = any. E.g. 24 and 96000 for hi-res files only.
+    pub min_bit_depth: u8,
+    pub min_sample_rate: u32,
+    // Where the transcoded files go, in the same layout as the library
+    pub mirror: String,
+    // Job file to write, .csv for CSV, anything else is JSON
+    pub file: String,
+    // Options given to ffmpeg before the output file
+    pub ffmpeg_args: Vec<String>,
+    // ffmpegs to run at once
+    pub jobs: usize,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        TranscodeConfig {
+            to: String::from("opus"),
+            types: Vec::new(),
+            min_bit_depth: 0,
+            min_sample_rate: 0,
+            mirror: String::new(),
+            file: String::from("transcode.json"),
+            ffmpeg_args: vec![String::from("-b:a"), String::from("160k")],
+            jobs: 2,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Job {
+    source: String,
+    target: String,
+    bit_depth: Option<u8>,
+    sample_rate: Option<u32>,
+}
+
+pub const COMMAND: cli::Command = cli::Command {
+    name: "transcode",
+    usage: "[--run] [path...]",
+    about: "List files to transcode in a job file, and transcode them into a mirror",
+    flags: &[Flag {
+        name: "--run",
+        values: None,
+        about: "Run ffmpeg on the jobs rather than only writing the job file",
+    }],
+};
+
+pub fn run(config: &Config, args: &[String]) {
+    let tc = &config.transcode;
+    let run = args.iter().any(|a| a == "--run");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--run").cloned().collect();
+    if let Some(a) = paths.iter().find(|a| a.starts_with("--")) {
+        error!("Unknown option {a}");
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+    if tc.mirror.is_empty() {
+        error!("Set mirror in the [transcode] section of the config first");
+        exit(1);
+    }
+
+    let mut jobs = Vec::new();
+    for file_name in music_files(config, &paths) {
+        if let Some(job) = job(config, &file_name) {
+            jobs.push(job);
+        }
+    }
+    match write_jobs(&tc.file, &jobs) {
+        Ok(_) => log!("Wrote {} ({} files)", tc.file, jobs.len()),
+        Err(e) => {
+            error!("Error writing {}: {e}", tc.file);
+            exit(1);
+        }
+    }
+
+    let (done, failed) = if run { transcode(tc, &jobs) } else { (0, 0) };
+    total!(
+        "To transcode {}, Transcoded: {}, Failed: {}",
+        jobs.len(),
+        done,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "jobs": jobs.len(), "transcoded": done, "failed": failed }),
+    );
+}
+
+fn job(config: &Config, file_name: &str) -> Option<Job> {
+    let tc = &config.transcode;
+    let ext = file_ext(file_name);
+    if ext == tc.to || !(tc.types.is_empty() || tc.types.contains(&ext)) {
+        return None;
+    }
+    let (bit_depth, sample_rate) = match Probe::open(file_name).and_then(|p| p.read()) {
+        Ok(f) => (f.properties().bit_depth(), f.properties().sample_rate()),
+        Err(_) => (None, None),
+    };
+    if bit_depth.unwrap_or(0) < tc.min_bit_depth || sample_rate.unwrap_or(0) < tc.min_sample_rate {
+        return None;
+    }
+    let target = mirror_path(config, file_name, &tc.mirror).with_extension(&tc.to);
+    Some(Job {
+        source: file_name.to_string(),
+        target: target.to_string_lossy().to_string(),
+        bit_depth,
+        sample_rate,
+    })
+}
+
+// Where a library file goes under a mirror directory: the same path
+// relative to its scan root, or just the file name if it's under none
+pub fn mirror_path(config: &Config, file_name: &str, mirror: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    let relative = config
+        .root_for(file_name)
+        .and_then(|r| path.strip_prefix(&r.path).ok())
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path);
+    Path::new(mirror).join(relative)
+}
+
+fn write_jobs(file: &str, jobs: &[Job]) -> Result<(), String> {
+    let contents = if file_ext(file) == "csv" {
+        let mut csv = String::from("source,target,bit_depth,sample_rate\n");
+        for j in jobs {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                csv_field(&j.source),
+                csv_field(&j.target),
+                j.bit_depth.map_or(String::new(), |b| b.to_string()),
+                j.sample_rate.map_or(String::new(), |r| r.to_string())
+            ));
+        }
+        csv
+    } else {
+        serde_json::to_string_pretty(jobs).map_err(|e| e.to_string())?
+    };
+    fs::write(file, contents).map_err(|e| e.to_string())
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// Run the jobs through up to jobs ffmpegs at once. Targets newer than
+// their source are taken as done already.
+fn transcode(tc: &TranscodeConfig, jobs: &[Job]) -> (u32, u32) {
+    let queue = Mutex::new(jobs.iter());
+    let counts = Mutex::new((0, 0));
+    thread::scope(|s| {
+        for _ in 0..tc.jobs.max(1) {
+            s.spawn(|| loop {
+                let job = match queue.lock().unwrap().next() {
+                    Some(j) => j,
+                    None => break,
+                };
+                if up_to_date(&job.source, &job.target) {
+                    continue;
+                }
+                let res = ffmpeg(tc, &job.source, &job.target);
+                let mut counts = counts.lock().unwrap();
+                match res {
+                    Ok(_) => {
+                        log!("Transcoded {}", job.target);
+                        counts.0 += 1;
+                    }
+                    Err(e) => {
+                        error!("Error transcoding {}: {e}", job.source);
+                        term::event("error", json!({ "path": job.source, "message": e }));
+                        counts.1 += 1;
+                    }
+                }
+            });
+        }
+    });
+    counts.into_inner().unwrap()
+}
+
+// True if target exists and is at least as new as source
+pub fn up_to_date(source: &str, target: &str) -> bool {
+    let modified = |p: &str| fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(source), modified(target)) {
+        (Some(s), Some(t)) => t >= s,
+        _ => false,
+    }
+}
+
+pub fn ffmpeg(tc: &TranscodeConfig, source: &str, target: &str) -> Result<(), String> {
+    if let Some(dir) = Path::new(target).parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let output = Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-y", "-i", source])
+        .args(["-map", "0:a", "-map_metadata", "0"])
+        .args(&tc.ffmpeg_args)
+        .arg(target)
+        .output()
+        .map_err(|e| format!("running ffmpeg: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let _ = fs::remove_file(target);
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
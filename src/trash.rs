@@ -0,0 +1,198 @@
+// Deleting by moving to the trash, so a clean up that took too much can
+// be undone from the file manager. That's ~/.Trash on macOS, and the
+// freedesktop.org trash everywhere else, with the .trashinfo file the
+// file managers need to put a file back. A file on another filesystem,
+// e.g. a NAS, goes to that filesystem's own trash at the top of it
+// (.Trash/<uid> or .Trash-<uid>, .Trashes/<uid> on macOS) so it's only
+// moved, and is only copied home when that can't be made. There's no
+// Windows recycle bin support, use --permanent there. Commands that delete
+// take --permanent to skip it.
+use crate::feed::civil;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Delete path, into the trash unless permanent
+pub fn remove(path: &Path, permanent: bool) -> io::Result<()> {
+    if permanent {
+        return fs::remove_file(path);
+    }
+    let path = fs::canonicalize(path)?;
+    let (files, info, top) = dirs(&path)?;
+    fs::create_dir_all(&files)?;
+    if let Some(info) = &info {
+        fs::create_dir_all(info)?;
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no file name"))?;
+
+    // "name", "name 2", "name 3"... whichever is free. With a .trashinfo,
+    // creating it is what claims the name.
+    let mut n = 1;
+    let (to, mut info_file) = loop {
+        let candidate = match n {
+            1 => name.clone(),
+            n => match name.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => format!("{stem} {n}.{ext}"),
+                _ => format!("{name} {n}"),
+            },
+        };
+        n += 1;
+        if files.join(&candidate).exists() {
+            continue;
+        }
+        let Some(info) = &info else {
+            break (files.join(candidate), None);
+        };
+        let info_file = info.join(format!("{candidate}.trashinfo"));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_file)
+        {
+            Ok(f) => break (files.join(candidate), Some((info_file, f))),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let res = match &mut info_file {
+        Some((_, f)) => write!(
+            f,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            // A filesystem's own trash has it from the top
+            escape(top.and_then(|t| path.strip_prefix(t).ok()).unwrap_or(&path)),
+            now()
+        ),
+        None => Ok(()),
+    }
+    .and_then(|_| move_file(&path, &to));
+    // Nothing went in the trash, so its .trashinfo mustn't either
+    if let (Err(_), Some((info_file, _))) = (&res, &info_file) {
+        let _ = fs::remove_file(info_file);
+    }
+    res
+}
+
+// Rename, or copy and remove across filesystems. On an error the file is
+// left where it was and not in both places.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            let res = fs::copy(from, to).and_then(|_| fs::remove_file(from));
+            if res.is_err() {
+                let _ = fs::remove_file(to);
+            }
+            res
+        }
+        res => res,
+    }
+}
+
+// Where the files go, their .trashinfo files if there are any, and the
+// top of the filesystem when it's that filesystem's own trash
+fn dirs(path: &Path) -> io::Result<(PathBuf, Option<PathBuf>, Option<PathBuf>)> {
+    let home = home_dirs();
+    #[cfg(unix)]
+    {
+        let device = |p: &Path| fs::metadata(p).map(|m| crate::links::device(&m));
+        let dev = device(path)?;
+        // The home trash, or the first of its parents that's there
+        let home_dev = home
+            .as_ref()
+            .ok()
+            .and_then(|(files, _)| files.ancestors().find_map(|p| device(p).ok()));
+        if home_dev != Some(dev) {
+            let mut top = path.parent().unwrap_or(path);
+            while let Some(p) = top.parent().filter(|p| device(p).ok() == Some(dev)) {
+                top = p;
+            }
+            if let Ok((files, info)) = volume_dirs(top) {
+                return Ok((files, info, Some(top.to_path_buf())));
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    let (files, info) = home?;
+    Ok((files, info, None))
+}
+
+fn home_dirs() -> io::Result<(PathBuf, Option<PathBuf>)> {
+    let home = env::var_os("HOME")
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "HOME isn't set, use --permanent"))?;
+    if cfg!(target_os = "macos") {
+        return Ok((home.join(".Trash"), None));
+    }
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local/share"));
+    let trash = data.join("Trash");
+    Ok((trash.join("files"), Some(trash.join("info"))))
+}
+
+// The trash at the top of a filesystem: the shared .Trash if the admin
+// made one (sticky and not a link), otherwise .Trash-<uid> only we can
+// get into
+#[cfg(unix)]
+fn volume_dirs(top: &Path) -> io::Result<(PathBuf, Option<PathBuf>)> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    // SAFETY: getuid can't fail and touches no memory
+    let uid = unsafe { libc::getuid() };
+    if cfg!(target_os = "macos") {
+        let dir = top.join(".Trashes").join(uid.to_string());
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+        return Ok((dir, None));
+    }
+    let shared = top.join(".Trash");
+    let sticky = fs::symlink_metadata(&shared)
+        .is_ok_and(|m| m.is_dir() && m.permissions().mode() & 0o1000 != 0);
+    let dir = match sticky {
+        true => shared.join(uid.to_string()),
+        false => top.join(format!(".Trash-{uid}")),
+    };
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    Ok((dir.join("files"), Some(dir.join("info"))))
+}
+
+// The path as the spec wants it, URL escaped
+fn escape(path: &Path) -> String {
+    let mut out = String::new();
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+// Like 2024-05-01T13:45:00, in UTC
+fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil(secs / 86400);
+    let rem = secs % 86400;
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,73 @@
+// Group classical tracks by composer and work instead of artist and album.
+// Only tracks with a work tag are classical here, plenty of pop tracks
+// have a composer tag.
+use crate::TrackInfo;
+use lofty::id3::v2::Id3v2Tag;
+use lofty::tag::{ItemKey, Tag, TagType};
+use std::collections::BTreeMap;
+
+pub struct Work<'a> {
+    // The composer, or the artist when there is no composer tag
+    pub composer: &'a str,
+    pub title: &'a str,
+    pub tracks: Vec<&'a TrackInfo>,
+}
+
+impl Work<'_> {
+    pub fn movement_total(&self) -> Option<u32> {
+        self.tracks.iter().filter_map(|t| t.movement_total).max()
+    }
+
+    // Movement numbers up to the movement total that no track has
+    pub fn missing_movements(&self) -> Vec<u32> {
+        let total = self.movement_total().unwrap_or(0);
+        (1..=total)
+            .filter(|n| !self.tracks.iter().any(|t| t.movement_number == Some(*n)))
+            .collect()
+    }
+
+    // Distinct conductors, in movement order
+    pub fn conductors(&self) -> Vec<&str> {
+        let mut conductors = Vec::new();
+        for c in self.tracks.iter().filter_map(|t| t.conductor.as_deref()) {
+            if !conductors.contains(&c) {
+                conductors.push(c);
+            }
+        }
+        conductors
+    }
+}
+
+// The work tag. lofty leaves TXXX:WORK, which is where Picard writes it,
+// in the ID3v2 tag behind the generic one.
+pub fn work_tag(tag: &Tag) -> Option<String> {
+    let work = match tag.get_string(&ItemKey::Work) {
+        Some(w) => Some(w.to_string()),
+        None if tag.tag_type() == TagType::Id3v2 => Id3v2Tag::from(tag.clone())
+            .get_user_text("WORK")
+            .map(String::from),
+        None => None,
+    };
+    work.map(|w| w.trim().to_string()).filter(|w| !w.is_empty())
+}
+
+pub fn works(tracks: &[TrackInfo]) -> Vec<Work<'_>> {
+    let mut grouped: BTreeMap<(&str, &str), Vec<&TrackInfo>> = BTreeMap::new();
+    for t in tracks {
+        if let Some(work) = t.work.as_deref() {
+            let composer = t.composer.as_deref().unwrap_or(&t.artist);
+            grouped.entry((composer, work)).or_default().push(t);
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|((composer, title), mut tracks)| {
+            tracks.sort_by_key(|t| (t.movement_number, t.track));
+            Work {
+                composer,
+                title,
+                tracks,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,307 @@
+// Ratings, tags and comments that file managers keep in extended
+// attributes rather than in the file: user.xdg.tags and user.xdg.comment
+// (Dolphin, Nautilus and others) and user.baloo.rating (KDE, 0-10). With
+// enabled in [xattrs] they're read with the tags and kept in the cache
+// with them, and "tag_test xattrs" copies the rating and comment between
+// the attributes and the tags either way. Windows Explorer's rating is in
+// the file's own tags already (rating.rs), and macOS Finder tags aren't
+// read. Only on Linux. A change to just the attributes doesn't change the
+// file's modification time, so the cache only sees it when the file
+// changes too.
+use crate::cli::{Command, Flag, DRY_RUN};
+use crate::history::{self, Recorder};
+use crate::{music_files, rating, term, Config};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+use std::process::exit;
+use std::sync::OnceLock;
+
+pub const COMMAND: Command = Command {
+    name: "xattrs",
+    usage: "[--dry-run] --to-tags | --to-xattrs [path...]",
+    about: "Copy the rating and comment between extended attributes and the tags",
+    flags: &[
+        DRY_RUN,
+        Flag {
+            name: "--to-tags",
+            values: None,
+            about: "From the attributes to the tags",
+        },
+        Flag {
+            name: "--to-xattrs",
+            values: None,
+            about: "From the tags to the attributes",
+        },
+    ],
+};
+
+const TAGS: &str = "user.xdg.tags";
+const COMMENT: &str = "user.xdg.comment";
+const RATING: &str = "user.baloo.rating";
+// Where the rating goes in tags that have no rating of their own; rating.rs
+// reads it back
+const FMPS_RATING: &str = "FMPS_RATING";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct XattrsConfig {
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Xattrs {
+    pub tags: Vec<String>,
+    // 0-100, like the tags' rating
+    pub rating: Option<u8>,
+    pub comment: Option<String>,
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+// Called once the config is loaded
+pub fn init(xc: &XattrsConfig) {
+    let _ = ENABLED.set(xc.enabled);
+}
+
+// None unless switched on, or if the file has none of them
+pub fn read(path: &str) -> Option<Xattrs> {
+    if !ENABLED.get().copied().unwrap_or(false) {
+        return None;
+    }
+    read_all(Path::new(path))
+}
+
+fn read_all(path: &Path) -> Option<Xattrs> {
+    let text = |name| {
+        sys::get(path, name)
+            .ok()
+            .flatten()
+            .map(|v| String::from_utf8_lossy(&v).trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let x = Xattrs {
+        tags: text(TAGS)
+            .map(|t| t.split(',').map(|t| t.trim().to_string()).collect())
+            .unwrap_or_default(),
+        rating: text(RATING)
+            .and_then(|r| r.parse::<u8>().ok())
+            .filter(|r| *r <= 10)
+            .map(|r| r * 10),
+        comment: text(COMMENT),
+    };
+    (x != Xattrs::default()).then_some(x)
+}
+
+pub fn run(config: &Config, args: &[String]) {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let to_tags = args.iter().any(|a| a == "--to-tags");
+    let to_xattrs = args.iter().any(|a| a == "--to-xattrs");
+    let paths: Vec<String> = args
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .cloned()
+        .collect();
+    let known = ["--dry-run", "--to-tags", "--to-xattrs"];
+    if to_tags == to_xattrs
+        || args
+            .iter()
+            .any(|a| a.starts_with("--") && !known.contains(&a.as_str()))
+    {
+        log!("{}", COMMAND.usage());
+        exit(1);
+    }
+
+    let mut history = Recorder::journal(config).filter(|_| !dry_run);
+    let (mut changed, mut failed) = (0, 0);
+    for file_name in music_files(config, &paths) {
+        let res = match to_tags {
+            true => to_tag(&file_name, dry_run, history.as_mut()),
+            false => to_xattr(&file_name, dry_run),
+        };
+        match res {
+            Ok(true) => changed += 1,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error copying the attributes of {file_name}: {e}");
+                term::event("error", json!({ "path": file_name, "message": e }));
+                failed += 1;
+            }
+        }
+    }
+    total!(
+        "{} {}, Failed: {}",
+        if dry_run { "Would change" } else { "Changed" },
+        changed,
+        failed
+    );
+    term::event(
+        "summary",
+        json!({ "dry_run": dry_run, "changed": changed, "failed": failed }),
+    );
+    if let Some(h) = history {
+        h.finish();
+    }
+}
+
+// The attributes' rating and comment into the tags, where they have them
+fn to_tag(file_name: &str, dry_run: bool, history: Option<&mut Recorder>) -> Result<bool, String> {
+    let x = match read_all(Path::new(file_name)) {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let mut tag = match tagged_file.primary_tag() {
+        Some(t) => t.clone(),
+        None => return Ok(false),
+    };
+    let mut written = Vec::new();
+    if let Some(r) = x.rating.filter(|r| rating::rating(&tag) != Some(*r)) {
+        let old = rating::rating(&tag);
+        log!("{file_name}: rating {old:?} -> {r}");
+        let value = format!("{:.1}", r as f64 / 100.0);
+        if history::set(&mut tag, ItemKey::Unknown(FMPS_RATING.into()), &value) {
+            let old = old.map(|o| o.to_string()).unwrap_or_default();
+            written.push(("rating", old, r.to_string()));
+        }
+    }
+    if let Some(c) = x.comment.filter(|c| tag.comment().as_deref() != Some(c)) {
+        let old = tag.comment().unwrap_or_default().to_string();
+        log!("{file_name}: comment {old:?} -> {c:?}");
+        if history::set(&mut tag, ItemKey::Comment, &c) {
+            written.push(("comment", old, c));
+        }
+    }
+    if written.is_empty() {
+        return Ok(false);
+    }
+    if !dry_run {
+        tag.save_to_path(file_name, WriteOptions::default())
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(h) = history {
+        for (field, old, new) in written {
+            h.change(file_name, field, old, new);
+        }
+    }
+    Ok(true)
+}
+
+// The tags' rating and comment into the attributes
+fn to_xattr(file_name: &str, dry_run: bool) -> Result<bool, String> {
+    let path = Path::new(file_name);
+    let tagged_file = Probe::open(file_name)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+    let tag = match tagged_file.primary_tag() {
+        Some(t) => t,
+        None => return Ok(false),
+    };
+    let x = read_all(path).unwrap_or_default();
+    let mut change = false;
+    if let Some(r) = rating::rating(tag).filter(|r| x.rating != Some(r / 10 * 10)) {
+        log!("{file_name}: {RATING} {:?} -> {}", x.rating, r / 10);
+        if !dry_run {
+            sys::set(path, RATING, (r / 10).to_string().as_bytes()).map_err(|e| e.to_string())?;
+        }
+        change = true;
+    }
+    let comment = tag
+        .comment()
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty());
+    if let Some(c) = comment.filter(|c| x.comment.as_ref() != Some(c)) {
+        log!(
+            "{file_name}: {COMMENT} {:?} -> {c:?}",
+            x.comment.unwrap_or_default()
+        );
+        if !dry_run {
+            sys::set(path, COMMENT, c.as_bytes()).map_err(|e| e.to_string())?;
+        }
+        change = true;
+    }
+    Ok(change)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // Attributes bigger than this aren't for us
+    const MAX: usize = 64 * 1024;
+
+    fn c_strings(path: &Path, name: &str) -> io::Result<(CString, CString)> {
+        let p = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+        let n = CString::new(name).map_err(io::Error::other)?;
+        Ok((p, n))
+    }
+
+    // None if the file doesn't have it, or the filesystem has no
+    // attributes
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let (p, n) = c_strings(path, name)?;
+        let mut buf = vec![0u8; 256];
+        loop {
+            // SAFETY: the strings are NUL terminated and buf is as long as
+            // the size given
+            let len = unsafe {
+                libc::getxattr(p.as_ptr(), n.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+            };
+            if len >= 0 {
+                buf.truncate(len as usize);
+                return Ok(Some(buf));
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::ENODATA | libc::ENOTSUP) => return Ok(None),
+                Some(libc::ERANGE) if buf.len() < MAX => buf.resize(buf.len() * 4, 0),
+                _ => return Err(e),
+            }
+        }
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (p, n) = c_strings(path, name)?;
+        // SAFETY: the strings are NUL terminated and value is as long as
+        // the size given
+        let res = unsafe {
+            libc::setxattr(
+                p.as_ptr(),
+                n.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        match res {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attributes are only written on Linux",
+        ))
+    }
+}